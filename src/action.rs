@@ -0,0 +1,733 @@
+use crate::error::FdupError;
+use crate::fdup::checksum;
+use crate::fdup::partial_checksum;
+use crate::trash::trash;
+use rayon::prelude::*;
+use std::fs::hard_link;
+use std::fs::metadata;
+use std::fs::remove_file;
+use std::fs::rename;
+use std::fs::Metadata;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Values accepted by `--keep`, in the same order as the variants of `Keep`.
+pub const KEEP_VALUES: &[&str] = &["first", "newest", "oldest", "shortest-path"];
+
+/// Strategy for choosing the one file of a duplicate group that survives an action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keep {
+    /// Lexicographically smallest path
+    First,
+    /// Most recently modified file
+    Newest,
+    /// Least recently modified file
+    Oldest,
+    /// Path with the fewest bytes
+    ShortestPath,
+}
+
+impl FromStr for Keep {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Keep::First),
+            "newest" => Ok(Keep::Newest),
+            "oldest" => Ok(Keep::Oldest),
+            "shortest-path" => Ok(Keep::ShortestPath),
+            _ => Err(format!("unknown keep strategy {:?}", s)),
+        }
+    }
+}
+
+/// Values accepted by `--reverify`, in the same order as the variants of `Reverify`.
+pub const REVERIFY_VALUES: &[&str] = &["none", "quick", "full"];
+
+/// How thoroughly a group is checked to still be identical right before acting on it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Reverify {
+    /// Trust the results of the scan
+    None,
+    /// Compare sizes and partial checksums
+    #[default]
+    Quick,
+    /// Compare sizes, partial checksums, and then full checksums
+    Full,
+}
+
+impl FromStr for Reverify {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Reverify::None),
+            "quick" => Ok(Reverify::Quick),
+            "full" => Ok(Reverify::Full),
+            _ => Err(format!("unknown reverify depth {:?}", s)),
+        }
+    }
+}
+
+/// Values accepted by `--symlink-style`, in the same order as the variants of `SymlinkStyle`.
+pub const SYMLINK_STYLE_VALUES: &[&str] = &["relative", "absolute"];
+
+/// Form of the target stored in symlinks created by `Action::Symlink`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkStyle {
+    /// Relative to the directory containing the link, so trees can be moved as a whole
+    #[default]
+    Relative,
+    /// Canonical absolute path of the survivor
+    Absolute,
+}
+
+impl FromStr for SymlinkStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relative" => Ok(SymlinkStyle::Relative),
+            "absolute" => Ok(SymlinkStyle::Absolute),
+            _ => Err(format!("unknown symlink style {:?}", s)),
+        }
+    }
+}
+
+/// What to do with the files of a duplicate group that are not kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Remove them
+    Delete,
+    /// Move them to the trash
+    Trash,
+    /// Replace them with hard links to the survivor
+    Hardlink,
+    /// Replace them with symbolic links to the survivor
+    Symlink(SymlinkStyle),
+    /// Make them share the survivor's extents on copy-on-write filesystems, keeping them
+    /// otherwise independent files
+    Reflink,
+}
+
+impl Action {
+    /// # Returns
+    ///
+    /// Verb describing what happened to a file the action was applied to, or what would have
+    /// happened to it if this were not a `dry_run`.
+    fn verb(self, dry_run: bool) -> &'static str {
+        match (self, dry_run) {
+            (Action::Delete, false) => "deleted",
+            (Action::Delete, true) => "would delete",
+            (Action::Trash, false) => "trashed",
+            (Action::Trash, true) => "would trash",
+            (Action::Hardlink, false) => "hardlinked",
+            (Action::Hardlink, true) => "would hardlink",
+            (Action::Symlink(_), false) => "symlinked",
+            (Action::Symlink(_), true) => "would symlink",
+            (Action::Reflink, false) => "reflinked",
+            (Action::Reflink, true) => "would reflink",
+        }
+    }
+}
+
+/// Rules that govern how actions are applied to groups.
+#[derive(Clone, Debug)]
+pub struct Policy {
+    /// Strategy for choosing the file that survives
+    pub keep: Keep,
+    /// Path prefixes whose files survive in favour of any others, best first; `keep` only
+    /// decides between files under the same prefix
+    pub prefer: Vec<PathBuf>,
+    /// Paths under which no file is ever acted upon
+    pub protect: Vec<PathBuf>,
+    /// How thoroughly to check that a group is still identical right before acting on it
+    pub reverify: Reverify,
+    /// Only report what would be done, without touching the filesystem
+    pub dry_run: bool,
+    /// Report nothing about the files acted upon but failures
+    pub quiet: bool,
+}
+
+impl Policy {
+    /// # Returns
+    ///
+    /// Whether `path` is under one of the protected paths.
+    pub fn is_protected(&self, path: &Path) -> bool {
+        !self.protect.is_empty() && prefix_index(&self.protect, path).is_some()
+    }
+}
+
+/// # Returns
+///
+/// Index of the first of the canonical `prefixes` that `path` is under, whether as given or once
+/// canonicalized.
+fn prefix_index(prefixes: &[PathBuf], path: &Path) -> Option<usize> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    prefixes
+        .iter()
+        .position(|prefix| path.starts_with(prefix) || canonical.starts_with(prefix))
+}
+
+/// # Returns
+///
+/// Index into `group` of the file that should survive according to `prefer` and then `keep`.
+/// Ties are broken by choosing the lexicographically smallest path, so the choice does not depend
+/// on the order of `group`.
+pub fn choose_survivor(keep: Keep, prefer: &[PathBuf], group: &[PathBuf]) -> usize {
+    let mtime = |path: &PathBuf| {
+        metadata(path)
+            .and_then(|meta| meta.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    };
+    let rank = |path: &PathBuf| prefix_index(prefer, path).unwrap_or(prefer.len());
+    let ranks: Vec<usize> = match prefer.is_empty() {
+        true => vec![0; group.len()],
+        false => group.iter().map(rank).collect(),
+    };
+    let best = ranks.iter().min().cloned().unwrap_or(0);
+    let indices = (0..group.len()).filter(|&i| ranks[i] == best);
+    let chosen = match keep {
+        Keep::First => indices.min_by_key(|&i| &group[i]),
+        Keep::Newest => indices.max_by(|&a, &b| {
+            // reverse the tiebreak, since `max_by` prefers later elements
+            mtime(&group[a])
+                .cmp(&mtime(&group[b]))
+                .then_with(|| group[b].cmp(&group[a]))
+        }),
+        Keep::Oldest => indices.min_by_key(|&i| (mtime(&group[i]), &group[i])),
+        Keep::ShortestPath => indices.min_by_key(|&i| (group[i].as_os_str().len(), &group[i])),
+    };
+    chosen.unwrap_or(0)
+}
+
+/// Check, in parallel, that the files of `group` are still identical, climbing the same ladder
+/// as the scan itself (size, partial checksum, full checksum) only as far as `depth` asks, and
+/// stopping at the first rung where they differ.
+///
+/// # Returns
+///
+/// `Err` describing the first discrepancy found, if any.
+pub fn reverify(depth: Reverify, group: &[PathBuf]) -> Result<(), String> {
+    fn all_equal<K, F>(group: &[PathBuf], key_f: F) -> Result<(), String>
+    where
+        K: PartialEq + Send,
+        F: Fn(&Path) -> Result<K, FdupError> + Send + Sync,
+    {
+        let keys: Vec<K> = group
+            .par_iter()
+            .map(|path| key_f(path).map_err(|err| err.to_string()))
+            .collect::<Result<_, _>>()?;
+        match keys.windows(2).position(|pair| pair[0] != pair[1]) {
+            Some(i) => Err(format!("{:?} and {:?} differ", group[i], group[i + 1])),
+            None => Ok(()),
+        }
+    }
+    if depth == Reverify::None {
+        return Ok(());
+    }
+    all_equal(group, |path| {
+        metadata(path)
+            .map(|meta| meta.len())
+            .map_err(|err| FdupError::io(path, err))
+    })?;
+    all_equal(group, partial_checksum)?;
+    if depth == Reverify::Full {
+        all_equal(group, checksum)?;
+    }
+    Ok(())
+}
+
+/// Apply `action` to every file in `group` except the one chosen by `policy`, reporting each step
+/// on stdout unless `policy` is quiet, and each failure on stderr. Nothing is done if the group
+/// fails to `reverify`.
+///
+/// # Returns
+///
+/// Number of bytes reclaimed, or that would be reclaimed in a dry run.
+pub fn apply(action: Action, policy: &Policy, group: &[PathBuf]) -> u64 {
+    apply_except(
+        action,
+        policy,
+        group,
+        &[choose_survivor(policy.keep, &policy.prefer, group)],
+    )
+}
+
+/// Apply `action` to every file in `group` except those at `kept` and those `policy` protects,
+/// reporting each step on stdout unless `policy` is quiet, and each failure on stderr. Links
+/// created by the action point to the first kept file. Nothing is done if the group fails to
+/// `reverify`.
+///
+/// # Returns
+///
+/// Number of bytes reclaimed, or that would be reclaimed in a dry run.
+pub fn apply_except(action: Action, policy: &Policy, group: &[PathBuf], kept: &[usize]) -> u64 {
+    let survivor = match kept.first() {
+        Some(&i) => &group[i],
+        None => return 0, // refuse to get rid of every copy
+    };
+    if !survivor.is_file() {
+        // never touch the copies if the one we mean to keep has gone missing
+        eprintln!("ERROR: {:?} disappeared, skipping its group", survivor);
+        return 0;
+    }
+    if let Err(err) = reverify(policy.reverify, group) {
+        eprintln!(
+            "ERROR: group changed since it was scanned, skipping it: {}",
+            err
+        );
+        return 0;
+    }
+
+    group
+        .iter()
+        .enumerate()
+        .filter(|(i, path)| !kept.contains(i) && !policy.is_protected(path))
+        .map(
+            |(_, path)| match apply_one(action, policy.dry_run, survivor, path) {
+                Ok(Some(size)) => {
+                    if !policy.quiet {
+                        let verb = action.verb(policy.dry_run);
+                        println!("{} {:?} (kept {:?})", verb, path, survivor);
+                    }
+                    size
+                }
+                Ok(None) => 0,
+                Err(err) => {
+                    eprintln!("ERROR with {:?}: {}", path, err);
+                    0
+                }
+            },
+        )
+        .sum()
+}
+
+/// # Returns
+///
+/// Number of bytes reclaimed by applying `action` to `path`, a duplicate of `survivor`, or `None`
+/// if there was nothing to do. In a `dry_run`, every check is made but nothing is modified.
+fn apply_one(
+    action: Action,
+    dry_run: bool,
+    survivor: &Path,
+    path: &Path,
+) -> Result<Option<u64>, String> {
+    let meta = metadata(path).map_err(|err| format!("{}", err))?;
+    match action {
+        Action::Delete if dry_run => (),
+        Action::Delete => remove_file(path).map_err(|err| format!("{}", err))?,
+        Action::Trash if dry_run => (),
+        Action::Trash => trash(path)?,
+        Action::Hardlink => {
+            let survivor_meta = metadata(survivor).map_err(|err| format!("{}", err))?;
+            if is_same_file(&survivor_meta, &meta) {
+                return Ok(None); // already linked
+            }
+            if !is_same_device(&survivor_meta, &meta) {
+                return Err(format!("not on the same filesystem as {:?}", survivor));
+            }
+            if !dry_run {
+                replace_with(path, |tmp| hard_link(survivor, tmp))?;
+            }
+        }
+        Action::Symlink(style) => {
+            let target = symlink_target(style, survivor, path)?;
+            if !dry_run {
+                replace_with(path, |tmp| symlink(&target, tmp))?;
+            }
+        }
+        Action::Reflink if dry_run => (),
+        Action::Reflink => reflink(survivor, path, &meta)?,
+    };
+    Ok(Some(reclaimable_bytes(&meta)))
+}
+
+/// # Returns
+///
+/// What a symlink at `link` should point to in order to resolve to `survivor`.
+pub fn symlink_target(
+    style: SymlinkStyle,
+    survivor: &Path,
+    link: &Path,
+) -> Result<PathBuf, String> {
+    let survivor = survivor
+        .canonicalize()
+        .map_err(|err| format!("{:?}: {}", survivor, err))?;
+    match style {
+        SymlinkStyle::Absolute => Ok(survivor),
+        SymlinkStyle::Relative => {
+            // canonicalize the parent rather than the link, which may itself be a symlink
+            let link_dir = link
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."))
+                .canonicalize()
+                .map_err(|err| format!("{:?}: {}", link, err))?;
+            Ok(relative_path(&link_dir, &survivor))
+        }
+    }
+}
+
+/// # Returns
+///
+/// Path that leads from the directory `from` to `to`, where both are absolute and free of `.`
+/// and `..` components.
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let ups = (common..from.len()).map(|_| Path::new(".."));
+    let downs = to[common..].iter().map(|component| component.as_ref());
+    ups.chain(downs).collect()
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Clone the extents of `survivor` into `path` in place, so that the file keeps its identity,
+/// permissions, and (restored afterwards) timestamps, described by `meta`.
+#[cfg(target_os = "linux")]
+fn reflink(survivor: &Path, path: &Path, meta: &Metadata) -> Result<(), String> {
+    use std::fs::File;
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
+    // _IOW(0x94, 9, int), whose encoding differs on a few architectures
+    #[cfg(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc64"
+    ))]
+    const FICLONE: libc::c_ulong = 0x8004_9409;
+    #[cfg(not(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc64"
+    )))]
+    const FICLONE: libc::c_ulong = 0x4004_9409;
+
+    let src = File::open(survivor).map_err(|err| format!("{:?}: {}", survivor, err))?;
+    let dst = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|err| format!("{}", err))?;
+
+    // safe, since both descriptors stay open for the duration of the calls
+    if unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE as _, src.as_raw_fd()) } != 0 {
+        return Err(format!("FICLONE: {}", std::io::Error::last_os_error()));
+    }
+    let times = [
+        libc::timespec {
+            tv_sec: meta.atime() as libc::time_t,
+            tv_nsec: meta.atime_nsec() as _,
+        },
+        libc::timespec {
+            tv_sec: meta.mtime() as libc::time_t,
+            tv_nsec: meta.mtime_nsec() as _,
+        },
+    ];
+    if unsafe { libc::futimens(dst.as_raw_fd(), times.as_ptr()) } != 0 {
+        return Err(format!(
+            "restoring times: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_survivor: &Path, _path: &Path, _meta: &Metadata) -> Result<(), String> {
+    Err(String::from("reflinks are only supported on Linux"))
+}
+
+/// Atomically replace `path` with whatever `create` makes at the temporary path it is given, so
+/// that `path` never goes missing even if something fails halfway.
+fn replace_with<F>(path: &Path, create: F) -> Result<(), String>
+where
+    F: FnOnce(&Path) -> std::io::Result<()>,
+{
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| String::from("path has no file name"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(".fdup-tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    create(&tmp).map_err(|err| format!("creating {:?}: {}", tmp, err))?;
+    rename(&tmp, path).map_err(|err| {
+        let _ = remove_file(&tmp); // best effort, the original is still intact
+        format!("replacing with {:?}: {}", tmp, err)
+    })
+}
+
+/// # Returns
+///
+/// Whether `a` and `b` describe the same file, i.e. are already hard links of each other.
+#[cfg(unix)]
+fn is_same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// # Returns
+///
+/// `false`, since there is no portable way to tell without unstable APIs.
+#[cfg(not(unix))]
+fn is_same_file(_a: &Metadata, _b: &Metadata) -> bool {
+    false
+}
+
+/// # Returns
+///
+/// Whether `a` and `b` live on the same filesystem, and so can be hard linked.
+#[cfg(unix)]
+fn is_same_device(a: &Metadata, b: &Metadata) -> bool {
+    a.dev() == b.dev()
+}
+
+/// # Returns
+///
+/// `true`, leaving it to the OS to refuse links across filesystems.
+#[cfg(not(unix))]
+fn is_same_device(_a: &Metadata, _b: &Metadata) -> bool {
+    true
+}
+
+/// # Returns
+///
+/// Bytes freed once the file described by `meta` loses a name; nothing is freed if other hard
+/// links keep its contents alive.
+#[cfg(unix)]
+fn reclaimable_bytes(meta: &Metadata) -> u64 {
+    match meta.nlink() {
+        1 => meta.len(),
+        _ => 0,
+    }
+}
+
+/// # Returns
+///
+/// Size of the file described by `meta`.
+#[cfg(not(unix))]
+fn reclaimable_bytes(meta: &Metadata) -> u64 {
+    meta.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::create_dir_all;
+    use std::fs::read;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+
+    #[test]
+    fn path_based_survivors() {
+        let group: Vec<PathBuf> = vec!["b/long/name", "a/long/name", "c/x"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(1, choose_survivor(Keep::First, &[], &group));
+        assert_eq!(2, choose_survivor(Keep::ShortestPath, &[], &group));
+
+        let prefer = [PathBuf::from("b/long"), PathBuf::from("a")];
+        assert_eq!(0, choose_survivor(Keep::ShortestPath, &prefer, &group));
+        assert_eq!(1, choose_survivor(Keep::First, &prefer[1..], &group));
+        assert_eq!(
+            1,
+            choose_survivor(Keep::First, &[PathBuf::from("b/lo")], &group)
+        );
+    }
+
+    #[test]
+    fn relative_paths() {
+        let rel = |from: &str, to: &str| relative_path(Path::new(from), Path::new(to));
+        assert_eq!(PathBuf::from("b/c"), rel("/a", "/a/b/c"));
+        assert_eq!(PathBuf::from("../../x/y"), rel("/a/b/c", "/a/x/y"));
+        assert_eq!(PathBuf::from("../f"), rel("/a/b", "/a/f"));
+    }
+
+    #[test]
+    fn reverify_catches_changed_files() {
+        let dir =
+            std::env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let group = vec![dir.join("a"), dir.join("b")];
+        write(&group[0], "same size").unwrap();
+        write(&group[1], "same size").unwrap();
+        assert_eq!(Ok(()), reverify(Reverify::Full, &group));
+
+        write(&group[1], "SAME SIZE").unwrap();
+        assert_eq!(Ok(()), reverify(Reverify::None, &group));
+        assert!(reverify(Reverify::Quick, &group).is_err());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn hardlinks_replace_duplicates() {
+        let dir =
+            std::env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let (survivor, duplicate) = (dir.join("a"), dir.join("b"));
+        write(&survivor, "same contents").unwrap();
+        write(&duplicate, "same contents").unwrap();
+        let before = metadata(&survivor).unwrap();
+
+        let reclaimed = apply_one(Action::Hardlink, false, &survivor, &duplicate).unwrap();
+        assert_eq!(Some(13), reclaimed);
+        let after = metadata(&survivor).unwrap();
+        assert_eq!(after.ino(), metadata(&duplicate).unwrap().ino());
+        assert_eq!(2, after.nlink());
+        assert_eq!(before.mtime_nsec(), after.mtime_nsec());
+        assert_eq!(b"same contents".to_vec(), read(&survivor).unwrap());
+        // only the two names are left, without the temporary link
+        assert_eq!(2, std::fs::read_dir(&dir).unwrap().count());
+
+        // linking again has nothing to do
+        assert_eq!(
+            Ok(None),
+            apply_one(Action::Hardlink, false, &survivor, &duplicate)
+        );
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_replacements_leave_no_temporary_file() {
+        let dir =
+            std::env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(dir.join("taken/inside")).unwrap();
+
+        // a file can't be renamed over a directory that isn't empty
+        let target = dir.join("taken");
+        let result = replace_with(&target, |tmp| write(tmp, "replacement"));
+        assert!(result.is_err());
+        assert!(target.join("inside").is_dir());
+        assert!(!dir.join(".taken.fdup-tmp").exists());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn protected_paths_survive_every_action() {
+        let dir =
+            std::env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        for action in [
+            Action::Delete,
+            Action::Hardlink,
+            Action::Symlink(SymlinkStyle::Relative),
+        ] {
+            create_dir_all(dir.join("protected")).unwrap();
+            let dir = dir.canonicalize().unwrap();
+            let group = vec![dir.join("a"), dir.join("c"), dir.join("protected/b")];
+            for path in group.iter() {
+                write(path, "same contents").unwrap();
+            }
+            let policy = Policy {
+                keep: Keep::First,
+                prefer: Vec::new(),
+                protect: vec![dir.join("protected")],
+                reverify: Reverify::Full,
+                dry_run: false,
+                quiet: true,
+            };
+
+            assert_eq!(13, apply(action, &policy, &group));
+            let protected = &group[2];
+            assert!(!protected
+                .symlink_metadata()
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!(b"same contents".to_vec(), read(protected).unwrap());
+            #[cfg(unix)]
+            assert_eq!(1, metadata(protected).unwrap().nlink());
+            // the one that isn't protected was acted upon
+            let file_type = group[1].symlink_metadata().map(|meta| meta.file_type());
+            match action {
+                Action::Symlink(_) => assert!(file_type.unwrap().is_symlink()),
+                Action::Delete => assert!(file_type.is_err()),
+                #[cfg(unix)]
+                _ => assert_eq!(2, metadata(&group[1]).unwrap().nlink()),
+                #[cfg(not(unix))]
+                _ => assert!(file_type.unwrap().is_file()),
+            }
+
+            remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn dry_runs_touch_nothing() {
+        let dir =
+            std::env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let group: Vec<PathBuf> = ["a", "b", "c"].iter().map(|name| dir.join(name)).collect();
+        for path in group.iter() {
+            write(path, "same contents").unwrap();
+        }
+        let stamps = |group: &[PathBuf]| -> Vec<(u64, SystemTime, bool)> {
+            group
+                .iter()
+                .map(|path| {
+                    let meta = path.symlink_metadata().unwrap();
+                    (
+                        meta.len(),
+                        meta.modified().unwrap(),
+                        meta.file_type().is_file(),
+                    )
+                })
+                .collect()
+        };
+        let before = stamps(&group);
+        let policy = Policy {
+            keep: Keep::First,
+            prefer: Vec::new(),
+            protect: Vec::new(),
+            reverify: Reverify::Full,
+            dry_run: true,
+            quiet: true,
+        };
+
+        for action in [
+            Action::Delete,
+            Action::Trash,
+            Action::Hardlink,
+            Action::Symlink(SymlinkStyle::Absolute),
+            Action::Reflink,
+        ] {
+            assert_eq!(2 * 13, apply(action, &policy, &group));
+            assert_eq!(before, stamps(&group));
+            #[cfg(unix)]
+            assert!(group
+                .iter()
+                .all(|path| metadata(path).unwrap().nlink() == 1));
+        }
+        assert_eq!(3, std::fs::read_dir(&dir).unwrap().count());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
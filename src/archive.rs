@@ -0,0 +1,398 @@
+use crate::error::FdupError;
+use crate::fdup::DuplicateGroup;
+use crate::walk::plain_path;
+use flate2::read::GzDecoder;
+use sha2::Digest;
+use sha2::Sha512;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Formats of the archives whose files `--scan-archives` looks at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    /// tar compressed with gzip, as `.tar.gz` or `.tgz`
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// # Returns
+    ///
+    /// Format of the archive at `path` going by its extension, or `None` if it isn't one.
+    pub fn of(path: &Path) -> Option<ArchiveKind> {
+        let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+        match name {
+            _ if name.ends_with(".zip") => Some(ArchiveKind::Zip),
+            _ if name.ends_with(".tar") => Some(ArchiveKind::Tar),
+            _ if name.ends_with(".tar.gz") || name.ends_with(".tgz") => Some(ArchiveKind::TarGz),
+            _ => None,
+        }
+    }
+}
+
+/// Regular file inside an archive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    /// Path of the archive on disk
+    pub archive: PathBuf,
+    /// Path of the file inside the archive, without any leading `/` or `..`
+    pub name: PathBuf,
+    /// Size of the file once extracted
+    pub size: u64,
+}
+
+impl Member {
+    /// # Returns
+    ///
+    /// Path the file is reported under, as if the archive were a directory it is in.
+    pub fn path(&self) -> PathBuf {
+        self.archive.join(&self.name)
+    }
+}
+
+/// Files inside the archives found by a scan, which are compared with each other and with the
+/// files on disk as if they were files themselves, under their `Member::path`. Filled in from all
+/// threads of a scan, and looked up once it is done.
+#[derive(Debug, Default)]
+pub struct ArchiveIndex {
+    members: Mutex<HashMap<PathBuf, Member>>,
+    /// Number of files in each archive
+    counts: Mutex<HashMap<PathBuf, usize>>,
+    /// sha512 checksums of the files of the archives hashed so far, by `Member::path`
+    checksums: Mutex<HashMap<PathBuf, Vec<u8>>>,
+}
+
+impl ArchiveIndex {
+    /// List the files in the archive at `path`, to be looked up from then on.
+    ///
+    /// # Returns
+    ///
+    /// Files in the archive, or an error if it can't be read or isn't one after all. A file that
+    /// is in it more than once, as tar allows, is listed once, as the last copy that extracting
+    /// it would leave.
+    pub fn add(&self, path: &Path) -> io::Result<Vec<Member>> {
+        let mut members: Vec<Member> = Vec::new();
+        let mut at: HashMap<PathBuf, usize> = HashMap::new();
+        for_each_member(path, |name, size, _| {
+            let member = Member {
+                archive: path.to_path_buf(),
+                name: name.to_path_buf(),
+                size,
+            };
+            match at.get(name) {
+                Some(&i) => members[i] = member,
+                None => {
+                    at.insert(name.to_path_buf(), members.len());
+                    members.push(member);
+                }
+            }
+            Ok(())
+        })?;
+        let mut registered = self.members.lock().unwrap_or_else(|err| err.into_inner());
+        for member in members.iter() {
+            registered.insert(member.path(), member.clone());
+        }
+        self.counts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(path.to_path_buf(), members.len());
+        Ok(members)
+    }
+
+    /// # Returns
+    ///
+    /// File inside an archive that is reported as `path`, if it is one.
+    pub fn member(&self, path: &Path) -> Option<Member> {
+        let members = self.members.lock().unwrap_or_else(|err| err.into_inner());
+        members.get(path).cloned()
+    }
+
+    /// # Returns
+    ///
+    /// Whether `path` is that of a file inside an archive.
+    pub fn is_member(&self, path: &Path) -> bool {
+        let members = self.members.lock().unwrap_or_else(|err| err.into_inner());
+        members.contains_key(path)
+    }
+
+    /// # Returns
+    ///
+    /// Number of files in the archive at `archive`, which is 0 if it wasn't added.
+    pub fn count(&self, archive: &Path) -> usize {
+        let counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        counts.get(archive).copied().unwrap_or(0)
+    }
+
+    /// # Returns
+    ///
+    /// sha512 checksum of the contents of the file inside an archive that is reported as `path`,
+    /// the same as that of a copy of it on disk. Every file of its archive is hashed the first time
+    /// one of them is asked for, since most archives can only be read from start to end.
+    pub fn checksum(&self, path: &Path) -> Result<Vec<u8>, FdupError> {
+        if let Some(hash) = self.cached(path) {
+            return Ok(hash);
+        }
+        let member = match self.member(path) {
+            Some(member) => member,
+            None => {
+                let message = "not inside an archive";
+                return Err(FdupError::io(
+                    path,
+                    io::Error::new(io::ErrorKind::NotFound, message),
+                ));
+            }
+        };
+        let mut hashes = HashMap::new();
+        let hashed = for_each_member(&member.archive, |name, _, contents| {
+            let mut hasher = Sha512::default();
+            let mut buffer = vec![0; 131072];
+            loop {
+                match contents.read(&mut buffer)? {
+                    0 => break,
+                    n => hasher.input(&buffer[..n]),
+                }
+            }
+            hashes.insert(
+                member.archive.join(name),
+                hasher.result().as_slice().to_vec(),
+            );
+            Ok(())
+        });
+        if let Err(err) = hashed {
+            return Err(FdupError::io(path, err));
+        }
+        self.checksums
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .extend(hashes);
+        match self.cached(path) {
+            Some(hash) => Ok(hash),
+            None => {
+                let message = "no longer in its archive";
+                Err(FdupError::io(
+                    path,
+                    io::Error::new(io::ErrorKind::NotFound, message),
+                ))
+            }
+        }
+    }
+
+    fn cached(&self, path: &Path) -> Option<Vec<u8>> {
+        let checksums = self.checksums.lock().unwrap_or_else(|err| err.into_inner());
+        checksums.get(path).cloned()
+    }
+}
+
+/// Call `f` with the name, size and contents of each regular file in the archive at `path`, in
+/// the order they are stored in.
+fn for_each_member<F>(path: &Path, mut f: F) -> io::Result<()>
+where
+    F: FnMut(&Path, u64, &mut dyn Read) -> io::Result<()>,
+{
+    let file = BufReader::new(File::open(path)?);
+    match ArchiveKind::of(path) {
+        Some(ArchiveKind::Zip) => {
+            let mut zip = zip::ZipArchive::new(file).map_err(unreadable)?;
+            for i in 0..zip.len() {
+                let mut entry = zip.by_index(i).map_err(unreadable)?;
+                if let (true, Some(name)) = (entry.is_file(), relative(Path::new(entry.name()))) {
+                    f(&name, entry.size(), &mut entry)?;
+                }
+            }
+            Ok(())
+        }
+        Some(ArchiveKind::Tar) => for_each_tar_member(file, f),
+        Some(ArchiveKind::TarGz) => for_each_tar_member(GzDecoder::new(file), f),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a zip or tar archive",
+        )),
+    }
+}
+
+fn for_each_tar_member<R, F>(reader: R, mut f: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(&Path, u64, &mut dyn Read) -> io::Result<()>,
+{
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue; // directories, links and the like
+        }
+        if let Some(name) = relative(&entry.path()?) {
+            let size = entry.size();
+            f(&name, size, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+fn unreadable(err: zip::result::ZipError) -> io::Error {
+    match err {
+        zip::result::ZipError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+/// # Returns
+///
+/// `name` without a leading `/` or `.` components, so that it stays inside the archive when
+/// joined to its path, or `None` if it climbs out of it with `..` or is empty.
+fn relative(name: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => (),
+            Component::ParentDir => return None,
+        }
+    }
+    match relative.as_os_str().is_empty() {
+        true => None,
+        false => Some(relative),
+    }
+}
+
+/// Counts, for each pair of archives, of the files of one that have copies in the other, to find
+/// archives that hold the same files however they were packed.
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveContents {
+    /// Files in the first archive with a copy in the second
+    shared: HashMap<(PathBuf, PathBuf), usize>,
+}
+
+impl ArchiveContents {
+    /// Count the files of `group` that are inside archives of `index` towards every pair of those
+    /// archives.
+    pub fn add(&mut self, group: &DuplicateGroup, index: &ArchiveIndex) {
+        let mut files_in: HashMap<PathBuf, usize> = HashMap::new();
+        for member in group.paths.iter().filter_map(|path| index.member(path)) {
+            *files_in.entry(member.archive).or_default() += 1;
+        }
+        for (archive, files) in files_in.iter() {
+            for other in files_in.keys().filter(|other| *other != archive) {
+                let pair = (archive.clone(), other.clone());
+                *self.shared.entry(pair).or_default() += files;
+            }
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Every pair of archives of `index` where each file of either has a copy in the other,
+    /// sorted by path.
+    pub fn identical(&self, index: &ArchiveIndex) -> Vec<(PathBuf, PathBuf)> {
+        let mut pairs: Vec<(PathBuf, PathBuf)> = self
+            .shared
+            .iter()
+            .filter(|((archive, other), shared)| {
+                let back = self.shared.get(&(other.clone(), archive.clone()));
+                archive < other
+                    && **shared >= index.count(archive)
+                    && back.is_some_and(|back| *back >= index.count(other))
+            })
+            .map(|(pair, _)| pair.clone())
+            .collect();
+        pairs.sort();
+        pairs
+    }
+}
+
+/// Print `pairs` of archives that hold the same files to stderr, so that they don't mix with the
+/// groups on stdout.
+pub fn print_identical_archives(pairs: &[(PathBuf, PathBuf)]) {
+    eprintln!("archives with the same files: {}", pairs.len());
+    for (archive, other) in pairs {
+        eprintln!("  {:?} and {:?}", plain_path(archive), plain_path(other));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::io::Write;
+
+    #[test]
+    fn files_inside_archives_are_listed_and_hashed() {
+        let dir = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let files: &[(&str, &[u8])] = &[("docs/a.txt", b"alpha"), ("/b.txt", b"beta")];
+
+        let mut zip = zip::ZipWriter::new(File::create(dir.join("one.zip")).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.start_file("../escape", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+
+        let gz = GzEncoder::new(
+            File::create(dir.join("two.TGZ")).unwrap(),
+            Compression::fast(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        for (name, contents) in files.iter().rev() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_cksum();
+            tar.append_data(&mut header, name.trim_start_matches('/'), *contents)
+                .unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let index = ArchiveIndex::default();
+        let names = |members: Vec<Member>| -> Vec<PathBuf> {
+            members.into_iter().map(|member| member.name).collect()
+        };
+        let (a, b) = (PathBuf::from("docs/a.txt"), PathBuf::from("b.txt"));
+        let zip_path = dir.join("one.zip");
+        let tar_path = dir.join("two.TGZ");
+        assert_eq!(
+            vec![a.clone(), b.clone()],
+            names(index.add(&zip_path).unwrap())
+        );
+        assert_eq!(
+            vec![b.clone(), a.clone()],
+            names(index.add(&tar_path).unwrap())
+        );
+        assert_eq!(2, index.count(&zip_path));
+        assert!(index.is_member(&zip_path.join("docs/a.txt")));
+        assert!(!index.is_member(&dir.join("escape")));
+
+        let alpha = Sha512::digest(b"alpha").as_slice().to_vec();
+        assert_eq!(alpha, index.checksum(&zip_path.join(&a)).unwrap());
+        assert_eq!(alpha, index.checksum(&tar_path.join(&a)).unwrap());
+        assert!(index.checksum(&dir.join("loose")).is_err());
+        assert!(index.add(&dir.join("missing.zip")).is_err());
+
+        let mut contents = ArchiveContents::default();
+        for name in [&a, &b] {
+            let group = DuplicateGroup {
+                hash: vec![0],
+                size: 4,
+                paths: vec![zip_path.join(name), tar_path.join(name), dir.join("loose")],
+            };
+            contents.add(&group, &index);
+        }
+        assert_eq!(vec![(zip_path, tar_path)], contents.identical(&index));
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
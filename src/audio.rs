@@ -0,0 +1,386 @@
+use crate::error::ErrorLog;
+use crate::error::FdupError;
+use crate::walk::plain_path;
+use rayon::prelude::*;
+use realfft::RealFftPlanner;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as AudioError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Extensions of the files that are fingerprinted, compared regardless of case.
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "oga"];
+
+/// Seconds from the start of a track that go into its fingerprint, as many as Chromaprint takes.
+pub const FINGERPRINT_SECS: f64 = 120.0;
+
+/// Largest share of the bits of two fingerprints that may differ for them to be the same track,
+/// as Haitsma and Kalker suggest for fingerprints like these. Unrelated tracks differ in about
+/// half.
+pub const MAX_BIT_ERRORS: f64 = 0.35;
+
+/// Most seconds by which the lengths of two tracks may differ for them to be the same track, to
+/// allow for the padding and delay encoders add.
+pub const MAX_DURATION_GAP: f64 = 2.0;
+
+/// Length of the stretch of sound whose spectrum makes up each frame of a fingerprint, in seconds.
+const FRAME_SECS: f64 = 0.37;
+
+/// Frames in each `FRAME_SECS`, which overlap so that tracks that start a little later still line
+/// up with a frame.
+const FRAMES_PER_WINDOW: usize = 8;
+
+/// Most frames one fingerprint is shifted by against the other when comparing them.
+const MAX_SHIFT: usize = FRAMES_PER_WINDOW;
+
+/// Lowest and highest frequency, in Hz, of the bands whose energies are compared, where most of
+/// what people hear in music is.
+const BANDS_HZ: (f64, f64) = (300.0, 2000.0);
+
+/// Summary of how a track sounds, which survives encoding it in another format or at another bit
+/// rate.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fingerprint {
+    /// Length of the whole track, in seconds
+    pub secs: f64,
+    /// One word per frame, each bit telling whether the energy difference between two neighboring
+    /// frequency bands grew since the frame before
+    pub frames: Vec<u32>,
+}
+
+/// Tracks that sound the same, such as one song ripped to FLAC and encoded to MP3.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimilarTracks {
+    /// Largest share of bits by which the fingerprints of two of the tracks that were found alike
+    /// differ
+    pub bit_errors: f64,
+    /// Paths of the tracks, sorted
+    pub paths: Vec<PathBuf>,
+}
+
+/// # Returns
+///
+/// Whether `path` has one of `AUDIO_EXTENSIONS`.
+pub fn is_audio(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        AUDIO_EXTENSIONS
+            .iter()
+            .any(|audio| ext.eq_ignore_ascii_case(audio))
+    })
+}
+
+/// # Returns
+///
+/// Fingerprint of the track at `path`, or `None` if it isn't an MP3, FLAC or Ogg Vorbis file
+/// going by its extension. The whole file is decoded to know how long it is.
+pub fn fingerprint(path: &Path) -> Result<Option<Fingerprint>, FdupError> {
+    if !is_audio(path) {
+        return Ok(None);
+    }
+    let (samples, rate, secs) = decode(path).map_err(|err| FdupError::io(path, err))?;
+    let frames = fingerprint_samples(&samples, rate);
+    Ok(Some(Fingerprint { secs, frames }))
+}
+
+/// # Parameters
+///
+/// 1. `samples` -- sound of the track, mixed down to one channel
+/// 1. `rate` -- samples per second
+///
+/// # Returns
+///
+/// Frames of the fingerprint of `samples`, as in `Fingerprint`, one every `FRAME_SECS` divided by
+/// `FRAMES_PER_WINDOW`. The bands are spaced evenly on a logarithmic scale, like pitch, and found
+/// in Hz rather than in samples, so that a track sampled at another rate gives the same frames.
+pub fn fingerprint_samples(samples: &[f32], rate: u32) -> Vec<u32> {
+    let window = (f64::from(rate) * FRAME_SECS).round() as usize;
+    if window == 0 || samples.len() < window {
+        return Vec::new();
+    }
+    let hop = window / FRAMES_PER_WINDOW;
+    let (low, high) = BANDS_HZ;
+    let bins: Vec<usize> = (0..=33)
+        .map(|edge| low * (high / low).powf(edge as f64 / 33.0))
+        .map(|hz| (hz * window as f64 / f64::from(rate)).round() as usize)
+        .collect();
+    // Hann window, so that the edges of each stretch don't spill into every band
+    let hann: Vec<f32> = (0..window)
+        .map(|i| {
+            (std::f64::consts::PI * i as f64 / window as f64)
+                .sin()
+                .powi(2) as f32
+        })
+        .collect();
+
+    let fft = RealFftPlanner::<f32>::new().plan_fft_forward(window);
+    let mut input = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+    let mut previous: Option<Vec<f32>> = None;
+    let mut frames = Vec::new();
+    for start in (0..=samples.len() - window).step_by(hop.max(1)) {
+        for (i, sample) in input.iter_mut().enumerate() {
+            *sample = samples[start + i] * hann[i];
+        }
+        if fft.process(&mut input, &mut spectrum).is_err() {
+            break; // only if the buffers are the wrong size, which they aren't
+        }
+        let energies: Vec<f32> = bins
+            .windows(2)
+            .map(|band| {
+                let bins = band[0]..band[1].max(band[0] + 1).min(spectrum.len());
+                spectrum[bins].iter().map(|bin| bin.norm_sqr()).sum()
+            })
+            .collect();
+        if let Some(previous) = previous.as_ref() {
+            let frame = (0..32).fold(0, |frame, m| {
+                let now = energies[m] - energies[m + 1];
+                let before = previous[m] - previous[m + 1];
+                (frame << 1) | u32::from(now > before)
+            });
+            frames.push(frame);
+        }
+        previous = Some(energies);
+    }
+    frames
+}
+
+/// # Returns
+///
+/// Smallest share of bits by which `a` and `b` differ where they overlap, when either is shifted
+/// by up to `MAX_SHIFT` frames against the other, or 1 if they don't overlap at all.
+pub fn bit_errors(a: &[u32], b: &[u32]) -> f64 {
+    fn shifted(a: &[u32], b: &[u32], shift: usize) -> Option<f64> {
+        let overlap = a.get(shift..)?.iter().zip(b.iter());
+        let (bits, errors) = overlap.fold((0, 0), |(bits, errors), (x, y)| {
+            (bits + 32, errors + (x ^ y).count_ones())
+        });
+        match bits {
+            0 => None,
+            _ => Some(f64::from(errors) / f64::from(bits)),
+        }
+    }
+    (0..=MAX_SHIFT)
+        .flat_map(|shift| vec![shifted(a, b, shift), shifted(b, a, shift)])
+        .flatten()
+        .fold(1.0, |least: f64, errors| least.min(errors))
+}
+
+/// # Parameters
+///
+/// 1. `paths` -- files to look among, of which those that aren't audio are left out
+/// 1. `errors` -- where tracks that can't be read or decoded are recorded
+///
+/// # Returns
+///
+/// Groups of tracks that sound the same, each track lasting about as long as, and having at most
+/// `MAX_BIT_ERRORS` against, at least one other of its group, sorted by their first path. Only
+/// tracks of about the same length are compared, which keeps large libraries quick.
+pub fn similar_audio(paths: &[PathBuf], errors: &ErrorLog) -> Vec<SimilarTracks> {
+    let mut tracks: Vec<(&PathBuf, Fingerprint)> = paths
+        .par_iter()
+        .filter_map(|path| match fingerprint(path) {
+            Ok(fingerprint) => fingerprint.map(|fingerprint| (path, fingerprint)),
+            Err(err) => {
+                errors.record("decode", &err);
+                None
+            }
+        })
+        .collect();
+    tracks.sort_by(|(_, a), (_, b)| a.secs.total_cmp(&b.secs));
+
+    // each track points towards the first of its group, like a disjoint-set forest
+    let mut parents: Vec<usize> = (0..tracks.len()).collect();
+    fn first(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+    let mut worst = vec![0.0; tracks.len()];
+    for i in 0..tracks.len() {
+        let longer = tracks[i + 1..]
+            .iter()
+            .take_while(|(_, other)| other.secs - tracks[i].1.secs <= MAX_DURATION_GAP);
+        for (j, (_, other)) in longer.enumerate().map(|(j, track)| (i + 1 + j, track)) {
+            let errors = bit_errors(&tracks[i].1.frames, &other.frames);
+            if errors <= MAX_BIT_ERRORS {
+                let (a, b) = (first(&mut parents, i), first(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+                let root = a.min(b);
+                worst[root] = [worst[root], worst[a.max(b)], errors]
+                    .iter()
+                    .fold(0.0, |worst: f64, errors| worst.max(*errors));
+            }
+        }
+    }
+
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); tracks.len()];
+    for i in 0..tracks.len() {
+        members[first(&mut parents, i)].push(i);
+    }
+    let mut groups: Vec<SimilarTracks> = members
+        .into_iter()
+        .enumerate()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(root, members)| {
+            let mut paths: Vec<PathBuf> = members.iter().map(|&i| tracks[i].0.clone()).collect();
+            paths.sort();
+            SimilarTracks {
+                bit_errors: worst[root],
+                paths,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.paths.cmp(&b.paths));
+    groups
+}
+
+/// Print `groups` to stderr, so that they don't mix with the duplicates on stdout.
+pub fn print_similar_audio(groups: &[SimilarTracks]) {
+    eprintln!("similar audio: {}", groups.len());
+    for group in groups {
+        eprintln!("  {:.0}% of bits apart:", 100.0 * group.bit_errors);
+        for path in group.paths.iter() {
+            eprintln!("    {:?}", plain_path(path));
+        }
+    }
+}
+
+fn undecodable(err: AudioError) -> io::Error {
+    match err {
+        AudioError::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+/// # Returns
+///
+/// The first `FINGERPRINT_SECS` of the default track of the file at `path` mixed down to one
+/// channel, its sample rate, and how many seconds the whole track lasts.
+fn decode(path: &Path) -> io::Result<(Vec<f32>, u32, f64)> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(undecodable)?;
+    let mut format = probed.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| undecodable(AudioError::Unsupported("file without a track")))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(undecodable)?;
+
+    let mut mono = Vec::new();
+    let mut rate = track.codec_params.sample_rate.unwrap_or(0);
+    let mut decoded_frames = 0u64;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(AudioError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(undecodable(err)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // a corrupt packet, skipped like players do
+            Err(AudioError::DecodeError(_)) => continue,
+            Err(err) => return Err(undecodable(err)),
+        };
+        let spec = *decoded.spec();
+        rate = spec.rate;
+        decoded_frames += decoded.frames() as u64;
+        if mono.len() as f64 >= FINGERPRINT_SECS * f64::from(rate) {
+            continue; // only counted from here on
+        }
+        let mut interleaved = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        interleaved.copy_interleaved_ref(decoded);
+        let channels = spec.channels.count().max(1);
+        mono.extend(
+            interleaved
+                .samples()
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+    }
+    match rate {
+        0 => Err(undecodable(AudioError::Unsupported("unknown sample rate"))),
+        _ => Ok((mono, rate, decoded_frames as f64 / f64::from(rate))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// # Returns
+    ///
+    /// `secs` of chords that change every half second, picked by `seed`, sampled `rate` times a
+    /// second, starting `delay` seconds late, with a faint hiss on top if `hiss`.
+    fn music(seed: u64, secs: f64, rate: u32, delay: f64, hiss: bool) -> Vec<f32> {
+        let mut state = seed;
+        let mut random = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as f64 / (1u64 << 31) as f64
+        };
+        let chords: Vec<[f64; 3]> = (0..(secs * 2.0) as usize + 1)
+            .map(|_| [0; 3].map(|_| 300.0 + 1700.0 * random()))
+            .collect();
+        let mut noise = 7u64;
+        (0..(secs * f64::from(rate)) as usize)
+            .map(|i| {
+                let t = (i as f64 / f64::from(rate) - delay).max(0.0);
+                let chord = chords[(t * 2.0) as usize];
+                let swell = (t * 2.0).fract(); // each chord fades in
+                let tone: f64 = chord
+                    .iter()
+                    .map(|hz| (2.0 * std::f64::consts::PI * hz * t).sin())
+                    .sum();
+                noise = noise.wrapping_mul(6364136223846793005).wrapping_add(1);
+                let hiss = match hiss {
+                    true => ((noise >> 40) as f64 / (1u64 << 24) as f64 - 0.5) * 0.05,
+                    false => 0.0,
+                };
+                (0.3 * swell * tone + hiss) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_same_music_matches_whatever_its_sample_rate() {
+        let original = fingerprint_samples(&music(1, 8.0, 44100, 0.0, false), 44100);
+        let resampled = fingerprint_samples(&music(1, 8.0, 48000, 0.03, true), 48000);
+        let other = fingerprint_samples(&music(2, 8.0, 44100, 0.0, false), 44100);
+
+        assert!(!original.is_empty());
+        assert!(bit_errors(&original, &resampled) < 0.3);
+        assert!(bit_errors(&original, &other) > 0.4);
+        assert_eq!(1.0, bit_errors(&original, &[]));
+        assert!(fingerprint_samples(&[0.0; 100], 44100).is_empty());
+
+        assert!(is_audio(Path::new("song.FLAC")));
+        assert!(!is_audio(Path::new("song.wav")));
+        assert_eq!(None, fingerprint(Path::new("notes.txt")).unwrap());
+    }
+}
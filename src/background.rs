@@ -0,0 +1,127 @@
+use crate::throttle::set_throttle;
+use crate::throttle::Throttle;
+use std::time::Duration;
+
+/// Read limits of `--background`, slow enough to go unnoticed on a laptop during the workday.
+pub const BACKGROUND_THROTTLE: Throttle = Throttle {
+    bytes_per_sec: 16 * 1024 * 1024,
+    work: Duration::from_secs(10),
+    rest: Duration::from_secs(1),
+};
+
+/// Make the rest of this process as unobtrusive as possible: hash on a single thread, at the
+/// lowest CPU and I/O priority the OS offers, within `BACKGROUND_THROTTLE`. Must be called before
+/// anything is hashed. Priorities that can't be lowered are reported and otherwise ignored.
+pub fn enter_background() {
+    enter_low_priority();
+    set_threads(1);
+    set_throttle(BACKGROUND_THROTTLE);
+}
+
+/// Run the rest of this process at the lowest CPU priority and, on Linux, the idle I/O priority,
+/// leaving the number of threads and the rate of reads alone. Must be called before the hashing
+/// threads are started, since on Linux threads keep the priorities they were started with.
+/// Priorities that can't be lowered are reported and otherwise ignored, as they always are on
+/// platforms other than Unix.
+pub fn enter_low_priority() {
+    reported(lower_priority());
+}
+
+/// Print the error of `lowered`, the outcome of lowering the priorities, if any.
+///
+/// # Returns
+///
+/// The message printed, if any.
+fn reported(lowered: Result<(), String>) -> Option<String> {
+    let message = lowered
+        .err()
+        .map(|err| format!("ERROR lowering priority: {}", err));
+    if let Some(message) = &message {
+        eprintln!("{}", message);
+    }
+    message
+}
+
+/// Hash on `threads` threads instead of one per CPU. Must be called before anything is hashed;
+/// failures are reported and otherwise ignored.
+pub fn set_threads(threads: usize) {
+    if let Err(err) = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+    {
+        eprintln!("ERROR limiting threads: {}", err);
+    }
+}
+
+/// Lower the CPU priority of this process as far as possible, and its I/O priority to idle, so
+/// that it only gets disk time no one else wants.
+#[cfg(target_os = "linux")]
+fn lower_priority() -> Result<(), String> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+    const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+
+    lower_cpu_priority()?;
+    // safe, since the arguments are plain integers; 0 means the calling process
+    let set = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    };
+    match set {
+        0 => Ok(()),
+        _ => Err(format!("ioprio_set: {}", std::io::Error::last_os_error())),
+    }
+}
+
+/// Lower the CPU priority of this process as far as possible.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn lower_priority() -> Result<(), String> {
+    lower_cpu_priority()
+}
+
+#[cfg(not(unix))]
+fn lower_priority() -> Result<(), String> {
+    Err(String::from("not supported on this OS"))
+}
+
+#[cfg(unix)]
+fn lower_cpu_priority() -> Result<(), String> {
+    // safe, since the arguments are plain integers; 0 means the calling process
+    match unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, 19) } {
+        0 => Ok(()),
+        _ => Err(format!("setpriority: {}", std::io::Error::last_os_error())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_to_lower_priority_are_reported() {
+        assert_eq!(None, reported(Ok(())));
+        assert_eq!(
+            Some(String::from("ERROR lowering priority: setpriority: denied")),
+            reported(Err(String::from("setpriority: denied")))
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn priority_is_lowered() {
+        // on Linux, this only affects the thread the test runs on
+        assert_eq!(Ok(()), lower_cpu_priority());
+        // safe, since the arguments are plain integers; 0 means the calling process
+        assert_eq!(19, unsafe { libc::getpriority(libc::PRIO_PROCESS as _, 0) });
+    }
+
+    #[test]
+    #[cfg(not(unix))]
+    fn priority_is_left_alone() {
+        assert!(lower_priority().is_err());
+    }
+}
@@ -0,0 +1,599 @@
+use crate::error::FdupError;
+use crate::fdup::checksum;
+use crate::fdup::decompressed_checksum;
+use crate::fdup::partial_checksum;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::NO_PARAMS;
+use std::fs::metadata;
+use std::fs::Metadata;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Bumped whenever the schema changes; older caches are discarded rather than migrated.
+const SCHEMA_VERSION: i64 = 3;
+
+/// Files modified less than this many nanoseconds before being hashed may be modified again
+/// without their mtime changing, so their mtime is not trusted.
+const RACY_WINDOW_NANOS: i64 = 2_000_000_000;
+
+/// Checksums persisted in an SQLite database, so that files which have not changed since the
+/// last run need not be read again.
+pub struct HashCache {
+    conn: Mutex<Connection>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl HashCache {
+    /// Open the cache stored at `path`, creating the database and its schema if necessary.
+    pub fn open(path: &Path) -> rusqlite::Result<HashCache> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;",
+        )?;
+
+        let version: i64 = conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            // it's only a cache, so throwing it away is always safe
+            conn.execute_batch("DROP TABLE IF EXISTS checksums;")?;
+            conn.execute_batch(&format!("PRAGMA user_version = {};", SCHEMA_VERSION))?;
+        }
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS checksums (
+                 dev   INTEGER NOT NULL,
+                 inode INTEGER NOT NULL,
+                 size  INTEGER NOT NULL,
+                 mtime INTEGER,
+                 path  TEXT NOT NULL,
+                 hash  BLOB NOT NULL,
+                 partial BLOB,
+                 decompressed INTEGER NOT NULL DEFAULT 0,
+                 PRIMARY KEY (dev, inode, decompressed)
+             );
+             CREATE TABLE IF NOT EXISTS runs (
+                 id              INTEGER PRIMARY KEY,
+                 root            TEXT NOT NULL,
+                 timestamp       INTEGER NOT NULL,
+                 groups          INTEGER NOT NULL,
+                 redundant_files INTEGER NOT NULL,
+                 redundant_bytes INTEGER NOT NULL,
+                 cache_hits      INTEGER NOT NULL,
+                 cache_misses    INTEGER NOT NULL
+             );",
+        )?;
+        Ok(HashCache {
+            conn: Mutex::new(conn),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Drop-in replacement for `checksum` that consults the cache first.
+    ///
+    /// # Returns
+    ///
+    /// The cached checksum of `path` if the file it points to has the same device, inode, size and
+    /// mtime as when it was last hashed, otherwise a freshly calculated checksum, which is then
+    /// stored in the cache. Keying on the inode rather than the path means renamed files still hit
+    /// the cache.
+    ///
+    /// If the mtime cannot be trusted to change along with the contents, because it is missing,
+    /// coarse (e.g. FAT's 2 second resolution) or too recent, the cached entry must additionally
+    /// match a `partial_checksum` of the file.
+    pub fn checksum(&self, path: &Path) -> Result<Vec<u8>, FdupError> {
+        self.cached(path, false)
+    }
+
+    /// Drop-in replacement for `fdup::decompressed_checksum` that consults the cache first, the
+    /// same way as `checksum` does, but keeping its checksums apart from those of files as they
+    /// are.
+    pub fn decompressed_checksum(&self, path: &Path) -> Result<Vec<u8>, FdupError> {
+        self.cached(path, true)
+    }
+
+    /// # Returns
+    ///
+    /// Checksum of `path` as `checksum` returns it, of what it decompresses to if `decompressed`.
+    fn cached(&self, path: &Path, decompressed: bool) -> Result<Vec<u8>, FdupError> {
+        let cache_err = |source| FdupError::Cache {
+            path: path.to_path_buf(),
+            source,
+        };
+        let meta = metadata(path).map_err(|err| FdupError::io(path, err))?;
+        let (dev, inode) = identity(&meta, path);
+        let size = meta.len() as i64;
+        let mtime = mtime_nanos(&meta); // some filesystems don't have one
+        let partial = match is_trustworthy(mtime) {
+            true => None,
+            false => Some(partial_checksum(path)?),
+        };
+
+        let cached: Option<(Vec<u8>, Option<Vec<u8>>)> = self
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT hash, partial FROM checksums
+                 WHERE dev = ?1 AND inode = ?2 AND size = ?3 AND mtime IS ?4
+                       AND decompressed = ?5",
+                params![dev, inode, size, mtime, decompressed],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(cache_err)?;
+        let hit = match (cached, &partial) {
+            (Some((hash, _)), None) => Some(hash), // unchanged since last time
+            (Some((hash, Some(cached_partial))), Some(partial)) if cached_partial == *partial => {
+                Some(hash) // unchanged as far as we can tell without hashing everything
+            }
+            _ => None,
+        };
+        if let Some(hash) = hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(hash);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // hash outside of the lock so that other threads can keep using the cache
+        let hash = match decompressed {
+            true => decompressed_checksum(path)?,
+            false => checksum(path)?,
+        };
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR REPLACE INTO checksums
+                     (dev, inode, size, mtime, path, hash, partial, decompressed)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    dev,
+                    inode,
+                    size,
+                    mtime,
+                    path.to_string_lossy(),
+                    hash,
+                    partial,
+                    decompressed
+                ],
+            )
+            .map_err(cache_err)?;
+        Ok(hash)
+    }
+
+    /// Append the summary of a completed scan to the run history.
+    pub fn record_run(&self, run: &RunSummary) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO runs (root, timestamp, groups, redundant_files, redundant_bytes,
+                               cache_hits, cache_misses)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                run.root,
+                run.timestamp,
+                run.groups,
+                run.redundant_files,
+                run.redundant_bytes,
+                run.cache_hits,
+                run.cache_misses
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// # Returns
+    ///
+    /// Every recorded run, ordered by root and then chronologically.
+    pub fn runs(&self) -> rusqlite::Result<Vec<RunSummary>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT root, timestamp, groups, redundant_files, redundant_bytes,
+                    cache_hits, cache_misses
+             FROM runs ORDER BY root, timestamp, id",
+        )?;
+        let runs = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok(RunSummary {
+                    root: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    groups: row.get(2)?,
+                    redundant_files: row.get(3)?,
+                    redundant_bytes: row.get(4)?,
+                    cache_hits: row.get(5)?,
+                    cache_misses: row.get(6)?,
+                })
+            })?
+            .collect();
+        runs
+    }
+
+    /// # Returns
+    ///
+    /// `(hits, misses)` of `checksum` since the cache was opened.
+    pub fn hits_and_misses(&self) -> (i64, i64) {
+        (
+            self.hits.load(Ordering::Relaxed) as i64,
+            self.misses.load(Ordering::Relaxed) as i64,
+        )
+    }
+
+    /// # Returns
+    ///
+    /// Size of the cache and the outcome of the most recent run that used it.
+    pub fn stats(&self) -> rusqlite::Result<CacheStats> {
+        let conn = self.conn.lock().unwrap();
+        let entries = conn.query_row("SELECT COUNT(*) FROM checksums", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+        let page_count: i64 = conn.query_row("PRAGMA page_count", NO_PARAMS, |row| row.get(0))?;
+        let page_size: i64 = conn.query_row("PRAGMA page_size", NO_PARAMS, |row| row.get(0))?;
+        let last_run = conn
+            .query_row(
+                "SELECT root, timestamp, groups, redundant_files, redundant_bytes,
+                        cache_hits, cache_misses
+                 FROM runs ORDER BY timestamp DESC, id DESC LIMIT 1",
+                NO_PARAMS,
+                |row| {
+                    Ok(RunSummary {
+                        root: row.get(0)?,
+                        timestamp: row.get(1)?,
+                        groups: row.get(2)?,
+                        redundant_files: row.get(3)?,
+                        redundant_bytes: row.get(4)?,
+                        cache_hits: row.get(5)?,
+                        cache_misses: row.get(6)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(CacheStats {
+            entries,
+            bytes: page_count * page_size,
+            last_run,
+        })
+    }
+
+    /// Remove entries whose file no longer exists under the path it was last seen at, or has
+    /// changed since it was hashed.
+    ///
+    /// # Returns
+    ///
+    /// Number of entries removed.
+    pub fn prune(&self) -> rusqlite::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let entries: Vec<(i64, i64, i64, Option<i64>, String)> = {
+            let mut stmt = conn.prepare("SELECT dev, inode, size, mtime, path FROM checksums")?;
+            let rows = stmt.query_map(NO_PARAMS, |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let tx = conn.transaction()?;
+        let mut pruned = 0;
+        for (dev, inode, size, mtime, path) in entries {
+            let path = Path::new(&path);
+            let is_fresh = match metadata(path) {
+                Ok(meta) => {
+                    identity(&meta, path) == (dev, inode)
+                        && meta.len() as i64 == size
+                        && mtime_nanos(&meta) == mtime
+                }
+                Err(_) => false,
+            };
+            if !is_fresh {
+                pruned += tx.execute(
+                    "DELETE FROM checksums WHERE dev = ?1 AND inode = ?2",
+                    params![dev, inode],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(pruned)
+    }
+
+    /// Rebuild the database file, returning unused pages to the filesystem.
+    pub fn vacuum(&self) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute_batch("VACUUM;")
+    }
+
+    /// Remove every cached checksum and the run history.
+    ///
+    /// # Returns
+    ///
+    /// Number of checksums removed.
+    pub fn clear(&self) -> rusqlite::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let cleared = tx.execute("DELETE FROM checksums", NO_PARAMS)?;
+        tx.execute("DELETE FROM runs", NO_PARAMS)?;
+        tx.commit()?;
+        Ok(cleared)
+    }
+}
+
+/// Overview of a cache, as shown by `fdup cache stats`.
+#[derive(Clone, Debug)]
+pub struct CacheStats {
+    /// Number of cached checksums
+    pub entries: i64,
+    /// Size of the database in bytes
+    pub bytes: i64,
+    /// Most recent run recorded in the cache, if any
+    pub last_run: Option<RunSummary>,
+}
+
+/// Outcome of a single scan, as recorded in the run history.
+#[derive(Clone, Debug, Default)]
+pub struct RunSummary {
+    /// Root directory that was scanned
+    pub root: String,
+    /// Seconds since the unix epoch at which the scan started
+    pub timestamp: i64,
+    /// Number of duplicate groups found
+    pub groups: i64,
+    /// Number of files that could be removed while keeping one copy of each group
+    pub redundant_files: i64,
+    /// Bytes that could be reclaimed while keeping one copy of each group
+    pub redundant_bytes: i64,
+    /// Number of checksums served from the cache
+    pub cache_hits: i64,
+    /// Number of checksums that had to be calculated
+    pub cache_misses: i64,
+}
+
+/// # Returns
+///
+/// `(device, inode)` pair identifying the file independently of its path.
+#[cfg(unix)]
+pub fn identity(meta: &Metadata, _path: &Path) -> (i64, i64) {
+    (meta.dev() as i64, meta.ino() as i64)
+}
+
+/// # Returns
+///
+/// Stand-in for `(device, inode)` on platforms where std does not expose them, derived from the
+/// path; renames will miss the cache, but changed files are still caught by size and mtime. The
+/// path is hashed with xxh3, whose output is fixed, unlike that of std's hashers, so that the
+/// cache stays valid across toolchains.
+#[cfg(not(unix))]
+pub fn identity(_meta: &Metadata, path: &Path) -> (i64, i64) {
+    let hash = xxhash_rust::xxh3::xxh3_64(path.to_string_lossy().as_bytes());
+    (0, hash as i64)
+}
+
+/// # Returns
+///
+/// Whether a file with modification time `mtime` (see `mtime_nanos`) is guaranteed to get a
+/// different mtime if it is modified.
+fn is_trustworthy(mtime: Option<i64>) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_nanos() as i64)
+        .unwrap_or(0);
+    match mtime {
+        None | Some(0) => false,                                 // missing
+        Some(mtime) if mtime % 1_000_000_000 == 0 => false,      // second granularity or worse
+        Some(mtime) if now - mtime < RACY_WINDOW_NANOS => false, // could change within a tick
+        Some(_) => true,
+    }
+}
+
+/// # Returns
+///
+/// Modification time of the file as nanoseconds since the unix epoch, if it has one after it.
+pub fn mtime_nanos(meta: &Metadata) -> Option<i64> {
+    let since_epoch = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_secs() as i64 * 1_000_000_000 + i64::from(since_epoch.subsec_nanos()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdup::PARTIAL_LEN;
+    use std::env;
+    use std::fs::remove_file;
+    use std::fs::write;
+
+    #[test]
+    fn cache_agrees_with_checksum() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let db_path = env::temp_dir().join(format!("{}.sqlite", prefix));
+        let file_path = env::temp_dir().join(&prefix);
+        if db_path.exists() {
+            remove_file(&db_path).unwrap();
+        }
+        write(&file_path, "some content").unwrap();
+
+        let cache = HashCache::open(&db_path).unwrap();
+        let expected = checksum(&file_path).unwrap();
+        assert_eq!(expected, cache.checksum(&file_path).unwrap()); // miss
+        assert_eq!(expected, cache.checksum(&file_path).unwrap()); // hit
+
+        // a modified file must not be served from the cache
+        write(&file_path, "different content").unwrap();
+        assert_eq!(
+            checksum(&file_path).unwrap(),
+            cache.checksum(&file_path).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn renamed_files_hit_the_cache() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let db_path = env::temp_dir().join(format!("{}.sqlite", prefix));
+        let file_path = env::temp_dir().join(&prefix);
+        let renamed = env::temp_dir().join(format!("{}_renamed", prefix));
+        if db_path.exists() {
+            remove_file(&db_path).unwrap();
+        }
+        write(&file_path, "some content").unwrap();
+
+        let cache = HashCache::open(&db_path).unwrap();
+        let expected = cache.checksum(&file_path).unwrap();
+        std::fs::rename(&file_path, &renamed).unwrap();
+        assert_eq!(expected, cache.checksum(&renamed).unwrap());
+        assert_eq!((1, 1), cache.hits_and_misses());
+
+        remove_file(&renamed).unwrap();
+    }
+
+    /// Set the mtime of the file at `path` to `mtime`, as if it had never been rewritten.
+    fn set_mtime(path: &Path, mtime: SystemTime) {
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(mtime).unwrap();
+    }
+
+    #[test]
+    fn racy_entries_are_rechecked() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let db_path = env::temp_dir().join(format!("{}.sqlite", prefix));
+        let file_path = env::temp_dir().join(&prefix);
+        if db_path.exists() {
+            remove_file(&db_path).unwrap();
+        }
+        write(&file_path, "some content").unwrap();
+        let meta = metadata(&file_path).unwrap();
+        assert!(!is_trustworthy(mtime_nanos(&meta)));
+
+        let cache = HashCache::open(&db_path).unwrap();
+        cache.checksum(&file_path).unwrap();
+        // rewritten within the same tick, so neither size nor mtime tell
+        write(&file_path, "SOME CONTENT").unwrap();
+        set_mtime(&file_path, meta.modified().unwrap());
+        assert_eq!(
+            checksum(&file_path).unwrap(),
+            cache.checksum(&file_path).unwrap()
+        );
+        assert_eq!((0, 2), cache.hits_and_misses());
+
+        remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn changed_ends_invalidate_coarse_entries() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let db_path = env::temp_dir().join(format!("{}.sqlite", prefix));
+        let file_path = env::temp_dir().join(&prefix);
+        if db_path.exists() {
+            remove_file(&db_path).unwrap();
+        }
+        // long enough for the middle to be left out of the partial checksum
+        let mut contents = vec![b'x'; 3 * PARTIAL_LEN];
+        // long ago, but only to the second, as on FAT
+        let mtime = UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+        let rewrite = |contents: &[u8]| {
+            write(&file_path, contents).unwrap();
+            set_mtime(&file_path, mtime);
+        };
+        rewrite(&contents);
+        assert!(!is_trustworthy(mtime_nanos(&metadata(&file_path).unwrap())));
+
+        let cache = HashCache::open(&db_path).unwrap();
+        cache.checksum(&file_path).unwrap(); // miss
+        cache.checksum(&file_path).unwrap(); // hit
+        for at in [0, contents.len() - 1] {
+            contents[at] = b'y';
+            rewrite(&contents);
+            assert_eq!(
+                checksum(&file_path).unwrap(),
+                cache.checksum(&file_path).unwrap()
+            );
+        }
+        assert_eq!((1, 3), cache.hits_and_misses());
+
+        remove_file(&file_path).unwrap();
+    }
+
+    #[test]
+    fn prune_and_clear_drop_what_they_should() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let db_path = env::temp_dir().join(format!("{}.sqlite", prefix));
+        let kept = env::temp_dir().join(format!("{}_kept", prefix));
+        let gone = env::temp_dir().join(format!("{}_gone", prefix));
+        if db_path.exists() {
+            remove_file(&db_path).unwrap();
+        }
+        write(&kept, "still here").unwrap();
+        write(&gone, "soon gone").unwrap();
+
+        let cache = HashCache::open(&db_path).unwrap();
+        cache.checksum(&kept).unwrap();
+        cache.checksum(&gone).unwrap();
+        cache.record_run(&RunSummary::default()).unwrap();
+        remove_file(&gone).unwrap();
+        assert_eq!(1, cache.prune().unwrap());
+        assert_eq!(1, cache.stats().unwrap().entries);
+        cache.checksum(&kept).unwrap();
+        assert_eq!((1, 2), cache.hits_and_misses());
+
+        cache.vacuum().unwrap();
+        assert_eq!(1, cache.clear().unwrap());
+        let stats = cache.stats().unwrap();
+        assert_eq!(0, stats.entries);
+        assert!(stats.last_run.is_none());
+        assert!(cache.runs().unwrap().is_empty());
+
+        remove_file(&kept).unwrap();
+    }
+
+    #[test]
+    fn runs_are_recorded_and_read_back() {
+        let prefix = format!("{}_{}_{}", module_path!(), line!(), column!());
+        let db_path = env::temp_dir().join(format!("{}.sqlite", prefix));
+        if db_path.exists() {
+            remove_file(&db_path).unwrap();
+        }
+        let run = |root: &str, timestamp: i64| RunSummary {
+            root: String::from(root),
+            timestamp,
+            groups: 3,
+            redundant_files: 4,
+            redundant_bytes: 5000,
+            cache_hits: 6,
+            cache_misses: 7,
+        };
+
+        let cache = HashCache::open(&db_path).unwrap();
+        for (root, timestamp) in [("/b", 20), ("/a", 30), ("/b", 10)] {
+            cache.record_run(&run(root, timestamp)).unwrap();
+        }
+        drop(cache);
+
+        // ordered by root and then by time, whatever order they were recorded in
+        let runs = HashCache::open(&db_path).unwrap().runs().unwrap();
+        let keys: Vec<(&str, i64)> = runs
+            .iter()
+            .map(|run| (run.root.as_str(), run.timestamp))
+            .collect();
+        assert_eq!(vec![("/a", 30), ("/b", 10), ("/b", 20)], keys);
+        let first = &runs[0];
+        assert_eq!(
+            (3, 4, 5000, 6, 7),
+            (
+                first.groups,
+                first.redundant_files,
+                first.redundant_bytes,
+                first.cache_hits,
+                first.cache_misses
+            )
+        );
+        let stats = HashCache::open(&db_path).unwrap().stats().unwrap();
+        assert_eq!(Some(30), stats.last_run.map(|run| run.timestamp));
+    }
+}
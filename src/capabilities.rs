@@ -0,0 +1,99 @@
+use crate::action::KEEP_VALUES;
+use crate::fdup::GROUP_BY_VALUES;
+use crate::fdup::HASH_VALUES;
+use crate::fdup::PREHASH_VALUES;
+use crate::fdup::STAGE_VALUES;
+use crate::filter::FILE_TYPE_VALUES;
+use crate::output::FORMAT_VALUES;
+use crate::text::TEXT_NORMALIZE_VALUES;
+use serde::Serialize;
+
+/// Features of this particular build of fdup, printed as JSON by `--capabilities` so that
+/// wrappers can adapt to whichever binary is installed.
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    /// Version of fdup
+    pub version: &'static str,
+    /// Operating system fdup was built for
+    pub os: &'static str,
+    /// Checksum algorithms that can be used to compare contents
+    pub hash_algorithms: Vec<&'static str>,
+    /// Values accepted by `--prehash`
+    pub prehash_algorithms: &'static [&'static str],
+    /// Stages accepted in `--stages`
+    pub stages: &'static [&'static str],
+    /// Values accepted by `--text-normalize`
+    pub text_normalizations: &'static [&'static str],
+    /// Values accepted by `--group-by`
+    pub groupings: &'static [&'static str],
+    /// Values accepted by `--format`
+    pub formats: &'static [&'static str],
+    /// Values accepted by `--keep`
+    pub keep_strategies: &'static [&'static str],
+    /// File types accepted by `--min-size-for`
+    pub file_types: &'static [&'static str],
+    /// Actions that can be applied to duplicates on this OS
+    pub actions: Vec<&'static str>,
+    /// Whether `--check-extents` can inspect physical layouts on this OS
+    pub extent_comparison: bool,
+    /// Whether checksums can be cached with `--cache`
+    pub cache: bool,
+    /// Whether `fdup tui` is available
+    pub tui: bool,
+    /// Whether `--similar-images` can find pictures that look alike
+    pub similar_images: bool,
+    /// Whether `--similar-audio` can find tracks that sound the same
+    pub similar_audio: bool,
+    /// Whether the contents of archives can be scanned
+    pub archive_scanning: bool,
+    /// Whether directories can be watched for new duplicates
+    pub watch: bool,
+    /// Whether `fdup daemon` can serve queries over a Unix socket
+    pub daemon: bool,
+    /// Whether `--streams` can find alternate data streams, which only NTFS under Windows has
+    pub alternate_streams: bool,
+    /// Whether `--match-xattrs` can read extended attributes on this OS
+    pub extended_attributes: bool,
+    /// Whether the number of hashing threads adapts to roots on spinning disks
+    pub storage_detection: bool,
+}
+
+impl Capabilities {
+    /// # Returns
+    ///
+    /// Capabilities of the running binary.
+    pub fn current() -> Capabilities {
+        let mut actions = vec!["delete", "hardlink", "symlink"];
+        if cfg!(unix) {
+            actions.push("trash");
+        }
+        if cfg!(target_os = "linux") {
+            actions.push("reflink");
+        }
+
+        Capabilities {
+            version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            hash_algorithms: HASH_VALUES.to_vec(),
+            prehash_algorithms: PREHASH_VALUES,
+            stages: STAGE_VALUES,
+            text_normalizations: TEXT_NORMALIZE_VALUES,
+            groupings: GROUP_BY_VALUES,
+            formats: FORMAT_VALUES,
+            keep_strategies: KEEP_VALUES,
+            file_types: FILE_TYPE_VALUES,
+            actions,
+            extent_comparison: cfg!(target_os = "linux"),
+            cache: true,
+            tui: cfg!(feature = "tui"),
+            similar_images: cfg!(feature = "images"),
+            similar_audio: cfg!(feature = "audio"),
+            archive_scanning: true,
+            watch: cfg!(feature = "watch"),
+            daemon: cfg!(unix),
+            alternate_streams: cfg!(windows),
+            extended_attributes: cfg!(any(target_os = "linux", target_os = "macos")),
+            storage_detection: cfg!(target_os = "linux"),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::cache::mtime_nanos;
+use crate::report::hex;
+use crate::report::unhex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::metadata;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Longest a checksum stays in memory before it is written to the checkpoint file, i.e. the most
+/// hashing an interrupted scan can lose.
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// One line of a checkpoint file: a file as it was when it was hashed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CheckpointEntry {
+    path: String,
+    size: u64,
+    mtime: Option<i64>,
+    hash: String,
+}
+
+/// Checksums of a scan written to a file as they are calculated, so that a scan that gets
+/// interrupted can be resumed without hashing again the files it already got through.
+///
+/// Only hashing is saved; the walk is repeated, which is quick next to it. Files whose path isn't
+/// valid unicode are never saved, and are hashed again on resuming.
+pub struct Checkpoint {
+    /// Files hashed by the scans being resumed, by path
+    done: HashMap<PathBuf, CheckpointEntry>,
+    out: Mutex<Progress>,
+}
+
+struct Progress {
+    out: BufWriter<File>,
+    flushed: Instant,
+    failed: bool,
+}
+
+impl Progress {
+    fn write(&mut self, entry: &CheckpointEntry) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, entry)?;
+        self.out.write_all(b"\n")?;
+        if self.flushed.elapsed() >= CHECKPOINT_INTERVAL {
+            self.flushed = Instant::now();
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Checkpoint {
+    /// # Parameters
+    ///
+    /// 1. `path` -- checkpoint file
+    /// 1. `resume` -- read the checksums already in the file and keep adding to it, instead of
+    ///    starting it over
+    pub fn open(path: &Path, resume: bool) -> io::Result<Checkpoint> {
+        let mut done = HashMap::new();
+        if resume {
+            for line in BufReader::new(File::open(path)?).split(b'\n') {
+                // the last line may have been cut short by whatever interrupted the scan
+                if let Ok(entry) = serde_json::from_slice::<CheckpointEntry>(&line?) {
+                    done.insert(PathBuf::from(&entry.path), entry);
+                }
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(resume)
+            .write(true)
+            .truncate(!resume)
+            .open(path)?;
+        let mut out = BufWriter::new(file);
+        if resume {
+            out.write_all(b"\n")?; // leaves any line cut short on a line of its own
+        }
+        Ok(Checkpoint {
+            done,
+            out: Mutex::new(Progress {
+                out,
+                flushed: Instant::now(),
+                failed: false,
+            }),
+        })
+    }
+
+    /// # Returns
+    ///
+    /// Number of files the scans being resumed got through.
+    pub fn resumed(&self) -> usize {
+        self.done.len()
+    }
+
+    /// # Returns
+    ///
+    /// Checksum of the file at `path` saved by a scan being resumed, if the file still has the
+    /// same size and mtime as then.
+    pub fn lookup(&self, path: &Path) -> Option<Vec<u8>> {
+        let entry = self.done.get(path)?;
+        let meta = metadata(path).ok()?;
+        let mtime = mtime_nanos(&meta)?; // without an mtime, changes go unnoticed
+        match meta.len() == entry.size && Some(mtime) == entry.mtime {
+            true => unhex(&entry.hash),
+            false => None,
+        }
+    }
+
+    /// Save that the file at `path` has the checksum `hash`, writing out everything saved so far
+    /// if the last write was `CHECKPOINT_INTERVAL` ago.
+    pub fn record(&self, path: &Path, hash: &[u8]) {
+        let (text, meta) = match (path.to_str(), metadata(path)) {
+            (Some(text), Ok(meta)) => (text, meta),
+            _ => return,
+        };
+        let entry = CheckpointEntry {
+            path: String::from(text),
+            size: meta.len(),
+            mtime: mtime_nanos(&meta),
+            hash: hex(hash),
+        };
+        let mut progress = self.out.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = progress.write(&entry) {
+            if !progress.failed {
+                eprintln!("ERROR writing checkpoint: {}", err);
+                progress.failed = true; // once is enough
+            }
+        }
+    }
+
+    /// Write out everything saved so far.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut progress = self.out.lock().unwrap_or_else(|err| err.into_inner());
+        progress.flushed = Instant::now();
+        progress.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdup::checksum;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+
+    #[test]
+    fn interrupted_scans_resume() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&root).unwrap();
+        let (a, b, file) = (root.join("a"), root.join("b"), root.join("checkpoint"));
+        write(&a, "kept").unwrap();
+        write(&b, "changed").unwrap();
+
+        let checkpoint = Checkpoint::open(&file, false).unwrap();
+        checkpoint.record(&a, &checksum(&a).unwrap());
+        checkpoint.record(&b, &checksum(&b).unwrap());
+        checkpoint.flush().unwrap();
+        drop(checkpoint);
+        // killed halfway through a line
+        let mut text = std::fs::read_to_string(&file).unwrap();
+        text.push_str("{\"path\":\"c\",\"si");
+        write(&file, text).unwrap();
+        write(&b, "changed size").unwrap();
+
+        let checkpoint = Checkpoint::open(&file, true).unwrap();
+        assert_eq!(2, checkpoint.resumed());
+        assert_eq!(Some(checksum(&a).unwrap()), checkpoint.lookup(&a));
+        assert_eq!(None, checkpoint.lookup(&b));
+        assert_eq!(None, checkpoint.lookup(&root.join("c")));
+
+        assert_eq!(0, Checkpoint::open(&file, false).unwrap().resumed());
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,14 +1,916 @@
+use crate::action::Action;
+use crate::action::Keep;
+use crate::action::Policy;
+use crate::action::Reverify;
+use crate::action::SymlinkStyle;
+use crate::action::KEEP_VALUES;
+use crate::action::REVERIFY_VALUES;
+use crate::action::SYMLINK_STYLE_VALUES;
+use crate::extents::ReadOrder;
+use crate::extents::READ_ORDER_VALUES;
+use crate::fdup::GroupBy;
+use crate::fdup::Prehash;
+use crate::fdup::Shard;
+use crate::fdup::SortBy;
+use crate::fdup::Stages;
+use crate::fdup::GROUP_BY_VALUES;
+use crate::fdup::PREHASH_VALUES;
+use crate::fdup::SORT_BY_VALUES;
+use crate::filter::MinSize;
+use crate::normalize::Normalization;
+use crate::normalize::NORMALIZE_VALUES;
+use crate::output::Format;
+use crate::output::FORMAT_VALUES;
+use crate::script::ScriptKind;
+use crate::script::SCRIPT_VALUES;
+use crate::streams::Streams;
+use crate::streams::STREAMS_VALUES;
+use crate::text::TextNormalize;
+use crate::text::TEXT_NORMALIZE_VALUES;
+use crate::walk::Traversal;
+use crate::walk::TRAVERSAL_VALUES;
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::time::Duration;
+use structopt::clap;
+use structopt::clap::ArgMatches;
+use structopt::clap::ErrorKind;
 use structopt::StructOpt;
 
+/// Command line of fdup. `fdup [OPTIONS] ROOT`, without a subcommand, is short for
+/// `fdup scan [OPTIONS] ROOT`.
 #[derive(StructOpt, Debug)]
-#[structopt(name = "fdup")]
+#[structopt(
+    name = "fdup",
+    after_help = "Without a subcommand, `fdup [OPTIONS] ROOT` is short for \
+                  `fdup scan [OPTIONS] ROOT`."
+)]
 pub struct Opt {
+    /// Run unobtrusively: one thread, lowest CPU and idle I/O priority, reads limited to 16 MiB/s
+    /// with a 1 s rest every 10 s
+    #[structopt(long = "background", raw(global = "true"))]
+    pub background: bool,
+
+    /// Run at the lowest CPU priority and, on Linux, the idle I/O priority, like --background but
+    /// on every thread and without limiting reads; priorities that can't be lowered, as on
+    /// platforms other than Unix, are reported and otherwise ignored
+    #[structopt(long = "nice", raw(global = "true"))]
+    pub nice: bool,
+
+    /// Hash on this many threads instead of one per CPU, or a single one when any root is on a
+    /// spinning disk
+    #[structopt(
+        long = "threads",
+        value_name = "N",
+        conflicts_with = "background",
+        raw(global = "true")
+    )]
+    pub threads: Option<usize>,
+
+    /// Limit reads while hashing to this many megabytes per second, in total across all threads;
+    /// with --background, instead of its 16 MiB/s
+    #[structopt(
+        long = "throttle",
+        value_name = "MB/s",
+        raw(validator = "positive", global = "true")
+    )]
+    pub throttle: Option<f64>,
+
+    /// Give up on a file that takes longer than this many seconds to read, as on a hung network
+    /// mount or a failing disk, and count it as one that could not be read
+    #[structopt(
+        long = "file-timeout",
+        value_name = "SECS",
+        raw(validator = "seconds", global = "true")
+    )]
+    pub file_timeout: Option<f64>,
+
+    /// Plain, line-oriented output for screen readers: ASCII decorations only, no bars, colors or
+    /// video attributes
+    #[structopt(long = "plain", raw(global = "true"))]
+    pub plain: bool,
+
+    /// Render timestamps, such as run start/end and file mtimes, in the local time zone instead of
+    /// UTC; either way they are RFC 3339
+    #[structopt(long = "local-time", raw(global = "true"))]
+    pub local_time: bool,
+
+    /// Read defaults from this TOML file instead of `$XDG_CONFIG_HOME/fdup/config.toml`, or
+    /// `~/.config/fdup/config.toml`; options given here override it
+    #[structopt(
+        long = "config",
+        value_name = "FILE",
+        parse(from_os_str),
+        raw(global = "true")
+    )]
+    pub config: Option<PathBuf>,
+
+    /// Print the features of this build as JSON and exit
+    #[structopt(long = "capabilities")]
+    pub capabilities: bool,
+
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+/// Options that decide which files are considered at all, and how they are read.
+#[derive(StructOpt, Debug)]
+pub struct WalkOpt {
+    /// SQLite database in which to cache checksums between runs
+    #[structopt(long = "cache", parse(from_os_str))]
+    pub cache: Option<PathBuf>,
+
+    /// Order in which to walk the tree: depth-first, or breadth-first to reach files in sibling
+    /// directories sooner on wide trees
+    #[structopt(
+        long = "traversal",
+        default_value = "dfs",
+        raw(possible_values = "TRAVERSAL_VALUES")
+    )]
+    pub traversal: Traversal,
+
+    /// Only consider files with one of these extensions, given without the dot and separated by
+    /// commas, e.g. jpg,png,cr2, whatever their case; others are not even looked at. May be
+    /// repeated
+    #[structopt(
+        long = "ext",
+        value_name = "EXTENSIONS",
+        raw(use_delimiter = "true", require_delimiter = "true")
+    )]
+    pub ext: Vec<String>,
+
+    /// Only consider files owned by this user, given by name or id
+    #[structopt(long = "owner", value_name = "USER")]
+    pub owner: Option<String>,
+
+    /// Only consider files that belong to this group, given by name or id
+    #[structopt(long = "group", value_name = "GROUP")]
+    pub group: Option<String>,
+
+    /// Never consider files under this path; may be repeated
+    #[structopt(long = "exclude", parse(from_os_str), raw(number_of_values = "1"))]
+    pub exclude: Vec<PathBuf>,
+
+    /// Skip files of a type smaller than a size, e.g. image=100K; may be repeated. Types are
+    /// image, video, audio, document and archive, judged by extension
+    #[structopt(long = "min-size-for", raw(number_of_values = "1"))]
+    pub min_size_for: Vec<MinSize>,
+
+    /// Leave zero-byte files out, which is the default
+    #[structopt(long = "ignore-empty")]
+    pub ignore_empty: bool,
+
+    /// Report zero-byte files as a group of duplicates like any other
+    #[structopt(long = "group-empty", conflicts_with = "ignore_empty")]
+    pub group_empty: bool,
+
+    /// Print canonical paths: absolute, with every symlink and `.` or `..` resolved
+    #[structopt(long = "canonical")]
+    pub canonical: bool,
+
+    /// Walk into everything, even what `.fdupignore` files, which list in gitignore syntax what
+    /// beneath the directory they are in to leave out, say to skip
+    #[structopt(long = "no-fdupignore")]
+    pub no_fdupignore: bool,
+}
+
+/// Options of a scan for duplicates: what to look for, and how to report it.
+#[derive(StructOpt, Debug)]
+pub struct ScanOpt {
+    #[structopt(flatten)]
+    pub walk: WalkOpt,
+
     /// Sort each vector lexicographically
     #[structopt(short = "s", long = "sort-vec")]
     pub sort_vec: bool,
 
+    /// Order of the groups: by wasted bytes or number of files, largest first, or by path. Groups
+    /// are only printed once the whole scan is done
+    #[structopt(long = "sort-by", raw(possible_values = "SORT_BY_VALUES"))]
+    pub sort_by: Option<SortBy>,
+
+    /// Only output the N groups that waste the most bytes, in order of `--sort-by`, or of wasted
+    /// bytes if not given
+    #[structopt(long = "top", value_name = "N")]
+    pub top: Option<usize>,
+
+    /// Only report contents that exist at least this many times, e.g. 3 to hunt for runaway copy
+    /// jobs
+    #[structopt(
+        long = "min-copies",
+        value_name = "N",
+        default_value = "2",
+        raw(validator = "at_least_two")
+    )]
+    pub min_copies: usize,
+
+    /// Leave out groups whose files are all in the same directory, which are often intentional,
+    /// such as exported variants
+    #[structopt(long = "cross-dir-only")]
+    pub cross_dir_only: bool,
+
+    /// Only report groups whose files are all in the same directory, such as "photo (1).jpg"
+    /// copies left behind by downloads and sync clients
+    #[structopt(long = "same-dir-only", conflicts_with = "cross_dir_only")]
+    pub same_dir_only: bool,
+
+    /// What files have to have in common to be reported together: identical contents, only the
+    /// same name, or the same name and exact size, which are quick since no file is read, to find
+    /// copies scattered across directories or on network shares; no action can be taken on files
+    /// grouped by name
+    #[structopt(
+        long = "group-by",
+        default_value = "content",
+        raw(possible_values = "GROUP_BY_VALUES")
+    )]
+    pub group_by: GroupBy,
+
+    /// With --group-by name or name-size, group names that only differ in case, as Windows and
+    /// macOS compare them, e.g. Photo.JPG and photo.jpg
+    #[structopt(long = "ignore-case")]
+    pub ignore_case: bool,
+
+    /// Only group files whose modification times match too, to the second, to tell mirrored
+    /// backups, which keep them, from copies that were made or recreated independently
+    #[structopt(long = "match-mtime")]
+    pub match_mtime: bool,
+
+    /// Only group files whose user extended attributes, such as tags, match too; on Linux, those
+    /// in the user namespace, on macOS, all of them
+    #[structopt(long = "match-xattrs")]
+    pub match_xattrs: bool,
+
+    /// With --match-xattrs, only group files whose POSIX ACLs match too, on Linux
+    #[structopt(long = "match-acls", requires = "match_xattrs")]
+    pub match_acls: bool,
+
+    /// Only hash and report files whose size falls into shard i of N, e.g. 2/5
+    #[structopt(long = "shard")]
+    pub shard: Option<Shard>,
+
+    /// Only report files that have a copy under this directory, which is scanned along with the
+    /// root; files under it are always kept and never acted upon
+    #[structopt(long = "reference", parse(from_os_str))]
+    pub reference: Option<PathBuf>,
+
+    /// Don't count copies that already share physical extents (reflinks, snapshots) as
+    /// reclaimable
+    #[structopt(long = "check-extents")]
+    pub check_extents: bool,
+
+    /// How to print duplicate groups
+    #[structopt(
+        long = "format",
+        default_value = "debug",
+        raw(possible_values = "FORMAT_VALUES")
+    )]
+    pub format: Format,
+
+    /// Print the files whose contents appear exactly once instead, or, with --reference, the
+    /// files under the root that have no copy anywhere
+    #[structopt(long = "unique")]
+    pub unique: bool,
+
+    /// Which file of each group survives an action
+    #[structopt(
+        long = "keep",
+        default_value = "first",
+        raw(possible_values = "KEEP_VALUES")
+    )]
+    pub keep: Keep,
+
+    /// Keep files under this path over copies elsewhere; may be repeated, earlier paths winning
+    /// over later ones, with --keep deciding between files under the same path
+    #[structopt(long = "prefer", parse(from_os_str), raw(number_of_values = "1"))]
+    pub prefer: Vec<PathBuf>,
+
+    /// Never delete, link over or propose removing any file under this path, whatever --keep
+    /// says; may be repeated
+    #[structopt(long = "protect", parse(from_os_str), raw(number_of_values = "1"))]
+    pub protect: Vec<PathBuf>,
+
+    /// Once done, and after any action, list directories without a single regular file anywhere
+    /// beneath them to stderr, such as the husks a cleanup leaves behind
+    #[structopt(long = "report-empty-dirs")]
+    pub report_empty_dirs: bool,
+
+    /// Write the files that could not be read, with the operation that failed, errno and message,
+    /// to this file as JSON, instead of printing them to stderr as they happen
+    #[structopt(long = "errors-json", value_name = "FILE", parse(from_os_str))]
+    pub errors_json: Option<PathBuf>,
+
+    /// Along with how many directories and files were skipped because access to them was denied,
+    /// which is printed to stderr once done, list them
+    #[structopt(long = "list-denied")]
+    pub list_denied: bool,
+
+    /// Fail with exit status 4 if access to any directory or file was denied, so that a scan that
+    /// completes is known to have seen everything
+    #[structopt(long = "require-full-access")]
+    pub require_full_access: bool,
+
+    /// Write the checksum and path of every file hashed to this file, in the format of sha512sum,
+    /// so that other tools can reuse them; files whose size is unique are never hashed. Paths are
+    /// relative to the root with --relative
+    #[structopt(long = "emit-manifest", value_name = "FILE", parse(from_os_str))]
+    pub emit_manifest: Option<PathBuf>,
+
+    /// Print to stderr how the files of each size are compared: files of a few hundred megabytes
+    /// or more with at most three copies are sampled throughout before they are hashed in full,
+    /// other files over 64K are told apart by their first few kilobytes first
+    #[structopt(short = "v", long = "verbose")]
+    pub verbose: bool,
+
+    /// Split files of the same size by a fast non-cryptographic checksum before comparing their
+    /// sha512 checksums, which saves time when many files share a size but differ; each file is
+    /// then read twice, so it doesn't pay off with --cache
+    #[structopt(
+        long = "prehash",
+        default_value = "none",
+        raw(possible_values = "PREHASH_VALUES")
+    )]
+    pub prehash: Prehash,
+
+    /// Instead of comparing files of the same size as --verbose describes, put them through
+    /// these stages in turn, e.g. size,prefix-hash,hash,bytes, to trade precision for speed at
+    /// will: size, prefix-hash, sampled-hash, xxh3, crc32, hash (sha512), or bytes, which
+    /// compares the files themselves. Size has to come first; unless hash or bytes is among them,
+    /// files that make it through are only likely to be identical, and can only be listed
+    #[structopt(long = "stages", value_name = "STAGES")]
+    pub stages: Option<Stages>,
+
+    /// Report text files, those without a NUL byte among their first 8000 bytes, as copies even if
+    /// they differ in this: line endings, so that Windows and Unix checkouts of a file match, or
+    /// whitespace too, i.e. line endings, trailing whitespace and runs of blank lines, so that
+    /// trivially reformatted copies match. Text files are then compared with each other whatever
+    /// their size, by hashing each of them in full, without the cache, and can only be listed
+    #[structopt(
+        long = "text-normalize",
+        default_value = "none",
+        raw(possible_values = "TEXT_NORMALIZE_VALUES")
+    )]
+    pub text_normalize: TextNormalize,
+
+    /// Compare JPEG, PNG, MP3 and FLAC files without the metadata embedded in them, such as EXIF,
+    /// XMP, IPTC and ID3 tags, Vorbis comments and cover art, so that the same photo or song
+    /// tagged by different programs is still reported; such files are then compared with each
+    /// other by the size of the rest, hashed without the cache, and can only be listed
+    #[structopt(long = "strip-metadata")]
+    pub strip_metadata: bool,
+
+    /// Compare PDFs by the pages, fonts and images they hold, inflated, rather than byte for byte,
+    /// leaving out the producer, dates, XMP metadata and object layout, so that the same document
+    /// saved by different tools is still reported; PDFs are then compared with each other whatever
+    /// their size, by reading each of them whole, without the cache, and can only be listed
+    #[structopt(long = "pdf-content")]
+    pub pdf_content: bool,
+
+    /// Also compare the files inside the zip, tar and tar.gz archives found with each other and
+    /// with the files on disk, as if each archive were a directory, and print pairs of archives
+    /// that hold the same files however they were packed to stderr once done; such files are
+    /// hashed without the cache, and can only be listed
+    #[structopt(long = "scan-archives")]
+    pub scan_archives: bool,
+
+    /// Compare gzip, zstd and xz files by what they decompress to, so that a log is still found
+    /// next to a compressed copy of it; such files are told apart by their first bytes, sorted by
+    /// the size their format says they decompress to, and only decompressed to be hashed if
+    /// another file is as large, without the cache. Those that don't say are decompressed once more
+    /// to be sorted, and those that can't be, or decompress to more than 4 GiB, are compared as
+    /// they are and counted in the summary. Such copies can only be listed
+    #[structopt(long = "decompress")]
+    pub decompress: bool,
+
+    /// Order in which to read files of the same size: as walked, by inode number, or by where
+    /// they lie on disk, which saves seeks on rotational disks, especially with --threads 1
+    #[structopt(
+        long = "read-order",
+        default_value = "walk",
+        raw(possible_values = "READ_ORDER_VALUES")
+    )]
+    pub read_order: ReadOrder,
+
+    /// Keep the sizes of the files walked in a temporary database on disk rather than in memory,
+    /// for trees with more files than fit in memory; slower otherwise
+    #[structopt(long = "low-memory")]
+    pub low_memory: bool,
+
+    /// What to do with the alternate data streams of files on NTFS: leave them out, count them
+    /// and print the totals to stderr once done, or hash them along with the contents, so that
+    /// files only match if their streams do too; files have none on other systems
+    #[structopt(
+        long = "streams",
+        default_value = "ignore",
+        raw(possible_values = "STREAMS_VALUES")
+    )]
+    pub streams: Streams,
+
+    /// Skip files that another process has locked or, on Linux and Windows, open for writing,
+    /// instead of hashing contents that are about to change; files that change while being read
+    /// are skipped either way
+    #[structopt(long = "skip-busy")]
+    pub skip_busy: bool,
+
+    /// Save the checksums calculated to this file as the scan goes, every few seconds, so that
+    /// the scan can be resumed with --resume if it gets interrupted
+    #[structopt(
+        long = "checkpoint",
+        value_name = "FILE",
+        parse(from_os_str),
+        conflicts_with = "resume"
+    )]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Pick up a scan that was interrupted where the checkpoint it left in this file ends, hashing
+    /// only the files it didn't get to or that changed since, and keep adding to the checkpoint
+    #[structopt(long = "resume", value_name = "FILE", parse(from_os_str))]
+    pub resume: Option<PathBuf>,
+
+    /// Also write the groups found to this file as a JSON report, whatever --format is, for a
+    /// later `fdup diff` against another run
+    #[structopt(long = "save-snapshot", value_name = "FILE", parse(from_os_str))]
+    pub save_snapshot: Option<PathBuf>,
+
+    /// Also write the groups and totals to FILE as a Markdown document, with a collapsible
+    /// section per directory, for pasting into issues and wikis
+    #[structopt(long = "report-md", value_name = "FILE", parse(from_os_str))]
+    pub report_md: Option<PathBuf>,
+
+    /// Print neither the groups, what was done to them, nor errors with individual files, for cron
+    /// jobs that only need the exit status and maybe `--summary`
+    #[structopt(short = "q", long = "quiet")]
+    pub quiet: bool,
+
+    /// Print totals of files scanned, bytes hashed, groups, redundant copies and reclaimable
+    /// bytes to stderr once done
+    #[structopt(long = "summary")]
+    pub summary: bool,
+
+    /// Print the reclaimable bytes and redundant copies under each directory, subdirectories
+    /// included, to stderr once done, largest first
+    #[structopt(long = "by-dir")]
+    pub by_dir: bool,
+
+    /// Print pairs of directories where at least PERCENT of the files directly in one have a copy
+    /// directly in the other to stderr once done, to find folders that were mostly copied; only
+    /// the groups reported count
+    #[structopt(
+        long = "similar-dirs",
+        value_name = "PERCENT",
+        raw(validator = "percent")
+    )]
+    pub similar_dirs: Option<f64>,
+
+    /// Print groups of PNG and JPEG pictures that look alike to stderr once done, such as resized
+    /// or re-encoded copies of a photo, going by perceptual hashes that differ by at most BITS of
+    /// their 64; needs the `images` feature
+    #[structopt(
+        long = "similar-images",
+        value_name = "BITS",
+        raw(validator = "hash_bits")
+    )]
+    pub similar_images: Option<u32>,
+
+    /// Print groups of MP3, FLAC and Ogg Vorbis tracks that sound the same to stderr once done,
+    /// such as a song ripped to FLAC and encoded to MP3, going by fingerprints of their first two
+    /// minutes; every track is decoded, so this is slow; needs the `audio` feature
+    #[structopt(long = "similar-audio")]
+    pub similar_audio: bool,
+
+    /// After the scan, print N randomly chosen groups with the checksum, size, mtime and
+    /// permissions of every file to stderr, to check the results by hand before trusting them
+    #[structopt(long = "review-sample", value_name = "N")]
+    pub review_sample: Option<usize>,
+
+    /// Seed for choosing the groups of `--review-sample`; the seed used is always printed, so a
+    /// sample can be drawn again
+    #[structopt(long = "seed", requires = "review_sample")]
+    pub seed: Option<u64>,
+
+    /// Print paths relative to the root instead of in whatever form the root was given, so that
+    /// reports are portable between machines that mount the same data elsewhere. Shell scripts
+    /// change to the root first, which can be given as their argument
+    #[structopt(long = "relative", conflicts_with = "canonical")]
+    pub relative: bool,
+
+    /// Print the checksum of each group, hex-encoded, along with its paths, so that groups found
+    /// by different runs or on different machines can be matched up without hashing again; the
+    /// `json` format always includes it
+    #[structopt(long = "show-hash")]
+    pub show_hash: bool,
+
+    /// Print the size and modification time of each file along with its path, to pick which copy
+    /// to keep without looking each one up again; the `json` format always includes them
+    #[structopt(long = "show-meta")]
+    pub show_meta: bool,
+
+    /// Print paths, and with --group-by name or name-size compare names, in this Unicode
+    /// normalization form, so that names synced through macOS, which decomposes them, and other
+    /// systems, which mostly don't, match; the commands of the `shell` format still name the
+    /// files as they are
+    #[structopt(
+        long = "normalize",
+        default_value = "none",
+        raw(possible_values = "NORMALIZE_VALUES")
+    )]
+    pub normalize: Normalization,
+
     /// Root directory from which to start the search
     #[structopt(parse(from_os_str))]
     pub root: PathBuf,
 }
+
+/// Options of `fdup dedup`: what to do with the duplicates a scan finds. They are always given
+/// along with `ScanOpt`, whose options they may conflict with.
+#[derive(StructOpt, Debug, Default)]
+pub struct DedupOpt {
+    /// Delete all but one file of each duplicate group
+    #[structopt(long = "delete", conflicts_with = "unique")]
+    pub delete: bool,
+
+    /// Move files to the trash instead of deleting them permanently, wherever files would be
+    /// deleted; files are left in place if there is no trash to move them to
+    #[structopt(long = "trash")]
+    pub trash: bool,
+
+    /// Replace all but one file of each duplicate group with hard links to it
+    #[structopt(
+        long = "hardlink",
+        raw(conflicts_with_all = r#"&["delete", "unique"]"#)
+    )]
+    pub hardlink: bool,
+
+    /// Replace all but one file of each duplicate group with symbolic links to it
+    #[structopt(
+        long = "symlink",
+        raw(conflicts_with_all = r#"&["delete", "hardlink", "unique"]"#)
+    )]
+    pub symlink: bool,
+
+    /// Make all but one file of each duplicate group share its extents, on btrfs or XFS
+    #[structopt(
+        long = "reflink",
+        raw(conflicts_with_all = r#"&["delete", "hardlink", "symlink", "unique"]"#)
+    )]
+    pub reflink: bool,
+
+    /// Whether symbolic links created by --symlink are relative or absolute
+    #[structopt(
+        long = "symlink-style",
+        default_value = "relative",
+        raw(possible_values = "SYMLINK_STYLE_VALUES")
+    )]
+    pub symlink_style: SymlinkStyle,
+
+    /// Only print what the chosen action would do, without touching the filesystem
+    #[structopt(short = "n", long = "dry-run")]
+    pub dry_run: bool,
+
+    /// Instead of acting, print a script that performs the chosen action (deleting by default)
+    #[structopt(
+        long = "emit-script",
+        raw(
+            possible_values = "SCRIPT_VALUES",
+            conflicts_with_all = r#"&["unique", "quiet"]"#
+        )
+    )]
+    pub emit_script: Option<ScriptKind>,
+
+    /// Ask which files of each group to keep, then delete the rest (or apply the chosen action)
+    #[structopt(
+        short = "i",
+        long = "interactive",
+        raw(conflicts_with_all = r#"&["unique", "quiet"]"#)
+    )]
+    pub interactive: bool,
+
+    /// How thoroughly to recheck that a group is still identical right before acting on it
+    #[structopt(
+        long = "reverify",
+        default_value = "quick",
+        raw(possible_values = "REVERIFY_VALUES")
+    )]
+    pub reverify: Reverify,
+}
+
+/// Validator of `--similar-dirs`.
+fn percent(s: String) -> Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(n) if n > 0.0 && n <= 100.0 => Ok(()),
+        _ => Err(format!(
+            "expected a percentage above 0 and up to 100, got {:?}",
+            s
+        )),
+    }
+}
+
+/// Validator of `--similar-images`.
+fn hash_bits(s: String) -> Result<(), String> {
+    match s.parse::<u32>() {
+        Ok(n) if n <= 64 => Ok(()),
+        _ => Err(format!("expected a number of bits up to 64, got {:?}", s)),
+    }
+}
+
+/// Validator of `--file-timeout`, which has to fit in a `Duration` too.
+fn seconds(s: String) -> Result<(), String> {
+    positive(s.clone())?;
+    match Duration::try_from_secs_f64(s.parse().unwrap_or_default()) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("{}, got {:?}", err, s)),
+    }
+}
+
+/// Validator of `--throttle`.
+fn positive(s: String) -> Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok(()),
+        _ => Err(format!("expected a number above 0, got {:?}", s)),
+    }
+}
+
+/// Validator of `--min-copies`, since a single file is not a duplicate.
+fn at_least_two(s: String) -> Result<(), String> {
+    match s.parse::<usize>() {
+        Ok(n) if n >= 2 => Ok(()),
+        _ => Err(format!(
+            "expected a whole number of at least 2, got {:?}",
+            s
+        )),
+    }
+}
+
+/// Parse `args`, the whole command line including the name of the program, reading
+/// `fdup [OPTIONS] ROOT` as `fdup scan [OPTIONS] ROOT`.
+pub fn parse_args<I, T>(args: I) -> Result<ArgMatches<'static>, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString>,
+{
+    let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    match Opt::clap().get_matches_from_safe(&args) {
+        // neither a subcommand nor an option of the top level, so presumably one of scan
+        Err(ref err)
+            if err.kind == ErrorKind::UnknownArgument
+                || err.kind == ErrorKind::UnrecognizedSubcommand =>
+        {
+            args.insert(args.len().min(1), OsString::from("scan"));
+            Opt::clap().get_matches_from_safe(args)
+        }
+        parsed => parsed,
+    }
+}
+
+impl Opt {
+    /// # Returns
+    ///
+    /// Scan options of the command given, if it scans for duplicates.
+    pub fn scan_mut(&mut self) -> Option<&mut ScanOpt> {
+        match &mut self.cmd {
+            Some(Command::Scan { scan })
+            | Some(Command::Dedup { scan, .. })
+            | Some(Command::Tui { scan, .. }) => Some(scan),
+            _ => None,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Walk options of the command given, if it walks any trees.
+    pub fn walk_mut(&mut self) -> Option<&mut WalkOpt> {
+        match &mut self.cmd {
+            Some(Command::Compare { walk, .. })
+            | Some(Command::Verify { walk, .. })
+            | Some(Command::Watch { walk, .. })
+            | Some(Command::Daemon { walk, .. }) => Some(walk),
+            Some(Command::Scan { scan })
+            | Some(Command::Dedup { scan, .. })
+            | Some(Command::Tui { scan, .. }) => Some(&mut scan.walk),
+            _ => None,
+        }
+    }
+}
+
+impl ScanOpt {
+    /// # Returns
+    ///
+    /// Rules for applying the actions of `dedup`, or for suggesting which files to keep.
+    pub fn policy(&self, dedup: &DedupOpt) -> Policy {
+        // canonical, so that prefixes match however the root was spelled
+        let canonical = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
+                .collect()
+        };
+        let reference = self.reference.iter().cloned();
+        let prefer: Vec<PathBuf> = reference
+            .clone()
+            .chain(self.prefer.iter().cloned())
+            .collect();
+        let protect: Vec<PathBuf> = reference.chain(self.protect.iter().cloned()).collect();
+        Policy {
+            keep: self.keep,
+            prefer: canonical(&prefer),
+            protect: canonical(&protect),
+            reverify: dedup.reverify,
+            dry_run: dedup.dry_run,
+            quiet: self.quiet,
+        }
+    }
+}
+
+impl DedupOpt {
+    /// # Returns
+    ///
+    /// Action to apply to every group, if one was chosen; `--trash` on its own means deleting to
+    /// the trash.
+    pub fn action(&self) -> Option<Action> {
+        let actions = [
+            (self.hardlink, Action::Hardlink),
+            (self.symlink, Action::Symlink(self.symlink_style)),
+            (self.reflink, Action::Reflink),
+            (self.delete || self.trash, self.delete_action()),
+        ];
+        actions
+            .iter()
+            .find(|(chosen, _)| *chosen)
+            .map(|(_, action)| *action)
+    }
+
+    /// # Returns
+    ///
+    /// Action that gets rid of unwanted files, honouring `--trash`.
+    pub fn delete_action(&self) -> Action {
+        match self.trash {
+            true => Action::Trash,
+            false => Action::Delete,
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Scan a tree for duplicates and report them, without touching any file
+    #[structopt(name = "scan")]
+    Scan {
+        #[structopt(flatten)]
+        scan: ScanOpt,
+    },
+
+    /// Scan a tree for duplicates and get rid of them, by deleting or linking all but one file of
+    /// each group, asking first, or writing a script to do it
+    #[structopt(name = "dedup")]
+    Dedup {
+        #[structopt(flatten)]
+        scan: ScanOpt,
+
+        #[structopt(flatten)]
+        dedup: DedupOpt,
+    },
+
+    /// Show how redundant bytes have changed across the runs recorded in a cache
+    #[structopt(name = "history")]
+    History {
+        /// Cache database previously passed to --cache
+        #[structopt(parse(from_os_str))]
+        db: PathBuf,
+    },
+
+    /// Combine JSON reports from several shards or runs into one
+    #[structopt(name = "merge")]
+    Merge {
+        /// Where to write the merged report
+        #[structopt(parse(from_os_str))]
+        out: PathBuf,
+
+        /// Reports written by --format json, from oldest to newest
+        #[structopt(parse(from_os_str), raw(required = "true"))]
+        inputs: Vec<PathBuf>,
+    },
+
+    /// Show which duplicate groups are new, resolved, or changed in size between two runs
+    #[structopt(name = "diff")]
+    Diff {
+        /// Snapshot or JSON report of the earlier run
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// Snapshot or JSON report of the later run
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
+
+    /// Classify the files of two trees by whether their contents exist in the other one
+    #[structopt(name = "compare")]
+    Compare {
+        #[structopt(flatten)]
+        walk: WalkOpt,
+
+        /// Root of the first tree
+        #[structopt(parse(from_os_str))]
+        left: PathBuf,
+
+        /// Root of the second tree
+        #[structopt(parse(from_os_str))]
+        right: PathBuf,
+    },
+
+    /// Check a tree against a manifest written by sha512sum, listing missing, modified and extra
+    /// files
+    #[structopt(name = "verify")]
+    Verify {
+        #[structopt(flatten)]
+        walk: WalkOpt,
+
+        /// Manifest of checksums and paths, one file per line
+        #[structopt(parse(from_os_str))]
+        manifest: PathBuf,
+
+        /// Directory the paths of the manifest are relative to
+        #[structopt(parse(from_os_str))]
+        root: PathBuf,
+    },
+
+    /// Keep watching a tree, printing each duplicate group a file joins as files are created or
+    /// modified
+    #[structopt(name = "watch")]
+    Watch {
+        #[structopt(flatten)]
+        walk: WalkOpt,
+
+        /// Directory to watch
+        #[structopt(parse(from_os_str))]
+        root: PathBuf,
+    },
+
+    /// Keep the duplicates of some trees in memory, answering queries about them over a Unix socket
+    /// in JSON, one object per line: {"query":"duplicates","path":...} or {"query":"duplicates",
+    /// "hash":...} for the copies of a file, {"query":"wasted"} for totals, and
+    /// {"query":"rescan"} to scan again
+    #[structopt(name = "daemon")]
+    Daemon {
+        #[structopt(flatten)]
+        walk: WalkOpt,
+
+        /// Socket to listen on, created at startup
+        #[structopt(long = "socket", value_name = "FILE", parse(from_os_str))]
+        socket: PathBuf,
+
+        /// Directories to index
+        #[structopt(parse(from_os_str), raw(required = "true"))]
+        roots: Vec<PathBuf>,
+    },
+
+    /// Review duplicate groups full-screen, marking files to delete or hard link
+    #[structopt(name = "tui")]
+    Tui {
+        #[structopt(flatten)]
+        scan: ScanOpt,
+
+        #[structopt(flatten)]
+        dedup: DedupOpt,
+    },
+
+    /// Inspect and maintain a cache database
+    #[structopt(name = "cache")]
+    Cache {
+        #[structopt(subcommand)]
+        cmd: CacheCommand,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum CacheCommand {
+    /// Show the number of cached checksums and the hit rate of the last run
+    #[structopt(name = "stats")]
+    Stats {
+        /// Cache database previously passed to --cache
+        #[structopt(parse(from_os_str))]
+        db: PathBuf,
+    },
+
+    /// Remove entries for files that have since been deleted or modified
+    #[structopt(name = "prune")]
+    Prune {
+        /// Cache database previously passed to --cache
+        #[structopt(parse(from_os_str))]
+        db: PathBuf,
+    },
+
+    /// Rebuild the database to reclaim unused space
+    #[structopt(name = "vacuum")]
+    Vacuum {
+        /// Cache database previously passed to --cache
+        #[structopt(parse(from_os_str))]
+        db: PathBuf,
+    },
+
+    /// Remove all cached checksums and the run history
+    #[structopt(name = "clear")]
+    Clear {
+        /// Cache database previously passed to --cache
+        #[structopt(parse(from_os_str))]
+        db: PathBuf,
+    },
+}
@@ -0,0 +1,103 @@
+use crate::cache::HashCache;
+use crate::fdup::duplicate_groups;
+use crate::fdup::ScanConfig;
+use crate::filter::Filter;
+use crate::index::ScanIndex;
+use crate::walk::files;
+use crate::walk::Traversal;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Files of two trees classified by whether their contents exist in the other tree.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Comparison {
+    /// Files of the left tree, each paired with a copy of it in the right tree
+    pub both: Vec<(PathBuf, PathBuf)>,
+    /// Files of the left tree with no copy in the right tree
+    pub only_left: Vec<PathBuf>,
+    /// Files of the right tree with no copy in the left tree
+    pub only_right: Vec<PathBuf>,
+}
+
+/// Compare the contents of the trees under `left` and `right`, using the same size and checksum
+/// pipeline as a scan for duplicates. Where files are, and what they are called, doesn't matter.
+///
+/// # Parameters
+///
+/// 1. `cache` -- if given, checksums are read from and written to it instead of always hashing
+/// 1. `filter` -- conditions files have to meet to be considered at all
+/// 1. `traversal` -- order in which to walk the trees
+/// 1. `left`, `right` -- roots of the trees to compare
+///
+/// # Returns
+///
+/// Every file of both trees, classified and sorted by path.
+pub fn compare(
+    cache: Option<&HashCache>,
+    filter: &Filter,
+    traversal: Traversal,
+    left: &Path,
+    right: &Path,
+) -> Comparison {
+    let config = ScanConfig {
+        cache,
+        traversal,
+        ..ScanConfig::new(filter)
+    };
+    let index: ScanIndex = duplicate_groups(config, &[left, right]).collect();
+    let copy_in = |path: &Path, other: &Path| {
+        index
+            .duplicates_of(path)
+            .and_then(|group| group.paths.iter().filter(|p| p.starts_with(other)).min())
+            .cloned()
+    };
+
+    let mut comparison = Comparison::default();
+    for path in files(left, traversal, filter) {
+        match copy_in(&path, right) {
+            Some(copy) => comparison.both.push((path, copy)),
+            None => comparison.only_left.push(path),
+        }
+    }
+    for path in files(right, traversal, filter) {
+        if copy_in(&path, left).is_none() {
+            comparison.only_right.push(path);
+        }
+    }
+    comparison
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+
+    #[test]
+    fn classifies_by_contents() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        let (left, right) = (root.join("left"), root.join("right"));
+        create_dir_all(left.join("sub")).unwrap();
+        create_dir_all(&right).unwrap();
+        write(left.join("a"), "migrated").unwrap();
+        write(left.join("sub/b"), "left behind").unwrap();
+        write(left.join("c"), "left behind").unwrap();
+        write(right.join("renamed"), "migrated").unwrap();
+        write(right.join("new"), "only on the new disk").unwrap();
+
+        let comparison = compare(None, &Filter::default(), Traversal::Dfs, &left, &right);
+        assert_eq!(
+            vec![(left.join("a"), right.join("renamed"))],
+            comparison.both
+        );
+        assert_eq!(
+            vec![left.join("c"), left.join("sub/b")],
+            comparison.only_left
+        );
+        assert_eq!(vec![right.join("new")], comparison.only_right);
+
+        remove_dir_all(&root).unwrap();
+    }
+}
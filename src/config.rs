@@ -0,0 +1,313 @@
+use crate::clargs::Opt;
+use crate::fdup::HASH_VALUES;
+use crate::filter::MinSize;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use structopt::clap::ArgMatches;
+
+/// Defaults read from a TOML file, for options too long or too constant to pass on every run,
+/// e.g.
+///
+/// ```toml
+/// exclude = ["~/photos/.thumbnails", "/var/cache"]
+/// protect = ["~/originals"]
+/// min_size_for = ["image=100K"]
+/// format = "json"
+/// hash = "sha512"
+/// cache = "~/.cache/fdup.sqlite"
+/// threads = 4
+/// ```
+///
+/// or from `FDUP_*` environment variables, which override the file. Options given on the command
+/// line always win.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Paths never to consider, like `--exclude`
+    pub exclude: Vec<PathBuf>,
+    /// Paths never to act on, like `--protect`
+    pub protect: Vec<PathBuf>,
+    /// Per-type size thresholds, like `--min-size-for`
+    pub min_size_for: Vec<String>,
+    /// How to print duplicate groups, like `--format`
+    pub format: Option<String>,
+    /// Checksum algorithm used to compare contents
+    pub hash: Option<String>,
+    /// Checksum cache, like `--cache`
+    pub cache: Option<PathBuf>,
+    /// Number of hashing threads, like `--threads`
+    pub threads: Option<usize>,
+}
+
+impl Config {
+    /// # Parameters
+    ///
+    /// 1. `path` -- file given with `--config`, or `None` for the one named by `FDUP_CONFIG`, or
+    ///    else the default location
+    ///
+    /// # Returns
+    ///
+    /// Contents of the configuration file, or `None` if no file was given and there is none at the
+    /// default location.
+    pub fn load(path: Option<&Path>) -> Result<Option<Config>, String> {
+        let given = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var_os("FDUP_CONFIG").map(PathBuf::from));
+        let (path, required) = match (given, default_path()) {
+            (Some(path), _) => (path, true),
+            (None, Some(path)) => (path, false),
+            (None, None) => return Ok(None),
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => return Ok(None),
+            Err(err) => return Err(format!("reading config {:?}: {}", path, err)),
+        };
+        toml::from_str(&text)
+            .map(Some)
+            .map_err(|err| format!("in config {:?}: {}", path, err))
+    }
+
+    /// # Returns
+    ///
+    /// Configuration given by the environment: `FDUP_EXCLUDE` and `FDUP_PROTECT` as lists of paths
+    /// separated like `PATH`, `FDUP_MIN_SIZE_FOR` as a comma separated list of thresholds, and
+    /// `FDUP_FORMAT`, `FDUP_HASH`, `FDUP_CACHE` and `FDUP_THREADS` as single values. Empty
+    /// variables count as unset.
+    pub fn from_env() -> Result<Config, String> {
+        let var = |name: &str| env::var_os(name).filter(|value| !value.is_empty());
+        let text = |name: &str| match var(name).map(|value| value.into_string()) {
+            Some(Ok(value)) => Ok(Some(value)),
+            Some(Err(value)) => Err(format!("{} is not valid unicode: {:?}", name, value)),
+            None => Ok(None),
+        };
+        let paths = |name: &str| match var(name) {
+            Some(value) => env::split_paths(&value).collect(),
+            None => Vec::new(),
+        };
+        let threads = match text("FDUP_THREADS")? {
+            Some(threads) => Some(threads.parse().map_err(|_| {
+                format!(
+                    "FDUP_THREADS: expected a number of threads, got {:?}",
+                    threads
+                )
+            })?),
+            None => None,
+        };
+        Ok(Config {
+            exclude: paths("FDUP_EXCLUDE"),
+            protect: paths("FDUP_PROTECT"),
+            min_size_for: match text("FDUP_MIN_SIZE_FOR")? {
+                Some(list) => list.split(',').map(String::from).collect(),
+                None => Vec::new(),
+            },
+            format: text("FDUP_FORMAT")?,
+            hash: text("FDUP_HASH")?,
+            cache: var("FDUP_CACHE").map(PathBuf::from),
+            threads,
+        })
+    }
+
+    /// # Returns
+    ///
+    /// This configuration with `over` laid on top: its values replace single values, and its lists
+    /// are added to lists, except that its thresholds replace those for the same type.
+    pub fn overlay(mut self, mut over: Config) -> Config {
+        let types: Vec<_> = over
+            .min_size_for
+            .iter()
+            .filter_map(|min_size| min_size.parse::<MinSize>().ok())
+            .map(|min_size| min_size.file_type)
+            .collect();
+        self.min_size_for
+            .retain(|min_size| match min_size.parse::<MinSize>() {
+                Ok(min_size) => !types.contains(&min_size.file_type),
+                Err(_) => true, // reported by `apply`
+            });
+        self.exclude.append(&mut over.exclude);
+        self.protect.append(&mut over.protect);
+        self.min_size_for.append(&mut over.min_size_for);
+        Config {
+            format: over.format.or(self.format),
+            hash: over.hash.or(self.hash),
+            cache: over.cache.or(self.cache),
+            threads: over.threads.or(self.threads),
+            ..self
+        }
+    }
+
+    /// Fill in the options of `opt` that were not given on the command line from this one. Lists
+    /// are combined, except that thresholds on the command line replace those for the same type.
+    ///
+    /// # Parameters
+    ///
+    /// 1. `opt` -- options parsed from `matches`
+    /// 1. `matches` -- the command line, to tell options given from defaults
+    pub fn apply(&self, opt: &mut Opt, matches: &ArgMatches) -> Result<(), String> {
+        if let Some(hash) = &self.hash {
+            if !HASH_VALUES.contains(&hash.as_str()) {
+                return Err(format!("unknown hash algorithm {:?}", hash));
+            }
+        }
+        if opt.threads.is_none() && !opt.background {
+            opt.threads = self.threads;
+        }
+        let combined = |configured: &[PathBuf], given: &mut Vec<PathBuf>| {
+            let mut paths: Vec<PathBuf> = configured.iter().map(|path| expand_home(path)).collect();
+            paths.append(given);
+            *given = paths;
+        };
+        // options of the subcommand are in its own matches
+        let matches = matches.subcommand().1.unwrap_or(matches);
+        if let Some(scan) = opt.scan_mut() {
+            if let Some(format) = &self.format {
+                if matches.occurrences_of("format") == 0 {
+                    scan.format = format.parse()?;
+                }
+            }
+            combined(&self.protect, &mut scan.protect);
+        }
+        let walk = match opt.walk_mut() {
+            Some(walk) => walk,
+            None => return Ok(()),
+        };
+
+        let mut min_sizes = Vec::with_capacity(self.min_size_for.len());
+        for min_size in self.min_size_for.iter() {
+            let min_size: MinSize = min_size.parse()?;
+            let overridden = walk
+                .min_size_for
+                .iter()
+                .any(|given| given.file_type == min_size.file_type);
+            if !overridden {
+                min_sizes.push(min_size);
+            }
+        }
+        min_sizes.append(&mut walk.min_size_for);
+        walk.min_size_for = min_sizes;
+        combined(&self.exclude, &mut walk.exclude);
+        if walk.cache.is_none() {
+            walk.cache = self.cache.as_deref().map(expand_home);
+        }
+        Ok(())
+    }
+}
+
+/// # Returns
+///
+/// `$XDG_CONFIG_HOME/fdup/config.toml`, falling back to `~/.config/fdup/config.toml`.
+fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_home.join("fdup").join("config.toml"))
+}
+
+/// # Returns
+///
+/// `path` with a leading `~` replaced by the home directory, since no shell expands it in a file.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clargs::parse_args;
+    use crate::clargs::Command;
+    use crate::output::Format;
+    use structopt::StructOpt;
+
+    #[test]
+    fn command_line_wins() {
+        let config: Config = toml::from_str(
+            r#"
+            exclude = ["/var/cache"]
+            protect = ["/originals"]
+            min_size_for = ["image=100K", "video=10M"]
+            format = "json"
+            "#,
+        )
+        .unwrap();
+        let apply = |args: &[&str]| {
+            let matches = parse_args(args).unwrap();
+            let mut opt = Opt::from_clap(&matches);
+            config.apply(&mut opt, &matches).map(|_| opt)
+        };
+        let scan = |mut opt: Opt| match opt.cmd.take() {
+            Some(Command::Scan { scan }) | Some(Command::Dedup { scan, .. }) => scan,
+            cmd => panic!("not a scan: {:?}", cmd),
+        };
+
+        let opt = scan(apply(&["fdup", "."]).unwrap());
+        assert_eq!(Format::Json, opt.format);
+        assert_eq!(vec![PathBuf::from("/var/cache")], opt.walk.exclude);
+        assert_eq!(2, opt.walk.min_size_for.len());
+
+        let opt = scan(
+            apply(&[
+                "fdup",
+                "dedup",
+                "--format",
+                "debug",
+                "--protect",
+                "mine",
+                "--min-size-for",
+                "image=1K",
+                ".",
+            ])
+            .unwrap(),
+        );
+        assert_eq!(Format::Debug, opt.format);
+        assert_eq!(
+            vec![PathBuf::from("/originals"), PathBuf::from("mine")],
+            opt.protect
+        );
+        assert_eq!(
+            vec![
+                "video=10M".parse::<MinSize>().unwrap(),
+                "image=1K".parse().unwrap()
+            ],
+            opt.walk.min_size_for
+        );
+
+        let config = Config {
+            hash: Some(String::from("md5")),
+            ..Config::default()
+        };
+        let matches = parse_args(&["fdup", "."]).unwrap();
+        let mut opt = Opt::from_clap(&matches);
+        assert!(config.apply(&mut opt, &matches).is_err());
+        assert!(toml::from_str::<Config>("exclud = []").is_err());
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let file = Config {
+            exclude: vec![PathBuf::from("a")],
+            min_size_for: vec![String::from("image=1K"), String::from("video=1M")],
+            format: Some(String::from("json")),
+            threads: Some(2),
+            ..Config::default()
+        };
+        let env = Config {
+            exclude: vec![PathBuf::from("b")],
+            min_size_for: vec![String::from("image=2K")],
+            format: Some(String::from("shell")),
+            ..Config::default()
+        };
+        let config = file.overlay(env);
+        assert_eq!(vec![PathBuf::from("a"), PathBuf::from("b")], config.exclude);
+        assert_eq!(vec!["video=1M", "image=2K"], config.min_size_for);
+        assert_eq!(Some("shell"), config.format.as_deref());
+        assert_eq!(Some(2), config.threads);
+    }
+}
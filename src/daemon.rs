@@ -0,0 +1,247 @@
+use crate::index::ScanIndex;
+use crate::report::unhex;
+use crate::report::ReportGroup;
+use serde::Deserialize;
+use serde::Serialize;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long a client may stay silent before the daemon moves on to the next one, since clients
+/// are served one at a time.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A question to `fdup daemon`, sent as one JSON object per line, e.g.
+/// `{"query":"duplicates","path":"/photos/a.jpg"}` or `{"query":"wasted"}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "query", rename_all = "snake_case")]
+pub enum Request {
+    /// Files with the same contents as the file at `path`, or with the hex-encoded checksum `hash`
+    Duplicates {
+        #[serde(default)]
+        path: Option<PathBuf>,
+        #[serde(default)]
+        hash: Option<String>,
+    },
+    /// Space taken up by copies across all roots
+    Wasted,
+    /// Scan the roots again, to pick up whatever changed since the last scan
+    Rescan,
+}
+
+/// The answer to a `Request`, sent back as one JSON object per line.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "answer", rename_all = "snake_case")]
+pub enum Response {
+    /// The group asked for, or `None` if the file has no duplicates
+    Duplicates { group: Option<ReportGroup> },
+    /// Totals over every duplicate group
+    Wasted {
+        groups: usize,
+        redundant_files: u64,
+        redundant_bytes: u64,
+    },
+    /// Number of duplicate groups found by a new scan
+    Rescanned { groups: usize },
+    /// Why the request couldn't be answered
+    Error { message: String },
+}
+
+/// # Parameters
+///
+/// 1. `index` -- results of the latest scan
+/// 1. `request` -- anything but `Request::Rescan`, which only the server can answer
+/// 1. `local` -- render mtimes in the local time zone instead of UTC
+pub fn answer(index: &ScanIndex, request: &Request, local: bool) -> Response {
+    let error = |message: &str| Response::Error {
+        message: String::from(message),
+    };
+    match request {
+        Request::Duplicates {
+            path: Some(path),
+            hash: None,
+        } => {
+            // the roots are canonical, so look the path up that way too
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            let group = index.duplicates_of(&canonical);
+            Response::Duplicates {
+                group: group.map(|group| ReportGroup::new(group, local)),
+            }
+        }
+        Request::Duplicates {
+            path: None,
+            hash: Some(hash),
+        } => match unhex(hash) {
+            Some(hash) => Response::Duplicates {
+                group: index
+                    .with_hash(&hash)
+                    .map(|group| ReportGroup::new(group, local)),
+            },
+            None => error("hash is not hexadecimal"),
+        },
+        Request::Duplicates { .. } => error("expected exactly one of path and hash"),
+        Request::Wasted => Response::Wasted {
+            groups: index.groups().len(),
+            redundant_files: index
+                .groups()
+                .iter()
+                .map(|group| group.paths.len() as u64 - 1)
+                .sum(),
+            redundant_bytes: index.groups().iter().map(|group| group.wasted()).sum(),
+        },
+        Request::Rescan => error("rescan is answered by the server"),
+    }
+}
+
+/// Answer requests from clients connecting to `listener`, one client at a time, forever.
+///
+/// # Parameters
+///
+/// 1. `listener` -- socket clients connect to
+/// 1. `local` -- render mtimes in the local time zone instead of UTC
+/// 1. `scan` -- scans the roots, once at the start and again on every `Request::Rescan`
+pub fn serve<F>(listener: &UnixListener, local: bool, mut scan: F)
+where
+    F: FnMut() -> ScanIndex,
+{
+    let mut index = scan();
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| converse(stream, &mut index, local, &mut scan));
+        if let Err(err) = result {
+            eprintln!("ERROR with client: {}", err);
+        }
+    }
+}
+
+/// Answer requests from `stream` until the client hangs up or goes quiet.
+fn converse<F>(
+    stream: UnixStream,
+    index: &mut ScanIndex,
+    local: bool,
+    scan: &mut F,
+) -> io::Result<()>
+where
+    F: FnMut() -> ScanIndex,
+{
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut out = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(ref err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::TimedOut =>
+            {
+                break; // gone quiet
+            }
+            Err(err) => return Err(err),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(Request::Rescan) => {
+                *index = scan();
+                Response::Rescanned {
+                    groups: index.groups().len(),
+                }
+            }
+            Ok(request) => answer(index, &request, local),
+            Err(err) => Response::Error {
+                message: format!("{}", err),
+            },
+        };
+        serde_json::to_writer(&mut out, &response)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdup::DuplicateGroup;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::thread;
+
+    fn index() -> ScanIndex {
+        let group = |hash: u8, size: u64, paths: &[&str]| DuplicateGroup {
+            hash: vec![hash],
+            size,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        };
+        vec![
+            group(0xab, 10, &["/a", "/b"]),
+            group(0xcd, 5, &["/c", "/d", "/e"]),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn requests_are_answered() {
+        let index = index();
+        let ask = |json: &str| answer(&index, &serde_json::from_str(json).unwrap(), false);
+        let paths = |response: Response| match response {
+            Response::Duplicates { group: Some(group) } => group.paths,
+            response => panic!("unexpected {:?}", response),
+        };
+
+        assert_eq!(
+            vec!["/a", "/b"],
+            paths(ask(r#"{"query":"duplicates","path":"/b"}"#))
+        );
+        assert_eq!(3, paths(ask(r#"{"query":"duplicates","hash":"CD"}"#)).len());
+        assert_eq!(
+            Response::Duplicates { group: None },
+            ask(r#"{"query":"duplicates","path":"/f"}"#)
+        );
+        assert_eq!(
+            Response::Wasted {
+                groups: 2,
+                redundant_files: 3,
+                redundant_bytes: 20
+            },
+            ask(r#"{"query":"wasted"}"#)
+        );
+        assert!(matches!(
+            ask(r#"{"query":"duplicates"}"#),
+            Response::Error { .. }
+        ));
+        assert!(matches!(
+            ask(r#"{"query":"duplicates","hash":"xyz"}"#),
+            Response::Error { .. }
+        ));
+    }
+
+    #[test]
+    fn clients_talk_over_the_socket() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&root).unwrap();
+        let socket = root.join("fdup.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || serve(&listener, false, index));
+
+        let stream = UnixStream::connect(&socket).unwrap();
+        (&stream)
+            .write_all(b"{\"query\":\"rescan\"}\nnonsense\n{\"query\":\"wasted\"}\n")
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let responses: Vec<Response> = BufReader::new(&stream)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+        assert_eq!(Response::Rescanned { groups: 2 }, responses[0]);
+        assert!(matches!(responses[1], Response::Error { .. }));
+        assert!(matches!(responses[2], Response::Wasted { groups: 2, .. }));
+
+        remove_dir_all(&root).unwrap();
+    }
+}
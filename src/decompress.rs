@@ -0,0 +1,323 @@
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+
+/// Most bytes a file is decompressed to before it is given up on, so that a small file that
+/// decompresses to far more, whether by accident or as a decompression bomb, can't stall the scan.
+pub const DECOMPRESSED_LIMIT: u64 = 1 << 32;
+
+/// Formats of the compressed files whose contents `--decompress` compares, each wrapping a single
+/// file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// # Returns
+    ///
+    /// Format of the file at `path` going by its first bytes, whatever its extension, or `None`
+    /// if it isn't compressed in one of them.
+    pub fn of(path: &Path) -> io::Result<Option<Compression>> {
+        let mut start = Vec::with_capacity(6);
+        File::open(path)?.take(6).read_to_end(&mut start)?;
+        let kind = match &start[..] {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [0xfd, b'7', b'z', b'X', b'Z', 0] => Some(Compression::Xz),
+            _ => None,
+        };
+        Ok(kind)
+    }
+}
+
+/// # Returns
+///
+/// What the file at `path`, compressed as `kind`, decompresses to, read as it is decompressed.
+/// Files made of several compressed parts one after another, as `cat` of two of them makes, are
+/// read through all of them, like the command line tools do. Reading fails with `InvalidData`
+/// once more than `limit` bytes came out.
+pub fn decompressed(path: &Path, kind: Compression, limit: u64) -> io::Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path)?);
+    let inner: Box<dyn Read> = match kind {
+        Compression::Gzip => Box::new(MultiGzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(file)?),
+        Compression::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(file)),
+    };
+    Ok(Box::new(Limited {
+        inner,
+        limit,
+        left: limit,
+    }))
+}
+
+/// # Returns
+///
+/// Number of bytes the file at `path`, compressed as `kind`, decompresses to, counted without
+/// keeping them, or `InvalidData` if that is more than `limit`.
+pub fn decompressed_len(path: &Path, kind: Compression, limit: u64) -> io::Result<u64> {
+    io::copy(&mut decompressed(path, kind, limit)?, &mut io::sink())
+}
+
+/// # Returns
+///
+/// Number of bytes the file at `path`, compressed as `kind`, says it decompresses to, read from
+/// where the format keeps it without decompressing anything, or `None` if it doesn't say:
+///
+/// - gzip keeps the length, modulo 2^32, at its very end, which for files made of several
+///   compressed parts is only that of the last; such files are then sized wrong and may be missed,
+///   but never mistaken for copies of anything, since what they decompress to is hashed in full
+/// - zstd keeps it in the header of each frame, if the tool that wrote them knew it
+/// - xz keeps it in the index at its end, which is only trusted if it accounts for the whole file
+pub fn stored_len(path: &Path, kind: Compression) -> io::Result<Option<u64>> {
+    let mut file = BufReader::new(File::open(path)?);
+    match kind {
+        Compression::Gzip => {
+            let mut trailer = [0; 4];
+            file.seek(SeekFrom::End(-4))?;
+            file.read_exact(&mut trailer)?;
+            Ok(Some(u64::from(u32::from_le_bytes(trailer))))
+        }
+        Compression::Zstd => zstd_content_size(&mut file),
+        Compression::Xz => xz_uncompressed_size(&mut file),
+    }
+}
+
+/// # Returns
+///
+/// Sum of the content sizes in the headers of the zstd frames `file` consists of, found by going
+/// from block header to block header, or `None` if a frame doesn't give its size.
+fn zstd_content_size<R: Read + Seek>(file: &mut R) -> io::Result<Option<u64>> {
+    let mut total: u64 = 0;
+    loop {
+        let mut magic = [0; 4];
+        match file.read(&mut magic[..1])? {
+            0 => return Ok(Some(total)),
+            _ => file.read_exact(&mut magic[1..])?,
+        }
+        match u32::from_le_bytes(magic) {
+            0xfd2f_b528 => (),
+            // skippable frames, which hold no contents
+            0x184d_2a50..=0x184d_2a5f => {
+                let len = read_u32(file)?;
+                file.seek(SeekFrom::Current(i64::from(len)))?;
+                continue;
+            }
+            _ => return Err(invalid("not a zstd frame")),
+        }
+        let descriptor = read_u8(file)?;
+        let single_segment = descriptor & 0x20 != 0;
+        let dictionary_id_len = [0, 1, 2, 4][usize::from(descriptor & 3)];
+        let skipped = i64::from(!single_segment) + dictionary_id_len;
+        file.seek(SeekFrom::Current(skipped))?;
+        let content_size = match (descriptor >> 6, single_segment) {
+            (0, false) => return Ok(None),
+            (0, true) => u64::from(read_u8(file)?),
+            (1, _) => u64::from(u16::from_le_bytes([read_u8(file)?, read_u8(file)?])) + 256,
+            (2, _) => u64::from(read_u32(file)?),
+            _ => u64::from(read_u32(file)?) | u64::from(read_u32(file)?) << 32,
+        };
+        total = total.saturating_add(content_size);
+        loop {
+            let header = u32::from_le_bytes([read_u8(file)?, read_u8(file)?, read_u8(file)?, 0]);
+            let size = match (header >> 1) & 3 {
+                1 => 1, // the one byte it repeats
+                3 => return Err(invalid("reserved zstd block type")),
+                _ => header >> 3,
+            };
+            file.seek(SeekFrom::Current(i64::from(size)))?;
+            if header & 1 == 1 {
+                break; // the last block of the frame
+            }
+        }
+        if descriptor & 4 != 0 {
+            file.seek(SeekFrom::Current(4))?; // checksum
+        }
+    }
+}
+
+/// # Returns
+///
+/// Sum of the uncompressed sizes of the blocks in the index of the xz stream `file` holds, or
+/// `None` if the file is more than that one stream, e.g. several of them one after another.
+fn xz_uncompressed_size<R: Read + Seek>(file: &mut R) -> io::Result<Option<u64>> {
+    const HEADER_LEN: u64 = 12;
+    const FOOTER_LEN: u64 = 12;
+
+    let file_len = file.seek(SeekFrom::End(0))?;
+    if file_len < HEADER_LEN + FOOTER_LEN {
+        return Err(invalid("truncated xz stream"));
+    }
+    let mut footer = [0; FOOTER_LEN as usize];
+    file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+    file.read_exact(&mut footer)?;
+    if &footer[10..] != b"YZ" {
+        return Ok(None); // padded, or not the end of a stream
+    }
+    let backward_size = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]);
+    let index_len = (u64::from(backward_size) + 1) * 4;
+    let index_start = match (file_len - FOOTER_LEN).checked_sub(index_len) {
+        Some(start) if start >= HEADER_LEN => start,
+        _ => return Err(invalid("xz index larger than the file")),
+    };
+    file.seek(SeekFrom::Start(index_start))?;
+    if read_u8(file)? != 0 {
+        return Err(invalid("no xz index where the footer says"));
+    }
+    let (mut blocks_len, mut total) = (0u64, 0u64);
+    for _ in 0..read_varint(file)? {
+        let unpadded = read_varint(file)?;
+        blocks_len = blocks_len.saturating_add(unpadded.saturating_add(3) & !3);
+        total = total.saturating_add(read_varint(file)?);
+    }
+    match HEADER_LEN + blocks_len == index_start {
+        true => Ok(Some(total)),
+        false => Ok(None),
+    }
+}
+
+fn read_u8<R: Read>(file: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    file.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u32<R: Read>(file: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    file.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// # Returns
+///
+/// Number xz stores in 7 bits per byte, least significant first, in at most 9 bytes.
+fn read_varint<R: Read>(file: &mut R) -> io::Result<u64> {
+    let mut number = 0;
+    for i in 0..9 {
+        let byte = read_u8(file)?;
+        number |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(number);
+        }
+    }
+    Err(invalid("xz number longer than 9 bytes"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reader that fails once more than `limit` bytes were read from `inner`.
+struct Limited<R> {
+    inner: R,
+    limit: u64,
+    left: u64,
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        match self.left.checked_sub(read as u64) {
+            Some(left) => {
+                self.left = left;
+                Ok(read)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("decompresses to more than {} bytes", self.limit),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::io::Write;
+
+    #[test]
+    fn compressed_files_are_read_through() {
+        let dir = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let log = b"one line\nanother line\n";
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(&log[..9]).unwrap();
+        let first = gzip.finish().unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(first, flate2::Compression::fast());
+        gzip.write_all(&log[9..]).unwrap();
+        std::fs::write(dir.join("log.gz"), gzip.finish().unwrap()).unwrap();
+        std::fs::write(dir.join("log.zst"), zstd::encode_all(&log[..], 3).unwrap()).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(log).unwrap();
+        std::fs::write(dir.join("log.xz"), xz.finish().unwrap()).unwrap();
+        std::fs::write(dir.join("log"), log).unwrap();
+
+        for (name, kind) in [
+            ("log.gz", Compression::Gzip),
+            ("log.zst", Compression::Zstd),
+            ("log.xz", Compression::Xz),
+        ] {
+            let path = dir.join(name);
+            assert_eq!(Some(kind), Compression::of(&path).unwrap());
+            let mut contents = Vec::new();
+            decompressed(&path, kind, DECOMPRESSED_LIMIT)
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            assert_eq!(log.to_vec(), contents);
+            let len = log.len() as u64;
+            assert_eq!(len, decompressed_len(&path, kind, len).unwrap());
+            let err = decompressed_len(&path, kind, len - 1).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+        assert_eq!(None, Compression::of(&dir.join("log")).unwrap());
+
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stored_lengths_are_read_without_decompressing() {
+        let dir = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let log = b"one line\nanother line\n".repeat(50);
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(&log).unwrap();
+        std::fs::write(dir.join("log.gz"), gzip.finish().unwrap()).unwrap();
+        // two frames, each knowing its size, with a skippable one in between
+        let mut zstd = zstd::bulk::compress(&log[..300], 3).unwrap();
+        zstd.extend(&[0x50, 0x2a, 0x4d, 0x18, 2, 0, 0, 0, b'h', b'i']);
+        zstd.extend(zstd::bulk::compress(&log[300..], 19).unwrap());
+        std::fs::write(dir.join("log.zst"), &zstd).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&log).unwrap();
+        let xz = xz.finish().unwrap();
+        std::fs::write(dir.join("log.xz"), &xz).unwrap();
+        std::fs::write(dir.join("twice.xz"), [&xz[..], &xz[..]].concat()).unwrap();
+        // streamed, so the frame doesn't know how much it holds
+        let mut streamed = zstd::stream::write::Encoder::new(Vec::new(), 3).unwrap();
+        streamed.write_all(&log).unwrap();
+        std::fs::write(dir.join("streamed.zst"), streamed.finish().unwrap()).unwrap();
+
+        let len = Some(log.len() as u64);
+        let stored = |name: &str, kind| stored_len(&dir.join(name), kind).unwrap();
+        assert_eq!(len, stored("log.gz", Compression::Gzip));
+        assert_eq!(len, stored("log.zst", Compression::Zstd));
+        assert_eq!(len, stored("log.xz", Compression::Xz));
+        assert_eq!(None, stored("twice.xz", Compression::Xz));
+        assert_eq!(None, stored("streamed.zst", Compression::Zstd));
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+use crate::fdup::DuplicateGroup;
+use crate::filter::Filter;
+use crate::walk::plain_path;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::read_dir;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Redundancy found under one directory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirTotal {
+    /// Number of copies that could be removed
+    pub copies: u64,
+    /// Bytes taken up by those copies
+    pub bytes: u64,
+}
+
+/// Redundant copies of duplicate groups added up by directory, each copy counting towards every
+/// directory above it up to the root it was found under, so that the directories where most of
+/// the redundancy lives stand out.
+#[derive(Clone, Debug, Default)]
+pub struct DirTotals {
+    roots: Vec<PathBuf>,
+    totals: HashMap<PathBuf, DirTotal>,
+    /// Bytes taken up by all copies added
+    bytes: u64,
+}
+
+impl DirTotals {
+    /// # Parameters
+    ///
+    /// 1. `roots` -- directories that were scanned, above which nothing is added up
+    pub fn new(roots: Vec<PathBuf>) -> DirTotals {
+        DirTotals {
+            roots,
+            ..DirTotals::default()
+        }
+    }
+
+    /// Add every file of `group` but the one at `survivor`, the copy that would be kept.
+    pub fn add(&mut self, group: &DuplicateGroup, survivor: usize) {
+        let copies = group.paths.iter().enumerate();
+        for (_, path) in copies.filter(|(i, _)| *i != survivor) {
+            self.bytes += group.size;
+            for dir in path.ancestors().skip(1) {
+                if dir.as_os_str().is_empty() {
+                    break;
+                }
+                let total = self.totals.entry(dir.to_path_buf()).or_default();
+                total.copies += 1;
+                total.bytes += group.size;
+                if self.roots.iter().any(|root| root == dir) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Bytes taken up by all copies added, i.e. the total of each root.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// # Returns
+    ///
+    /// Every directory with redundant copies under it, those with the most bytes first, then by
+    /// path.
+    pub fn sorted(&self) -> Vec<(&Path, DirTotal)> {
+        let mut sorted: Vec<(&Path, DirTotal)> = self
+            .totals
+            .iter()
+            .map(|(dir, total)| (dir.as_path(), *total))
+            .collect();
+        sorted.sort_by(|(a, a_total), (b, b_total)| {
+            b_total.bytes.cmp(&a_total.bytes).then_with(|| a.cmp(b))
+        });
+        sorted
+    }
+}
+
+/// Print `totals` to stderr, so that they don't mix with the groups on stdout, with the share of
+/// all reclaimable bytes each directory holds.
+pub fn print_dir_totals(totals: &DirTotals) {
+    eprintln!("reclaimable by directory:");
+    for (dir, total) in totals.sorted() {
+        let share = match totals.bytes() {
+            0 => 0.0,
+            bytes => 100.0 * total.bytes as f64 / bytes as f64,
+        };
+        eprintln!(
+            "  {:>5.1}% {:>14} bytes in {:>6} copies  {:?}",
+            share,
+            total.bytes,
+            total.copies,
+            plain_path(dir)
+        );
+    }
+}
+
+/// How much of the contents of one directory is also in another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Containment {
+    /// Directory whose files are looked for
+    pub dir: PathBuf,
+    /// Directory they were found in
+    pub other: PathBuf,
+    /// Number of files directly in `dir` with a copy directly in `other`
+    pub shared: usize,
+    /// Number of files directly in `dir`
+    pub files: usize,
+}
+
+impl Containment {
+    /// # Returns
+    ///
+    /// Share of the files of `dir` that have a copy in `other`, in percent.
+    pub fn percent(&self) -> f64 {
+        100.0 * self.shared as f64 / self.files.max(1) as f64
+    }
+}
+
+/// Counts, for each pair of directories, of the files of one that have copies in the other, to
+/// find directories that are mostly, if not exactly, copies of each other.
+#[derive(Clone, Debug, Default)]
+pub struct SharedContents {
+    /// Files directly in the first directory with a copy directly in the second
+    shared: HashMap<(PathBuf, PathBuf), usize>,
+}
+
+impl SharedContents {
+    /// Count the files of `group` towards every pair of the directories they are in.
+    pub fn add(&mut self, group: &DuplicateGroup) {
+        let mut files_in: HashMap<&Path, usize> = HashMap::new();
+        for path in group.paths.iter() {
+            if let Some(dir) = path.parent() {
+                *files_in.entry(dir).or_default() += 1;
+            }
+        }
+        for (dir, files) in files_in.iter() {
+            for other in files_in.keys().filter(|other| *other != dir) {
+                let pair = (dir.to_path_buf(), other.to_path_buf());
+                *self.shared.entry(pair).or_default() += files;
+            }
+        }
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `min_percent` -- least share of the files of a directory that has to be in the other
+    /// 1. `files_in` -- number of files directly in a directory
+    ///
+    /// # Returns
+    ///
+    /// Every pair of directories where at least `min_percent` of the files of the first have a
+    /// copy in the second, the most contained first, then by path.
+    pub fn containments<F>(&self, min_percent: f64, files_in: F) -> Vec<Containment>
+    where
+        F: Fn(&Path) -> usize,
+    {
+        let mut files: HashMap<&Path, usize> = HashMap::new();
+        let mut containments: Vec<Containment> = self
+            .shared
+            .iter()
+            .map(|((dir, other), shared)| Containment {
+                dir: dir.clone(),
+                other: other.clone(),
+                shared: *shared,
+                // never less than what was found in it, should it have changed since
+                files: (*files.entry(dir).or_insert_with(|| files_in(dir))).max(*shared),
+            })
+            .filter(|containment| containment.percent() >= min_percent)
+            .collect();
+        containments.sort_by(|a, b| {
+            b.percent()
+                .partial_cmp(&a.percent())
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| (&a.dir, &a.other).cmp(&(&b.dir, &b.other)))
+        });
+        containments
+    }
+}
+
+/// # Returns
+///
+/// Number of regular files directly in `dir` that `filter` admits, which is 0 if it can't be read.
+pub fn files_in(dir: &Path, filter: &Filter) -> usize {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| match entry.metadata() {
+            Ok(meta) => {
+                meta.is_file()
+                    && filter.admits(&entry.path(), meta.len())
+                    && filter.admits_owner(&meta)
+            }
+            Err(_) => false,
+        })
+        .count()
+}
+
+/// Print `containments` to stderr, so that they don't mix with the groups on stdout.
+pub fn print_containments(containments: &[Containment]) {
+    eprintln!("similar directories: {}", containments.len());
+    for containment in containments {
+        eprintln!(
+            "  {:?} is {:.0}% contained in {:?} ({} of {} files)",
+            plain_path(&containment.dir),
+            containment.percent(),
+            plain_path(&containment.other),
+            containment.shared,
+            containment.files
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_count_towards_every_directory_above_them() {
+        let group = |size: u64, paths: &[&str]| DuplicateGroup {
+            hash: vec![0],
+            size,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        };
+        let mut totals = DirTotals::new(vec![PathBuf::from("/data/")]);
+        totals.add(&group(100, &["/data/keep", "/data/pics/exports/a"]), 0);
+        totals.add(&group(10, &["/data/pics/b", "/data/docs/c", "/data/d"]), 2);
+
+        assert_eq!(120, totals.bytes());
+        let total = |copies, bytes| DirTotal { copies, bytes };
+        assert_eq!(
+            vec![
+                (Path::new("/data"), total(3, 120)),
+                (Path::new("/data/pics"), total(2, 110)),
+                (Path::new("/data/pics/exports"), total(1, 100)),
+                (Path::new("/data/docs"), total(1, 10)),
+            ],
+            totals.sorted()
+        );
+    }
+
+    #[test]
+    fn mostly_copied_directories_are_found() {
+        let group = |paths: &[&str]| DuplicateGroup {
+            hash: vec![0],
+            size: 1,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        };
+        let mut shared = SharedContents::default();
+        for name in &["1", "2", "3", "4"] {
+            shared.add(&group(&[&format!("/a/{}", name), &format!("/b/{}", name)]));
+        }
+        shared.add(&group(&["/a/5", "/c/5"]));
+        let files_in = |dir: &Path| match dir.to_str() {
+            Some("/a") => 5,
+            Some("/b") => 4,
+            _ => 10,
+        };
+
+        let containments = shared.containments(50.0, files_in);
+        let pairs: Vec<(&str, &str, usize, usize)> = containments
+            .iter()
+            .map(|c| {
+                let dir = c.dir.to_str().unwrap();
+                (dir, c.other.to_str().unwrap(), c.shared, c.files)
+            })
+            .collect();
+        assert_eq!(vec![("/b", "/a", 4, 4), ("/a", "/b", 4, 5)], pairs);
+        assert_eq!(4, shared.containments(0.0, files_in).len());
+    }
+}
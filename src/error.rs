@@ -0,0 +1,297 @@
+use serde::Deserialize;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Exit status of a scan that found no duplicates.
+pub const EXIT_NO_DUPLICATES: i32 = 0;
+/// Exit status of a scan that found duplicates.
+pub const EXIT_DUPLICATES: i32 = 1;
+/// Exit status of a scan that completed, but had to skip files it couldn't read.
+pub const EXIT_FILE_ERRORS: i32 = 2;
+/// Exit status of `fdup verify` when the tree does not match the manifest.
+pub const EXIT_MISMATCH: i32 = 1;
+/// Exit status when fdup could not do what it was asked at all, e.g. because of bad arguments.
+pub const EXIT_FATAL: i32 = 3;
+/// Exit status of a scan with `--require-full-access` that was denied access to anything.
+pub const EXIT_ACCESS_DENIED: i32 = 4;
+
+/// Why a file could not be sized, hashed or looked up.
+#[derive(Debug)]
+pub enum FdupError {
+    /// Reading `path` is not allowed
+    PermissionDenied { path: PathBuf, source: io::Error },
+    /// Any other I/O error on `path`
+    Io { path: PathBuf, source: io::Error },
+    /// `path` is not a regular file, e.g. a directory or a symlink
+    NotAFile { path: PathBuf },
+    /// `path` is locked, or open for writing, by another process
+    Busy { path: PathBuf },
+    /// `path` changed while it was being read, so that its checksum is already stale
+    Changed { path: PathBuf },
+    /// The checksum cache failed while handling `path`
+    Cache {
+        path: PathBuf,
+        source: rusqlite::Error,
+    },
+}
+
+impl FdupError {
+    /// # Returns
+    ///
+    /// `source`, encountered on `path`, as `PermissionDenied` or `Io` depending on its kind.
+    pub fn io(path: &Path, source: io::Error) -> FdupError {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => FdupError::PermissionDenied { path, source },
+            _ => FdupError::Io { path, source },
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Path of the file the error is about.
+    pub fn path(&self) -> &Path {
+        match self {
+            FdupError::PermissionDenied { path, .. }
+            | FdupError::Io { path, .. }
+            | FdupError::NotAFile { path }
+            | FdupError::Busy { path }
+            | FdupError::Changed { path }
+            | FdupError::Cache { path, .. } => path,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// OS error code behind the error, if there is one.
+    pub fn errno(&self) -> Option<i32> {
+        match self {
+            FdupError::PermissionDenied { source, .. } | FdupError::Io { source, .. } => {
+                source.raw_os_error()
+            }
+            FdupError::NotAFile { .. }
+            | FdupError::Busy { .. }
+            | FdupError::Changed { .. }
+            | FdupError::Cache { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for FdupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FdupError::PermissionDenied { path, source } | FdupError::Io { path, source } => {
+                write!(f, "{:?}: {}", path, source)
+            }
+            FdupError::NotAFile { path } => write!(f, "{:?}: not a file", path),
+            FdupError::Busy { path } => write!(f, "{:?}: in use by another process", path),
+            FdupError::Changed { path } => write!(f, "{:?}: changed while being read", path),
+            FdupError::Cache { path, source } => write!(f, "{:?}: cache: {}", path, source),
+        }
+    }
+}
+
+impl Error for FdupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FdupError::PermissionDenied { source, .. } | FdupError::Io { source, .. } => {
+                Some(source)
+            }
+            FdupError::NotAFile { .. } | FdupError::Busy { .. } | FdupError::Changed { .. } => None,
+            FdupError::Cache { source, .. } => Some(source),
+        }
+    }
+}
+
+/// A per-file error as written by `--errors-json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    /// Path of the file the error is about
+    pub path: String,
+    /// What was being done to the file, e.g. `stat` or `hash`
+    pub operation: String,
+    /// OS error code, if the error came from the OS
+    pub errno: Option<i32>,
+    /// Human readable description of the error
+    pub message: String,
+}
+
+/// Paths a scan was denied access to, and so skipped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessDenied {
+    /// Directories that could not be listed, so that nothing beneath them was scanned
+    pub dirs: Vec<PathBuf>,
+    /// Files that could not be read
+    pub files: Vec<PathBuf>,
+}
+
+impl AccessDenied {
+    /// # Returns
+    ///
+    /// Whether the scan could read everything.
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty() && self.files.is_empty()
+    }
+}
+
+/// Collects the per-file errors of a scan from all threads, instead of or in addition to printing
+/// them as they happen.
+#[derive(Debug, Default)]
+pub struct ErrorLog {
+    /// Print each error to stderr as it is recorded
+    pub echo: bool,
+    records: Mutex<Vec<ErrorRecord>>,
+    denied: Mutex<AccessDenied>,
+}
+
+impl ErrorLog {
+    /// # Returns
+    ///
+    /// Empty log, which also prints each error as it is recorded if `echo`.
+    pub fn new(echo: bool) -> ErrorLog {
+        ErrorLog {
+            echo,
+            ..ErrorLog::default()
+        }
+    }
+
+    /// Record `err`, which happened while doing `operation` to a file, or to a directory if the
+    /// operation is `list`.
+    pub fn record(&self, operation: &str, err: &FdupError) {
+        if self.echo {
+            eprintln!("ERROR with {}", err);
+        }
+        if let FdupError::PermissionDenied { path, .. } = err {
+            let mut denied = self.denied.lock().unwrap_or_else(|err| err.into_inner());
+            match operation == "list" {
+                true => denied.dirs.push(path.clone()),
+                false => denied.files.push(path.clone()),
+            }
+        }
+        let record = ErrorRecord {
+            path: err.path().to_string_lossy().into_owned(),
+            operation: String::from(operation),
+            errno: err.errno(),
+            message: match err {
+                FdupError::PermissionDenied { source, .. } | FdupError::Io { source, .. } => {
+                    source.to_string()
+                }
+                FdupError::NotAFile { .. } => String::from("not a file"),
+                FdupError::Busy { .. } => String::from("in use by another process"),
+                FdupError::Changed { .. } => String::from("changed while being read"),
+                FdupError::Cache { source, .. } => source.to_string(),
+            },
+        };
+        self.records
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(record);
+    }
+
+    /// # Returns
+    ///
+    /// Number of errors recorded so far.
+    pub fn len(&self) -> usize {
+        self.records
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .len()
+    }
+
+    /// # Returns
+    ///
+    /// Whether no errors have been recorded so far.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// # Returns
+    ///
+    /// Every path access was denied to so far, each sorted by path.
+    pub fn denied(&self) -> AccessDenied {
+        let mut denied = self
+            .denied
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        denied.dirs.sort();
+        denied.dirs.dedup();
+        denied.files.sort();
+        denied.files.dedup();
+        denied
+    }
+
+    /// # Returns
+    ///
+    /// Every error recorded, ordered by path and then operation, whichever thread recorded it.
+    pub fn into_records(self) -> Vec<ErrorRecord> {
+        let mut records = self
+            .records
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner());
+        records.sort_by(|a, b| (&a.path, &a.operation).cmp(&(&b.path, &b.operation)));
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_are_classified() {
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let err = FdupError::io(Path::new("a"), denied);
+        assert!(matches!(err, FdupError::PermissionDenied { .. }));
+        assert_eq!(Path::new("a"), err.path());
+
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        let err = FdupError::io(Path::new("b"), missing);
+        assert!(matches!(err, FdupError::Io { .. }));
+        assert!(err.to_string().starts_with("\"b\": "));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn errors_are_logged_in_order() {
+        let log = ErrorLog::new(false);
+        let not_found =
+            |path: &str| FdupError::io(Path::new(path), io::Error::from_raw_os_error(2));
+        log.record("hash", &not_found("b"));
+        log.record("stat", &not_found("a"));
+        assert_eq!(2, log.len());
+
+        let records = log.into_records();
+        assert_eq!("a", records[0].path);
+        assert_eq!("stat", records[0].operation);
+        assert_eq!(Some(2), records[1].errno);
+    }
+
+    #[test]
+    fn denied_access_is_counted_apart() {
+        let log = ErrorLog::new(false);
+        let denied = |path: &str| {
+            let source = io::Error::from(io::ErrorKind::PermissionDenied);
+            FdupError::io(Path::new(path), source)
+        };
+        log.record("hash", &denied("b"));
+        log.record("list", &denied("private"));
+        log.record("stat", &denied("a"));
+        log.record("hash", &denied("a"));
+        log.record(
+            "stat",
+            &FdupError::io(Path::new("c"), io::Error::from_raw_os_error(2)),
+        );
+        assert!(AccessDenied::default().is_empty());
+
+        let denied = log.denied();
+        assert_eq!(vec![PathBuf::from("private")], denied.dirs);
+        assert_eq!(vec![PathBuf::from("a"), PathBuf::from("b")], denied.files);
+        assert_eq!(5, log.len());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::cache::identity;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fs::symlink_metadata;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Physical location of one extent of a file, as `(physical offset, length)` in bytes.
+pub type Extent = (u64, u64);
+
+/// Where a file lies for reading it in order, as `(device, physical offset, inode)`.
+type ReadPosition = (i64, u64, i64);
+
+/// Values accepted by `--read-order`, in the same order as the variants of `ReadOrder`.
+pub const READ_ORDER_VALUES: &[&str] = &["walk", "inode", "physical"];
+
+/// Order in which files of the same size are read to be compared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadOrder {
+    /// In the order they were walked
+    #[default]
+    Walk,
+    /// By device and inode number, which roughly follows where file systems put them on disk
+    Inode,
+    /// By device and where the first extent of each file lies on disk, on Linux, falling back to
+    /// inode numbers elsewhere and for files whose extents can't be determined
+    Physical,
+}
+
+impl FromStr for ReadOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "walk" => Ok(ReadOrder::Walk),
+            "inode" => Ok(ReadOrder::Inode),
+            "physical" => Ok(ReadOrder::Physical),
+            _ => Err(format!("unknown read order {:?}", s)),
+        }
+    }
+}
+
+/// # Returns
+///
+/// `paths` in the order given by `order`, so that the heads of a rotational disk sweep across it
+/// instead of seeking back and forth. Files that can't be examined go last, in their original
+/// order.
+pub fn sort_for_reading(paths: Vec<PathBuf>, order: ReadOrder) -> Vec<PathBuf> {
+    if order == ReadOrder::Walk {
+        return paths;
+    }
+    let mut keyed: Vec<(Option<ReadPosition>, PathBuf)> = paths
+        .into_par_iter()
+        .map(|path| (read_position(&path, order), path))
+        .collect();
+    // stable, and with the files that could not be examined last rather than first like `None`
+    keyed.sort_by(|(a, _), (b, _)| a.is_none().cmp(&b.is_none()).then_with(|| a.cmp(b)));
+    keyed.into_iter().map(|(_, path)| path).collect()
+}
+
+/// # Returns
+///
+/// Position of the file at `path` to sort it by for `order`, where the offset is that of its first
+/// extent, 0 if not asked for and `u64::MAX` if unknown, or `None` if the file can't be examined.
+fn read_position(path: &Path, order: ReadOrder) -> Option<ReadPosition> {
+    let meta = symlink_metadata(path).ok()?;
+    let (device, inode) = identity(&meta, path);
+    let offset = match order {
+        ReadOrder::Walk | ReadOrder::Inode => 0,
+        ReadOrder::Physical => match extents(path) {
+            Ok(Some(layout)) => layout.first().map_or(u64::MAX, |(offset, _)| *offset),
+            _ => u64::MAX,
+        },
+    };
+    Some((device, offset, inode))
+}
+
+/// # Returns
+///
+/// Number of physically distinct copies among `paths`: files whose extents are all shared with
+/// another member (reflinks, snapshots, hard links) only count once. Files whose layout can't be
+/// determined count as distinct.
+pub fn physical_copies(paths: &[PathBuf]) -> usize {
+    let mut seen: HashSet<Vec<Extent>> = HashSet::new();
+    paths
+        .iter()
+        .filter(|path| match extents(path) {
+            Ok(Some(layout)) => seen.insert(layout),
+            _ => true,
+        })
+        .count()
+}
+
+/// # Returns
+///
+/// Physical extents of the file at `path`, or `None` if they are not meaningful for comparison
+/// (e.g. data stored inline in metadata, or not yet allocated).
+#[cfg(target_os = "linux")]
+pub fn extents(path: &Path) -> std::io::Result<Option<Vec<Extent>>> {
+    use std::fs::File;
+    use std::os::unix::io::AsRawFd;
+
+    const FS_IOC_FIEMAP: libc::c_ulong = 0xc020_660b;
+    const FIEMAP_FLAG_SYNC: u32 = 0x1;
+    const FIEMAP_EXTENT_UNKNOWN: u32 = 0x4;
+    const FIEMAP_EXTENT_DATA_INLINE: u32 = 0x200;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct FiemapExtent {
+        fe_logical: u64,
+        fe_physical: u64,
+        fe_length: u64,
+        fe_reserved64: [u64; 2],
+        fe_flags: u32,
+        fe_reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    struct Fiemap {
+        fm_start: u64,
+        fm_length: u64,
+        fm_flags: u32,
+        fm_mapped_extents: u32,
+        fm_extent_count: u32,
+        fm_reserved: u32,
+    }
+
+    let file = File::open(path)?;
+    let fiemap = |extent_count: u32| -> std::io::Result<(Fiemap, Vec<FiemapExtent>)> {
+        // header immediately followed by room for `extent_count` extents, as the kernel expects
+        #[repr(C)]
+        struct Request {
+            header: Fiemap,
+            extents: [FiemapExtent; 0],
+        }
+        let header_len = std::mem::size_of::<Fiemap>();
+        let extent_len = std::mem::size_of::<FiemapExtent>();
+        let words = (header_len + extent_len * extent_count as usize).div_ceil(8);
+        let mut buffer = vec![0u64; words]; // u64s, for alignment
+        let request = buffer.as_mut_ptr() as *mut Request;
+
+        // safe, since `buffer` is large enough and aligned for the header and extents
+        unsafe {
+            (*request).header = Fiemap {
+                fm_length: u64::MAX,
+                fm_flags: FIEMAP_FLAG_SYNC,
+                fm_extent_count: extent_count,
+                ..Fiemap::default()
+            };
+            if libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, request) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let header = std::ptr::read(&(*request).header);
+            let mapped = header.fm_mapped_extents.min(extent_count) as usize;
+            let first = (*request).extents.as_ptr();
+            let extents = (0..mapped).map(|i| *first.add(i)).collect();
+            Ok((header, extents))
+        }
+    };
+
+    // first ask how many extents there are, then fetch them all
+    let (header, _) = fiemap(0)?;
+    let (_, raw_extents) = fiemap(header.fm_mapped_extents)?;
+
+    let unusable = FIEMAP_EXTENT_UNKNOWN | FIEMAP_EXTENT_DATA_INLINE;
+    match raw_extents
+        .iter()
+        .any(|extent| extent.fe_flags & unusable != 0 || extent.fe_physical == 0)
+    {
+        true => Ok(None),
+        false => Ok(Some(
+            raw_extents
+                .iter()
+                .map(|extent| (extent.fe_physical, extent.fe_length))
+                .collect(),
+        )),
+    }
+}
+
+/// # Returns
+///
+/// `None`, since extents can only be queried on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn extents(_path: &Path) -> std::io::Result<Option<Vec<Extent>>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+
+    #[test]
+    fn files_are_read_in_inode_order() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&root).unwrap();
+        let mut paths: Vec<PathBuf> = ["c", "a", "d", "b"]
+            .iter()
+            .map(|name| root.join(name))
+            .collect();
+        for path in paths.iter() {
+            write(path, "same").unwrap();
+        }
+        paths.push(root.join("missing"));
+        paths.insert(0, root.join("gone"));
+
+        let inode =
+            |path: &PathBuf| read_position(path, ReadOrder::Inode).map(|(_, _, inode)| inode);
+        let sorted = sort_for_reading(paths.clone(), ReadOrder::Inode);
+        let inodes: Vec<Option<i64>> = sorted.iter().map(inode).collect();
+        assert!(inodes[..4].windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(vec![root.join("gone"), root.join("missing")], sorted[4..]);
+
+        let sorted = sort_for_reading(paths.clone(), ReadOrder::Physical);
+        assert_eq!(6, sorted.len());
+        assert_eq!(root.join("missing"), sorted[5]);
+        assert_eq!(paths, sort_for_reading(paths.clone(), ReadOrder::Walk));
+
+        remove_dir_all(&root).unwrap();
+    }
+}
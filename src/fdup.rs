@@ -1,17 +1,73 @@
+use crate::archive::ArchiveIndex;
+use crate::archive::ArchiveKind;
+use crate::cache::HashCache;
+use crate::checkpoint::Checkpoint;
+use crate::decompress::decompressed;
+use crate::decompress::decompressed_len;
+use crate::decompress::stored_len;
+use crate::decompress::Compression;
+use crate::decompress::DECOMPRESSED_LIMIT;
+use crate::error::ErrorLog;
+use crate::error::FdupError;
+use crate::extents::sort_for_reading;
+use crate::extents::ReadOrder;
+use crate::filter::Filter;
+use crate::manifest::ManifestLog;
+use crate::media::payload;
+use crate::media::payload_len;
+use crate::normalize::Normalization;
+use crate::output::path_bytes;
+use crate::pdf::content_streams;
+use crate::pdf::is_pdf;
+use crate::spill::Buckets;
+use crate::spill::SpilledSizes;
+use crate::streams::alternate_streams;
+use crate::streams::AlternateStream;
+use crate::streams::Streams;
+use crate::text::looks_like_text;
+use crate::text::Normalizer;
+use crate::text::TextNormalize;
+use crate::throttle::pace;
+use crate::timeout::timed;
+use crate::walk::distinct_roots;
+use crate::walk::walk_logged;
+use crate::walk::Traversal;
+use crate::xattrs::extended_attributes;
+use caseless::default_case_fold_str;
 use colmac::*;
 use rayon::prelude::*;
 use sha2::Digest;
 use sha2::Sha512;
 use std::borrow::Borrow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
 use std::fmt::Debug;
+use std::fs::metadata;
 use std::fs::File;
+use std::fs::FileType;
+use std::fs::OpenOptions;
 use std::hash::Hash;
+use std::io;
+use std::io::ErrorKind;
 use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 use walkdir::DirEntry;
-use walkdir::WalkDir;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Checksum algorithms contents can be compared with; `checksum` implements the only one so far.
+pub const HASH_VALUES: &[&str] = &["sha512"];
 
 /// Calculate the checksum of a file.
 ///
@@ -19,127 +75,1786 @@ use walkdir::WalkDir;
 /// - `path`: path to the file whose contents will be used for to calculate the checksum
 ///
 /// # Returns
-/// sha512 checksum of the contents of the file
-pub fn checksum(path: &Path) -> Result<Vec<u8>, Option<String>> {
-    let mut hasher = Sha512::default();
-    let mut file = match File::open(path) {
-        Ok(f) => f,
-        Err(err) => return Err(Some(format!("{}", err))),
+/// sha512 checksum of the contents of the file, or an error if it can't be read to the end
+pub fn checksum(path: &Path) -> Result<Vec<u8>, FdupError> {
+    timed(path, |path| {
+        let mut hasher = Sha512::default();
+        read_through(path, |bytes| hasher.input(bytes))?;
+        Ok(hasher.result().as_slice().to_vec())
+    })
+}
+
+/// # Returns
+///
+/// sha512 checksum of the contents of the file at `path` followed by the name, size and contents
+/// of each of `streams`, its alternate data streams; the same as `checksum` if there are none.
+pub fn checksum_with_streams(
+    path: &Path,
+    streams: &[AlternateStream],
+) -> Result<Vec<u8>, FdupError> {
+    let streams = streams.to_vec();
+    timed(path, move |path| {
+        let mut hasher = Sha512::default();
+        read_through(path, |bytes| hasher.input(bytes))?;
+        for stream in streams {
+            // terminated and sized, so that neither can run into what follows
+            hasher.input(stream.name.to_string_lossy().as_bytes());
+            hasher.input([0]);
+            hasher.input(stream.size.to_le_bytes());
+            read_through(&stream.path(path), |bytes| hasher.input(bytes))?;
+        }
+        Ok(hasher.result().as_slice().to_vec())
+    })
+}
+
+/// Size bucket that text files are put in, whatever their size, when differences that change it
+/// are to be left out, such as line endings; no file is ever this large.
+pub const TEXT_BUCKET: u64 = i64::MAX as u64;
+
+/// # Returns
+///
+/// sha512 checksum of the contents of the text file at `path` after `normalize` was applied.
+pub fn text_checksum(path: &Path, normalize: TextNormalize) -> Result<Vec<u8>, FdupError> {
+    timed(path, move |path| {
+        let mut hasher = Sha512::default();
+        let mut normalizer = Normalizer::new(normalize);
+        read_through(path, |bytes| {
+            normalizer.feed(bytes, |line| hasher.input(line))
+        })?;
+        normalizer.finish(|line| hasher.input(line));
+        Ok(hasher.result().as_slice().to_vec())
+    })
+}
+
+/// Size bucket that PDFs are put in, whatever their size, when they are to be compared by what
+/// they show; just below `TEXT_BUCKET`, which no file is ever as large as either.
+pub const PDF_BUCKET: u64 = TEXT_BUCKET - 1;
+
+/// # Returns
+///
+/// sha512 checksum of the streams of the PDF at `path` that make up what it shows, leaving out the
+/// metadata and layout that differ between tools that saved the same document. Each stream is
+/// hashed on its own, and their checksums sorted before they are hashed together, so that neither
+/// does the order tools wrote them in matter. A PDF without such streams is hashed as it is.
+pub fn pdf_checksum(path: &Path) -> Result<Vec<u8>, FdupError> {
+    timed(path, |path| {
+        let mut contents = Vec::new();
+        read_through(path, |bytes| contents.extend_from_slice(bytes))?;
+        let mut hashes: Vec<Vec<u8>> = content_streams(&contents)
+            .iter()
+            .map(|stream| Sha512::digest(stream).as_slice().to_vec())
+            .collect();
+        if hashes.is_empty() {
+            hashes.push(contents);
+        }
+        hashes.sort();
+        let mut hasher = Sha512::default();
+        for hash in hashes {
+            hasher.input(hash);
+        }
+        Ok(hasher.result().as_slice().to_vec())
+    })
+}
+
+/// # Returns
+///
+/// sha512 checksum of what the file at `path` decompresses to, the same as that of a copy of it
+/// that isn't compressed; an error if it isn't compressed as one of `Compression`, or decompresses
+/// to more than `DECOMPRESSED_LIMIT` bytes.
+pub fn decompressed_checksum(path: &Path) -> Result<Vec<u8>, FdupError> {
+    timed(path, |path| {
+        let io_err = |err| FdupError::io(path, err);
+        let kind = Compression::of(path)
+            .and_then(|kind| {
+                kind.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "not compressed"))
+            })
+            .map_err(io_err)?;
+        let mut reader = decompressed(path, kind, DECOMPRESSED_LIMIT).map_err(io_err)?;
+        let mut hasher = Sha512::default();
+        let mut buffer = vec![0; 131072];
+        loop {
+            match reader.read(&mut buffer).map_err(io_err)? {
+                0 => break,
+                n => hasher.input(&buffer[..n]),
+            }
+        }
+        Ok(hasher.result().as_slice().to_vec())
+    })
+}
+
+/// Size buckets that media files are put in when their metadata is to be left out, as this plus
+/// the size of the rest, so that they are neither mixed up with other files nor with text files.
+pub const MEDIA_BUCKETS: u64 = 1 << 62;
+
+/// # Returns
+///
+/// sha512 checksum of the picture or sound of the media file at `path`, i.e. of its `payload`,
+/// without the metadata embedded in it.
+pub fn payload_checksum(path: &Path) -> Result<Vec<u8>, FdupError> {
+    timed(path, |path| {
+        let io_err = |err| FdupError::io(path, err);
+        let spans = match payload(path).map_err(io_err)? {
+            Some(spans) => spans,
+            None => {
+                let message = "no longer a media file";
+                return Err(io_err(io::Error::new(ErrorKind::InvalidData, message)));
+            }
+        };
+        let mut file = open_regular(path)?;
+        let mut hasher = Sha512::default();
+        let mut buffer = vec![0; 131072];
+        for (offset, len) in spans {
+            file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+            let mut span = (&mut file).take(len);
+            loop {
+                match span.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        pace(read);
+                        hasher.input(&buffer[..read]);
+                    }
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(io_err(err)),
+                }
+            }
+        }
+        Ok(hasher.result().as_slice().to_vec())
+    })
+}
+
+/// Read the file at `path` from start to end, handing each chunk read to `feed`.
+fn read_through<F>(path: &Path, mut feed: F) -> Result<(), FdupError>
+where
+    F: FnMut(&[u8]),
+{
+    let mut file = open_regular(path)?;
+    let mut buffer = [0; 131072]; // read this much at a time
+
+    // feed the hasher one buffer's worth at a time
+    loop {
+        match file.read(&mut buffer) {
+            Ok(size) if size == 0 => break, // done reading
+            Ok(size) => {
+                pace(size);
+                feed(&buffer[..size]) // feed the hasher
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue, // just try again
+            // e.g. a bad sector, or the file was truncated; give up on this file only
+            Err(err) => return Err(FdupError::io(path, err)),
+        };
+    }
+    Ok(())
+}
+
+/// Values accepted by `--prehash`, in the same order as the variants of `Prehash`.
+pub const PREHASH_VALUES: &[&str] = &["none", "xxh3", "crc32"];
+
+/// Fast, non-cryptographic checksum that files of the same size are split by before they are
+/// compared by `checksum`, so that files that differ are told apart more cheaply.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Prehash {
+    /// Go straight to `checksum`
+    #[default]
+    None,
+    /// 64-bit XXH3
+    Xxh3,
+    /// CRC-32
+    Crc32,
+}
+
+impl FromStr for Prehash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Prehash::None),
+            "xxh3" => Ok(Prehash::Xxh3),
+            "crc32" => Ok(Prehash::Crc32),
+            _ => Err(format!("unknown pre-hash {:?}", s)),
+        }
+    }
+}
+
+/// Calculate the pre-hash of a file.
+///
+/// # Parameters
+/// - `path`: path to the file whose contents will be used to calculate the pre-hash
+/// - `kind`: which pre-hash to calculate
+///
+/// # Returns
+/// pre-hash of the contents of the file, empty for `Prehash::None`, or an error if it can't be
+/// read to the end
+pub fn prehash(path: &Path, kind: Prehash) -> Result<Vec<u8>, FdupError> {
+    match kind {
+        Prehash::None => Ok(Vec::new()),
+        Prehash::Xxh3 => timed(path, |path| {
+            let mut hasher = Xxh3::new();
+            read_through(path, |bytes| hasher.update(bytes))?;
+            Ok(hasher.digest().to_be_bytes().to_vec())
+        }),
+        Prehash::Crc32 => timed(path, |path| {
+            let mut hasher = crc32fast::Hasher::new();
+            read_through(path, |bytes| hasher.update(bytes))?;
+            Ok(hasher.finalize().to_be_bytes().to_vec())
+        }),
+    }
+}
+
+/// Calculate a cheap checksum from only the beginning and end of a file.
+///
+/// # Parameters
+/// - `path`: path to the file whose head and tail will be used to calculate the checksum
+///
+/// # Returns
+/// sha512 checksum of at most `PARTIAL_LEN` bytes from each end of the file
+pub fn partial_checksum(path: &Path) -> Result<Vec<u8>, FdupError> {
+    timed(path, |path| {
+        let mut hasher = Sha512::default();
+        let io_err = |err| FdupError::io(path, err);
+        let mut file = open_regular(path)?;
+        let len = file.metadata().map_err(io_err)?.len();
+        let mut buffer = [0; PARTIAL_LEN];
+
+        // head, then tail if the file is long enough to have one that doesn't overlap the head
+        let mut offsets = vec![0];
+        if len > PARTIAL_LEN as u64 {
+            offsets.push(
+                len.saturating_sub(PARTIAL_LEN as u64)
+                    .max(PARTIAL_LEN as u64),
+            );
+        }
+        for offset in offsets {
+            file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+            let size = file.read(&mut buffer).map_err(io_err)?;
+            pace(size);
+            hasher.input(&buffer[..size]);
+        }
+
+        Ok(hasher.result().as_slice().to_vec())
+    })
+}
+
+/// Number of bytes read from each end of a file by `partial_checksum`.
+pub const PARTIAL_LEN: usize = 4096;
+
+/// Number of bytes read at each place a file is sampled by `sampled_hash`, and from the start of
+/// it by `prefix_hash`.
+pub const SAMPLE_LEN: u64 = 4096;
+
+/// Number of places a file is sampled at by `sampled_hash`, including both ends.
+pub const SAMPLES: u64 = 8;
+
+/// Files up to this size are always compared by `checksum` right away, since a cheaper pass would
+/// hardly read less of them.
+pub const DIRECT_MAX: u64 = 64 * 1024;
+
+/// Files at least this large, if there are only a few of some size, are sampled throughout rather
+/// than only at the start, since big files of the same size are most likely copies, and if they
+/// aren't, e.g. disk images or videos, they tend to share their headers.
+pub const HUGE_MIN: u64 = 256 * 1024 * 1024;
+
+/// Most files of one size that count as a few for `HUGE_MIN`.
+pub const FEW_MAX: usize = 3;
+
+/// How the files of one size are compared before their `checksum`s are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Plan {
+    /// Compare checksums right away
+    Direct,
+    /// Split the files by `prefix_hash` first
+    Prefix,
+    /// Split the files by `sampled_hash` first
+    Sampled,
+}
+
+impl Plan {
+    /// # Parameters
+    ///
+    /// 1. `count` -- number of files of the same size
+    /// 1. `size` -- size in bytes of each file
+    /// 1. `cached` -- whether checksums may come from a cache without reading the files
+    ///
+    /// # Returns
+    ///
+    /// Cheapest plan to compare the files with, going by how likely a cheap pass is to tell them
+    /// apart.
+    pub fn choose(count: usize, size: u64, cached: bool) -> Plan {
+        if cached || size <= DIRECT_MAX {
+            Plan::Direct
+        } else if size >= HUGE_MIN && count <= FEW_MAX {
+            Plan::Sampled
+        } else {
+            Plan::Prefix
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Plan::Direct => write!(f, "full hash"),
+            Plan::Prefix => write!(f, "prefix hash, then full hash"),
+            Plan::Sampled => write!(f, "sampled hash, then full hash"),
+        }
+    }
+}
+
+/// # Returns
+///
+/// xxh3 of the first `SAMPLE_LEN` bytes of the file at `path`.
+pub fn prefix_hash(path: &Path) -> Result<Vec<u8>, FdupError> {
+    hash_at(path, &[0])
+}
+
+/// # Returns
+///
+/// xxh3 of `SAMPLE_LEN` bytes from each of `SAMPLES` places spread evenly over the file at `path`,
+/// which is `size` bytes long, from its start to its end.
+pub fn sampled_hash(path: &Path, size: u64) -> Result<Vec<u8>, FdupError> {
+    let last = size.saturating_sub(SAMPLE_LEN);
+    let offsets: Vec<u64> = (0..SAMPLES).map(|i| last * i / (SAMPLES - 1)).collect();
+    hash_at(path, &offsets)
+}
+
+/// # Returns
+///
+/// xxh3 of at most `SAMPLE_LEN` bytes from each of `offsets` into the file at `path`.
+fn hash_at(path: &Path, offsets: &[u64]) -> Result<Vec<u8>, FdupError> {
+    let offsets = offsets.to_vec();
+    timed(path, move |path| {
+        let io_err = |err| FdupError::io(path, err);
+        let mut file = open_regular(path)?;
+        let mut hasher = Xxh3::new();
+        let mut buffer = Vec::with_capacity(SAMPLE_LEN as usize);
+        for offset in offsets {
+            file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+            buffer.clear();
+            (&mut file)
+                .take(SAMPLE_LEN)
+                .read_to_end(&mut buffer)
+                .map_err(io_err)?;
+            pace(buffer.len());
+            hasher.update(&buffer);
+        }
+        Ok(hasher.digest().to_be_bytes().to_vec())
+    })
+}
+
+/// Number of bytes of each file compared at a time by the `Stage::Bytes` stage.
+pub const BYTES_CHUNK: u64 = 64 * 1024;
+
+/// # Returns
+///
+/// At most `BYTES_CHUNK` bytes of the file at `path`, from `offset` on.
+pub fn chunk_at(path: &Path, offset: u64) -> Result<Vec<u8>, FdupError> {
+    timed(path, move |path| {
+        let io_err = |err| FdupError::io(path, err);
+        let mut file = open_regular(path)?;
+        file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+        let mut buffer = Vec::with_capacity(BYTES_CHUNK as usize);
+        (&mut file)
+            .take(BYTES_CHUNK)
+            .read_to_end(&mut buffer)
+            .map_err(io_err)?;
+        pace(buffer.len());
+        Ok(buffer)
+    })
+}
+
+/// Values accepted in `--stages`, in the same order as the variants of `Stage`.
+pub const STAGE_VALUES: &[&str] = &[
+    "size",
+    "prefix-hash",
+    "sampled-hash",
+    "xxh3",
+    "crc32",
+    "hash",
+    "bytes",
+];
+
+/// One pass over the files of a group candidates go through, which splits it into smaller ones
+/// by some property of the files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Size, which is known from the walk
+    Size,
+    /// `prefix_hash`
+    PrefixHash,
+    /// `sampled_hash`
+    SampledHash,
+    /// `prehash` with `Prehash::Xxh3`
+    Xxh3,
+    /// `prehash` with `Prehash::Crc32`
+    Crc32,
+    /// `checksum`, which is the only stage that gives groups their hash
+    Hash,
+    /// The contents themselves, compared `BYTES_CHUNK` at a time, for those who trust no checksum
+    Bytes,
+}
+
+impl Stage {
+    /// # Returns
+    ///
+    /// Whether files grouped by this stage are known to be identical.
+    pub fn is_exact(self) -> bool {
+        matches!(self, Stage::Hash | Stage::Bytes)
+    }
+}
+
+impl FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(Stage::Size),
+            "prefix-hash" => Ok(Stage::PrefixHash),
+            "sampled-hash" => Ok(Stage::SampledHash),
+            "xxh3" => Ok(Stage::Xxh3),
+            "crc32" => Ok(Stage::Crc32),
+            "hash" => Ok(Stage::Hash),
+            "bytes" => Ok(Stage::Bytes),
+            _ => Err(format!("unknown stage {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Size => write!(f, "size"),
+            Stage::PrefixHash => write!(f, "prefix hash"),
+            Stage::SampledHash => write!(f, "sampled hash"),
+            Stage::Xxh3 => write!(f, "xxh3"),
+            Stage::Crc32 => write!(f, "crc32"),
+            Stage::Hash => write!(f, "full hash"),
+            Stage::Bytes => write!(f, "byte comparison"),
+        }
+    }
+}
+
+/// Chain of stages that replaces the `Plan` chosen for each size, so that precision can be
+/// traded for speed at will, e.g. `size,prefix-hash,hash,bytes`. Files are always grouped by
+/// size first, since sizes come with the walk for free and every other stage needs one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stages(Vec<Stage>);
+
+impl Stages {
+    /// # Returns
+    ///
+    /// Whether files that make it through every stage are known to be identical.
+    pub fn is_exact(&self) -> bool {
+        self.0.iter().any(|stage| stage.is_exact())
+    }
+
+    /// # Returns
+    ///
+    /// Stages in the order they are gone through.
+    pub fn iter(&self) -> impl Iterator<Item = Stage> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+impl FromStr for Stages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let stages: Vec<Stage> = s.split(',').map(str::parse).collect::<Result<_, _>>()?;
+        if stages.first() != Some(&Stage::Size) {
+            return Err(format!("expected stages starting with size, got {:?}", s));
+        }
+        match stages
+            .iter()
+            .enumerate()
+            .find(|(i, stage)| stages[..*i].contains(stage))
+        {
+            Some((_, stage)) => Err(format!(
+                "stage {:?} given more than once",
+                stage.to_string()
+            )),
+            None => Ok(Stages(stages)),
+        }
+    }
+}
+
+impl fmt::Display for Stages {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<String> = self.0.iter().map(Stage::to_string).collect();
+        write!(f, "{}", names.join(", then "))
+    }
+}
+
+/// Kinds of files that are neither regular files, directories nor symlinks. fdup never reads them,
+/// since reading a FIFO or a device can block forever or never come to an end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecialKind {
+    Fifo,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+impl SpecialKind {
+    /// Every kind, in the order of `ScanStats::special_files`
+    pub const ALL: [SpecialKind; 4] = [
+        SpecialKind::Fifo,
+        SpecialKind::Socket,
+        SpecialKind::BlockDevice,
+        SpecialKind::CharDevice,
+    ];
+
+    /// # Returns
+    ///
+    /// Kind of special file `file_type` is, if it is one.
+    #[cfg(unix)]
+    pub fn of(file_type: &FileType) -> Option<SpecialKind> {
+        use std::os::unix::fs::FileTypeExt;
+        match file_type {
+            kind if kind.is_fifo() => Some(SpecialKind::Fifo),
+            kind if kind.is_socket() => Some(SpecialKind::Socket),
+            kind if kind.is_block_device() => Some(SpecialKind::BlockDevice),
+            kind if kind.is_char_device() => Some(SpecialKind::CharDevice),
+            _ => None,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// `None`, since std can only tell special files apart on unix.
+    #[cfg(not(unix))]
+    pub fn of(_file_type: &FileType) -> Option<SpecialKind> {
+        None
+    }
+}
+
+impl fmt::Display for SpecialKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpecialKind::Fifo => write!(f, "FIFOs"),
+            SpecialKind::Socket => write!(f, "sockets"),
+            SpecialKind::BlockDevice => write!(f, "block devices"),
+            SpecialKind::CharDevice => write!(f, "character devices"),
+        }
+    }
+}
+
+/// Open the file at `path` for reading, unless it is a special file. It is looked at before it is
+/// opened, and opened without blocking, so that a FIFO that took its place in the meantime is
+/// found out rather than waited on forever.
+fn open_regular(path: &Path) -> Result<File, FdupError> {
+    let io_err = |err| FdupError::io(path, err);
+    let not_a_file = || FdupError::NotAFile {
+        path: path.to_path_buf(),
+    };
+    if SpecialKind::of(&metadata(path).map_err(io_err)?.file_type()).is_some() {
+        return Err(not_a_file());
+    }
+    let file = open_nonblocking(path).map_err(io_err)?;
+    match SpecialKind::of(&file.metadata().map_err(io_err)?.file_type()) {
+        Some(_) => Err(not_a_file()),
+        None => Ok(file),
+    }
+}
+
+#[cfg(unix)]
+fn open_nonblocking(path: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    // only affects opening FIFOs and devices; reads of regular files never block anyway
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open_nonblocking(path: &Path) -> std::io::Result<File> {
+    File::open(path)
+}
+
+/// # Returns
+///
+/// `Err(FdupError::Busy)` if another process has the file at `path` locked, with `flock` or
+/// `fcntl`, or, on Linux, open for writing, which is found out by trying to take a read lease on
+/// it; the lease is given up again right away.
+#[cfg(unix)]
+pub fn check_idle(path: &Path) -> Result<(), FdupError> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = open_regular(path)?;
+    let fd = file.as_raw_fd();
+    let last_errno = || std::io::Error::last_os_error().raw_os_error();
+    // safe, since `fd` stays open until `file` is dropped, and `lock` is a plain struct
+    let busy = unsafe {
+        let flocked = match libc::flock(fd, libc::LOCK_SH | libc::LOCK_NB) {
+            0 => libc::flock(fd, libc::LOCK_UN) != 0,
+            _ => last_errno() == Some(libc::EWOULDBLOCK),
+        };
+        let mut lock: libc::flock = std::mem::zeroed();
+        lock.l_type = libc::F_RDLCK as _;
+        lock.l_whence = libc::SEEK_SET as _;
+        let fcntl_locked = libc::fcntl(fd, libc::F_GETLK, &mut lock) == 0
+            && i32::from(lock.l_type) != libc::F_UNLCK;
+        flocked || fcntl_locked || is_open_for_writing(fd)
+    };
+    match busy {
+        true => Err(FdupError::Busy {
+            path: path.to_path_buf(),
+        }),
+        false => Ok(()),
+    }
+}
+
+/// # Returns
+///
+/// Whether any process has the file open as `fd` open for writing, as far as can be told: a read
+/// lease can't be taken on such a file, but can't be taken by anyone but its owner either.
+///
+/// # Safety
+///
+/// `fd` must be open, read-only.
+#[cfg(target_os = "linux")]
+unsafe fn is_open_for_writing(fd: libc::c_int) -> bool {
+    match libc::fcntl(fd, libc::F_SETLEASE, libc::F_RDLCK) {
+        0 => {
+            libc::fcntl(fd, libc::F_SETLEASE, libc::F_UNLCK);
+            false
+        }
+        _ => std::io::Error::last_os_error().raw_os_error() == Some(libc::EAGAIN),
+    }
+}
+
+/// # Returns
+///
+/// `false`, since only Linux tells whether a file is open for writing, through leases.
+///
+/// # Safety
+///
+/// Always safe, but unsafe like the Linux version.
+#[cfg(all(unix, not(target_os = "linux")))]
+unsafe fn is_open_for_writing(_fd: libc::c_int) -> bool {
+    false
+}
+
+/// # Returns
+///
+/// `Err(FdupError::Busy)` if another process has the file at `path` open for writing, or locked,
+/// which is found out by opening it without letting anyone else write to it.
+#[cfg(windows)]
+pub fn check_idle(path: &Path) -> Result<(), FdupError> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_SHARE_READ: u32 = 0x1;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+
+    match OpenOptions::new()
+        .read(true)
+        .share_mode(FILE_SHARE_READ)
+        .open(path)
+    {
+        Ok(_) => Ok(()),
+        Err(err) => match err.raw_os_error() {
+            Some(ERROR_SHARING_VIOLATION) | Some(ERROR_LOCK_VIOLATION) => Err(FdupError::Busy {
+                path: path.to_path_buf(),
+            }),
+            _ => Err(FdupError::io(path, err)),
+        },
+    }
+}
+
+/// # Returns
+///
+/// `Ok(())`, since there is no telling whether files are in use on this OS.
+#[cfg(not(any(unix, windows)))]
+pub fn check_idle(_path: &Path) -> Result<(), FdupError> {
+    Ok(())
+}
+
+/// Size and modification time of a file, which change along with its contents as far as can be
+/// told without reading them.
+type Stamp = (u64, Option<SystemTime>);
+
+/// # Returns
+///
+/// `Stamp` of the file at `path`, or `None` if its metadata can't be read.
+fn stamp(path: &Path) -> Option<Stamp> {
+    metadata(path)
+        .ok()
+        .map(|meta| (meta.len(), meta.modified().ok()))
+}
+
+/// # Returns
+///
+/// `hash`, the checksum of the file at `path`, unless the file no longer has the stamp it had
+/// `before` it was hashed, in which case `Err(FdupError::Changed)`, since the checksum is of
+/// contents that are already gone, or were never all there at once.
+fn unchanged_since(
+    path: &Path,
+    before: Option<Stamp>,
+    hash: Vec<u8>,
+) -> Result<Vec<u8>, FdupError> {
+    match before.is_some() && stamp(path) == before {
+        true => Ok(hash),
+        false => Err(FdupError::Changed {
+            path: path.to_path_buf(),
+        }),
+    }
+}
+
+/// # Returns
+///
+/// Size of the file in bytes if it is a regular file, `Err(FdupError::NotAFile)` if it is not a
+/// regular file, another `Err` if its metadata can't be read.
+pub fn filesize(entry: &DirEntry) -> Result<u64, FdupError> {
+    match entry.metadata() {
+        Ok(meta) if meta.is_file() => Ok(meta.len()),
+        Ok(_) => Err(FdupError::NotAFile {
+            path: entry.path().to_path_buf(),
+        }),
+        Err(err) => Err(FdupError::io(entry.path(), err.into())),
+    }
+}
+/// # Parameters
+///
+/// 1. `key_f` -- some function that maps a borrowed form of `T` into `Result<Option<K>, FdupError>`,
+///    where items mapped to `Ok(None)` are skipped, and those mapped to `Err(_)` are skipped after
+///    printing the error
+/// 1. `threshold` -- only `Vec`'s with length `> theshold` are included in the returned iterator
+/// 1. `items` -- the items to uses
+///
+/// ## Types
+///
+/// 1. `B` -- some type such that `T` implements `Borrow<B>`
+/// 1. `F` -- mapping from `&B` to some wrapper type around `K`
+/// 1. `K` -- key values uesd to group `items`
+/// 1. `T` -- items being grouped
+///
+/// # Returns
+///
+/// `Iterator` of `Vec`'s, `I = [v1, v2, ...]`, such that two elements `t1` and `t2` are in the same
+/// set `vi` if and only if `key_f(&t1) == key_f(&t2)`. Put another way, each set `v` is
+/// characterized by a unique output value `o` of `key_f`, and `key_f` maps each element in `v` to
+/// the unique `o` of that set.
+pub fn disjoint_by_filter_map<B, F, K, T>(
+    key_f: &F,
+    threshold: usize,
+    items: &Vec<T>,
+) -> impl Iterator<Item = Vec<T>>
+where
+    B: ?Sized,
+    F: Fn(&B) -> Result<Option<K>, FdupError> + Send + Sync,
+    K: Clone + Eq + Hash + Send + Sync,
+    T: Borrow<B> + Clone + Debug + Send + Sync,
+{
+    keyed_disjoint_by_filter_map(key_f, threshold, items).map(|(_, v)| v)
+}
+
+/// Same as `disjoint_by_filter_map`, except that each `Vec` comes paired with the output value of
+/// `key_f` that characterizes it.
+pub fn keyed_disjoint_by_filter_map<B, F, K, T>(
+    key_f: &F,
+    threshold: usize,
+    items: &Vec<T>,
+) -> impl Iterator<Item = (K, Vec<T>)>
+where
+    B: ?Sized,
+    F: Fn(&B) -> Result<Option<K>, FdupError> + Send + Sync,
+    K: Clone + Eq + Hash + Send + Sync,
+    T: Borrow<B> + Clone + Debug + Send + Sync,
+{
+    // map input items to key values in parallel
+    // collect, because hashmap construction needss to be sequential
+    //
+    // the hashmap holds `O(n)` anyway, so holding things in this intermediate set doesn't
+    // really change things too much
+    let k_to_t_mapping: Vec<(K, T)> = items
+        .par_iter()
+        .filter_map(|item| match key_f(item.borrow()) {
+            Ok(Some(key_res)) => Some((key_res, item.clone())), // good to go
+            Ok(None) => None,                                   // skip without a word
+            Err(err) => {
+                eprintln!("ERROR with {}", err); // err, report error
+                None
+            }
+        })
+        .collect();
+    let mapping_len = k_to_t_mapping.len(); // for preallocation purposes
+
+    // use hashmap to group things by the key values
+    k_to_t_mapping
+        .into_iter()
+        .fold(
+            HashMap::<K, Vec<T>>::with_capacity(mapping_len),
+            |mut acc, (key, item)| {
+                // if acc[key] is None, acc[key] = vec![item], else acc[key].push(item)
+                match acc.get_mut(&key) {
+                    Some(vec) => vec.push(item),
+                    None => {
+                        let val = vec![item];
+                        acc.insert(key, val);
+                    }
+                };
+                acc
+            },
+        )
+        .into_iter()
+        .filter(move |(_, v)| v.len() > threshold)
+}
+
+/// One of `count` disjoint slices of the size-bucket keyspace, numbered from 1, so that several
+/// hosts or processes can split a scan of the same tree between them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl Shard {
+    /// # Returns
+    ///
+    /// Whether files of `size` bytes are this shard's responsibility. Every host computes the
+    /// same answer, and each size belongs to exactly one of the `count` shards.
+    pub fn contains(&self, size: u64) -> bool {
+        // splitmix64 finalizer, so that shards get a fair mix of small and large sizes
+        let mut x = size;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^= x >> 31;
+        x % self.count == self.index - 1
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a shard like 2/5, got {:?}", s);
+        let mut parts = s.splitn(2, '/');
+        let index = parts
+            .next()
+            .and_then(|i| i.parse().ok())
+            .ok_or_else(invalid)?;
+        let count = parts
+            .next()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(invalid)?;
+        match 1 <= index && index <= count {
+            true => Ok(Shard { index, count }),
+            false => Err(invalid()),
+        }
+    }
+}
+
+/// Files with identical contents, or only the same name, and size, as `GroupBy` says.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// sha512 checksum of the contents, empty if they were not compared
+    pub hash: Vec<u8>,
+    /// Size in bytes of each file, 0 if the files were grouped by name alone and may differ, or
+    /// the smallest of theirs if text files were compared after normalizing them, or media files
+    /// without their metadata
+    pub size: u64,
+    /// Paths to the files
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateGroup {
+    /// # Returns
+    ///
+    /// Whether the group has copies both under the canonical `dir` and outside of it.
+    pub fn spans(&self, dir: &Path) -> bool {
+        let is_inside = |path: &&PathBuf| {
+            path.starts_with(dir)
+                || matches!(path.canonicalize(), Ok(canonical) if canonical.starts_with(dir))
+        };
+        let inside = self.paths.iter().filter(is_inside).count();
+        0 < inside && inside < self.paths.len()
+    }
+
+    /// # Returns
+    ///
+    /// Whether all of the files are in the same directory, going by their paths as given.
+    pub fn is_in_one_dir(&self) -> bool {
+        let mut dirs = self.paths.iter().map(|path| path.parent());
+        let first = dirs.next();
+        dirs.all(|dir| Some(dir) == first)
+    }
+
+    /// # Returns
+    ///
+    /// Bytes taken up by all but one of the files, i.e. what getting rid of the copies reclaims.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64).saturating_sub(1)
+    }
+}
+
+/// Values accepted by `--group-by`, in the same order as the variants of `GroupBy`.
+pub const GROUP_BY_VALUES: &[&str] = &["content", "name", "name-size"];
+
+/// What files have to have in common to be reported together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Identical contents, which is the point of fdup
+    #[default]
+    Content,
+    /// The same name, in different directories, without reading any of them, to spot scattered
+    /// copies cheaply
+    Name,
+    /// The same name and exact size, again without reading any of them, which is quick enough
+    /// for network shares while leaving out most files that merely share a name
+    NameSize,
+}
+
+impl FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "content" => Ok(GroupBy::Content),
+            "name" => Ok(GroupBy::Name),
+            "name-size" => Ok(GroupBy::NameSize),
+            _ => Err(format!("unknown grouping {:?}", s)),
+        }
+    }
+}
+
+/// Values accepted by `--sort-by`, in the same order as the variants of `SortBy`.
+pub const SORT_BY_VALUES: &[&str] = &["size", "count", "path"];
+
+/// Order in which to output duplicate groups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortBy {
+    /// Most wasted bytes first
+    Size,
+    /// Most files first
+    Count,
+    /// Lexicographically by the smallest path of each group
+    Path,
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size" => Ok(SortBy::Size),
+            "count" => Ok(SortBy::Count),
+            "path" => Ok(SortBy::Path),
+            _ => Err(format!("unknown sort order {:?}", s)),
+        }
+    }
+}
+
+/// Sort `groups` by `by`. The sort is stable, so ties stay in the order `duplicate_groups`
+/// produced them in.
+pub fn sort_groups(groups: &mut [DuplicateGroup], by: SortBy) {
+    match by {
+        SortBy::Size => groups.sort_by_key(|group| Reverse(group.wasted())),
+        SortBy::Count => groups.sort_by_key(|group| Reverse(group.paths.len())),
+        SortBy::Path => groups.sort_by(|a, b| a.paths.iter().min().cmp(&b.paths.iter().min())),
+    }
+}
+
+/// # Returns
+///
+/// The `n` groups of `groups` that waste the most bytes, most first. Of groups that waste the same
+/// amount, the earlier ones win. Only `n` groups are held in memory at a time.
+pub fn top_groups<I>(groups: I, n: usize) -> Vec<DuplicateGroup>
+where
+    I: IntoIterator<Item = DuplicateGroup>,
+{
+    // min-heap of the best groups so far, so that the worst of them is the one to drop
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    for (seq, group) in groups.into_iter().enumerate() {
+        heap.push(Reverse(Ranked { seq, group }));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse(ranked)| ranked.group)
+        .collect()
+}
+
+/// Group ranked by wasted bytes, and then by how early it came.
+struct Ranked {
+    seq: usize,
+    group: DuplicateGroup,
+}
+
+impl Ranked {
+    fn key(&self) -> (u64, Reverse<usize>) {
+        (self.group.wasted(), Reverse(self.seq))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Running totals of the work done by a scan, which can be read while it is still going.
+#[derive(Debug, Default)]
+pub struct ScanStats {
+    /// Regular files considered
+    pub files: AtomicU64,
+    /// Bytes of the files whose checksums had to be compared, whether they were hashed or found
+    /// in the cache
+    pub bytes_hashed: AtomicU64,
+    /// Files with alternate data streams, if they were looked for
+    pub files_with_streams: AtomicU64,
+    /// Alternate data streams of those files
+    pub streams: AtomicU64,
+    /// Bytes in those streams
+    pub stream_bytes: AtomicU64,
+    /// Special files skipped, of each kind in `SpecialKind::ALL`
+    pub special_files: [AtomicU64; 4],
+    /// Files skipped because they were in use by another process or changed while being read
+    pub unstable_files: AtomicU64,
+    /// Compressed files compared as they are with `decompress`, since they couldn't be
+    /// decompressed, or would decompress to more than `DECOMPRESSED_LIMIT` bytes
+    pub undecompressed_files: AtomicU64,
+}
+
+/// How to search for duplicates, everything but where. Start from `ScanConfig::new` and override
+/// fields as needed.
+#[derive(Clone, Copy)]
+pub struct ScanConfig<'a> {
+    /// Sort each group lexicographically
+    pub sort_vec: bool,
+    /// If given, checksums are read from and written to it instead of always hashing
+    pub cache: Option<&'a HashCache>,
+    /// If given, only files whose size falls into this shard are hashed and reported
+    pub shard: Option<Shard>,
+    /// Fewest files with identical contents that make a group, at least 2
+    pub min_copies: usize,
+    /// Conditions files have to meet to be considered at all
+    pub filter: &'a Filter,
+    /// Order in which to walk the tree
+    pub traversal: Traversal,
+    /// If given, totals of the work done are added to it
+    pub stats: Option<&'a ScanStats>,
+    /// If given, per-file errors are recorded in it instead of being printed
+    pub errors: Option<&'a ErrorLog>,
+    /// If given, the checksum of every file hashed is recorded in it
+    pub manifest: Option<&'a ManifestLog>,
+    /// If given, checksums saved in it by an interrupted scan are reused, and new ones are saved
+    pub checkpoint: Option<&'a Checkpoint>,
+    /// If given, the zip and tar archives found are listed in it, and the files inside them
+    /// compared with each other and with those on disk as if they were on disk too
+    pub archives: Option<&'a ArchiveIndex>,
+    /// Split files of the same size by this before comparing their checksums
+    pub prehash: Prehash,
+    /// If given, files of the same size go through these instead of the `Plan` chosen for them,
+    /// and `prehash`
+    pub stages: Option<&'a Stages>,
+    /// Order in which to read files of the same size
+    pub read_order: ReadOrder,
+    /// Print to stderr the `Plan` chosen for each size
+    pub verbose: bool,
+    /// Keep the sizes of the files walked in a temporary database on disk instead of in memory,
+    /// trading speed for scanning trees with more files than fit in memory
+    pub low_memory: bool,
+    /// What to do with alternate data streams
+    pub streams: Streams,
+    /// Skip files that are locked, or open for writing, by another process
+    pub skip_busy: bool,
+    /// What files have to have in common to be grouped
+    pub group_by: GroupBy,
+    /// Group files by name regardless of case, as Windows and macOS compare names
+    pub ignore_case: bool,
+    /// Unicode normalization form names are brought to before files are grouped by them
+    pub normalize: Normalization,
+    /// Differences to leave out when comparing text files, which are then all compared with one
+    /// another, whatever their size
+    pub text_normalize: TextNormalize,
+    /// Compare JPEG, PNG, MP3 and FLAC files without the metadata embedded in them
+    pub strip_metadata: bool,
+    /// Compare gzip, zstd and xz files by what they decompress to, in the bucket of its size
+    pub decompress: bool,
+    /// Compare PDFs by their content streams, which are then all compared with one another,
+    /// whatever their size
+    pub pdf_content: bool,
+    /// Also split groups by the modification times of their files, so that only copies that
+    /// kept them, such as mirrored backups, are grouped
+    pub match_mtime: bool,
+    /// Also split groups by the extended attributes users set on their files, such as tags
+    pub match_xattrs: bool,
+    /// With `match_xattrs`, split groups by the POSIX ACLs of their files too
+    pub match_acls: bool,
+}
+
+impl<'a> ScanConfig<'a> {
+    /// # Returns
+    ///
+    /// Plain search for every pair or more of identical files admitted by `filter`, hashing each
+    /// of them.
+    pub fn new(filter: &'a Filter) -> ScanConfig<'a> {
+        ScanConfig {
+            sort_vec: false,
+            cache: None,
+            shard: None,
+            min_copies: 2,
+            filter,
+            traversal: Traversal::Dfs,
+            stats: None,
+            errors: None,
+            manifest: None,
+            checkpoint: None,
+            archives: None,
+            prehash: Prehash::None,
+            stages: None,
+            read_order: ReadOrder::Walk,
+            verbose: false,
+            low_memory: false,
+            streams: Streams::Ignore,
+            skip_busy: false,
+            group_by: GroupBy::Content,
+            ignore_case: false,
+            normalize: Normalization::None,
+            text_normalize: TextNormalize::None,
+            strip_metadata: false,
+            decompress: false,
+            pdf_content: false,
+            match_mtime: false,
+            match_xattrs: false,
+            match_acls: false,
+        }
+    }
+}
+
+/// # Parameters
+///
+/// 1. `config` -- how to search
+/// 1. `roots` -- directories from which to start the search
+///
+/// # Returns
+///
+/// Groups ordered by size and then checksum, so that the same tree always produces the same
+/// output.
+pub fn duplicate_groups<'a>(
+    config: ScanConfig<'a>,
+    roots: &[&Path],
+) -> impl Iterator<Item = DuplicateGroup> + 'a {
+    let ScanConfig {
+        sort_vec,
+        cache,
+        shard,
+        min_copies,
+        filter,
+        traversal,
+        stats,
+        errors,
+        manifest,
+        checkpoint,
+        archives,
+        prehash: prehash_kind,
+        stages,
+        read_order,
+        verbose,
+        low_memory,
+        streams,
+        skip_busy,
+        group_by,
+        ignore_case,
+        normalize,
+        text_normalize,
+        strip_metadata,
+        decompress,
+        pdf_content,
+        match_mtime,
+        match_xattrs,
+        match_acls,
+    } = config;
+    // files found to be compressed by `bucket_f` are hashed by what they decompress to, but
+    // otherwise the same way as the rest
+    let guarded_f = move |path: &Path, unpacked: bool| {
+        if skip_busy {
+            if let Err(err) = check_idle(path) {
+                return logged(errors, "lock", Err(counted(stats, err)));
+            }
+        }
+        let before = stamp(path);
+        if unpacked {
+            // neither checkpoints nor manifests know about decompressed contents
+            let result = match cache {
+                Some(cache) => cache.decompressed_checksum(path),
+                None => decompressed_checksum(path),
+            };
+            let result = result
+                .and_then(|hash| unchanged_since(path, before, hash))
+                .map_err(|err| counted(stats, err));
+            return logged(errors, "hash", result.map(Some));
+        }
+        let found = match streams {
+            Streams::Hash => match alternate_streams(path) {
+                Ok(found) => found,
+                Err(err) => return logged(errors, "list streams", Err(FdupError::io(path, err))),
+            },
+            Streams::Ignore | Streams::Count => Vec::new(),
+        };
+        if !found.is_empty() {
+            // neither the cache, checkpoints nor manifests know about streams
+            let result = checksum_with_streams(path, &found)
+                .and_then(|hash| unchanged_since(path, before, hash))
+                .map_err(|err| counted(stats, err));
+            return logged(errors, "hash", result.map(Some));
+        }
+        let (result, resumed) = match (checkpoint.and_then(|c| c.lookup(path)), cache) {
+            (Some(hash), _) => (Ok(hash), true),
+            (None, Some(cache)) => (cache.checksum(path), false),
+            (None, None) => (checksum(path), false),
+        };
+        let result = result
+            .and_then(|hash| unchanged_since(path, before, hash))
+            .map_err(|err| counted(stats, err));
+        if let (Some(manifest), Ok(hash)) = (manifest, &result) {
+            manifest.record(path, hash);
+        }
+        if let (Some(checkpoint), Ok(hash), false) = (checkpoint, &result, resumed) {
+            checkpoint.record(path, hash);
+        }
+        logged(errors, "hash", result.map(Some))
+    };
+    let checksum_f = move |path: &Path| guarded_f(path, false);
+
+    let size_f = move |entry: &DirEntry| {
+        if !entry.file_type().is_dir() && !filter.admits_path(entry.path()) {
+            return Ok(None); // filtered out, without even a stat
+        }
+        let size = match filesize(entry) {
+            Ok(size) => size,
+            Err(FdupError::NotAFile { .. }) => {
+                if let (Some(stats), Some(kind)) = (stats, SpecialKind::of(&entry.file_type())) {
+                    stats.special_files[kind as usize].fetch_add(1, Ordering::Relaxed);
+                }
+                return Ok(None); // not a file, so skip
+            }
+            Err(err) => return logged(errors, "stat", Err(err)),
+        };
+        if shard.is_some_and(|shard| !shard.contains(size)) {
+            return Ok(None); // another shard's problem
+        }
+        if !filter.admits(entry.path(), size) {
+            return Ok(None); // filtered out
+        }
+        if filter.restricts_owner()
+            && !entry
+                .metadata()
+                .is_ok_and(|meta| filter.admits_owner(&meta))
+        {
+            return Ok(None); // someone else's
+        }
+        if let Some(stats) = stats {
+            stats.files.fetch_add(1, Ordering::Relaxed);
+            if streams != Streams::Ignore {
+                let found = alternate_streams(entry.path()).unwrap_or_default();
+                if !found.is_empty() {
+                    let bytes: u64 = found.iter().map(|stream| stream.size).sum();
+                    stats.files_with_streams.fetch_add(1, Ordering::Relaxed);
+                    stats
+                        .streams
+                        .fetch_add(found.len() as u64, Ordering::Relaxed);
+                    stats.stream_bytes.fetch_add(bytes, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(Some(size))
+    };
+    // compressed files, put in the bucket of what they decompress to
+    let unpacked: Mutex<HashSet<PathBuf>> = Mutex::default();
+    let unpacking = &unpacked;
+    // files are only told apart by size if their contents are to be compared, and would still
+    // have the same size once normalized
+    let bucket_f = move |entry: &DirEntry| match group_by {
+        GroupBy::Content => {
+            let size = size_f(entry)?;
+            if size.is_some() && strip_metadata {
+                // files that can't be made sense of are compared as they are
+                if let Ok(Some(spans)) = payload(entry.path()) {
+                    return Ok(Some(MEDIA_BUCKETS + payload_len(&spans)));
+                }
+            }
+            if size.is_some() && pdf_content && is_pdf(entry.path()) {
+                return Ok(Some(PDF_BUCKET));
+            }
+            let kind = match size.is_some() && decompress {
+                true => Compression::of(entry.path()).ok().flatten(),
+                false => None,
+            };
+            if let Some(kind) = kind {
+                // going by what the format says where it can, so that only files that share the
+                // bucket with others get decompressed, when they are hashed
+                let path = entry.path();
+                let len = match stored_len(path, kind) {
+                    Ok(Some(len)) => Ok(len),
+                    Ok(None) | Err(_) => decompressed_len(path, kind, DECOMPRESSED_LIMIT),
+                };
+                match len {
+                    Ok(len) if len <= DECOMPRESSED_LIMIT => {
+                        let mut unpacked = unpacking.lock().unwrap_or_else(|err| err.into_inner());
+                        unpacked.insert(path.to_path_buf());
+                        return Ok(Some(len));
+                    }
+                    // compared as they are, like files that aren't compressed
+                    _ => {
+                        if let Some(stats) = stats {
+                            stats.undecompressed_files.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }
+            if size.is_some()
+                && text_normalize != TextNormalize::None
+                && looks_like_text(entry.path())
+            {
+                return Ok(Some(TEXT_BUCKET));
+            }
+            Ok(size)
+        }
+        GroupBy::NameSize => size_f(entry),
+        GroupBy::Name => size_f(entry).map(|size| size.map(|_| 0)),
     };
-    let mut buffer = [0; 131072]; // read this much at a time
+    let name_f = move |path: &Path| Ok(name_key(path, ignore_case, normalize));
 
-    // feed the hasher one buffer's worth at a time
-    loop {
-        match file.read(&mut buffer) {
-            Ok(size) if size == 0 => break,            // done reading
-            Ok(size) => hasher.input(&buffer[..size]), // feed the hasher
-            Err(err) => panic!("failed reading {:?} to buffer {}", path, err), // undecided
+    // 1. group files by filesize first, discarding sets with fewer than `min_copies` files
+    // 2. within each group, group items by checksum, discarding sets with fewer than `min_copies`
+    let threshold = min_copies.max(2) - 1;
+    let spilled = match low_memory {
+        true => spilled_sizes(roots, traversal, filter, errors, &bucket_f, threshold)
+            .map_err(|err| eprintln!("ERROR spilling to disk, scanning in memory: {}", err))
+            .ok(),
+        false => None,
+    };
+    let by_size: Box<dyn Iterator<Item = (u64, Vec<PathBuf>)>> = match spilled {
+        Some(buckets) => Box::new(buckets),
+        None => {
+            // get all files, logging the directories that can't be listed
+            let files: Vec<_> = distinct_roots(roots)
+                .into_iter()
+                .flat_map(|root| walk_logged(root, traversal, filter.fdupignore, errors))
+                .collect();
+            let mut by_size: Vec<(u64, Vec<PathBuf>)> = match archives {
+                Some(index) => with_members(index, filter, errors, threshold, &bucket_f, &files),
+                None => keyed_disjoint_by_filter_map(&bucket_f, threshold, &files)
+                    .map(|(size, vec)| (size, vec.into_iter().map(DirEntry::into_path).collect()))
+                    .collect(),
+            };
+            by_size.sort_by_key(|(size, _)| *size);
+            Box::new(by_size.into_iter())
+        }
+    };
+    let unpacked = unpacked.into_inner().unwrap_or_else(|err| err.into_inner());
+    by_size
+        .flat_map(move |(size, set): (u64, Vec<PathBuf>)| {
+            let mut by_key: Vec<(Vec<u8>, Vec<PathBuf>)> = match group_by {
+                GroupBy::Name | GroupBy::NameSize => {
+                    keyed_disjoint_by_filter_map(&name_f, threshold, &set).collect()
+                }
+                GroupBy::Content if size == TEXT_BUCKET => {
+                    if let Some(stats) = stats {
+                        let bytes = set
+                            .iter()
+                            .filter_map(|path| metadata(path).ok())
+                            .map(|meta| meta.len());
+                        stats.bytes_hashed.fetch_add(bytes.sum(), Ordering::Relaxed);
+                    }
+                    // neither the cache, checkpoints nor manifests know about normalized contents
+                    let text_f = |path: &Path| {
+                        let result = text_checksum(path, text_normalize);
+                        logged(errors, "hash", result.map(Some))
+                    };
+                    keyed_disjoint_by_filter_map(&text_f, threshold, &set).collect()
+                }
+                GroupBy::Content if size == PDF_BUCKET => {
+                    if let Some(stats) = stats {
+                        let bytes = set
+                            .iter()
+                            .filter_map(|path| metadata(path).ok())
+                            .map(|meta| meta.len());
+                        stats.bytes_hashed.fetch_add(bytes.sum(), Ordering::Relaxed);
+                    }
+                    let pdf_f = |path: &Path| logged(errors, "hash", pdf_checksum(path).map(Some));
+                    keyed_disjoint_by_filter_map(&pdf_f, threshold, &set).collect()
+                }
+                GroupBy::Content if size >= MEDIA_BUCKETS => {
+                    if let Some(stats) = stats {
+                        let bytes = (size - MEDIA_BUCKETS) * set.len() as u64;
+                        stats.bytes_hashed.fetch_add(bytes, Ordering::Relaxed);
+                    }
+                    let media_f =
+                        |path: &Path| logged(errors, "hash", payload_checksum(path).map(Some));
+                    keyed_disjoint_by_filter_map(&media_f, threshold, &set).collect()
+                }
+                GroupBy::Content if set.iter().any(|path| unpacked.contains(path)) => {
+                    if let Some(stats) = stats {
+                        let bytes = size * set.len() as u64;
+                        stats.bytes_hashed.fetch_add(bytes, Ordering::Relaxed);
+                    }
+                    let unpacked_f = |path: &Path| guarded_f(path, unpacked.contains(path));
+                    keyed_disjoint_by_filter_map(&unpacked_f, threshold, &set).collect()
+                }
+                GroupBy::Content
+                    if archives.is_some_and(|index| set.iter().any(|p| index.is_member(p))) =>
+                {
+                    if let Some(stats) = stats {
+                        let bytes = size * set.len() as u64;
+                        stats.bytes_hashed.fetch_add(bytes, Ordering::Relaxed);
+                    }
+                    // neither the cache, checkpoints nor manifests know about files in archives
+                    let member_f = |path: &Path| match archives.filter(|i| i.is_member(path)) {
+                        Some(index) => logged(errors, "hash", index.checksum(path).map(Some)),
+                        None => checksum_f(path),
+                    };
+                    keyed_disjoint_by_filter_map(&member_f, threshold, &set).collect()
+                }
+                GroupBy::Content => {
+                    if let (Some(stats), true) = (stats, stages.map_or(true, Stages::is_exact)) {
+                        let bytes = size * set.len() as u64;
+                        stats.bytes_hashed.fetch_add(bytes, Ordering::Relaxed);
+                    }
+                    let set = sort_for_reading(set, read_order);
+                    match stages {
+                        Some(stages) => {
+                            if verbose {
+                                eprintln!("{} files of {} bytes: {}", set.len(), size, stages);
+                            }
+                            staged(stages, set, size, threshold, errors, &checksum_f)
+                        }
+                        None => {
+                            let plan = Plan::choose(set.len(), size, cache.is_some());
+                            if verbose {
+                                eprintln!("{} files of {} bytes: {}", set.len(), size, plan);
+                            }
+                            let mut subsets: Vec<Vec<PathBuf>> = vec![set];
+                            subsets = match plan {
+                                Plan::Direct => subsets,
+                                Plan::Prefix => split_by(subsets, threshold, &|path: &Path| {
+                                    logged(errors, "hash", prefix_hash(path).map(Some))
+                                }),
+                                Plan::Sampled => split_by(subsets, threshold, &|path: &Path| {
+                                    logged(errors, "hash", sampled_hash(path, size).map(Some))
+                                }),
+                            };
+                            if prehash_kind != Prehash::None {
+                                subsets = split_by(subsets, threshold, &|path: &Path| {
+                                    logged(errors, "hash", prehash(path, prehash_kind).map(Some))
+                                });
+                            }
+                            subsets
+                                .iter()
+                                .flat_map(|subset| {
+                                    keyed_disjoint_by_filter_map(&checksum_f, threshold, subset)
+                                })
+                                .collect()
+                        }
+                    }
+                }
+            };
+            if match_mtime {
+                by_key = split_keyed(by_key, threshold, &|path: &Path| {
+                    logged(errors, "stat", mtime_secs(path).map(Some))
+                });
+            }
+            if match_xattrs {
+                by_key = split_keyed(by_key, threshold, &|path: &Path| {
+                    let xattrs = extended_attributes(path, match_acls);
+                    logged(
+                        errors,
+                        "read xattrs",
+                        xattrs.map(Some).map_err(|err| FdupError::io(path, err)),
+                    )
+                });
+            }
+            // groups without a hash to tell them apart are ordered by their files
+            by_key.sort();
+            by_key.into_iter().map(move |(key, paths)| {
+                let hash = match group_by {
+                    GroupBy::Content => key,
+                    GroupBy::Name | GroupBy::NameSize => Vec::new(), // nothing was hashed
+                };
+                let size = match size {
+                    size if size >= MEDIA_BUCKETS => {
+                        let sizes = paths.iter().filter_map(|path| metadata(path).ok());
+                        sizes.map(|meta| meta.len()).min().unwrap_or(0)
+                    }
+                    size => size,
+                };
+                DuplicateGroup { hash, size, paths }
+            })
+        })
+        .map(move |group| match sort_vec {
+            true => DuplicateGroup {
+                paths: sorted!(group.paths),
+                ..group
+            },
+            false => group,
+        })
+}
+
+/// # Returns
+///
+/// `files` in buckets by `bucket_f` like `keyed_disjoint_by_filter_map`, along with the files
+/// inside the archives among them that `filter` admits, each in the bucket of its size, once each
+/// archive was listed in `index`. No bucket is left out before they are all in, since a file in
+/// an archive can be the only other file of its size.
+fn with_members<F>(
+    index: &ArchiveIndex,
+    filter: &Filter,
+    errors: Option<&ErrorLog>,
+    threshold: usize,
+    bucket_f: &F,
+    files: &Vec<DirEntry>,
+) -> Vec<(u64, Vec<PathBuf>)>
+where
+    F: Fn(&DirEntry) -> Result<Option<u64>, FdupError> + Send + Sync,
+{
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for (size, set) in keyed_disjoint_by_filter_map(bucket_f, 0, files) {
+        let paths = set.into_iter().map(DirEntry::into_path);
+        by_size.entry(size).or_default().extend(paths);
+    }
+    let archives: Vec<PathBuf> = by_size
+        .values()
+        .flatten()
+        .filter(|path| ArchiveKind::of(path).is_some())
+        .cloned()
+        .collect();
+    let listed: Vec<_> = archives
+        .par_iter()
+        .map(|archive| (archive, index.add(archive)))
+        .collect();
+    for (archive, members) in listed {
+        let members = match members {
+            Ok(members) => members,
+            Err(err) => {
+                let err = FdupError::io(archive, err);
+                if let Err(err) = logged::<()>(errors, "list archive", Err(err)) {
+                    eprintln!("ERROR with {}", err);
+                }
+                continue;
+            }
         };
+        for member in members {
+            let path = member.path();
+            if filter.admits_path(&path) && filter.admits(&path, member.size) {
+                by_size.entry(member.size).or_default().push(path);
+            }
+        }
     }
-
-    Ok(hasher.result().as_slice().to_vec())
+    by_size
+        .into_iter()
+        .filter(|(_, set)| set.len() > threshold)
+        .collect()
 }
 
 /// # Returns
 ///
-/// Size of the file in bytes if it is a regular file, `Err(None)` if it is not a regular file,
-/// `Err(Some(_))` otherwise.
-pub fn filesize(entry: &DirEntry) -> Result<usize, Option<String>> {
-    match entry.metadata() {
-        Ok(meta) if meta.is_file() => Ok(meta.len() as usize),
-        Ok(_) => Err(None), // not a file, so skip
-        Err(err) => Err(Some(format!("{}", err))),
-    }
+/// Each of `subsets` split further by `key_f`, like `disjoint_by_filter_map`.
+fn split_by<F>(subsets: Vec<Vec<PathBuf>>, threshold: usize, key_f: &F) -> Vec<Vec<PathBuf>>
+where
+    F: Fn(&Path) -> Result<Option<Vec<u8>>, FdupError> + Send + Sync,
+{
+    subsets
+        .iter()
+        .flat_map(|subset| disjoint_by_filter_map(key_f, threshold, subset))
+        .collect()
 }
+
 /// # Parameters
 ///
-/// 1. `key_f` -- some function that maps a borrowed form of `T` into `Result<K, Option<String>>`,
-///    where output values `Err(None)` are ignored, and `Err(Some(_))` are printed before being
-///    ignored
-/// 1. `threshold` -- only `Vec`'s with length `> theshold` are included in the returned iterator
-/// 1. `items` -- the items to uses
-///
-/// ## Types
+/// 1. `stages` -- stages to go through, the first of which, size, the files are already past
+/// 1. `set` -- files of `size` bytes each
+/// 1. `threshold` -- only groups with more than this many files are kept
+/// 1. `errors` -- if given, files that can't be read are recorded in it instead of being printed
+/// 1. `checksum_f` -- checksum of a file, for `Stage::Hash`
 ///
-/// 1. `B` -- some type such that `T` implements `Borrow<B>`
-/// 1. `F` -- mapping from `&B` to some wrapper type around `K`
-/// 1. `K` -- key values uesd to group `items`
-/// 1. `T` -- items being grouped
+/// # Returns
 ///
+/// Groups of the files in `set` that made it through every stage together, each with its
+/// checksum, which is empty unless one of the stages is `Stage::Hash`.
+fn staged<F>(
+    stages: &Stages,
+    set: Vec<PathBuf>,
+    size: u64,
+    threshold: usize,
+    errors: Option<&ErrorLog>,
+    checksum_f: &F,
+) -> Vec<(Vec<u8>, Vec<PathBuf>)>
+where
+    F: Fn(&Path) -> Result<Option<Vec<u8>>, FdupError> + Send + Sync,
+{
+    let mut keyed: Vec<(Vec<u8>, Vec<PathBuf>)> = vec![(Vec::new(), set)];
+    for stage in stages.iter() {
+        keyed = match stage {
+            Stage::Size => keyed, // grouped by size as they were walked
+            Stage::Hash => keyed
+                .iter()
+                .flat_map(|(_, subset)| keyed_disjoint_by_filter_map(checksum_f, threshold, subset))
+                .collect(),
+            Stage::Bytes => keyed
+                .into_iter()
+                .flat_map(|(key, subset)| {
+                    let mut subsets = vec![subset];
+                    let mut offset = 0;
+                    while offset < size && !subsets.is_empty() {
+                        subsets = split_by(subsets, threshold, &|path: &Path| {
+                            logged(errors, "compare", chunk_at(path, offset).map(Some))
+                        });
+                        offset += BYTES_CHUNK;
+                    }
+                    subsets.into_iter().map(move |subset| (key.clone(), subset))
+                })
+                .collect(),
+            Stage::PrefixHash | Stage::SampledHash | Stage::Xxh3 | Stage::Crc32 => {
+                split_keyed(keyed, threshold, &|path: &Path| {
+                    let hash = match stage {
+                        Stage::SampledHash => sampled_hash(path, size),
+                        Stage::Xxh3 => prehash(path, Prehash::Xxh3),
+                        Stage::Crc32 => prehash(path, Prehash::Crc32),
+                        _ => prefix_hash(path),
+                    };
+                    logged(errors, "hash", hash.map(Some))
+                })
+            }
+        };
+    }
+    keyed
+}
+
 /// # Returns
 ///
-/// `Iterator` of `Vec`'s, `I = [v1, v2, ...]`, such that two elements `t1` and `t2` are in the same
-/// set `vi` if and only if `key_f(&t1) == key_f(&t2)`. Put another way, each set `v` is
-/// characterized by a unique output value `o` of `key_f`, and `key_f` maps each element in `v` to
-/// the unique `o` of that set.
-pub fn disjoint_by_filter_map<B, F, K, T>(
-    key_f: &F,
+/// Each group of `keyed` split further by `key_f`, like `split_by`, every part keeping the key
+/// of the group it came from.
+fn split_keyed<F, K>(
+    keyed: Vec<(Vec<u8>, Vec<PathBuf>)>,
     threshold: usize,
-    items: &Vec<T>,
-) -> impl Iterator<Item = Vec<T>>
+    key_f: &F,
+) -> Vec<(Vec<u8>, Vec<PathBuf>)>
 where
-    B: ?Sized,
-    F: Fn(&B) -> Result<K, Option<String>> + Send + Sync,
+    F: Fn(&Path) -> Result<Option<K>, FdupError> + Send + Sync,
     K: Clone + Eq + Hash + Send + Sync,
-    T: Borrow<B> + Clone + Debug + Send + Sync,
 {
-    // map input items to key values in parallel
-    // collect, because hashmap construction needss to be sequential
-    //
-    // the hashmap holds `O(n)` anyway, so holding things in this intermediate set doesn't
-    // really change things too much
-    let k_to_t_mapping: Vec<(K, T)> = items
-        .par_iter()
-        .filter_map(|item| match key_f(item.borrow()) {
-            Ok(key_res) => Some((key_res, item.clone())), // good to go
-            Err(None) => None,                            // err, but don't print anything
-            Err(Some(err)) => {
-                eprintln!("ERROR with {:?}: {}", item, err); // err, report error
-                None
-            }
+    keyed
+        .into_iter()
+        .flat_map(|(key, subset)| {
+            disjoint_by_filter_map(key_f, threshold, &subset)
+                .map(|part| (key.clone(), part))
+                .collect::<Vec<_>>()
         })
-        .collect();
-    let mapping_len = k_to_t_mapping.len(); // for preallocation purposes
+        .collect()
+}
 
-    // use hashmap to group things by the key values
-    k_to_t_mapping
-        .into_iter()
-        .fold(
-            HashMap::<K, Vec<T>>::with_capacity(mapping_len),
-            |mut acc, (key, item)| {
-                // if acc[key] is None, acc[key] = vec![item], else acc[key].push(item)
-                match acc.get_mut(&key) {
-                    Some(vec) => vec.push(item),
-                    None => {
-                        let val = vec![item];
-                        acc.insert(key, val);
-                    }
-                };
-                acc
-            },
-        )
-        .into_iter()
-        .map(|(_, v)| v)
-        .filter(move |v| v.len() > threshold)
+/// # Returns
+///
+/// Name of the file at `path` to group it by, folded to the same case if `ignore_case`, as
+/// Unicode has it, or only ASCII letters if the name isn't Unicode, then brought to the
+/// `normalize` form, or `None` if it has no name.
+fn name_key(path: &Path, ignore_case: bool, normalize: Normalization) -> Option<Vec<u8>> {
+    let name = path.file_name()?;
+    let key = match (ignore_case, name.to_str()) {
+        (false, Some(name)) => normalize.apply(name).into_bytes(),
+        (true, Some(name)) => normalize.apply(&default_case_fold_str(name)).into_bytes(),
+        (false, None) => path_bytes(Path::new(name)),
+        (true, None) => path_bytes(Path::new(name)).to_ascii_lowercase(),
+    };
+    Some(key)
+}
+
+/// # Returns
+///
+/// Modification time of the file at `path` in whole seconds since the epoch, which is as
+/// precise as copies keep it across most file systems and tools.
+fn mtime_secs(path: &Path) -> Result<i64, FdupError> {
+    let modified = metadata(path)
+        .and_then(|meta| meta.modified())
+        .map_err(|err| FdupError::io(path, err))?;
+    Ok(match modified.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => since_epoch.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64),
+    })
 }
 
-pub fn duplicate_files(sort_vec: bool, path: &Path) -> impl Iterator<Item = Vec<PathBuf>> {
-    // get all files, ignoring all errors
-    let files: Vec<_> = WalkDir::new(&path)
+/// # Parameters
+///
+/// 1. `roots` -- directories from which to start the search
+/// 1. `traversal` -- order in which to walk the trees
+/// 1. `filter` -- conditions files have to meet to be considered at all
+/// 1. `errors` -- if given, directories that can't be listed are recorded in it
+/// 1. `size_f` -- size of a file to group it by, or `None` to skip it
+/// 1. `threshold` -- only sizes shared by more than this many files are returned
+///
+/// # Returns
+///
+/// Files under `roots` grouped by size like `keyed_disjoint_by_filter_map` would, in increasing
+/// order of size, but with only one group held in memory at a time.
+fn spilled_sizes<F>(
+    roots: &[&Path],
+    traversal: Traversal,
+    filter: &Filter,
+    errors: Option<&ErrorLog>,
+    size_f: &F,
+    threshold: usize,
+) -> rusqlite::Result<Buckets>
+where
+    F: Fn(&DirEntry) -> Result<Option<u64>, FdupError>,
+{
+    let mut spilled = SpilledSizes::create()?;
+    for entry in distinct_roots(roots)
         .into_iter()
-        .filter_map(Result::ok)
-        .collect();
+        .flat_map(|root| walk_logged(root, traversal, filter.fdupignore, errors))
+    {
+        match size_f(&entry) {
+            Ok(Some(size)) => spilled.insert(size, entry.path())?,
+            Ok(None) => (),
+            Err(err) => eprintln!("ERROR with {}", err),
+        }
+    }
+    spilled.buckets(threshold)
+}
 
-    // 1. group files by filesize first, discarding sets with size <= 1
-    // 2. within each group, group items by checksum, discarding sets with size <= 1
-    // 3. print each one as json
-    disjoint_by_filter_map(&filesize, 1, &files)
-        .map(|vec| vec.into_iter().map(DirEntry::into_path).collect())
-        .flat_map(|set| disjoint_by_filter_map(&checksum, 1, &set))
-        .map(move |vec| match sort_vec {
-            true => sorted!(vec),
-            false => vec,
-        })
+/// # Returns
+///
+/// `err`, after counting it in `stats`, if given, if it is about a file that was in use or
+/// changing.
+fn counted(stats: Option<&ScanStats>, err: FdupError) -> FdupError {
+    if let (Some(stats), FdupError::Busy { .. } | FdupError::Changed { .. }) = (stats, &err) {
+        stats.unstable_files.fetch_add(1, Ordering::Relaxed);
+    }
+    err
+}
+
+/// # Returns
+///
+/// `result`, unless it is an error and there is a log of `errors`, in which case the error is
+/// recorded there as having happened during `operation`, and the item skipped.
+fn logged<K>(
+    errors: Option<&ErrorLog>,
+    operation: &str,
+    result: Result<Option<K>, FdupError>,
+) -> Result<Option<K>, FdupError> {
+    match (result, errors) {
+        (Err(err), Some(errors)) => {
+            errors.record(operation, &err);
+            Ok(None)
+        }
+        (result, _) => result,
+    }
 }
 
 #[cfg(test)]
@@ -157,6 +1872,7 @@ mod tests {
     use std::io;
     use std::io::Write;
     use std::path::PathBuf;
+    use walkdir::WalkDir;
 
     /// # Returns
     ///
@@ -180,6 +1896,56 @@ mod tests {
         Ok(path_to_temp)
     }
 
+    /// Directory for a test to put its files in, removed once dropped, so that it is cleaned up
+    /// even when an assertion fails.
+    struct Scratch(PathBuf);
+
+    impl std::ops::Deref for Scratch {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = remove_dir_all(&self.0);
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Empty `Scratch` directory named after where the macro is used, so that tests don't share
+    /// one.
+    macro_rules! scratch {
+        () => {{
+            let name = format!("{}_{}_{}", module_path!(), line!(), column!());
+            let dir = Scratch(env::temp_dir().join(name));
+            let _ = remove_dir_all(&*dir);
+            create_dir_all(&*dir).unwrap();
+            dir
+        }};
+    }
+
+    /// # Returns
+    ///
+    /// The only group of duplicates found in `dir` with the default configuration as `configure`
+    /// changes it, with its paths sorted.
+    fn sole_group<'a, F>(dir: &Path, configure: F) -> DuplicateGroup
+    where
+        F: FnOnce(ScanConfig<'a>) -> ScanConfig<'a>,
+    {
+        static FILTER: std::sync::OnceLock<Filter> = std::sync::OnceLock::new();
+        let config = ScanConfig {
+            sort_vec: true,
+            ..configure(ScanConfig::new(FILTER.get_or_init(Filter::default)))
+        };
+        let mut groups: Vec<DuplicateGroup> = duplicate_groups(config, &[dir]).collect();
+        assert_eq!(1, groups.len());
+        groups.remove(0)
+    }
+
     fn test_data() -> Vec<String> {
         vec![
             "",
@@ -226,7 +1992,7 @@ mod tests {
                     .nth(0)
                     .unwrap();
                 let result = filesize(&temp_as_entry).unwrap();
-                let expected = content.len();
+                let expected = content.len() as u64;
                 assert_eq!(expected, result);
             });
     }
@@ -256,9 +2022,10 @@ mod tests {
             assert_eq!(content, read_to_string(&path_buf).unwrap());
         });
 
-        let results: HashSet<Vec<PathBuf>> = duplicate_files(false, &test_dir)
-            .map(|v| sorted!(v))
-            .collect();
+        let results: HashSet<Vec<PathBuf>> =
+            duplicate_groups(ScanConfig::new(&Filter::default()), &[&test_dir])
+                .map(|group| sorted!(group.paths))
+                .collect();
         let expected = hashset![
             sorted!(vec![test_dir.join("d1/f1"), test_dir.join("d1/f2")]),
             sorted!(vec![
@@ -268,4 +2035,413 @@ mod tests {
         ];
         assert_eq!(expected, results);
     }
+
+    #[test]
+    fn low_memory_finds_the_same_groups() {
+        let dir = scratch!();
+        for (name, contents) in &[("a", "a\nbc2"), ("b", "a\nbc2"), ("c", "abcde")] {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+
+        let group = sole_group(&dir, |config| config);
+        assert_eq!(vec![dir.join("a"), dir.join("b")], group.paths);
+        let spilled = sole_group(&dir, |config| ScanConfig {
+            low_memory: true,
+            ..config
+        });
+        assert_eq!((&group.paths, &group.hash), (&spilled.paths, &spilled.hash));
+    }
+
+    #[test]
+    fn prehashes_find_the_same_groups() {
+        let dir = scratch!();
+        for (name, contents) in &[("a", "a\nbc2"), ("b", "a\nbc2"), ("c", "abcde")] {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+
+        let group = sole_group(&dir, |config| config);
+        for &prehash in &[Prehash::Xxh3, Prehash::Crc32] {
+            let prehashed = sole_group(&dir, |config| ScanConfig { prehash, ..config });
+            assert_eq!(
+                (&group.paths, &group.hash),
+                (&prehashed.paths, &prehashed.hash)
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn read_errors_are_returned() {
+        // a directory opens like a file on linux, but fails on the first read
+        let dir = scratch!();
+        assert!(matches!(checksum(&dir), Err(FdupError::Io { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifos_are_skipped_and_counted() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = scratch!();
+        let fifo = dir.join("fifo");
+        let c_path = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        // safe, since `c_path` is NUL-terminated
+        assert_eq!(0, unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) });
+
+        // would block forever if it were opened for reading
+        assert!(matches!(checksum(&fifo), Err(FdupError::NotAFile { .. })));
+        let filter = Filter::default();
+        let stats = ScanStats::default();
+        let config = ScanConfig {
+            stats: Some(&stats),
+            ..ScanConfig::new(&filter)
+        };
+        assert_eq!(0, duplicate_groups(config, &[&dir]).count());
+        let fifos = &stats.special_files[SpecialKind::Fifo as usize];
+        assert_eq!(1, fifos.load(Ordering::Relaxed));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn busy_and_changing_files_are_found_out() {
+        use std::os::unix::io::AsRawFd;
+
+        let dir = scratch!();
+        let path = dir.join("a");
+        std::fs::write(&path, "contents").unwrap();
+        assert!(check_idle(&path).is_ok());
+
+        let locked = File::open(&path).unwrap();
+        // safe, since `locked` is open
+        assert_eq!(0, unsafe { libc::flock(locked.as_raw_fd(), libc::LOCK_EX) });
+        assert!(matches!(check_idle(&path), Err(FdupError::Busy { .. })));
+        drop(locked);
+        assert!(check_idle(&path).is_ok());
+        if cfg!(target_os = "linux") {
+            let writer = OpenOptions::new().append(true).open(&path).unwrap();
+            assert!(matches!(check_idle(&path), Err(FdupError::Busy { .. })));
+            drop(writer);
+        }
+
+        let before = stamp(&path);
+        assert_eq!(vec![1], unchanged_since(&path, before, vec![1]).unwrap());
+        std::fs::write(&path, "longer contents").unwrap();
+        let err = unchanged_since(&path, before, vec![1]).unwrap_err();
+        assert!(matches!(err, FdupError::Changed { .. }));
+    }
+
+    #[test]
+    fn text_files_match_whatever_their_line_endings() {
+        let dir = scratch!();
+        std::fs::write(dir.join("unix.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(dir.join("windows.txt"), "one\r\ntwo\r\n").unwrap();
+        std::fs::write(dir.join("a.bin"), b"\0one\r\n").unwrap();
+        std::fs::write(dir.join("b.bin"), b"\0one\n").unwrap();
+
+        let group = sole_group(&dir, |config| ScanConfig {
+            text_normalize: TextNormalize::Eol,
+            ..config
+        });
+        let paths = vec![dir.join("unix.txt"), dir.join("windows.txt")];
+        assert_eq!(paths, group.paths);
+        assert_eq!(8, group.size);
+        assert_eq!(
+            checksum(&dir.join("unix.txt")).unwrap(),
+            text_checksum(&dir.join("windows.txt"), TextNormalize::Eol).unwrap()
+        );
+    }
+
+    #[test]
+    fn media_files_match_whatever_their_metadata() {
+        let dir = scratch!();
+        let image = [0xff, 0xdb, 0, 3, 7, 0xff, 0xda, 1, 2, 0xff, 0xd9];
+        let jpeg = |metadata: &[u8]| [&[0xff, 0xd8][..], metadata, &image].concat();
+        std::fs::write(dir.join("a.jpg"), jpeg(&[])).unwrap();
+        std::fs::write(dir.join("b.jpg"), jpeg(&[0xff, 0xfe, 0, 4, b'h', b'i'])).unwrap();
+        std::fs::write(dir.join("c.jpg"), jpeg(&[0xff, 0xe1, 0, 3, b'x'])).unwrap();
+        std::fs::write(dir.join("d"), jpeg(&[0xff, 0xe1, 0, 3, b'x'])).unwrap();
+
+        let group = sole_group(&dir, |config| ScanConfig {
+            strip_metadata: true,
+            ..config
+        });
+        let paths: Vec<PathBuf> = ["a.jpg", "b.jpg", "c.jpg", "d"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        assert_eq!(paths, group.paths);
+        assert_eq!(13, group.size);
+        assert_eq!(checksum(&paths[0]).unwrap(), group.hash);
+    }
+
+    #[test]
+    fn pdfs_match_whatever_saved_them() {
+        use flate2::write::ZlibEncoder;
+        use flate2::Compression;
+
+        let dir = scratch!();
+        let stream = |contents: &[u8], level: u32| {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+            encoder.write_all(contents).unwrap();
+            let compressed = encoder.finish().unwrap();
+            let dict = format!("<< /Length {} /Filter /FlateDecode >>", compressed.len());
+            [
+                dict.as_bytes(),
+                b"\nstream\n",
+                &compressed,
+                b"\nendstream\n",
+            ]
+            .concat()
+        };
+        let pdf = |producer: &str, streams: &[Vec<u8>]| {
+            let info = format!("%PDF-1.7\n<< /Producer ({}) >>\n", producer);
+            [&[info.into_bytes()][..], streams].concat().concat()
+        };
+        let (page, font) = (&b"BT (Hello) Tj ET"[..], &b"font program"[..]);
+        let one = pdf("one", &[stream(page, 9), stream(font, 9)]);
+        let other = pdf("another tool", &[stream(font, 1), stream(page, 1)]);
+        let changed = pdf("one", &[stream(b"BT (Bye) Tj ET", 9), stream(font, 9)]);
+        std::fs::write(dir.join("a.pdf"), one).unwrap();
+        std::fs::write(dir.join("b.pdf"), other).unwrap();
+        std::fs::write(dir.join("c.pdf"), changed).unwrap();
+
+        let group = sole_group(&dir, |config| ScanConfig {
+            pdf_content: true,
+            ..config
+        });
+        assert_eq!(vec![dir.join("a.pdf"), dir.join("b.pdf")], group.paths);
+        assert_eq!(metadata(dir.join("a.pdf")).unwrap().len(), group.size);
+    }
+
+    #[test]
+    fn files_inside_archives_match_loose_ones() {
+        let dir = scratch!();
+        std::fs::write(dir.join("loose"), "packed away").unwrap();
+        std::fs::write(dir.join("unique"), "only once!!").unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(dir.join("backup.zip")).unwrap());
+        zip.start_file("old/loose", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(b"packed away").unwrap();
+        zip.finish().unwrap();
+        let index = ArchiveIndex::default();
+
+        let group = sole_group(&dir, |config| ScanConfig {
+            archives: Some(&index),
+            ..config
+        });
+        let paths = vec![dir.join("backup.zip/old/loose"), dir.join("loose")];
+        assert_eq!(paths, group.paths);
+        assert_eq!(checksum(&dir.join("loose")).unwrap(), group.hash);
+        assert!(index.is_member(&paths[0]));
+        let filter = Filter::default();
+        assert_eq!(
+            0,
+            duplicate_groups(ScanConfig::new(&filter), &[&dir]).count()
+        );
+    }
+
+    #[test]
+    fn compressed_files_match_their_contents() {
+        let dir = scratch!();
+        let log = "started\nstopped\n".repeat(100);
+        std::fs::write(dir.join("app.log"), &log).unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gzip.write_all(log.as_bytes()).unwrap();
+        std::fs::write(dir.join("app.log.1.gz"), gzip.finish().unwrap()).unwrap();
+        std::fs::write(dir.join("broken.gz"), b"\x1f\x8b not really").unwrap();
+
+        let group = sole_group(&dir, |config| ScanConfig {
+            decompress: true,
+            ..config
+        });
+        let paths = vec![dir.join("app.log"), dir.join("app.log.1.gz")];
+        assert_eq!(paths, group.paths);
+        assert_eq!(checksum(&dir.join("app.log")).unwrap(), group.hash);
+        assert_eq!(log.len() as u64, group.size);
+        let filter = Filter::default();
+        assert_eq!(
+            0,
+            duplicate_groups(ScanConfig::new(&filter), &[&dir]).count()
+        );
+    }
+
+    #[test]
+    fn files_are_grouped_by_name_without_reading_them() {
+        let dir = scratch!();
+        create_dir_all(dir.join("old")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "first draft").unwrap();
+        std::fs::write(dir.join("old/notes.txt"), "draft").unwrap();
+        std::fs::write(dir.join("old/other.txt"), "draft").unwrap();
+        let filter = Filter::default();
+        let config = ScanConfig {
+            group_by: GroupBy::Name,
+            ..ScanConfig::new(&filter)
+        };
+
+        let groups: Vec<DuplicateGroup> = duplicate_groups(config, &[&dir]).collect();
+        assert_eq!(1, groups.len());
+        assert!(groups[0].hash.is_empty());
+        let mut paths = groups[0].paths.clone();
+        paths.sort();
+        assert_eq!(
+            vec![dir.join("notes.txt"), dir.join("old/notes.txt")],
+            paths
+        );
+        assert_eq!(Ok(GroupBy::Name), "name".parse());
+        let none = Normalization::None;
+        let key = |name: &str| Some(name.as_bytes().to_vec());
+        assert_eq!(
+            key("strasse.jpg"),
+            name_key(Path::new("a/STRAßE.JPG"), true, none)
+        );
+        assert_eq!(key("B"), name_key(Path::new("a/B"), false, none));
+        let decomposed = Path::new("a/Cafe\u{301}");
+        assert_eq!(key("Cafe\u{301}"), name_key(decomposed, false, none));
+        let nfc = Normalization::Nfc;
+        assert_eq!(key("caf\u{e9}"), name_key(decomposed, true, nfc));
+
+        let config = ScanConfig {
+            group_by: GroupBy::NameSize,
+            ..ScanConfig::new(&filter)
+        };
+        assert_eq!(0, duplicate_groups(config, &[&dir]).count());
+        std::fs::write(dir.join("old/notes.txt"), "final draft").unwrap();
+        let groups: Vec<DuplicateGroup> = duplicate_groups(config, &[&dir]).collect();
+        assert_eq!(1, groups.len());
+        assert_eq!((11, true), (groups[0].size, groups[0].hash.is_empty()));
+    }
+
+    #[test]
+    fn stages_are_gone_through_in_turn() {
+        assert!("prefix-hash,hash".parse::<Stages>().is_err());
+        assert!("size,hash,hash".parse::<Stages>().is_err());
+        assert!("size,md5".parse::<Stages>().is_err());
+        let quick: Stages = "size,prefix-hash".parse().unwrap();
+        assert!(!quick.is_exact());
+        assert_eq!("size, then prefix hash", quick.to_string());
+
+        let dir = scratch!();
+        let mut contents = vec![0u8; 3 * BYTES_CHUNK as usize];
+        std::fs::write(dir.join("a"), &contents).unwrap();
+        std::fs::write(dir.join("b"), &contents).unwrap();
+        *contents.last_mut().unwrap() = 1;
+        std::fs::write(dir.join("c"), &contents).unwrap();
+        let filter = Filter::default();
+        let groups = |stages: &Stages| {
+            let config = ScanConfig {
+                stages: Some(stages),
+                sort_vec: true,
+                ..ScanConfig::new(&filter)
+            };
+            duplicate_groups(config, &[&dir]).collect::<Vec<_>>()
+        };
+
+        let quick_groups = groups(&quick);
+        assert_eq!(1, quick_groups.len());
+        assert_eq!(3, quick_groups[0].paths.len());
+        assert!(quick_groups[0].hash.is_empty());
+        let bytes_groups = groups(&"size,bytes".parse().unwrap());
+        assert_eq!(vec![dir.join("a"), dir.join("b")], bytes_groups[0].paths);
+        assert_eq!(1, bytes_groups.len());
+        let hash_groups = groups(&"size,hash,bytes".parse().unwrap());
+        assert_eq!(bytes_groups[0].paths, hash_groups[0].paths);
+        assert_eq!(checksum(&dir.join("a")).unwrap(), hash_groups[0].hash);
+    }
+
+    #[test]
+    fn copies_can_be_told_apart_by_mtime() {
+        let dir = scratch!();
+        let mirrored = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        for (name, modified) in &[("a", mirrored), ("b", mirrored), ("c", SystemTime::now())] {
+            std::fs::write(dir.join(name), "same").unwrap();
+            let file = OpenOptions::new().write(true).open(dir.join(name)).unwrap();
+            file.set_modified(*modified).unwrap();
+        }
+        assert_eq!(1_000_000, mtime_secs(&dir.join("a")).unwrap());
+
+        let group = sole_group(&dir, |config| ScanConfig {
+            match_mtime: true,
+            ..config
+        });
+        assert_eq!(vec![dir.join("a"), dir.join("b")], group.paths);
+        assert_eq!(checksum(&dir.join("c")).unwrap(), group.hash);
+    }
+
+    #[test]
+    fn plans_fit_groups() {
+        assert_eq!(Plan::Direct, Plan::choose(100, DIRECT_MAX, false));
+        assert_eq!(Plan::Prefix, Plan::choose(100, DIRECT_MAX + 1, false));
+        assert_eq!(Plan::Sampled, Plan::choose(2, HUGE_MIN, false));
+        assert_eq!(Plan::Prefix, Plan::choose(FEW_MAX + 1, HUGE_MIN, false));
+        assert_eq!(Plan::Direct, Plan::choose(2, HUGE_MIN, true));
+
+        let dir = scratch!();
+        let mut contents = vec![0u8; 100_000];
+        std::fs::write(dir.join("a"), &contents).unwrap();
+        *contents.last_mut().unwrap() = 1;
+        std::fs::write(dir.join("b"), &contents).unwrap();
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        assert_eq!(prefix_hash(&a).unwrap(), prefix_hash(&b).unwrap());
+        assert_ne!(
+            sampled_hash(&a, 100_000).unwrap(),
+            sampled_hash(&b, 100_000).unwrap()
+        );
+    }
+
+    #[test]
+    fn shards_partition_sizes() {
+        let shards: Vec<Shard> = (1..=3).map(|i| Shard { index: i, count: 3 }).collect();
+        for size in 0..1000 {
+            let owners = shards.iter().filter(|shard| shard.contains(size)).count();
+            assert_eq!(1, owners);
+        }
+        assert!("0/3".parse::<Shard>().is_err());
+        assert!("4/3".parse::<Shard>().is_err());
+        assert_eq!(Ok(Shard { index: 3, count: 3 }), "3/3".parse());
+    }
+
+    #[test]
+    fn sort_orders() {
+        let group = |size: u64, paths: &[&str]| DuplicateGroup {
+            hash: vec![],
+            size,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        };
+        let mut groups = vec![
+            group(100, &["c", "d"]),
+            group(30, &["e", "f", "g", "h"]),
+            group(10, &["b", "a", "i"]),
+        ];
+        let order = |groups: &[DuplicateGroup]| groups.iter().map(|g| g.size).collect::<Vec<_>>();
+
+        sort_groups(&mut groups, SortBy::Size);
+        assert_eq!(vec![100, 30, 10], order(&groups));
+        sort_groups(&mut groups, SortBy::Count);
+        assert_eq!(vec![30, 10, 100], order(&groups));
+        sort_groups(&mut groups, SortBy::Path);
+        assert_eq!(vec![10, 100, 30], order(&groups));
+        assert_eq!(20, groups[0].wasted());
+
+        let top = top_groups(groups.clone(), 2);
+        assert_eq!(vec![100, 30], order(&top));
+        let tied = vec![group(1, &["x", "y"]), group(1, &["z", "w"])];
+        assert_eq!(
+            vec![PathBuf::from("x"), PathBuf::from("y")],
+            top_groups(tied, 1)[0].paths
+        );
+        assert!(top_groups(groups, 0).is_empty());
+    }
+
+    #[test]
+    fn directories_of_groups() {
+        let group = |paths: &[&str]| DuplicateGroup {
+            hash: vec![],
+            size: 1,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        };
+        assert!(group(&["a/x", "a/y (1)", "a/z"]).is_in_one_dir());
+        assert!(!group(&["a/x", "a/b/x"]).is_in_one_dir());
+        assert!(!group(&["a/x", "b/x"]).is_in_one_dir());
+    }
 }
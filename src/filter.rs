@@ -0,0 +1,393 @@
+use std::fs::Metadata;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Values accepted as the type in `--min-size-for`, in the same order as the variants of
+/// `FileType`.
+pub const FILE_TYPE_VALUES: &[&str] = &["image", "video", "audio", "document", "archive"];
+
+/// Broad kind of contents a file holds, as far as can be told from its extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    Image,
+    Video,
+    Audio,
+    Document,
+    Archive,
+}
+
+impl FromStr for FileType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "image" => Ok(FileType::Image),
+            "video" => Ok(FileType::Video),
+            "audio" => Ok(FileType::Audio),
+            "document" => Ok(FileType::Document),
+            "archive" => Ok(FileType::Archive),
+            _ => Err(format!("unknown file type {:?}", s)),
+        }
+    }
+}
+
+impl FileType {
+    /// # Returns
+    ///
+    /// Type of the file at `path` judging by its extension, or `None` if the extension is
+    /// missing or not one fdup knows about.
+    pub fn of(path: &Path) -> Option<FileType> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "bmp" | "tif" | "tiff" | "webp" | "heic" | "svg"
+            | "ico" | "raw" | "cr2" | "nef" => Some(FileType::Image),
+            "mp4" | "m4v" | "mkv" | "mov" | "avi" | "wmv" | "webm" | "flv" | "mpg" | "mpeg"
+            | "3gp" => Some(FileType::Video),
+            "mp3" | "flac" | "ogg" | "opus" | "wav" | "m4a" | "aac" | "wma" | "aiff" => {
+                Some(FileType::Audio)
+            }
+            "pdf" | "doc" | "docx" | "odt" | "rtf" | "txt" | "md" | "xls" | "xlsx" | "ods"
+            | "ppt" | "pptx" | "odp" | "epub" => Some(FileType::Document),
+            "zip" | "tar" | "gz" | "tgz" | "bz2" | "xz" | "zst" | "7z" | "rar" => {
+                Some(FileType::Archive)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Smallest size a file of a given type must have to be considered, e.g. `image=100K`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MinSize {
+    pub file_type: FileType,
+    pub bytes: u64,
+}
+
+impl FromStr for MinSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let file_type = parts.next().unwrap_or("").parse()?;
+        let bytes = match parts.next() {
+            Some(size) => parse_size(size)?,
+            None => return Err(format!("expected a threshold like image=100K, got {:?}", s)),
+        };
+        Ok(MinSize { file_type, bytes })
+    }
+}
+
+/// # Returns
+///
+/// Number of bytes in a size like `512`, `100K`, `10M` or `2G`, where suffixes are powers of 1024.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a size like 100K, got {:?}", s);
+    let (digits, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        Some('T') => (&s[..s.len() - 1], 1 << 40),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(invalid)
+}
+
+/// Conditions a file has to meet to be considered at all.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Per-type size thresholds; files of types not listed here are never skipped for their size
+    pub min_sizes: Vec<MinSize>,
+    /// Files, and directories whose files, are never considered, spelled the way the walk of the
+    /// root reaches them
+    pub excluded: Vec<PathBuf>,
+    /// Skip zero-byte files, which would otherwise all end up in one group
+    pub skip_empty: bool,
+    /// Leave out, without walking into them, the files and directories that `.fdupignore` files
+    /// in the tree ignore
+    pub fdupignore: bool,
+    /// Unless empty, only files with one of these extensions, lowercase and without the dot, are
+    /// considered, whatever the case of theirs
+    pub extensions: Vec<String>,
+    /// If given, only files owned by the user with this id are considered
+    pub owner: Option<u32>,
+    /// If given, only files that belong to the group with this id are considered
+    pub group: Option<u32>,
+}
+
+impl Filter {
+    /// # Returns
+    ///
+    /// Whether the file at `path`, which is `size` bytes long, should be considered.
+    pub fn admits(&self, path: &Path, size: u64) -> bool {
+        if !self.admits_path(path) {
+            return false;
+        }
+        if self.skip_empty && size == 0 {
+            return false;
+        }
+        match FileType::of(path) {
+            Some(file_type) => self
+                .min_sizes
+                .iter()
+                .filter(|min| min.file_type == file_type)
+                .all(|min| size >= min.bytes),
+            None => true,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Whether the file at `path` should be considered as far as its path tells, so that files
+    /// that shouldn't can be left out without even looking at them.
+    pub fn admits_path(&self, path: &Path) -> bool {
+        if self
+            .excluded
+            .iter()
+            .any(|excluded| path.starts_with(excluded))
+        {
+            return false;
+        }
+        match self.extensions.is_empty() {
+            true => true,
+            false => path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(str::to_lowercase)
+                .is_some_and(|extension| self.extensions.contains(&extension)),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Whether a file with metadata `meta` has the owner and group asked for, if any.
+    #[cfg(unix)]
+    pub fn admits_owner(&self, meta: &Metadata) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        self.owner.map_or(true, |uid| meta.uid() == uid)
+            && self.group.map_or(true, |gid| meta.gid() == gid)
+    }
+
+    /// # Returns
+    ///
+    /// `true`, since files have no owner or group to go by on this OS.
+    #[cfg(not(unix))]
+    pub fn admits_owner(&self, _meta: &Metadata) -> bool {
+        true
+    }
+
+    /// # Returns
+    ///
+    /// Whether files are only considered with some owner or group, for which their metadata has
+    /// to be read.
+    pub fn restricts_owner(&self) -> bool {
+        self.owner.is_some() || self.group.is_some()
+    }
+
+    /// Make sure `file`, which need not exist yet, or everything under it if it is a directory, is
+    /// not considered when scanning `root`.
+    ///
+    /// # Returns
+    ///
+    /// Whether `file` is inside `root` at all.
+    pub fn exclude(&mut self, root: &Path, file: &Path) -> bool {
+        // canonicalize the parent rather than the file, which may not exist yet
+        let dir = file
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let relative = match (dir.canonicalize(), file.file_name(), root.canonicalize()) {
+            (Ok(dir), Some(name), Ok(canonical_root)) => dir
+                .join(name)
+                .strip_prefix(&canonical_root)
+                .map(Path::to_path_buf)
+                .ok(),
+            _ => None,
+        };
+        match relative {
+            Some(relative) => {
+                self.excluded.push(root.join(relative));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// # Returns
+///
+/// Id of `user`, given by name or as a number.
+#[cfg(unix)]
+pub fn user_id(user: &str) -> Result<u32, String> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let unknown = || format!("unknown user {:?}", user);
+    let name = std::ffi::CString::new(user).map_err(|_| unknown())?;
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        // safe, since `passwd` is a plain struct and `buffer` is as long as claimed
+        let (status, found, uid) = unsafe {
+            let mut passwd: libc::passwd = std::mem::zeroed();
+            let mut found = std::ptr::null_mut();
+            let status = libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            );
+            (status, !found.is_null(), passwd.pw_uid)
+        };
+        match (status, found) {
+            (libc::ERANGE, _) => buffer.resize(buffer.len() * 2, 0), // too long for the buffer
+            (0, true) => return Ok(uid),
+            _ => return Err(unknown()),
+        }
+    }
+}
+
+/// # Returns
+///
+/// Id of `group`, given by name or as a number.
+#[cfg(unix)]
+pub fn group_id(group: &str) -> Result<u32, String> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    let unknown = || format!("unknown group {:?}", group);
+    let name = std::ffi::CString::new(group).map_err(|_| unknown())?;
+    let mut buffer: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        // safe, since `entry` is a plain struct and `buffer` is as long as claimed
+        let (status, found, gid) = unsafe {
+            let mut entry: libc::group = std::mem::zeroed();
+            let mut found = std::ptr::null_mut();
+            let status = libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            );
+            (status, !found.is_null(), entry.gr_gid)
+        };
+        match (status, found) {
+            // e.g. a group with many members
+            (libc::ERANGE, _) => buffer.resize(buffer.len() * 2, 0),
+            (0, true) => return Ok(gid),
+            _ => return Err(unknown()),
+        }
+    }
+}
+
+/// # Returns
+///
+/// An error, since files have no owner to go by on this OS.
+#[cfg(not(unix))]
+pub fn user_id(_user: &str) -> Result<u32, String> {
+    Err(String::from("files have no owner on this OS"))
+}
+
+/// # Returns
+///
+/// An error, since files have no group to go by on this OS.
+#[cfg(not(unix))]
+pub fn group_id(_group: &str) -> Result<u32, String> {
+    Err(String::from("files have no group on this OS"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn thresholds_apply_per_type() {
+        assert_eq!(Ok(100 * 1024), parse_size("100K"));
+        assert_eq!(Ok(10 * 1024 * 1024), parse_size("10m"));
+        assert_eq!(Ok(7), parse_size("7"));
+        assert!(parse_size("K").is_err());
+        assert!("image".parse::<MinSize>().is_err());
+        assert!("font=1K".parse::<MinSize>().is_err());
+
+        let filter = Filter {
+            min_sizes: vec!["image=100K".parse().unwrap(), "video=10M".parse().unwrap()],
+            ..Filter::default()
+        };
+        assert!(!filter.admits(Path::new("thumb.JPG"), 5000));
+        assert!(filter.admits(Path::new("photo.jpg"), 200 * 1024));
+        assert!(!filter.admits(Path::new("clip.mp4"), 1 << 20));
+        assert!(filter.admits(Path::new("notes.txt"), 10));
+        assert!(filter.admits(Path::new("Makefile"), 10));
+        assert!(filter.admits(Path::new("Makefile"), 0));
+
+        let filter = Filter {
+            skip_empty: true,
+            ..Filter::default()
+        };
+        assert!(!filter.admits(Path::new("Makefile"), 0));
+        assert!(filter.admits(Path::new("Makefile"), 1));
+    }
+
+    #[test]
+    fn extensions_match_whatever_their_case() {
+        let filter = Filter {
+            extensions: vec![String::from("jpg"), String::from("cr2")],
+            ..Filter::default()
+        };
+        assert!(filter.admits_path(Path::new("photos/IMG_0001.JPG")));
+        assert!(filter.admits_path(Path::new("raw/a.Cr2")));
+        assert!(!filter.admits_path(Path::new("notes.txt")));
+        assert!(!filter.admits_path(Path::new("jpg")));
+        assert!(!filter.admits(Path::new("a.jpeg"), 100));
+        assert!(Filter::default().admits_path(Path::new("jpg")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn owners_are_looked_up_by_name_or_id() {
+        use std::os::unix::fs::MetadataExt;
+
+        assert_eq!(Ok(0), user_id("root"));
+        assert_eq!(Ok(1234), user_id("1234"));
+        assert!(user_id("no such user").is_err());
+        assert_eq!(Ok(0), group_id("root"));
+        assert!(group_id("no such group").is_err());
+
+        let meta = std::fs::metadata(file!()).unwrap();
+        let filter = |owner, group| Filter {
+            owner,
+            group,
+            ..Filter::default()
+        };
+        assert!(filter(None, None).admits_owner(&meta));
+        assert!(!filter(None, None).restricts_owner());
+        assert!(filter(Some(meta.uid()), Some(meta.gid())).admits_owner(&meta));
+        assert!(!filter(Some(meta.uid() + 1), None).admits_owner(&meta));
+        assert!(!filter(None, Some(meta.gid() + 1)).admits_owner(&meta));
+    }
+
+    #[test]
+    fn files_inside_the_root_are_excluded() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(root.join("sub")).unwrap();
+        let mut filter = Filter::default();
+
+        assert!(filter.exclude(&root, &root.join("sub/../cache.db")));
+        assert!(filter.exclude(&root, &root.join("sub/not-yet-created.db-wal")));
+        assert!(!filter.exclude(&root.join("sub"), &root.join("cache.db")));
+        assert!(!filter.admits(&root.join("cache.db"), 1));
+        assert!(!filter.admits(&root.join("sub/not-yet-created.db-wal"), 1));
+        assert!(filter.admits(&root.join("sub/cache.db"), 1));
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use crate::cache::RunSummary;
+use crate::time::rfc3339_from_secs;
+
+/// Width of the bar drawn for the run with the most redundant bytes under each root.
+const BAR_WIDTH: i64 = 40;
+
+/// Print the recorded runs as one table per root, with a bar per run proportional to the number
+/// of redundant bytes, so that trends are visible at a glance.
+///
+/// # Parameters
+///
+/// - `runs`: run summaries, ordered by root and then chronologically
+/// - `plain`: leave out the bars, so every line is just columns of text
+/// - `local`: show dates in the local time zone instead of UTC
+pub fn print_history(runs: &[RunSummary], plain: bool, local: bool) {
+    let mut remaining = runs;
+    while let Some(first) = remaining.first() {
+        let len = remaining
+            .iter()
+            .take_while(|run| run.root == first.root)
+            .count();
+        let (same_root, rest) = remaining.split_at(len);
+        remaining = rest;
+
+        let max_bytes = same_root
+            .iter()
+            .map(|run| run.redundant_bytes)
+            .max()
+            .unwrap_or(0);
+
+        println!("{}", first.root);
+        println!(
+            "  {:<20}  {:>8}  {:>8}  {:>16}  {:>16}",
+            "DATE", "GROUPS", "COPIES", "REDUNDANT BYTES", "CHANGE"
+        );
+        let mut previous: Option<i64> = None;
+        for run in same_root {
+            let change = match previous {
+                Some(prev) => format!("{:+}", run.redundant_bytes - prev),
+                None => String::new(),
+            };
+            let bar_len = match (plain, max_bytes) {
+                (true, _) | (_, 0) => 0,
+                (false, max) => run.redundant_bytes * BAR_WIDTH / max,
+            };
+            let line = format!(
+                "  {:<20}  {:>8}  {:>8}  {:>16}  {:>16}  {}",
+                rfc3339_from_secs(run.timestamp, local),
+                run.groups,
+                run.redundant_files,
+                run.redundant_bytes,
+                change,
+                "#".repeat(bar_len as usize)
+            );
+            println!("{}", line.trim_end());
+            previous = Some(run.redundant_bytes);
+        }
+        println!();
+    }
+}
@@ -0,0 +1,290 @@
+use crate::error::ErrorLog;
+use crate::error::FdupError;
+use crate::media::MediaKind;
+use crate::walk::plain_path;
+use rayon::prelude::*;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+use zune_jpeg::zune_core::colorspace::ColorSpace;
+use zune_jpeg::zune_core::options::DecoderOptions;
+use zune_jpeg::JpegDecoder;
+
+/// Pictures that look alike without being copies, e.g. the same photo resized or re-encoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimilarGroup {
+    /// Largest number of bits by which the perceptual hashes of any two of the pictures differ
+    pub distance: u32,
+    /// Paths of the pictures, sorted
+    pub paths: Vec<PathBuf>,
+}
+
+/// Picture decoded into one brightness byte per pixel, row by row.
+struct Gray {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+/// # Returns
+///
+/// Difference hash of the PNG or JPEG picture at `path`, or `None` if it is neither: the picture
+/// is shrunk to 9 by 8 pixels of gray, and each bit tells whether a pixel is brighter than the one
+/// to its right. Since only the broad shapes of the picture are left, resizing, re-encoding or
+/// slightly editing it changes few bits, if any.
+pub fn dhash(path: &Path) -> Result<Option<u64>, FdupError> {
+    let gray = match MediaKind::of(path).map_err(|err| FdupError::io(path, err))? {
+        Some(MediaKind::Png) => png_gray(path),
+        Some(MediaKind::Jpeg) => jpeg_gray(path),
+        _ => return Ok(None),
+    };
+    let gray = gray.map_err(|err| FdupError::io(path, err))?;
+    if gray.width == 0 || gray.height == 0 {
+        return Err(FdupError::io(path, undecodable("empty picture")));
+    }
+
+    let small = shrink(&gray, 9, 8);
+    let hash = (0..8)
+        .flat_map(|y| (0..8).map(move |x| (y, x)))
+        .fold(0, |hash, (y, x)| {
+            let brighter = small[y * 9 + x] > small[y * 9 + x + 1];
+            (hash << 1) | u64::from(brighter)
+        });
+    Ok(Some(hash))
+}
+
+/// # Returns
+///
+/// Number of bits by which `a` and `b` differ.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// # Parameters
+///
+/// 1. `paths` -- files to look among, of which those that aren't PNG or JPEG are left out
+/// 1. `max_distance` -- most bits by which the hashes of two pictures may differ for them to be
+///    alike
+/// 1. `errors` -- where pictures that can't be read or decoded are recorded
+///
+/// # Returns
+///
+/// Groups of pictures that are alike, each picture being within `max_distance` of at least one
+/// other of its group, sorted by their first path. Every pair of pictures is compared, which is
+/// quick next to decoding them even for tens of thousands.
+pub fn similar_images(
+    paths: &[PathBuf],
+    max_distance: u32,
+    errors: &ErrorLog,
+) -> Vec<SimilarGroup> {
+    let hashed: Vec<(&PathBuf, u64)> = paths
+        .par_iter()
+        .filter_map(|path| match dhash(path) {
+            Ok(hash) => hash.map(|hash| (path, hash)),
+            Err(err) => {
+                errors.record("decode", &err);
+                None
+            }
+        })
+        .collect();
+
+    // each picture points towards the first of its group, like a disjoint-set forest
+    let mut parents: Vec<usize> = (0..hashed.len()).collect();
+    fn first(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+    for i in 0..hashed.len() {
+        for j in i + 1..hashed.len() {
+            if distance(hashed[i].1, hashed[j].1) <= max_distance {
+                let (a, b) = (first(&mut parents, i), first(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); hashed.len()];
+    for i in 0..hashed.len() {
+        members[first(&mut parents, i)].push(i);
+    }
+    let mut groups: Vec<SimilarGroup> = members
+        .into_iter()
+        .filter(|members| members.len() > 1)
+        .map(|members| {
+            let hashes = members.iter().map(|&i| hashed[i].1);
+            let widest = hashes
+                .clone()
+                .flat_map(|a| hashes.clone().map(move |b| distance(a, b)))
+                .max()
+                .unwrap_or(0);
+            let mut paths: Vec<PathBuf> = members.iter().map(|&i| hashed[i].0.clone()).collect();
+            paths.sort();
+            SimilarGroup {
+                distance: widest,
+                paths,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| a.paths.cmp(&b.paths));
+    groups
+}
+
+/// Print `groups` to stderr, so that they don't mix with the duplicates on stdout.
+pub fn print_similar_images(groups: &[SimilarGroup]) {
+    eprintln!("similar images: {}", groups.len());
+    for group in groups {
+        eprintln!("  within {} bits:", group.distance);
+        for path in group.paths.iter() {
+            eprintln!("    {:?}", plain_path(path));
+        }
+    }
+}
+
+fn undecodable(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// # Returns
+///
+/// Average brightness of each of `width` by `height` equally sized areas of `gray`, row by row.
+fn shrink(gray: &Gray, width: usize, height: usize) -> Vec<f64> {
+    let span = |i: usize, of: usize, into: usize| {
+        let start = i * of / into;
+        start..((i + 1) * of / into).max(start + 1) // at least one pixel, for tiny pictures
+    };
+    let mut small = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let rows = span(y, gray.height, height);
+        for x in 0..width {
+            let columns = span(x, gray.width, width);
+            let count = rows.len() * columns.len();
+            let total: u64 = rows
+                .clone()
+                .flat_map(|row| {
+                    let line = &gray.pixels[row * gray.width..(row + 1) * gray.width];
+                    line[columns.clone()].iter().map(|&pixel| u64::from(pixel))
+                })
+                .sum();
+            small.push(total as f64 / count as f64);
+        }
+    }
+    small
+}
+
+fn png_gray(path: &Path) -> io::Result<Gray> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(undecodable)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(undecodable)?;
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+    let pixels = buffer
+        .chunks(info.line_size)
+        .take(height)
+        .flat_map(|line| line.chunks(channels).take(width))
+        .map(|pixel| match pixel {
+            // as bright as it would look, going by the Rec. 601 weights JPEG uses too
+            [r, g, b, ..] => {
+                let luma = 299 * u32::from(*r) + 587 * u32::from(*g) + 114 * u32::from(*b);
+                (luma / 1000) as u8
+            }
+            [gray, ..] => *gray,
+            [] => 0,
+        })
+        .collect();
+    Ok(Gray {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn jpeg_gray(path: &Path) -> io::Result<Gray> {
+    let contents = fs::read(path)?;
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::Luma);
+    let mut decoder = JpegDecoder::new_with_options(&contents[..], options);
+    let pixels = decoder
+        .decode()
+        .map_err(|err| undecodable(format!("{:?}", err)))?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| undecodable("JPEG without a frame"))?;
+    Ok(Gray {
+        width: usize::from(info.width),
+        height: usize::from(info.height),
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+
+    /// Write a grayscale PNG of `width` by `height` pixels, with `brightness` of each pixel.
+    fn write_png<F>(path: &Path, width: u32, height: u32, brightness: F)
+    where
+        F: Fn(u32, u32) -> u8,
+    {
+        let file = File::create(path).unwrap();
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        let mut writer = encoder.write_header().unwrap();
+        let pixels: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| brightness(x, y))
+            .collect();
+        writer.write_image_data(&pixels).unwrap();
+    }
+
+    #[test]
+    fn resized_pictures_are_alike() {
+        let dir = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let rings = |scale: u32| {
+            move |x: u32, y: u32| {
+                let (dx, dy) = (
+                    x as f64 / scale as f64 - 32.0,
+                    y as f64 / scale as f64 - 24.0,
+                );
+                ((dx * dx + dy * dy).sqrt() * 12.0) as u8
+            }
+        };
+        write_png(&dir.join("big.png"), 128, 96, rings(2));
+        write_png(&dir.join("small.png"), 64, 48, rings(1));
+        write_png(&dir.join("stripes.png"), 64, 48, |x, _| (x % 16 * 16) as u8);
+        fs::write(dir.join("notes.txt"), "not a picture").unwrap();
+        fs::write(dir.join("broken.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+
+        let big = dhash(&dir.join("big.png")).unwrap().unwrap();
+        let small = dhash(&dir.join("small.png")).unwrap().unwrap();
+        assert!(distance(big, small) <= 2);
+        assert_eq!(None, dhash(&dir.join("notes.txt")).unwrap());
+
+        let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        paths.sort();
+        let errors = ErrorLog::new(false);
+        let groups = similar_images(&paths, 4, &errors);
+        assert_eq!(1, groups.len());
+        assert_eq!(
+            vec![dir.join("big.png"), dir.join("small.png")],
+            groups[0].paths
+        );
+        assert!(groups[0].distance <= 2);
+        assert_eq!(1, errors.len());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
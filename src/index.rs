@@ -0,0 +1,148 @@
+use crate::cache::HashCache;
+use crate::fdup::duplicate_groups;
+use crate::fdup::DuplicateGroup;
+use crate::fdup::ScanConfig;
+use crate::filter::Filter;
+use crate::walk::files;
+use crate::walk::Traversal;
+use std::collections::HashMap;
+use std::iter::FromIterator;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Results of a finished scan, indexed by path so that per-file questions are answered in constant
+/// time instead of by searching every group.
+///
+/// Paths are looked up exactly as the scan reported them, i.e. joined onto the root as it was
+/// given to `duplicate_groups`.
+#[derive(Clone, Debug, Default)]
+pub struct ScanIndex {
+    groups: Vec<DuplicateGroup>,
+    /// index into `groups` of the group each path belongs to
+    by_path: HashMap<PathBuf, usize>,
+    /// index into `groups` of the group with each checksum
+    by_hash: HashMap<Vec<u8>, usize>,
+}
+
+impl ScanIndex {
+    /// # Returns
+    ///
+    /// Index over `groups`, typically the output of `duplicate_groups`.
+    pub fn new<I>(groups: I) -> ScanIndex
+    where
+        I: IntoIterator<Item = DuplicateGroup>,
+    {
+        let groups: Vec<DuplicateGroup> = groups.into_iter().collect();
+        let by_path = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, group)| group.paths.iter().map(move |path| (path.clone(), i)))
+            .collect();
+        let by_hash = groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| (group.hash.clone(), i))
+            .collect();
+        ScanIndex {
+            groups,
+            by_path,
+            by_hash,
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The group `path` is part of, if it has any duplicates.
+    pub fn duplicates_of(&self, path: &Path) -> Option<&DuplicateGroup> {
+        self.by_path.get(path).map(|&i| &self.groups[i])
+    }
+
+    /// # Returns
+    ///
+    /// The group of files whose checksum is `hash`, if there are at least two of them.
+    pub fn with_hash(&self, hash: &[u8]) -> Option<&DuplicateGroup> {
+        self.by_hash.get(hash).map(|&i| &self.groups[i])
+    }
+
+    /// # Returns
+    ///
+    /// Whether `path` has no duplicates, which includes every path the scan never saw.
+    pub fn is_unique(&self, path: &Path) -> bool {
+        !self.by_path.contains_key(path)
+    }
+
+    /// # Returns
+    ///
+    /// Every duplicate group, in the order the scan produced them.
+    pub fn groups(&self) -> &[DuplicateGroup] {
+        &self.groups
+    }
+}
+
+/// # Parameters
+///
+/// 1. `cache` -- if given, checksums are read from and written to it instead of always hashing
+/// 1. `filter` -- conditions files have to meet to be considered at all
+/// 1. `traversal` -- order in which to walk the trees
+/// 1. `root` -- directory whose unique files to find
+/// 1. `roots` -- directories to search for copies, which must cover `root`
+///
+/// # Returns
+///
+/// Files under `root` whose contents appear nowhere else under any of `roots`, sorted by path.
+pub fn unique_files(
+    cache: Option<&HashCache>,
+    filter: &Filter,
+    traversal: Traversal,
+    root: &Path,
+    roots: &[&Path],
+) -> Vec<PathBuf> {
+    let config = ScanConfig {
+        cache,
+        traversal,
+        ..ScanConfig::new(filter)
+    };
+    let index: ScanIndex = duplicate_groups(config, roots).collect();
+    files(root, traversal, filter)
+        .into_iter()
+        .filter(|path| index.is_unique(path))
+        .collect()
+}
+
+impl FromIterator<DuplicateGroup> for ScanIndex {
+    fn from_iter<I: IntoIterator<Item = DuplicateGroup>>(groups: I) -> Self {
+        ScanIndex::new(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookups_by_path() {
+        let group = |hash: u8, paths: &[&str]| DuplicateGroup {
+            hash: vec![hash],
+            size: 1,
+            paths: paths.iter().map(PathBuf::from).collect(),
+        };
+        let index: ScanIndex = vec![group(1, &["a", "b"]), group(2, &["c", "d", "e"])]
+            .into_iter()
+            .collect();
+
+        assert_eq!(
+            Some(&[2][..]),
+            index.duplicates_of(Path::new("e")).map(|g| &g.hash[..])
+        );
+        assert_eq!(
+            Some(&[1][..]),
+            index.duplicates_of(Path::new("a")).map(|g| &g.hash[..])
+        );
+        assert_eq!(None, index.duplicates_of(Path::new("f")));
+        assert_eq!(3, index.with_hash(&[2]).unwrap().paths.len());
+        assert_eq!(None, index.with_hash(&[3]));
+        assert!(index.is_unique(Path::new("f")));
+        assert!(!index.is_unique(Path::new("b")));
+        assert_eq!(2, index.groups().len());
+    }
+}
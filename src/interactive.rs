@@ -0,0 +1,105 @@
+use crate::fdup::DuplicateGroup;
+use crate::time::mtime;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+/// What the user decided to do with a duplicate group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Keep the files at these indices, and get rid of the rest
+    Keep(Vec<usize>),
+    /// Leave the whole group alone
+    Skip,
+    /// Leave this and every remaining group alone
+    Quit,
+}
+
+/// List the members of `group` with their sizes and mtimes, then ask on stdin which of them to
+/// keep, until a valid answer is given.
+///
+/// # Parameters
+///
+/// 1. `local` -- show mtimes in the local time zone instead of UTC
+/// 1. `index` -- position of the group in the output, starting from 1
+/// 1. `group` -- files with identical contents
+pub fn ask(local: bool, index: usize, group: &DuplicateGroup) -> io::Result<Decision> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "Set {}, {} files of {} bytes:",
+        index,
+        group.paths.len(),
+        group.size
+    )?;
+    for (i, path) in group.paths.iter().enumerate() {
+        let mtime = mtime(path, local).unwrap_or_else(|| String::from("?"));
+        writeln!(out, "  [{}] {}  {:?}", i + 1, mtime, path)?;
+    }
+
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        write!(
+            out,
+            "Preserve files [1 - {}, all, quit] (e.g. \"1 3\"): ",
+            group.paths.len()
+        )?;
+        out.flush()?;
+
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(Decision::Quit); // stdin closed
+        }
+        match parse_answer(&line, group.paths.len()) {
+            Some(decision) => return Ok(decision),
+            None => writeln!(out, "Invalid answer {:?}", line.trim())?,
+        }
+    }
+}
+
+/// # Returns
+///
+/// The decision expressed by `answer` for a group of `len` files, or `None` if it is malformed or
+/// would not keep anything.
+fn parse_answer(answer: &str, len: usize) -> Option<Decision> {
+    match answer.trim() {
+        "a" | "all" => return Some(Decision::Skip),
+        "q" | "quit" => return Some(Decision::Quit),
+        _ => (),
+    };
+
+    let mut kept = Vec::new();
+    for word in answer.split(|c: char| c == ',' || c.is_whitespace()) {
+        if word.is_empty() {
+            continue;
+        }
+        match word.parse::<usize>() {
+            Ok(n) if 1 <= n && n <= len => kept.push(n - 1),
+            _ => return None,
+        }
+    }
+    kept.dedup();
+    match kept.is_empty() {
+        true => None,
+        false => Some(Decision::Keep(kept)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers() {
+        assert_eq!(Some(Decision::Keep(vec![0, 2])), parse_answer("1, 3\n", 3));
+        assert_eq!(Some(Decision::Skip), parse_answer("all\n", 3));
+        assert_eq!(Some(Decision::Quit), parse_answer("q", 3));
+        assert_eq!(None, parse_answer("4", 3));
+        assert_eq!(None, parse_answer("\n", 3));
+        assert_eq!(None, parse_answer("1 x", 3));
+    }
+}
@@ -0,0 +1,57 @@
+//! Everything behind the `fdup` command line tool, for embedders that want to find duplicates
+//! in-process.
+
+pub mod action;
+pub mod archive;
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod background;
+pub mod cache;
+pub mod capabilities;
+pub mod checkpoint;
+pub mod clargs;
+pub mod compare;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod decompress;
+pub mod dirs;
+pub mod error;
+pub mod extents;
+pub mod fdup;
+pub mod filter;
+pub mod history;
+#[cfg(feature = "images")]
+pub mod images;
+pub mod index;
+pub mod interactive;
+pub mod manifest;
+pub mod markdown;
+pub mod media;
+pub mod normalize;
+pub mod output;
+pub mod pdf;
+pub mod printer;
+pub mod rdfind;
+pub mod report;
+pub mod rmlint;
+pub mod sample;
+pub mod script;
+pub mod spill;
+pub mod storage;
+pub mod streams;
+pub mod text;
+pub mod throttle;
+pub mod time;
+pub mod timeout;
+pub mod trash;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod walk;
+pub mod watch;
+pub mod xattrs;
+
+pub use crate::error::FdupError;
+pub use crate::fdup::duplicate_groups;
+pub use crate::fdup::DuplicateGroup;
+pub use crate::index::ScanIndex;
@@ -1,13 +1,970 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use structopt::clap::ErrorKind;
 use structopt::StructOpt;
 
-mod clargs;
-mod fdup;
-
-use clargs::*;
-use fdup::*;
+use fdup::archive::*;
+#[cfg(feature = "audio")]
+use fdup::audio::*;
+use fdup::background::*;
+use fdup::cache::*;
+use fdup::capabilities::*;
+use fdup::checkpoint::*;
+use fdup::clargs::*;
+use fdup::compare::*;
+use fdup::config::*;
+use fdup::dirs::*;
+use fdup::error::*;
+use fdup::fdup::*;
+use fdup::filter::*;
+use fdup::history::*;
+#[cfg(feature = "images")]
+use fdup::images::*;
+use fdup::index::*;
+use fdup::manifest::*;
+use fdup::markdown::write_markdown;
+use fdup::output::relative_path;
+use fdup::printer::*;
+use fdup::report::*;
+use fdup::storage::default_threads;
+use fdup::storage::storage_kind;
+use fdup::streams::Streams;
+use fdup::text::TextNormalize;
+use fdup::throttle::set_throttle;
+use fdup::throttle::Throttle;
+use fdup::time::rfc3339;
+use fdup::timeout::set_file_timeout;
+#[cfg(feature = "tui")]
+use fdup::tui;
+use fdup::walk::*;
+#[cfg(feature = "watch")]
+use fdup::watch::*;
 
 fn main() {
-    let Opt { sort_vec, root } = Opt::from_args();
+    let matches = parse_args(env::args_os()).unwrap_or_else(|err| match err.kind {
+        ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => err.exit(),
+        _ => {
+            eprintln!("{}", err.message);
+            process::exit(EXIT_FATAL);
+        }
+    });
+    let mut opt = Opt::from_clap(&matches);
+    let configured = Config::load(opt.config.as_deref())
+        .and_then(|file| Ok(file.unwrap_or_default().overlay(Config::from_env()?)))
+        .and_then(|config| config.apply(&mut opt, &matches));
+    if let Err(err) = configured {
+        eprintln!("ERROR {}", err);
+        process::exit(EXIT_FATAL);
+    }
+    canonicalize_roots(&mut opt);
+    extend_roots(&mut opt);
+    if opt.nice && !opt.background {
+        // before any threads are started, so that they inherit it
+        enter_low_priority();
+    }
+    match (opt.background, opt.threads) {
+        (true, _) => enter_background(),
+        (false, Some(threads)) => set_threads(threads),
+        (false, None) => adapt_threads(&mut opt),
+    }
+    if let Some(secs) = opt.file_timeout {
+        set_file_timeout(Duration::from_secs_f64(secs));
+    }
+    if let Some(megabytes_per_sec) = opt.throttle {
+        let steady = Throttle::steady(megabytes_per_sec);
+        set_throttle(match opt.background {
+            true => Throttle {
+                bytes_per_sec: steady.bytes_per_sec,
+                ..BACKGROUND_THROTTLE
+            },
+            false => steady,
+        });
+    }
+    if opt.capabilities {
+        match serde_json::to_string_pretty(&Capabilities::current()) {
+            Ok(json) => println!("{}", json),
+            Err(err) => {
+                eprintln!("ERROR: {}", err);
+                process::exit(EXIT_FATAL);
+            }
+        }
+        return;
+    }
+
+    let no_action = DedupOpt::default();
+    match &opt.cmd {
+        Some(Command::Scan { scan }) | Some(Command::Dedup { scan, .. })
+            if scan.similar_images.is_some() && !cfg!(feature = "images") =>
+        {
+            eprintln!("ERROR: fdup was built without the `images` feature");
+            process::exit(EXIT_FATAL);
+        }
+        Some(Command::Scan { scan }) | Some(Command::Dedup { scan, .. })
+            if scan.similar_audio && !cfg!(feature = "audio") =>
+        {
+            eprintln!("ERROR: fdup was built without the `audio` feature");
+            process::exit(EXIT_FATAL);
+        }
+        Some(Command::Scan { scan }) if scan.unique => unique(scan),
+        Some(Command::Scan { scan }) => process::exit(run_scan(scan, &no_action, opt.local_time)),
+        Some(Command::Dedup { dedup, .. })
+            if dedup.action().is_none() && !dedup.interactive && dedup.emit_script.is_none() =>
+        {
+            eprintln!(
+                "ERROR: nothing to do; choose one of --delete, --trash, --hardlink, --symlink, \
+                 --reflink, --interactive or --emit-script"
+            );
+            process::exit(EXIT_FATAL);
+        }
+        Some(Command::Dedup { scan, .. }) | Some(Command::Tui { scan, .. })
+            if scan.group_by != GroupBy::Content
+                || scan
+                    .stages
+                    .as_ref()
+                    .is_some_and(|stages| !stages.is_exact()) =>
+        {
+            eprintln!(
+                "ERROR: files grouped by name, or by stages without hash or bytes, can only be \
+                 listed, since their contents were never compared in full"
+            );
+            process::exit(EXIT_FATAL);
+        }
+        Some(Command::Dedup { scan, .. }) | Some(Command::Tui { scan, .. })
+            if scan.text_normalize != TextNormalize::None
+                || scan.strip_metadata
+                || scan.pdf_content =>
+        {
+            eprintln!(
+                "ERROR: copies found with --text-normalize, --strip-metadata or --pdf-content \
+                 hold different bytes, so they can't be checked again before acting on them, \
+                 and can only be listed"
+            );
+            process::exit(EXIT_FATAL);
+        }
+        Some(Command::Dedup { scan, .. }) | Some(Command::Tui { scan, .. })
+            if scan.scan_archives =>
+        {
+            eprintln!(
+                "ERROR: files inside archives can't be deleted or linked, so --scan-archives can \
+                 only be used to list them"
+            );
+            process::exit(EXIT_FATAL);
+        }
+        Some(Command::Dedup { scan, .. }) | Some(Command::Tui { scan, .. }) if scan.decompress => {
+            eprintln!(
+                "ERROR: copies found with --decompress hold different bytes, so they can't be \
+                 checked again before acting on them, and can only be listed"
+            );
+            process::exit(EXIT_FATAL);
+        }
+        Some(Command::Dedup { scan, dedup }) => {
+            process::exit(run_scan(scan, dedup, opt.local_time))
+        }
+        Some(Command::History { db }) => history(db, opt.plain, opt.local_time),
+        Some(Command::Cache { cmd }) => cache_command(cmd),
+        Some(Command::Merge { out, inputs }) => merge_reports(out, inputs),
+        Some(Command::Diff { old, new }) => diff_reports(old, new),
+        Some(Command::Compare { walk, left, right }) => compare_trees(walk, left, right),
+        Some(Command::Verify {
+            walk,
+            manifest,
+            root,
+        }) => process::exit(verify_tree(walk, manifest, root)),
+        Some(Command::Watch { walk, root }) => watch_tree(walk, root),
+        Some(Command::Daemon {
+            walk,
+            socket,
+            roots,
+        }) => daemon(walk, socket, roots, opt.local_time),
+        Some(Command::Tui { scan, dedup }) => review(scan, dedup, opt.plain, opt.local_time),
+        None => {
+            eprintln!("ERROR: no root directory given, see --help");
+            process::exit(EXIT_FATAL);
+        }
+    }
+}
+
+/// With `--canonical`, replace the directories given on the command line by their canonical
+/// forms, so that every path found under them is canonical too, exiting the process if one can't
+/// be resolved.
+fn canonicalize_roots(opt: &mut Opt) {
+    if !opt.walk_mut().is_some_and(|walk| walk.canonical) {
+        return;
+    }
+    for_each_root(opt, |path| match path.canonicalize() {
+        Ok(canonical) => *path = canonical,
+        Err(err) => {
+            eprintln!("ERROR resolving {:?}: {}", path, err);
+            process::exit(EXIT_FATAL);
+        }
+    });
+}
+
+/// Replace the directories given on the command line by their extended-length forms, so that
+/// files deeper than `MAX_PATH` under them can be opened too. Printed paths lose the prefix again
+/// wherever they can.
+#[cfg(windows)]
+fn extend_roots(opt: &mut Opt) {
+    for_each_root(opt, |path| *path = extended_length_path(path));
+}
+
+#[cfg(not(windows))]
+fn extend_roots(_opt: &mut Opt) {}
+
+/// Hash on fewer threads than one per CPU if the storage of the directories given on the command
+/// line calls for it.
+fn adapt_threads(opt: &mut Opt) {
+    let mut kinds = Vec::new();
+    for_each_root(opt, |root| kinds.push(storage_kind(root)));
+    if let Some(threads) = default_threads(&kinds) {
+        set_threads(threads);
+    }
+}
+
+/// Apply `f` to each directory given on the command line to walk.
+fn for_each_root<F>(opt: &mut Opt, mut f: F)
+where
+    F: FnMut(&mut PathBuf),
+{
+    if let Some(scan) = opt.scan_mut() {
+        f(&mut scan.root);
+        scan.reference.iter_mut().for_each(&mut f);
+    }
+    match &mut opt.cmd {
+        Some(Command::Compare { left, right, .. }) => {
+            f(left);
+            f(right);
+        }
+        Some(Command::Verify { root, .. }) | Some(Command::Watch { root, .. }) => f(root),
+        _ => (),
+    }
+}
+
+/// Open the cache at `path`, exiting the process if that is not possible.
+fn open_cache(path: &Path) -> HashCache {
+    match HashCache::open(path) {
+        Ok(cache) => cache,
+        Err(err) => {
+            eprintln!("ERROR opening cache {:?}: {}", path, err);
+            process::exit(EXIT_FATAL);
+        }
+    }
+}
+
+/// # Returns
+///
+/// Directories to scan for duplicates of files under the root: the reference tree, if any, and
+/// the root itself, unless one contains the other.
+fn roots(scan: &ScanOpt) -> Vec<&Path> {
+    match &scan.reference {
+        Some(reference) => distinct_roots(&[reference, &scan.root]),
+        None => vec![&scan.root],
+    }
+}
+
+/// # Returns
+///
+/// Conditions given on the command line that files under `roots` have to meet to be considered,
+/// which always exclude the files fdup itself is writing to.
+fn filter(walk: &WalkOpt, roots: &[&Path]) -> Filter {
+    let mut filter = Filter {
+        min_sizes: walk.min_size_for.clone(),
+        skip_empty: !walk.group_empty,
+        fdupignore: !walk.no_fdupignore,
+        extensions: walk
+            .ext
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_lowercase())
+            .collect(),
+        ..Filter::default()
+    };
+    let owner = walk.owner.as_deref().map(user_id).transpose();
+    let group = walk.group.as_deref().map(group_id).transpose();
+    match (owner, group) {
+        (Ok(owner), Ok(group)) => {
+            filter.owner = owner;
+            filter.group = group;
+        }
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("ERROR {}", err);
+            process::exit(EXIT_FATAL);
+        }
+    }
+    for excluded in walk.exclude.iter() {
+        for root in roots {
+            filter.exclude(root, excluded);
+        }
+    }
+    if let Some(db) = &walk.cache {
+        exclude_own_file(&mut filter, roots, "cache", db);
+        for root in roots {
+            // sqlite keeps its journal next to the database
+            for suffix in ["-wal", "-shm", "-journal"].iter() {
+                let mut journal = db.clone().into_os_string();
+                journal.push(suffix);
+                filter.exclude(root, Path::new(&journal));
+            }
+        }
+    }
+    filter
+}
+
+/// # Returns
+///
+/// `filter` of `scan.walk`, which also leaves out the checkpoint, since it is written to while
+/// files are hashed.
+fn scan_filter(scan: &ScanOpt, roots: &[&Path]) -> Filter {
+    let mut filter = filter(&scan.walk, roots);
+    if let Some(checkpoint) = scan.checkpoint.as_ref().or(scan.resume.as_ref()) {
+        exclude_own_file(&mut filter, roots, "checkpoint", checkpoint);
+    }
+    filter
+}
+
+/// Leave `file`, which fdup itself writes to, out of the scan of every one of `roots` it is
+/// inside, warning that it does so; `what` is what the file is for.
+fn exclude_own_file(filter: &mut Filter, roots: &[&Path], what: &str, file: &Path) {
+    for root in roots {
+        if filter.exclude(root, file) {
+            eprintln!(
+                "WARNING: not scanning the {} {:?}, which is inside {:?}",
+                what, file, root
+            );
+        }
+    }
+}
+
+/// # Returns
+///
+/// How to search for duplicates as given on the command line.
+fn scan_config<'a>(
+    scan: &'a ScanOpt,
+    cache: Option<&'a HashCache>,
+    filter: &'a Filter,
+) -> ScanConfig<'a> {
+    if scan.stages.is_some() && (scan.group_by != GroupBy::Content || scan.prehash != Prehash::None)
+    {
+        eprintln!("ERROR: --stages can't be combined with --group-by or --prehash");
+        process::exit(EXIT_FATAL);
+    }
+    if scan.scan_archives
+        && (scan.group_by != GroupBy::Content
+            || scan.low_memory
+            || scan.match_mtime
+            || scan.match_xattrs)
+    {
+        eprintln!(
+            "ERROR: --scan-archives can't be combined with --group-by, --low-memory, \
+             --match-mtime or --match-xattrs"
+        );
+        process::exit(EXIT_FATAL);
+    }
+    if scan.ignore_case && scan.group_by == GroupBy::Content {
+        eprintln!("ERROR: --ignore-case only applies to --group-by name or name-size");
+        process::exit(EXIT_FATAL);
+    }
+    ScanConfig {
+        sort_vec: scan.sort_vec,
+        cache,
+        shard: scan.shard,
+        min_copies: scan.min_copies,
+        traversal: scan.walk.traversal,
+        prehash: scan.prehash,
+        stages: scan.stages.as_ref(),
+        read_order: scan.read_order,
+        verbose: scan.verbose,
+        low_memory: scan.low_memory,
+        streams: scan.streams,
+        skip_busy: scan.skip_busy,
+        group_by: scan.group_by,
+        ignore_case: scan.ignore_case,
+        normalize: scan.normalize,
+        text_normalize: scan.text_normalize,
+        strip_metadata: scan.strip_metadata,
+        pdf_content: scan.pdf_content,
+        decompress: scan.decompress,
+        match_mtime: scan.match_mtime,
+        match_xattrs: scan.match_xattrs,
+        match_acls: scan.match_acls,
+        ..ScanConfig::new(filter)
+    }
+}
+
+/// # Returns
+///
+/// Whether `group` is worth reporting; with `--reference`, only groups with copies both inside
+/// and outside the reference tree are, with `--cross-dir-only`, only groups with copies in more
+/// than one directory, and with `--same-dir-only`, only groups within a single directory.
+fn is_reported(scan: &ScanOpt, group: &DuplicateGroup) -> bool {
+    if scan.cross_dir_only && group.is_in_one_dir() {
+        return false;
+    }
+    if scan.same_dir_only && !group.is_in_one_dir() {
+        return false;
+    }
+    match &scan.reference {
+        Some(reference) => group.spans(
+            &reference
+                .canonicalize()
+                .unwrap_or_else(|_| reference.clone()),
+        ),
+        None => true,
+    }
+}
+
+fn history(db: &Path, plain: bool, local: bool) {
+    match open_cache(db).runs() {
+        Ok(runs) => print_history(&runs, plain, local),
+        Err(err) => {
+            eprintln!("ERROR reading history from {:?}: {}", db, err);
+            process::exit(EXIT_FATAL);
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+fn review(scan: &ScanOpt, dedup: &DedupOpt, plain: bool, local: bool) {
+    let cache = scan.walk.cache.as_deref().map(open_cache);
+    let roots = roots(scan);
+    let filter = scan_filter(scan, &roots);
+    let groups = duplicate_groups(scan_config(scan, cache.as_ref(), &filter), &roots)
+        .filter(|group| is_reported(scan, group))
+        .collect();
+    if let Err(err) = tui::run(
+        groups,
+        &scan.policy(dedup),
+        dedup.delete_action(),
+        plain,
+        local,
+    ) {
+        eprintln!("ERROR: {}", err);
+        process::exit(EXIT_FATAL);
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+fn review(_scan: &ScanOpt, _dedup: &DedupOpt, _plain: bool, _local: bool) {
+    eprintln!("ERROR: fdup was built without the `tui` feature");
+    process::exit(EXIT_FATAL);
+}
+
+/// Print the pictures among `files` whose perceptual hashes differ by at most `max_distance`
+/// bits, recording those that can't be decoded in `errors`.
+#[cfg(feature = "images")]
+fn report_similar_images(files: &[PathBuf], max_distance: u32, errors: &ErrorLog) {
+    print_similar_images(&similar_images(files, max_distance, errors));
+}
+
+#[cfg(not(feature = "images"))]
+fn report_similar_images(_files: &[PathBuf], _max_distance: u32, _errors: &ErrorLog) {
+    eprintln!("ERROR: fdup was built without the `images` feature");
+    process::exit(EXIT_FATAL);
+}
+
+/// Print the tracks among `files` that sound the same, recording those that can't be decoded in
+/// `errors`.
+#[cfg(feature = "audio")]
+fn report_similar_audio(files: &[PathBuf], errors: &ErrorLog) {
+    print_similar_audio(&similar_audio(files, errors));
+}
+
+#[cfg(not(feature = "audio"))]
+fn report_similar_audio(_files: &[PathBuf], _errors: &ErrorLog) {
+    eprintln!("ERROR: fdup was built without the `audio` feature");
+    process::exit(EXIT_FATAL);
+}
+
+/// Serve queries about the duplicates under `roots` on `socket` until killed.
+#[cfg(unix)]
+fn daemon(walk: &WalkOpt, socket: &Path, roots: &[PathBuf], local: bool) {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+    use std::os::unix::net::UnixStream;
+
+    // clients ask about whatever path they have, so index canonical ones
+    let roots: Vec<PathBuf> = roots
+        .iter()
+        .map(|root| {
+            root.canonicalize().unwrap_or_else(|err| {
+                eprintln!("ERROR resolving {:?}: {}", root, err);
+                process::exit(EXIT_FATAL);
+            })
+        })
+        .collect();
+    // a socket left behind by a daemon that is gone would make binding fail
+    let stale = fs::symlink_metadata(socket).is_ok_and(|meta| meta.file_type().is_socket())
+        && UnixStream::connect(socket).is_err();
+    if stale {
+        let _ = fs::remove_file(socket);
+    }
+    let listener = UnixListener::bind(socket).unwrap_or_else(|err| {
+        eprintln!("ERROR listening on {:?}: {}", socket, err);
+        process::exit(EXIT_FATAL);
+    });
+
+    let cache = walk.cache.as_deref().map(open_cache);
+    let roots: Vec<&Path> = roots.iter().map(PathBuf::as_path).collect();
+    let filter = filter(walk, &roots);
+    fdup::daemon::serve(&listener, local, || {
+        let config = ScanConfig {
+            cache: cache.as_ref(),
+            traversal: walk.traversal,
+            ..ScanConfig::new(&filter)
+        };
+        let index: ScanIndex = duplicate_groups(config, &roots).collect();
+        eprintln!("indexed {} duplicate groups", index.groups().len());
+        index
+    });
+}
+
+#[cfg(not(unix))]
+fn daemon(_walk: &WalkOpt, _socket: &Path, _roots: &[PathBuf], _local: bool) {
+    eprintln!("ERROR: fdup daemon needs Unix domain sockets");
+    process::exit(EXIT_FATAL);
+}
+
+#[cfg(feature = "watch")]
+fn watch_tree(walk: &WalkOpt, root: &Path) {
+    // events name absolute paths, so the index has to use them too
+    let root = match env::current_dir() {
+        Ok(dir) => dir.join(root),
+        Err(err) => {
+            eprintln!("ERROR resolving {:?}: {}", root, err);
+            process::exit(EXIT_FATAL);
+        }
+    };
+    let cache = walk.cache.as_deref().map(open_cache);
+    let filter = filter(walk, &[&root]);
+    let mut index = WatchIndex::new(cache.as_ref(), &filter, walk.traversal, &root);
+    eprintln!("watching {} files under {:?}", index.len(), root);
+    let watched = watch(&mut index, &root, |group| println!("{:?}", group.paths));
+    if let Err(err) = watched {
+        eprintln!("ERROR watching {:?}: {}", root, err);
+        process::exit(EXIT_FATAL);
+    }
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch_tree(_walk: &WalkOpt, _root: &Path) {
+    eprintln!("ERROR: fdup was built without the `watch` feature");
+    process::exit(EXIT_FATAL);
+}
+
+fn unique(scan: &ScanOpt) {
+    let cache = scan.walk.cache.as_deref().map(open_cache);
+    let roots = roots(scan);
+    let filter = scan_filter(scan, &roots);
+    let traversal = scan.walk.traversal;
+    for path in unique_files(cache.as_ref(), &filter, traversal, &scan.root, &roots) {
+        println!("{:?}", path);
+    }
+}
+
+fn compare_trees(walk: &WalkOpt, left: &Path, right: &Path) {
+    let cache = walk.cache.as_deref().map(open_cache);
+    let filter = filter(walk, &[left, right]);
+    let comparison = compare(cache.as_ref(), &filter, walk.traversal, left, right);
+
+    for (path, copy) in comparison.both.iter() {
+        println!("identical  {:?} {:?}", path, copy);
+    }
+    for path in comparison.only_left.iter() {
+        println!("only-left  {:?}", path);
+    }
+    for path in comparison.only_right.iter() {
+        println!("only-right {:?}", path);
+    }
+    println!(
+        "{} identical, {} only in {:?}, {} only in {:?}",
+        comparison.both.len(),
+        comparison.only_left.len(),
+        left,
+        comparison.only_right.len(),
+        right
+    );
+}
+
+/// Check the tree under `root` against the manifest at `path`, listing every difference.
+///
+/// # Returns
+///
+/// Exit status telling whether the tree matches, and whether any file couldn't be read.
+fn verify_tree(walk: &WalkOpt, path: &Path, root: &Path) -> i32 {
+    let manifest = fs::read_to_string(path)
+        .map_err(|err| format!("{}", err))
+        .and_then(|text| parse_manifest(&text))
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR reading {:?}: {}", path, err);
+            process::exit(EXIT_FATAL);
+        });
+    let cache = walk.cache.as_deref().map(open_cache);
+    let filter = filter(walk, &[root]);
+    let verification = verify(cache.as_ref(), &filter, walk.traversal, &manifest, root);
+
+    for path in verification.missing.iter() {
+        println!("missing    {:?}", path);
+    }
+    for path in verification.modified.iter() {
+        println!("modified   {:?}", path);
+    }
+    for path in verification.unreadable.iter() {
+        println!("unreadable {:?}", path);
+    }
+    for path in verification.extra.iter() {
+        println!("extra      {:?}", path);
+    }
+    println!(
+        "{} intact, {} missing, {} modified, {} unreadable, {} extra",
+        verification.intact.len(),
+        verification.missing.len(),
+        verification.modified.len(),
+        verification.unreadable.len(),
+        verification.extra.len()
+    );
+    match (verification.unreadable.is_empty(), verification.is_intact()) {
+        (false, _) => EXIT_FILE_ERRORS,
+        (true, true) => EXIT_NO_DUPLICATES,
+        (true, false) => EXIT_MISMATCH,
+    }
+}
+
+fn read_report(path: &Path) -> Report {
+    File::open(path)
+        .map_err(|err| format!("{}", err))
+        .and_then(|file| {
+            serde_json::from_reader(BufReader::new(file)).map_err(|err| format!("{}", err))
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR reading {:?}: {}", path, err);
+            process::exit(EXIT_FATAL);
+        })
+}
+
+fn write_report(out: &Path, report: &Report) {
+    let written = File::create(out)
+        .map_err(|err| format!("{}", err))
+        .and_then(|file| {
+            serde_json::to_writer_pretty(BufWriter::new(file), report)
+                .map_err(|err| format!("{}", err))
+        });
+    if let Err(err) = written {
+        eprintln!("ERROR writing {:?}: {}", out, err);
+        process::exit(EXIT_FATAL);
+    }
+}
+
+fn merge_reports(out: &Path, inputs: &[PathBuf]) {
+    let reports: Vec<Report> = inputs.iter().map(|path| read_report(path)).collect();
+    write_report(out, &merge(&reports));
+}
+
+fn diff_reports(old: &Path, new: &Path) {
+    let diff = diff(&read_report(old), &read_report(new));
+
+    for group in diff.new.iter() {
+        let (count, size) = (group.paths.len(), group.size);
+        println!(
+            "new      {} x {} bytes {:?}",
+            count,
+            size,
+            group.path_bufs()
+        );
+    }
+    for group in diff.resolved.iter() {
+        let (count, size) = (group.paths.len(), group.size);
+        println!(
+            "resolved {} x {} bytes {:?}",
+            count,
+            size,
+            group.path_bufs()
+        );
+    }
+    for (before, group) in diff.grown.iter() {
+        let (was, count, size) = (before.paths.len(), group.paths.len(), group.size);
+        println!(
+            "grew     {} -> {} x {} bytes {:?}",
+            was,
+            count,
+            size,
+            group.path_bufs()
+        );
+    }
+    for (before, group) in diff.shrunk.iter() {
+        let (was, count, size) = (before.paths.len(), group.paths.len(), group.size);
+        println!(
+            "shrank   {} -> {} x {} bytes {:?}",
+            was,
+            count,
+            size,
+            group.path_bufs()
+        );
+    }
+    println!(
+        "{} new, {} resolved, {} grew, {} shrank, {} unchanged",
+        diff.new.len(),
+        diff.resolved.len(),
+        diff.grown.len(),
+        diff.shrunk.len(),
+        diff.unchanged
+    );
+}
+
+/// Write the errors recorded in `errors` to `out` as a JSON array, and how many there were to
+/// stderr, since they are not printed as they happen.
+fn write_errors(out: &Path, errors: ErrorLog) {
+    let records = errors.into_records();
+    let written = File::create(out)
+        .map_err(|err| format!("{}", err))
+        .and_then(|file| {
+            serde_json::to_writer_pretty(BufWriter::new(file), &records)
+                .map_err(|err| format!("{}", err))
+        });
+    match written {
+        Ok(()) if records.is_empty() => (),
+        Ok(()) => eprintln!("{} files could not be read; see {:?}", records.len(), out),
+        Err(err) => {
+            eprintln!("ERROR writing {:?}: {}", out, err);
+            process::exit(EXIT_FATAL);
+        }
+    }
+}
+
+fn cache_command(cmd: &CacheCommand) {
+    let result = match cmd {
+        CacheCommand::Stats { db } => open_cache(db).stats().map(|stats| {
+            println!("entries:  {}", stats.entries);
+            println!("size:     {} bytes", stats.bytes);
+            match stats.last_run {
+                Some(run) => {
+                    let lookups = run.cache_hits + run.cache_misses;
+                    let rate = match lookups {
+                        0 => 0.0,
+                        _ => 100.0 * run.cache_hits as f64 / lookups as f64,
+                    };
+                    println!("last run: {}", run.root);
+                    println!("hits:     {}", run.cache_hits);
+                    println!("misses:   {}", run.cache_misses);
+                    println!("hit rate: {:.1}%", rate);
+                }
+                None => println!("last run: none"),
+            }
+        }),
+        CacheCommand::Prune { db } => open_cache(db)
+            .prune()
+            .map(|pruned| println!("pruned {} stale entries", pruned)),
+        CacheCommand::Vacuum { db } => open_cache(db).vacuum(),
+        CacheCommand::Clear { db } => open_cache(db)
+            .clear()
+            .map(|cleared| println!("cleared {} entries", cleared)),
+    };
+    if let Err(err) = result {
+        eprintln!("ERROR: {}", err);
+        process::exit(EXIT_FATAL);
+    }
+}
+
+/// # Returns
+///
+/// Checkpoint to start or resume as `scan` asks, exiting the process if it can't be opened.
+fn open_checkpoint(scan: &ScanOpt) -> Option<Checkpoint> {
+    let (path, resume) = match (&scan.checkpoint, &scan.resume) {
+        (Some(path), _) => (path, false),
+        (None, Some(path)) => (path, true),
+        (None, None) => return None,
+    };
+    match Checkpoint::open(path, resume) {
+        Ok(checkpoint) if resume => {
+            eprintln!(
+                "resuming after {} files already hashed",
+                checkpoint.resumed()
+            );
+            Some(checkpoint)
+        }
+        Ok(checkpoint) => Some(checkpoint),
+        Err(err) => {
+            eprintln!("ERROR opening checkpoint {:?}: {}", path, err);
+            process::exit(EXIT_FATAL);
+        }
+    }
+}
+
+/// Scan for duplicates as `scan` asks, and do with them whatever `dedup` asks.
+///
+/// # Returns
+///
+/// Exit status telling whether duplicates were found, and whether any file couldn't be read.
+fn run_scan(scan: &ScanOpt, dedup: &DedupOpt, local: bool) -> i32 {
+    let root = &scan.root;
+    let cache = scan.walk.cache.as_deref().map(open_cache);
+    let action = dedup.action();
+    let started = SystemTime::now();
+    let timestamp = started
+        .duration_since(UNIX_EPOCH)
+        .map(|since_epoch| since_epoch.as_secs() as i64)
+        .unwrap_or(0);
+
+    let mut summary = RunSummary {
+        root: root
+            .canonicalize()
+            .unwrap_or_else(|_| root.to_path_buf())
+            .to_string_lossy()
+            .into_owned(),
+        timestamp,
+        ..RunSummary::default()
+    };
+    let roots = roots(scan);
+    let filter = scan_filter(scan, &roots);
+    let stats = ScanStats::default();
+    let errors = ErrorLog::new(scan.errors_json.is_none() && !scan.quiet);
+    let manifest = scan.emit_manifest.as_ref().map(|_| ManifestLog::default());
+    let checkpoint = open_checkpoint(scan);
+    let archives = ArchiveIndex::default();
+    let config = ScanConfig {
+        stats: Some(&stats),
+        errors: Some(&errors),
+        manifest: manifest.as_ref(),
+        checkpoint: checkpoint.as_ref(),
+        archives: match scan.scan_archives {
+            true => Some(&archives),
+            false => None,
+        },
+        ..scan_config(scan, cache.as_ref(), &filter)
+    };
+    let groups = duplicate_groups(config, &roots).filter(|group| is_reported(scan, group));
+    // ranking needs every group, so it gives up on printing them as soon as they are found
+    let groups: Box<dyn Iterator<Item = DuplicateGroup>> = match (scan.top, scan.sort_by) {
+        (None, None) => Box::new(groups),
+        (top, by) => {
+            let mut groups = match top {
+                Some(n) => top_groups(groups, n),
+                None => groups.collect(),
+            };
+            sort_groups(&mut groups, by.unwrap_or(SortBy::Size));
+            Box::new(groups.into_iter())
+        }
+    };
+    let mut snapshot = Vec::new();
+    let mut shared = SharedContents::default();
+    let mut packed = ArchiveContents::default();
+    let groups = groups.inspect(|group| {
+        if scan.similar_dirs.is_some() {
+            shared.add(group);
+        }
+        if scan.scan_archives {
+            packed.add(group, &archives);
+        }
+        if scan.save_snapshot.is_some() || scan.report_md.is_some() {
+            let shown: Vec<PathBuf> = match scan.relative {
+                true => group.paths.iter().map(|p| relative_path(p, root)).collect(),
+                false => group.paths.iter().map(|p| plain_path(p)).collect(),
+            };
+            snapshot.push(ReportGroup::shown_as(group, &shown, local));
+        }
+    });
+    print_in_background(groups, |groups| {
+        print_groups(scan, dedup, local, action, groups.into_iter(), &mut summary)
+    });
+    if scan.summary {
+        print_summary(&stats, &summary);
+    }
+    if scan.streams != Streams::Ignore {
+        print_streams(&stats);
+    }
+    if scan.report_empty_dirs {
+        let dirs: Vec<PathBuf> = roots
+            .iter()
+            .flat_map(|root| empty_dirs(root, scan.walk.traversal))
+            .collect();
+        print_empty_dirs(&dirs);
+    }
+    if let Some(min_percent) = scan.similar_dirs {
+        print_containments(&shared.containments(min_percent, |dir| files_in(dir, &filter)));
+    }
+    if scan.scan_archives {
+        print_identical_archives(&packed.identical(&archives));
+    }
+    if scan.similar_images.is_some() || scan.similar_audio {
+        let traversal = scan.walk.traversal;
+        let files: Vec<PathBuf> = roots
+            .iter()
+            .flat_map(|root| files(root, traversal, &filter))
+            .collect();
+        if let Some(max_distance) = scan.similar_images {
+            report_similar_images(&files, max_distance, &errors);
+        }
+        if scan.similar_audio {
+            report_similar_audio(&files, &errors);
+        }
+    }
+    let denied = errors.denied();
+    if !denied.is_empty() && (!scan.quiet || scan.list_denied) {
+        print_denied(&denied, scan.list_denied);
+    }
+    let status = match (errors.is_empty(), summary.groups) {
+        _ if scan.require_full_access && !denied.is_empty() => {
+            eprintln!("ERROR access was denied with --require-full-access");
+            EXIT_ACCESS_DENIED
+        }
+        (false, _) => EXIT_FILE_ERRORS,
+        (true, 0) => EXIT_NO_DUPLICATES,
+        (true, _) => EXIT_DUPLICATES,
+    };
+    if let Some(out) = &scan.errors_json {
+        write_errors(out, errors);
+    }
+    if let Some(checkpoint) = checkpoint {
+        if let Err(err) = checkpoint.flush() {
+            eprintln!("ERROR writing checkpoint: {}", err);
+        }
+    }
+    let report = Report {
+        started: Some(rfc3339(started, local)),
+        groups: snapshot,
+        finished: Some(rfc3339(SystemTime::now(), local)),
+    };
+    if let Some(out) = &scan.save_snapshot {
+        write_report(out, &report);
+    }
+    if let Some(out) = &scan.report_md {
+        let written = File::create(out)
+            .and_then(|file| write_markdown(BufWriter::new(file), &report, &summary));
+        if let Err(err) = written {
+            eprintln!("ERROR writing {:?}: {}", out, err);
+            process::exit(EXIT_FATAL);
+        }
+    }
+    if let (Some(out), Some(manifest)) = (&scan.emit_manifest, manifest) {
+        let mut entries = manifest.into_entries();
+        if scan.relative {
+            // so that `fdup verify` can check it against the root wherever it is mounted
+            for entry in entries.iter_mut() {
+                entry.path = relative_path(&entry.path, root);
+            }
+        }
+        let written =
+            File::create(out).and_then(|file| write_manifest(BufWriter::new(file), &entries));
+        if let Err(err) = written {
+            eprintln!("ERROR writing {:?}: {}", out, err);
+            process::exit(EXIT_FATAL);
+        }
+    }
 
-    duplicate_files(sort_vec, &root).for_each(|vec| println!("{:?}", vec));
+    if let Some(cache) = cache {
+        let (hits, misses) = cache.hits_and_misses();
+        summary.cache_hits = hits;
+        summary.cache_misses = misses;
+        if let Err(err) = cache.record_run(&summary) {
+            eprintln!("ERROR recording run in cache: {}", err);
+        }
+    }
+    status
 }
@@ -0,0 +1,301 @@
+use crate::cache::HashCache;
+use crate::fdup::checksum;
+use crate::filter::Filter;
+use crate::output::path_bytes;
+use crate::report::hex;
+use crate::walk::files;
+use crate::walk::Traversal;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// One line of a checksum manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Checksum of the file in lowercase hex
+    pub hash: String,
+    /// Path of the file, relative to the directory the manifest describes unless absolute
+    pub path: PathBuf,
+}
+
+/// # Returns
+///
+/// Entries of `text`, a manifest in the format of `sha512sum`: a checksum in hex, a space, a space
+/// or `*`, and a path, per line. Lines starting with a backslash have `\\` and `\n` escapes in
+/// their path, as `sha512sum` writes for names with backslashes or newlines in them. Blank lines
+/// are skipped.
+pub fn parse_manifest(text: &str) -> Result<Vec<ManifestEntry>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let invalid = || format!("line {}: expected a sha512 checksum and a path", i + 1);
+            let (escaped, line) = match line.strip_prefix('\\') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (hash, path) = line.split_at(line.find(' ').ok_or_else(invalid)?);
+            let path = path
+                .strip_prefix("  ")
+                .or_else(|| path.strip_prefix(" *"))
+                .filter(|path| !path.is_empty())
+                .ok_or_else(invalid)?;
+            if hash.len() != 128 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(invalid());
+            }
+            let path = match escaped {
+                true => unescape(path),
+                false => String::from(path),
+            };
+            Ok(ManifestEntry {
+                hash: hash.to_ascii_lowercase(),
+                path: PathBuf::from(path),
+            })
+        })
+        .collect()
+}
+
+/// # Returns
+///
+/// `path` with the escapes of `sha512sum` undone.
+fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('\\')) => unescaped.push('\\'),
+            ('\\', Some('n')) => unescaped.push('\n'),
+            _ => {
+                unescaped.push(c);
+                continue;
+            }
+        }
+        chars.next();
+    }
+    unescaped
+}
+
+/// Write `entries` to `out` in the format of `sha512sum`, so that `sha512sum -c` can check them.
+pub fn write_manifest<W: Write>(mut out: W, entries: &[ManifestEntry]) -> io::Result<()> {
+    for entry in entries {
+        let path = path_bytes(&entry.path);
+        // sha512sum marks lines whose path needs escapes with a leading backslash
+        if path.contains(&b'\\') || path.contains(&b'\n') {
+            out.write_all(b"\\")?;
+        }
+        out.write_all(entry.hash.as_bytes())?;
+        out.write_all(b"  ")?;
+        for &byte in path.iter() {
+            match byte {
+                b'\\' => out.write_all(b"\\\\")?,
+                b'\n' => out.write_all(b"\\n")?,
+                _ => out.write_all(&[byte])?,
+            }
+        }
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
+/// Collects the checksum of every file a scan hashes, from all threads, to write them as a
+/// manifest once it is done.
+#[derive(Debug, Default)]
+pub struct ManifestLog {
+    entries: Mutex<Vec<ManifestEntry>>,
+}
+
+impl ManifestLog {
+    /// Record that the file at `path` has the checksum `hash`.
+    pub fn record(&self, path: &Path, hash: &[u8]) {
+        let entry = ManifestEntry {
+            hash: hex(hash),
+            path: path.to_path_buf(),
+        };
+        self.entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(entry);
+    }
+
+    /// # Returns
+    ///
+    /// Every checksum recorded, ordered by path, whichever thread recorded it.
+    pub fn into_entries(self) -> Vec<ManifestEntry> {
+        let mut entries = self
+            .entries
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner());
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+}
+
+/// Files of a tree classified by how they compare to a manifest of it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// Files whose contents still match the manifest
+    pub intact: Vec<PathBuf>,
+    /// Files in the manifest that no longer exist
+    pub missing: Vec<PathBuf>,
+    /// Files whose contents no longer match the manifest
+    pub modified: Vec<PathBuf>,
+    /// Files in the manifest that exist, but could not be read
+    pub unreadable: Vec<PathBuf>,
+    /// Files of the tree that are not in the manifest
+    pub extra: Vec<PathBuf>,
+}
+
+impl Verification {
+    /// # Returns
+    ///
+    /// Whether the tree matches the manifest exactly.
+    pub fn is_intact(&self) -> bool {
+        self.missing.is_empty()
+            && self.modified.is_empty()
+            && self.unreadable.is_empty()
+            && self.extra.is_empty()
+    }
+}
+
+/// Check the tree under `root` against `manifest`, hashing the files the same way a scan for
+/// duplicates does.
+///
+/// # Parameters
+///
+/// 1. `cache` -- if given, checksums are read from and written to it instead of always hashing
+/// 1. `filter` -- conditions files have to meet to count as extra
+/// 1. `traversal` -- order in which to walk the tree
+/// 1. `manifest` -- what the tree should contain
+/// 1. `root` -- directory the paths of `manifest` are relative to
+///
+/// # Returns
+///
+/// Every file of the manifest and the tree, classified and sorted by path.
+pub fn verify(
+    cache: Option<&HashCache>,
+    filter: &Filter,
+    traversal: Traversal,
+    manifest: &[ManifestEntry],
+    root: &Path,
+) -> Verification {
+    enum Status {
+        Intact,
+        Missing,
+        Modified,
+        Unreadable,
+    }
+    let checked: Vec<(PathBuf, Status)> = manifest
+        .par_iter()
+        .map(|entry| {
+            let path = root.join(&entry.path);
+            if path.symlink_metadata().is_err() {
+                return (path, Status::Missing);
+            }
+            let hash = match cache {
+                Some(cache) => cache.checksum(&path),
+                None => checksum(&path),
+            };
+            let status = match hash {
+                Ok(hash) if hex(&hash) == entry.hash => Status::Intact,
+                Ok(_) => Status::Modified,
+                Err(err) => {
+                    eprintln!("ERROR with {}", err);
+                    Status::Unreadable
+                }
+            };
+            (path, status)
+        })
+        .collect();
+
+    let listed: HashSet<&Path> = checked.iter().map(|(path, _)| path.as_path()).collect();
+    let mut verification = Verification {
+        extra: files(root, traversal, filter)
+            .into_iter()
+            .filter(|path| !listed.contains(path.as_path()))
+            .collect(),
+        ..Verification::default()
+    };
+    for (path, status) in checked {
+        match status {
+            Status::Intact => verification.intact.push(path),
+            Status::Missing => verification.missing.push(path),
+            Status::Modified => verification.modified.push(path),
+            Status::Unreadable => verification.unreadable.push(path),
+        }
+    }
+    verification.intact.sort();
+    verification.missing.sort();
+    verification.modified.sort();
+    verification.unreadable.sort();
+    verification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+
+    #[test]
+    fn manifests_are_parsed() {
+        let hash = "ab".repeat(64);
+        let text = format!(
+            "{0}  a.txt\n\n{0} *dir/with space\n\\{0}  back\\\\slash\\nline\n",
+            hash.to_uppercase()
+        );
+        let entries = parse_manifest(&text).unwrap();
+        let paths: Vec<&Path> = entries.iter().map(|entry| entry.path.as_path()).collect();
+        assert_eq!(
+            vec![
+                Path::new("a.txt"),
+                Path::new("dir/with space"),
+                Path::new("back\\slash\nline")
+            ],
+            paths
+        );
+        assert!(entries.iter().all(|entry| entry.hash == hash));
+
+        assert!(parse_manifest("abc  short").is_err());
+        assert!(parse_manifest(&format!("{} nospace", hash)).is_err());
+
+        let mut written = Vec::new();
+        write_manifest(&mut written, &entries).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.starts_with(&format!("{}  a.txt\n", hash)));
+        assert_eq!(entries, parse_manifest(&written).unwrap());
+    }
+
+    #[test]
+    fn trees_are_checked_against_manifests() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(root.join("sub")).unwrap();
+        write(root.join("kept"), "same").unwrap();
+        write(root.join("sub/changed"), "before").unwrap();
+        let entry = |path: &str| ManifestEntry {
+            hash: hex(&checksum(&root.join(path)).unwrap()),
+            path: PathBuf::from(path),
+        };
+        let manifest = vec![entry("kept"), entry("sub/changed"), {
+            write(root.join("gone"), "").unwrap();
+            entry("gone")
+        }];
+        std::fs::remove_file(root.join("gone")).unwrap();
+        write(root.join("sub/changed"), "after").unwrap();
+        write(root.join("new"), "extra").unwrap();
+
+        let verification = verify(None, &Filter::default(), Traversal::Dfs, &manifest, &root);
+        assert_eq!(vec![root.join("kept")], verification.intact);
+        assert_eq!(vec![root.join("gone")], verification.missing);
+        assert_eq!(vec![root.join("sub/changed")], verification.modified);
+        assert_eq!(vec![root.join("new")], verification.extra);
+        assert!(!verification.is_intact());
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use crate::cache::RunSummary;
+use crate::report::Report;
+use crate::report::ReportGroup;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// Write `report` to `out` as a Markdown document to paste into issues and wikis: the totals of
+/// `summary` in a table, then a collapsible section per directory with the groups whose first
+/// file is in it.
+///
+/// # Parameters
+///
+/// 1. `out` -- where to write the document
+/// 1. `report` -- groups found by the scan, with the paths as they should be shown
+/// 1. `summary` -- totals of the scan
+pub fn write_markdown<W: Write>(
+    mut out: W,
+    report: &Report,
+    summary: &RunSummary,
+) -> io::Result<()> {
+    writeln!(out, "# Duplicate files in {}", code(&summary.root))?;
+    writeln!(out)?;
+    if let (Some(started), Some(finished)) = (&report.started, &report.finished) {
+        writeln!(out, "Scanned from {} to {}.", started, finished)?;
+        writeln!(out)?;
+    }
+    writeln!(out, "| groups | redundant files | reclaimable bytes |")?;
+    writeln!(out, "|-------:|----------------:|------------------:|")?;
+    writeln!(
+        out,
+        "| {} | {} | {} |",
+        summary.groups, summary.redundant_files, summary.redundant_bytes
+    )?;
+
+    let mut by_dir: BTreeMap<String, Vec<&ReportGroup>> = BTreeMap::new();
+    for group in report.groups.iter() {
+        let dir = match Path::new(&group.paths[0]).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_string_lossy().into_owned(),
+            _ => String::from("."),
+        };
+        by_dir.entry(dir).or_default().push(group);
+    }
+    for (dir, groups) in by_dir {
+        let wasted: u64 = groups
+            .iter()
+            .map(|group| group.size * (group.paths.len() as u64 - 1))
+            .sum();
+        writeln!(out)?;
+        writeln!(out, "<details>")?;
+        writeln!(
+            out,
+            "<summary><code>{}</code>: {} groups, {} bytes reclaimable</summary>",
+            html_escape(&dir),
+            groups.len(),
+            wasted
+        )?;
+        for group in groups {
+            writeln!(out)?;
+            writeln!(
+                out,
+                "- {} files of {} bytes, sha512 {}",
+                group.paths.len(),
+                group.size,
+                code(&group.hash[..16.min(group.hash.len())])
+            )?;
+            for path in group.paths.iter() {
+                writeln!(out, "  - {}", code(path))?;
+            }
+        }
+        writeln!(out)?;
+        writeln!(out, "</details>")?;
+    }
+    out.flush()
+}
+
+/// # Returns
+///
+/// `text` as a Markdown code span, fenced with more backticks than it contains in a row, and with
+/// control characters such as newlines escaped so that the span stays on one line.
+fn code(text: &str) -> String {
+    let escaped: String = text
+        .chars()
+        .map(|c| match c.is_control() {
+            true => c.escape_default().collect(),
+            false => c.to_string(),
+        })
+        .collect();
+    let longest_run = escaped.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run + 1);
+    // a span starting or ending with a backtick needs a space to tell it from the fence
+    match escaped.starts_with('`') || escaped.ends_with('`') {
+        true => format!("{0} {1} {0}", fence, escaped),
+        false => format!("{0}{1}{0}", fence, escaped),
+    }
+}
+
+/// # Returns
+///
+/// `text` with the characters that are special in HTML escaped.
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_are_sectioned_by_directory() {
+        let group = |size: u64, paths: &[&str]| ReportGroup {
+            hash: "ab".repeat(64),
+            size,
+            paths: paths.iter().map(|path| String::from(*path)).collect(),
+            raw_paths: Vec::new(),
+            mtimes: Vec::new(),
+        };
+        let report = Report {
+            started: None,
+            groups: vec![
+                group(10, &["b/x", "a/y"]),
+                group(5, &["a/`tick`", "b/new\nline"]),
+                group(1, &["top", "b/top"]),
+            ],
+            finished: None,
+        };
+        let summary = RunSummary {
+            root: String::from("/data"),
+            groups: 3,
+            redundant_files: 3,
+            redundant_bytes: 16,
+            ..RunSummary::default()
+        };
+        let mut written = Vec::new();
+        write_markdown(&mut written, &report, &summary).unwrap();
+        let written = String::from_utf8(written).unwrap();
+
+        assert!(written.starts_with("# Duplicate files in `/data`\n"));
+        assert!(written.contains("| 3 | 3 | 16 |\n"));
+        let a = written.find("<code>a</code>: 1 groups, 5 bytes").unwrap();
+        let b = written.find("<code>b</code>: 1 groups, 10 bytes").unwrap();
+        let top = written.find("<code>.</code>: 1 groups, 1 bytes").unwrap();
+        assert!(top < a && a < b);
+        assert!(written.contains("  - `` a/`tick` ``\n"));
+        assert!(written.contains("  - `b/new\\nline`\n"));
+        assert_eq!(3, written.matches("</details>").count());
+    }
+}
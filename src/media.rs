@@ -0,0 +1,260 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+
+/// Part of a file, as its offset and length in bytes.
+pub type Span = (u64, u64);
+
+/// Media formats whose embedded metadata `payload` can leave out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    /// JPEG, without its EXIF and XMP (APP1), IPTC (APP13) and comment segments
+    Jpeg,
+    /// PNG, without its text, EXIF and modification time chunks
+    Png,
+    /// MP3, without its ID3v2 tags at the start and ID3v1 tag at the end
+    Mp3,
+    /// FLAC, without any metadata block but STREAMINFO, such as Vorbis comments and pictures
+    Flac,
+}
+
+impl MediaKind {
+    /// # Returns
+    ///
+    /// Format of the file at `path` going by its first bytes, or by its extension for MP3 files
+    /// that don't start with a tag, or `None` if it is none of them.
+    pub fn of(path: &Path) -> io::Result<Option<MediaKind>> {
+        let mut start = Vec::with_capacity(8);
+        File::open(path)?.take(8).read_to_end(&mut start)?;
+        let mp3 = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"));
+        let kind = match &start[..] {
+            [0xff, 0xd8, 0xff, ..] => Some(MediaKind::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a] => Some(MediaKind::Png),
+            [b'f', b'L', b'a', b'C', ..] => Some(MediaKind::Flac),
+            [b'I', b'D', b'3', ..] => Some(MediaKind::Mp3),
+            _ if mp3 => Some(MediaKind::Mp3),
+            _ => None,
+        };
+        Ok(kind)
+    }
+}
+
+/// # Returns
+///
+/// Parts of the file at `path` that make up its picture or sound, in order, leaving out the
+/// metadata embedded in it, or `None` if it isn't in one of the formats of `MediaKind`. Only the
+/// headers of the file are read. Errors if the file is cut short or malformed.
+pub fn payload(path: &Path) -> io::Result<Option<Vec<Span>>> {
+    let kind = match MediaKind::of(path)? {
+        Some(kind) => kind,
+        None => return Ok(None),
+    };
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let spans = match kind {
+        MediaKind::Jpeg => jpeg_payload(&mut file, len)?,
+        MediaKind::Png => png_payload(&mut file, len)?,
+        MediaKind::Mp3 => mp3_payload(&mut file, len)?,
+        MediaKind::Flac => flac_payload(&mut file, len)?,
+    };
+    Ok(Some(spans))
+}
+
+/// # Returns
+///
+/// Number of bytes in `spans` altogether.
+pub fn payload_len(spans: &[Span]) -> u64 {
+    spans.iter().map(|(_, len)| len).sum()
+}
+
+/// # Returns
+///
+/// `N` bytes of `file`, from `offset` on.
+fn read_at<const N: usize>(file: &mut File, offset: u64) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn malformed(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed {}", what))
+}
+
+/// Every segment up to the first scan but those holding metadata, then all the rest.
+fn jpeg_payload(file: &mut File, len: u64) -> io::Result<Vec<Span>> {
+    const SOS: u8 = 0xda;
+    const APP1: u8 = 0xe1;
+    const APP13: u8 = 0xed;
+    const COM: u8 = 0xfe;
+
+    let mut spans = vec![(0, 2)]; // SOI
+    let mut pos = 2;
+    loop {
+        let [fill, marker] = read_at::<2>(file, pos)?;
+        match (fill, marker) {
+            (0xff, 0xff) => pos += 1, // padding before a marker
+            (0xff, SOS) => {
+                spans.push((pos, len - pos)); // the image itself, and whatever follows it
+                return Ok(spans);
+            }
+            (0xff, 0x01) | (0xff, 0xd0..=0xd8) => {
+                spans.push((pos, 2)); // no length of its own
+                pos += 2;
+            }
+            (0xff, _) => {
+                let segment = 2 + u64::from(u16::from_be_bytes(read_at::<2>(file, pos + 2)?));
+                if ![APP1, APP13, COM].contains(&marker) {
+                    spans.push((pos, segment));
+                }
+                pos += segment;
+            }
+            _ => return Err(malformed("JPEG segment")),
+        }
+    }
+}
+
+/// The signature and every chunk but those holding metadata.
+fn png_payload(file: &mut File, len: u64) -> io::Result<Vec<Span>> {
+    const METADATA: &[&[u8; 4]] = &[b"tEXt", b"zTXt", b"iTXt", b"eXIf", b"tIME"];
+
+    let mut spans = vec![(0, 8)];
+    let mut pos = 8;
+    while pos < len {
+        let header = read_at::<8>(file, pos)?;
+        let data_len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let chunk = 12 + u64::from(data_len); // length, type, data and CRC
+        if !METADATA.iter().any(|kind| header[4..] == kind[..]) {
+            spans.push((pos, chunk.min(len - pos)));
+        }
+        pos += chunk;
+    }
+    Ok(spans)
+}
+
+/// Everything between the ID3v2 tags at the start and the ID3v1 tag at the end.
+fn mp3_payload(file: &mut File, len: u64) -> io::Result<Vec<Span>> {
+    let mut start = 0;
+    while start + 10 <= len {
+        let header = read_at::<10>(file, start)?;
+        if &header[..3] != b"ID3" {
+            break;
+        }
+        // sizes are syncsafe, 7 bits to a byte
+        let size = header[6..]
+            .iter()
+            .fold(0, |size, byte| (size << 7) | u64::from(byte & 0x7f));
+        let footer = match header[5] & 0x10 {
+            0 => 0,
+            _ => 10,
+        };
+        start += 10 + size + footer;
+    }
+    let mut end = len;
+    if len >= start + 128 && &read_at::<3>(file, len - 128)? == b"TAG" {
+        end -= 128;
+    }
+    Ok(vec![(start.min(end), end.saturating_sub(start))])
+}
+
+/// The contents of the STREAMINFO block, without the header that says whether it is the last
+/// one, then all audio frames.
+fn flac_payload(file: &mut File, len: u64) -> io::Result<Vec<Span>> {
+    const STREAMINFO: u8 = 0;
+
+    let mut spans = Vec::new();
+    let mut pos = 4;
+    loop {
+        let header = read_at::<4>(file, pos)?;
+        let block = u64::from(u32::from_be_bytes([0, header[1], header[2], header[3]]));
+        if header[0] & 0x7f == STREAMINFO {
+            spans.push((pos + 4, block));
+        }
+        pos += 4 + block;
+        if header[0] & 0x80 != 0 {
+            break; // the last metadata block
+        }
+    }
+    if spans.is_empty() || pos > len {
+        return Err(malformed("FLAC metadata"));
+    }
+    spans.push((pos, len - pos));
+    Ok(spans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+
+    /// # Returns
+    ///
+    /// Bytes of `spans` of the file at `path`, one after another.
+    fn read_spans(path: &Path, spans: &[Span]) -> Vec<u8> {
+        let contents = std::fs::read(path).unwrap();
+        spans
+            .iter()
+            .flat_map(|&(offset, len)| contents[offset as usize..(offset + len) as usize].to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn metadata_is_left_out_of_the_payload() {
+        let dir = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let payload_of = |name: &str, contents: &[u8]| {
+            let path = dir.join(name);
+            write(&path, contents).unwrap();
+            let spans = payload(&path).unwrap().unwrap();
+            assert_eq!(payload_len(&spans), read_spans(&path, &spans).len() as u64);
+            read_spans(&path, &spans)
+        };
+
+        let image = [0xff, 0xdb, 0, 3, 7, 0xff, 0xda, 1, 2, 0xff, 0xd9];
+        let plain_jpeg = [&[0xff, 0xd8][..], &image].concat();
+        let exif = [0xff, 0xe1, 0, 6, b'E', b'x', b'i', b'f'];
+        let tagged_jpeg = [&[0xff, 0xd8][..], &exif, &image].concat();
+        assert_eq!(plain_jpeg, payload_of("plain.jpg", &plain_jpeg));
+        assert_eq!(plain_jpeg, payload_of("tagged.jpg", &tagged_jpeg));
+
+        let signature = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        let idat = [0, 0, 0, 1, b'I', b'D', b'A', b'T', 9, 0, 0, 0, 0];
+        let text = [0, 0, 0, 2, b't', b'E', b'X', b't', b'h', b'i', 0, 0, 0, 0];
+        let tagged_png = [&signature[..], &text, &idat].concat();
+        assert_eq!(
+            [&signature[..], &idat].concat(),
+            payload_of("tagged.png", &tagged_png)
+        );
+
+        let id3v2 = [b'I', b'D', b'3', 4, 0, 0, 0, 0, 0, 2, b'h', b'i'];
+        let frames = [0xff, 0xfb, 1, 2, 3];
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.resize(128, b' ');
+        let tagged_mp3 = [&id3v2[..], &frames, &id3v1].concat();
+        assert_eq!(frames.to_vec(), payload_of("tagged.mp3", &tagged_mp3));
+
+        let streaminfo = [0, 0, 0, 2, 4, 2];
+        let comment = [0x84, 0, 0, 1, b'x'];
+        let audio = [0xff, 0xf8, 5];
+        let tagged_flac = [&b"fLaC"[..], &streaminfo, &comment, &audio].concat();
+        assert_eq!(
+            vec![4, 2, 0xff, 0xf8, 5],
+            payload_of("tagged.flac", &tagged_flac)
+        );
+
+        write(dir.join("other"), "neither").unwrap();
+        assert_eq!(None, payload(&dir.join("other")).unwrap());
+        write(dir.join("cut.jpg"), [0xff, 0xd8, 0xff, 0xe1, 0]).unwrap();
+        assert!(payload(&dir.join("cut.jpg")).is_err());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+/// Values accepted by `--normalize`, in the same order as the variants of `Normalization`.
+pub const NORMALIZE_VALUES: &[&str] = &["none", "nfc", "nfd"];
+
+/// Unicode normalization form names are brought to, since the same name can be spelled with
+/// composed characters, as Linux and Windows usually write it, or decomposed ones, as macOS does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Leave names as they are
+    #[default]
+    None,
+    /// Canonical composition, e.g. é as a single character
+    Nfc,
+    /// Canonical decomposition, e.g. é as e followed by a combining acute accent
+    Nfd,
+}
+
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Normalization::None),
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            _ => Err(format!("unknown normalization form {:?}", s)),
+        }
+    }
+}
+
+impl Normalization {
+    /// # Returns
+    ///
+    /// `s` in this form.
+    pub fn apply(self, s: &str) -> String {
+        match self {
+            Normalization::None => s.to_string(),
+            Normalization::Nfc => s.nfc().collect(),
+            Normalization::Nfd => s.nfd().collect(),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// `path` in this form, or as it is if it isn't Unicode. The result may not open the file on
+    /// file systems that tell forms apart, such as ext4.
+    pub fn path(self, path: &Path) -> PathBuf {
+        match (self, path.to_str()) {
+            (Normalization::None, _) | (_, None) => path.to_path_buf(),
+            (_, Some(s)) => PathBuf::from(self.apply(s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forms_are_told_apart_and_brought_together() {
+        let composed = "caf\u{e9}.txt";
+        let decomposed = "cafe\u{301}.txt";
+        assert_ne!(composed, decomposed);
+        assert_eq!(composed, Normalization::Nfc.apply(decomposed));
+        assert_eq!(decomposed, Normalization::Nfd.apply(composed));
+        assert_eq!(decomposed, Normalization::None.apply(decomposed));
+        assert_eq!(
+            PathBuf::from(format!("dir/{}", composed)),
+            Normalization::Nfc.path(&Path::new("dir").join(decomposed))
+        );
+        assert_eq!(Ok(Normalization::Nfd), "nfd".parse());
+    }
+}
@@ -0,0 +1,410 @@
+use crate::action::choose_survivor;
+use crate::action::Policy;
+use crate::cache::RunSummary;
+use crate::fdup::DuplicateGroup;
+use crate::normalize::Normalization;
+use crate::rdfind::write_rdfind_footer;
+use crate::rdfind::write_rdfind_group;
+use crate::rdfind::write_rdfind_header;
+use crate::report::hex;
+use crate::report::ReportGroup;
+use crate::rmlint::rmlint_files;
+use crate::rmlint::write_rmlint_footer;
+use crate::rmlint::write_rmlint_header;
+use crate::time::mtime;
+use crate::time::rfc3339;
+use crate::walk::plain_path;
+use std::io;
+use std::io::Write;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// Values accepted by `--format`, in the same order as the variants of `Format`.
+pub const FORMAT_VALUES: &[&str] = &["debug", "shell", "json", "raw", "rmlint-json", "rdfind"];
+
+/// How duplicate groups are printed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One `Debug`-formatted `Vec` of paths per line
+    Debug,
+    /// Shell script with commented out `rm`/`ln` commands per group, for manual review
+    Shell,
+    /// A `Report` as JSON, which `fdup merge` can combine with others
+    Json,
+    /// Exact bytes of each path followed by a NUL, with an extra NUL after each group, so that
+    /// any file name, unicode or not, can be read back, e.g. with `xargs -0`; with `--show-hash`,
+    /// the checksum comes first, as if it were a path
+    Raw,
+    /// The JSON dump of rmlint, one object per file, for its review and removal tools
+    RmlintJson,
+    /// The `results.txt` of rdfind, one line per file with its device, inode and size
+    Rdfind,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "debug" => Ok(Format::Debug),
+            "shell" => Ok(Format::Shell),
+            "json" => Ok(Format::Json),
+            "raw" => Ok(Format::Raw),
+            "rmlint-json" => Ok(Format::RmlintJson),
+            "rdfind" => Ok(Format::Rdfind),
+            _ => Err(format!("unknown format {:?}", s)),
+        }
+    }
+}
+
+/// What is printed about each group besides the paths of its files, in the formats that don't
+/// always include it, and how the paths are spelled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Details {
+    /// Checksum of the group, in hex
+    pub hash: bool,
+    /// Size and modification time of each file
+    pub meta: bool,
+    /// Unicode normalization form of the paths, except in the commands of the `shell` format,
+    /// which have to name the files as they are
+    pub normalize: Normalization,
+}
+
+/// Print whatever has to precede the first group, which for JSON includes the start of the run.
+///
+/// # Parameters
+///
+/// 1. `format` -- how the groups will be printed
+/// 1. `local` -- render timestamps in the local time zone instead of UTC
+/// 1. `root` -- if given, paths are printed relative to it, so a shell script first changes to
+///    the directory given as its argument, or to `root` without one
+pub fn print_header(format: Format, local: bool, root: Option<&Path>) -> io::Result<()> {
+    match format {
+        Format::Debug | Format::Raw => Ok(()),
+        Format::Shell => {
+            let mut out = io::stdout();
+            writeln!(out, "#!/usr/bin/env bash")?;
+            writeln!(
+                out,
+                "# generated by fdup; for each group, uncomment either the rm or the"
+            )?;
+            writeln!(
+                out,
+                "# ln line of every file you want to get rid of, then run this script"
+            )?;
+            writeln!(out, "set -eu")?;
+            match root {
+                Some(root) => {
+                    writeln!(
+                        out,
+                        "# paths are relative to the root, which can be given as the argument"
+                    )?;
+                    out.write_all(b"root=${1:-")?;
+                    out.write_all(&shell_quote(root))?;
+                    writeln!(out, "}}")?;
+                    writeln!(out, "cd -- \"$root\"")
+                }
+                None => Ok(()),
+            }
+        }
+        Format::Json => write!(
+            io::stdout(),
+            "{{\"started\":\"{}\",\"groups\":[",
+            rfc3339(SystemTime::now(), local)
+        ),
+        Format::RmlintJson => write_rmlint_header(io::stdout()),
+        Format::Rdfind => write_rdfind_header(io::stdout()),
+    }
+}
+
+/// Print whatever has to follow the last group, which for JSON includes the end of the run.
+///
+/// # Parameters
+///
+/// 1. `format` -- how the groups were printed
+/// 1. `local` -- render timestamps in the local time zone instead of UTC
+/// 1. `summary` -- totals of the run, for formats that end with them
+pub fn print_footer(format: Format, local: bool, summary: &RunSummary) -> io::Result<()> {
+    match format {
+        Format::Debug | Format::Shell | Format::Raw => Ok(()),
+        Format::Json => writeln!(
+            io::stdout(),
+            "\n],\"finished\":\"{}\"}}",
+            rfc3339(SystemTime::now(), local)
+        ),
+        Format::RmlintJson => write_rmlint_footer(io::stdout(), summary),
+        Format::Rdfind => write_rdfind_footer(io::stdout()),
+    }
+}
+
+/// Print a single duplicate group.
+///
+/// # Parameters
+///
+/// 1. `format` -- how to print the group
+/// 1. `policy` -- rules used to suggest which file to keep, if the format makes suggestions
+/// 1. `local` -- render mtimes in the local time zone instead of UTC, if the format lists them
+/// 1. `root` -- if given, paths are printed relative to it
+/// 1. `details` -- what to print besides the paths; in the `raw` format, the checksum is the first
+///    field of the group and the size and mtime follow each path as fields of their own, with
+///    an empty mtime where it can't be read
+/// 1. `index` -- position of the group in the output, starting from 1
+/// 1. `group` -- files with identical contents
+pub fn print_group(
+    format: Format,
+    policy: &Policy,
+    local: bool,
+    root: Option<&Path>,
+    details: Details,
+    index: usize,
+    group: &DuplicateGroup,
+) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let DuplicateGroup { hash, size, paths } = group;
+    let shown: Vec<PathBuf> = match root {
+        Some(root) => paths.iter().map(|path| relative_path(path, root)).collect(),
+        None => paths.iter().map(|path| plain_path(path)).collect(),
+    };
+    let shown: Vec<PathBuf> = match format {
+        Format::Shell => shown,
+        _ => shown
+            .iter()
+            .map(|path| details.normalize.path(path))
+            .collect(),
+    };
+    // read from the actual paths, like the json format does
+    let mtimes: Vec<String> = match details.meta {
+        true => paths
+            .iter()
+            .map(|path| mtime(path, local).unwrap_or_default())
+            .collect(),
+        false => Vec::new(),
+    };
+    match format {
+        Format::Debug => {
+            if details.hash {
+                write!(out, "{} ", hex(hash))?;
+            }
+            match details.meta {
+                true => {
+                    let members: Vec<_> = shown
+                        .iter()
+                        .zip(mtimes.iter())
+                        .map(|(path, mtime)| (path, size, mtime))
+                        .collect();
+                    writeln!(out, "{:?}", members)
+                }
+                false => writeln!(out, "{:?}", shown),
+            }
+        }
+        Format::Raw => {
+            if details.hash {
+                out.write_all(hex(hash).as_bytes())?;
+                out.write_all(b"\0")?;
+            }
+            for (i, path) in shown.iter().enumerate() {
+                out.write_all(&path_bytes(path))?;
+                out.write_all(b"\0")?;
+                if details.meta {
+                    write!(out, "{}\0{}\0", size, mtimes[i])?;
+                }
+            }
+            out.write_all(b"\0")
+        }
+        Format::Json => {
+            let separator = match index {
+                1 => "",
+                _ => ",",
+            };
+            let json = serde_json::to_string(&ReportGroup::shown_as(group, &shown, local))?;
+            write!(out, "{}\n  {}", separator, json)
+        }
+        Format::RmlintJson => {
+            let survivor = choose_survivor(policy.keep, &policy.prefer, paths);
+            for file in rmlint_files(group, &shown, survivor, index) {
+                out.write_all(b",\n")?;
+                serde_json::to_writer(&mut out, &file)?;
+            }
+            Ok(())
+        }
+        Format::Rdfind => {
+            let survivor = choose_survivor(policy.keep, &policy.prefer, paths);
+            write_rdfind_group(out, group, &shown, survivor, index)
+        }
+        Format::Shell => {
+            // decide on the actual paths, print the shown ones
+            let survivor = choose_survivor(policy.keep, &policy.prefer, paths);
+
+            writeln!(out)?;
+            writeln!(
+                out,
+                "# group {}: {} files of {} bytes",
+                index,
+                paths.len(),
+                size
+            )?;
+            if details.hash {
+                writeln!(out, "# sha512 {}", hex(hash))?;
+            }
+            // a comment of its own, so that it stays one once the line is uncommented
+            let meta = |i: usize| match details.meta {
+                true => format!("  # {} bytes, modified {}", size, mtimes[i]),
+                false => String::new(),
+            };
+            out.write_all(b"# keep ")?;
+            out.write_all(&shell_quote(&shown[survivor]))?;
+            writeln!(out, "{}", meta(survivor))?;
+            for (i, path) in shown.iter().enumerate().filter(|(i, _)| *i != survivor) {
+                if policy.is_protected(&paths[i]) {
+                    out.write_all(b"# protected ")?;
+                    out.write_all(&shell_quote(path))?;
+                    writeln!(out, "{}", meta(i))?;
+                    continue;
+                }
+                out.write_all(b"#rm -- ")?;
+                out.write_all(&shell_quote(path))?;
+                write!(out, "{}", meta(i))?;
+                out.write_all(b"\n#ln -f -- ")?;
+                out.write_all(&shell_quote(&shown[survivor]))?;
+                out.write_all(b" ")?;
+                out.write_all(&shell_quote(path))?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// # Returns
+///
+/// `path` relative to `root`, climbing out of `root` with `..` if that's where it is, or `path`
+/// itself if the two have nothing in common. Symlinks in the directories leading up to `path` are
+/// resolved, but `path` itself is never followed.
+pub fn relative_path(path: &Path, root: &Path) -> PathBuf {
+    if let Ok(relative) = path.strip_prefix(root) {
+        return match relative.as_os_str().is_empty() {
+            true => PathBuf::from("."),
+            false => relative.to_path_buf(),
+        };
+    }
+    let canonical = |path: &Path| match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            parent.canonicalize().map(|parent| parent.join(name))
+        }
+        (_, Some(name)) => Path::new(".").canonicalize().map(|cwd| cwd.join(name)),
+        _ => path.canonicalize(),
+    };
+    match (canonical(path), root.canonicalize()) {
+        (Ok(path), Ok(root)) => {
+            let common = path
+                .components()
+                .zip(root.components())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let ups = root.components().count() - common;
+            (0..ups)
+                .map(|_| Component::ParentDir)
+                .chain(path.components().skip(common))
+                .collect()
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+/// # Returns
+///
+/// Bytes of `path` quoted such that bash reads them back as exactly one word equal to `path`,
+/// without ever containing a line break, so that quoted paths can't escape a comment.
+pub fn shell_quote(path: &Path) -> Vec<u8> {
+    let bytes = path_bytes(path);
+    let is_control = |b: &u8| *b < 0x20 || *b == 0x7f;
+
+    match bytes.iter().any(is_control) {
+        // inside single quotes everything is literal, except single quotes themselves
+        false => {
+            let mut quoted = vec![b'\''];
+            for &b in bytes.iter() {
+                match b {
+                    b'\'' => quoted.extend_from_slice(b"'\\''"),
+                    _ => quoted.push(b),
+                }
+            }
+            quoted.push(b'\'');
+            quoted
+        }
+        // ANSI-C quoting lets control characters be spelled out as escapes
+        true => {
+            let mut quoted = b"$'".to_vec();
+            for &b in bytes.iter() {
+                match b {
+                    b'\\' | b'\'' => quoted.extend_from_slice(&[b'\\', b]),
+                    _ if is_control(&b) => quoted.extend(format!("\\{:03o}", b).bytes()),
+                    _ => quoted.push(b),
+                }
+            }
+            quoted.push(b'\'');
+            quoted
+        }
+    }
+}
+
+/// # Returns
+///
+/// Raw bytes of `path` as the OS sees them.
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+/// # Returns
+///
+/// Bytes of `path`, with anything that isn't valid unicode replaced.
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().as_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn hostile_names_are_quoted() {
+        let quote = |s: &str| String::from_utf8(shell_quote(Path::new(s))).unwrap();
+        assert_eq!("'plain'", quote("plain"));
+        assert_eq!("'it'\\''s'", quote("it's"));
+        assert_eq!("'$(rm -rf ~) `x`'", quote("$(rm -rf ~) `x`"));
+        assert_eq!("$'a\\012b\\\\c\\'d'", quote("a\nb\\c'd"));
+    }
+
+    #[test]
+    fn paths_relative_to_the_root() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(root.join("scan/sub")).unwrap();
+        create_dir_all(root.join("reference")).unwrap();
+        let scan = root.join("scan");
+
+        assert_eq!(
+            PathBuf::from("sub/a"),
+            relative_path(&scan.join("sub/a"), &scan)
+        );
+        assert_eq!(
+            PathBuf::from("plain/a"),
+            relative_path(Path::new("./plain/a"), Path::new("."))
+        );
+        assert_eq!(
+            PathBuf::from("../reference/b"),
+            relative_path(&root.join("reference/b"), &scan.join("sub/.."))
+        );
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+use flate2::read::ZlibDecoder;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Types of streams that say nothing about what a document shows, but about who saved it when,
+/// or where its objects are: XMP metadata, cross-reference tables and object streams, which also
+/// hold the document information dictionary with the producer and dates.
+const SKIPPED_TYPES: &[&[u8]] = &[b"/Type/Metadata", b"/Type/XRef", b"/Type/ObjStm"];
+
+/// # Returns
+///
+/// Whether the file at `path` starts like a PDF, which is false if it can't be read.
+pub fn is_pdf(path: &Path) -> bool {
+    let mut start = Vec::with_capacity(5);
+    match File::open(path).and_then(|file| file.take(5).read_to_end(&mut start)) {
+        Ok(_) => start == b"%PDF-",
+        Err(_) => false,
+    }
+}
+
+/// # Returns
+///
+/// Contents of every stream of the PDF `contents` that is part of what the document shows, such
+/// as pages, fonts and images, in the order they appear in, inflated if they were compressed
+/// with `/FlateDecode` so that how hard a tool compressed them doesn't matter. Streams that can't
+/// be inflated are returned as they are.
+pub fn content_streams(contents: &[u8]) -> Vec<Vec<u8>> {
+    let mut streams = Vec::new();
+    let mut pos = 0;
+    while let Some(keyword) = find(contents, b"stream", pos) {
+        pos = keyword + b"stream".len();
+        if contents[..keyword].ends_with(b"end") {
+            continue; // the end of one
+        }
+        let dict = match dictionary_before(&contents[..keyword]) {
+            Some(dict) => dict,
+            None => continue, // the word in some text, not a keyword
+        };
+        // keys and values are looked up however a tool spaced them
+        let compact: Vec<u8> = dict
+            .iter()
+            .filter(|byte| !byte.is_ascii_whitespace())
+            .copied()
+            .collect();
+        let start = match &contents[pos..] {
+            [b'\r', b'\n', ..] => pos + 2,
+            [b'\n', ..] => pos + 1,
+            _ => continue,
+        };
+        let end = match stream_end(contents, start, dict) {
+            Some(end) => end,
+            None => break, // cut short
+        };
+        pos = end;
+        if SKIPPED_TYPES
+            .iter()
+            .any(|kind| find(&compact, kind, 0).is_some())
+        {
+            continue;
+        }
+        let data = &contents[start..end];
+        let mut inflated = Vec::new();
+        let flate = find(&compact, b"/FlateDecode", 0).is_some();
+        match flate && ZlibDecoder::new(data).read_to_end(&mut inflated).is_ok() {
+            true => streams.push(inflated),
+            false => streams.push(data.to_vec()),
+        }
+    }
+    streams
+}
+
+/// # Returns
+///
+/// Position of the first `needle` in `haystack` from `from` on.
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|at| from + at)
+}
+
+/// # Returns
+///
+/// The dictionary `before` ends with, but for whitespace, or `None` if it doesn't end with one.
+fn dictionary_before(before: &[u8]) -> Option<&[u8]> {
+    let end = before
+        .iter()
+        .rposition(|byte| !byte.is_ascii_whitespace())?
+        + 1;
+    if !before[..end].ends_with(b">>") {
+        return None;
+    }
+    // nested dictionaries, such as `/DecodeParms`, are part of it
+    let mut depth = 0;
+    let mut at = end;
+    while at >= 2 {
+        match &before[at - 2..at] {
+            b">>" => depth += 1,
+            b"<<" => depth -= 1,
+            _ => {
+                at -= 1;
+                continue;
+            }
+        }
+        at -= 2;
+        if depth == 0 {
+            return Some(&before[at..end]);
+        }
+    }
+    None
+}
+
+/// # Returns
+///
+/// Where the data of the stream that starts at `start` ends: after as many bytes as its `/Length`
+/// says, if it is given directly and `endstream` follows, or else right before the next
+/// `endstream` and the line break in front of it.
+fn stream_end(contents: &[u8], start: usize, dict: &[u8]) -> Option<usize> {
+    let mut key = find(dict, b"/Length", 0).map(|at| at + b"/Length".len());
+    while let Some(after) = key.filter(|&after| dict.get(after).is_some_and(u8::is_ascii_digit)) {
+        // `/Length1` and the like, which fonts have too
+        key = find(dict, b"/Length", after).map(|at| at + b"/Length".len());
+    }
+    let length = key.and_then(|key| {
+        let value = dict[key..].split(|byte| byte.is_ascii_whitespace());
+        let words: Vec<&[u8]> = value.filter(|word| !word.is_empty()).take(3).collect();
+        match &words[..] {
+            // a reference to another object, such as `12 0 R`
+            [_, _, reference, ..] if reference.starts_with(b"R") => None,
+            [number, ..] => {
+                let digits = number
+                    .iter()
+                    .take_while(|byte| byte.is_ascii_digit())
+                    .count();
+                std::str::from_utf8(&number[..digits]).ok()?.parse().ok()
+            }
+            [] => None,
+        }
+    });
+    if let Some(end) = length.and_then(|len| start.checked_add(len)) {
+        let rest = contents.get(end..).unwrap_or_default();
+        let skipped = rest
+            .iter()
+            .take_while(|byte| byte.is_ascii_whitespace())
+            .count();
+        if rest[skipped..].starts_with(b"endstream") {
+            return Some(end);
+        }
+    }
+    let keyword = find(contents, b"endstream", start)?;
+    let data = &contents[start..keyword];
+    let eol = match data {
+        [.., b'\r', b'\n'] => 2,
+        [.., b'\n'] | [.., b'\r'] => 1,
+        _ => 0,
+    };
+    Some(keyword - eol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn streams_are_found_and_inflated() {
+        let page = b"BT /F1 12 Tf (Hello) Tj ET";
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(page).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut pdf = b"%PDF-1.7\n1 0 obj\n<< /Producer (one) >>\nendobj\n".to_vec();
+        pdf.extend(
+            b"2 0 obj\n<</Length 5 0 R /Filter /FlateDecode\n/DecodeParms <<>> >>\nstream\n",
+        );
+        pdf.extend(&compressed);
+        pdf.extend(b"\nendstream\nendobj\n");
+        pdf.extend(b"3 0 obj\n<< /Type /Metadata /Length 5 >>\nstream\r\n<xmp>\r\nendstream\n");
+        pdf.extend(b"4 0 obj\n<< /Length1 2 /Length 10 >>stream\nraw stream\nendstream\nendobj\n");
+        pdf.extend(b"5 0 obj\n<< /Length 3 /Filter /FlateDecode >>\nstream\nbad\nendstream\n");
+        pdf.extend(b"(a stream of words)\n");
+
+        assert_eq!(
+            vec![page.to_vec(), b"raw stream".to_vec(), b"bad".to_vec()],
+            content_streams(&pdf)
+        );
+        assert!(content_streams(b"%PDF-1.7\n<< >>\nstream\nno end").is_empty());
+        assert!(!is_pdf(Path::new(file!())));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::action::apply;
+use crate::action::apply_except;
+use crate::action::choose_survivor;
+use crate::action::Action;
+use crate::cache::RunSummary;
+use crate::clargs::DedupOpt;
+use crate::clargs::ScanOpt;
+use crate::dirs::print_dir_totals;
+use crate::dirs::DirTotals;
+use crate::error::AccessDenied;
+use crate::error::EXIT_FATAL;
+use crate::extents::physical_copies;
+use crate::fdup::DuplicateGroup;
+use crate::fdup::ScanStats;
+use crate::fdup::SpecialKind;
+use crate::interactive::ask;
+use crate::interactive::Decision;
+use crate::output::print_footer;
+use crate::output::print_group;
+use crate::output::print_header;
+use crate::output::Details;
+use crate::sample::print_sample;
+use crate::sample::Sampler;
+use crate::script::print_script_group;
+use crate::script::print_script_header;
+use std::io;
+use std::panic;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::sync_channel;
+use std::sync::mpsc::Receiver;
+use std::thread;
+
+/// Number of groups the scan may get ahead of the printer before it has to wait for it.
+pub const OUTPUT_BUFFER: usize = 1024;
+
+/// Hand `groups` over to `print`, running on a dedicated thread, through a bounded channel.
+///
+/// Producing the groups (walking and hashing on the rayon pool) thus never waits on a slow
+/// terminal or pipe, unless the printer falls `OUTPUT_BUFFER` groups behind, in which case the
+/// scan pauses between groups until it catches up. If the printer hangs up early, e.g. because
+/// the user quit an interactive session, the scan stops.
+///
+/// # Returns
+///
+/// Whatever `print` returns.
+pub fn print_in_background<I, F, R>(groups: I, print: F) -> R
+where
+    I: Iterator<Item = DuplicateGroup>,
+    F: FnOnce(Receiver<DuplicateGroup>) -> R + Send,
+    R: Send,
+{
+    let (sender, receiver) = sync_channel(OUTPUT_BUFFER);
+    thread::scope(|scope| {
+        let printer = scope.spawn(move || print(receiver));
+        for group in groups {
+            if sender.send(group).is_err() {
+                break; // the printer hung up
+            }
+        }
+        drop(sender); // lets the printer know there is nothing more to come
+        printer
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+    })
+}
+
+/// Print, act on, and tally each of `groups` as requested on the command line, accumulating the
+/// totals in `summary`. This is the one place that decides what happens to results once they are
+/// found.
+///
+/// # Parameters
+///
+/// 1. `scan` -- options of the scan that found the groups
+/// 1. `dedup` -- what to do with the groups, all defaults for a plain scan
+/// 1. `local` -- render timestamps in the local time zone instead of UTC
+/// 1. `action` -- action selected by `dedup`, if any
+/// 1. `groups` -- duplicate groups, in the order they should be printed
+/// 1. `summary` -- totals of the run so far
+pub fn print_groups<I>(
+    scan: &ScanOpt,
+    dedup: &DedupOpt,
+    local: bool,
+    action: Option<Action>,
+    groups: I,
+    summary: &mut RunSummary,
+) where
+    I: Iterator<Item = DuplicateGroup>,
+{
+    let policy = scan.policy(dedup);
+    let mut sampler = scan.review_sample.map(|size| Sampler::new(size, scan.seed));
+    let mut dir_totals = match scan.by_dir {
+        true => {
+            let roots = Some(&scan.root).into_iter().chain(scan.reference.iter());
+            Some(DirTotals::new(roots.cloned().collect()))
+        }
+        false => None,
+    };
+    let mut reclaimed = 0;
+    let mut shared = 0;
+    let report = |result: io::Result<()>| match result {
+        Ok(()) => (),
+        // e.g. `| head`
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(err) => {
+            eprintln!("ERROR writing output: {}", err);
+            process::exit(EXIT_FATAL);
+        }
+    };
+    match (dedup.emit_script, scan.quiet) {
+        (Some(kind), _) => report(print_script_header(kind)),
+        (None, true) => (),
+        (None, false) => {
+            let root = Some(scan.root.as_path()).filter(|_| scan.relative);
+            report(print_header(scan.format, local, root))
+        }
+    };
+    for group in groups {
+        let copies = match scan.check_extents {
+            true => physical_copies(&group.paths) as i64 - 1,
+            false => group.paths.len() as i64 - 1,
+        };
+        shared += group.paths.len() as i64 - 1 - copies;
+        summary.groups += 1;
+        summary.redundant_files += copies;
+        summary.redundant_bytes += group.size as i64 * copies;
+        let index = summary.groups as usize;
+        if let Some(sampler) = sampler.as_mut() {
+            sampler.offer(index, &group);
+        }
+        if let Some(dir_totals) = dir_totals.as_mut() {
+            let survivor = choose_survivor(policy.keep, &policy.prefer, &group.paths);
+            dir_totals.add(&group, survivor);
+        }
+
+        if let Some(kind) = dedup.emit_script {
+            let action = action.unwrap_or_else(|| dedup.delete_action());
+            report(print_script_group(kind, action, &policy, index, &group));
+            continue;
+        }
+
+        if dedup.interactive {
+            let kept = match ask(local, index, &group) {
+                Ok(Decision::Keep(kept)) => kept,
+                Ok(Decision::Skip) => continue,
+                Ok(Decision::Quit) => break,
+                Err(err) => {
+                    eprintln!("ERROR reading answer: {}", err);
+                    break;
+                }
+            };
+            let action = action.unwrap_or_else(|| dedup.delete_action());
+            reclaimed += apply_except(action, &policy, &group.paths, &kept);
+            continue;
+        }
+
+        if !scan.quiet {
+            let root = Some(scan.root.as_path()).filter(|_| scan.relative);
+            report(print_group(
+                scan.format,
+                &policy,
+                local,
+                root,
+                Details {
+                    hash: scan.show_hash,
+                    meta: scan.show_meta,
+                    normalize: scan.normalize,
+                },
+                index,
+                &group,
+            ));
+        }
+        if let Some(action) = action {
+            reclaimed += apply(action, &policy, &group.paths);
+        }
+    }
+    if dedup.emit_script.is_none() && !scan.quiet {
+        report(print_footer(scan.format, local, summary));
+    }
+    if let Some(sampler) = sampler {
+        report(print_sample(sampler, local));
+    }
+    if let Some(dir_totals) = dir_totals {
+        print_dir_totals(&dir_totals);
+    }
+    if scan.check_extents {
+        eprintln!(
+            "{} copies already share extents; {} bytes are actually reclaimable",
+            shared, summary.redundant_bytes
+        );
+    }
+    if (action.is_some() || dedup.interactive) && dedup.emit_script.is_none() && !scan.quiet {
+        match dedup.dry_run {
+            true => println!("would reclaim {} bytes", reclaimed),
+            false => println!("reclaimed {} bytes", reclaimed),
+        };
+    }
+}
+
+/// Print totals of the work done by a scan and of what it found to stderr, so that they don't mix
+/// with the groups on stdout.
+pub fn print_summary(stats: &ScanStats, summary: &RunSummary) {
+    eprintln!("files scanned:    {}", stats.files.load(Ordering::Relaxed));
+    eprintln!(
+        "bytes hashed:     {}",
+        stats.bytes_hashed.load(Ordering::Relaxed)
+    );
+    eprintln!("duplicate groups: {}", summary.groups);
+    eprintln!("redundant copies: {}", summary.redundant_files);
+    eprintln!("reclaimable:      {} bytes", summary.redundant_bytes);
+    let special: Vec<String> = SpecialKind::ALL
+        .iter()
+        .map(|&kind| {
+            (
+                kind,
+                stats.special_files[kind as usize].load(Ordering::Relaxed),
+            )
+        })
+        .filter(|(_, count)| *count > 0)
+        .map(|(kind, count)| format!("{} {}", count, kind))
+        .collect();
+    if !special.is_empty() {
+        eprintln!("special files:    skipped {}", special.join(", "));
+    }
+    let unstable = stats.unstable_files.load(Ordering::Relaxed);
+    if unstable > 0 {
+        eprintln!("unstable files:   skipped {} in use or changing", unstable);
+    }
+    let undecompressed = stats.undecompressed_files.load(Ordering::Relaxed);
+    if undecompressed > 0 {
+        eprintln!(
+            "compressed files: {} compared as they are, being broken or too large",
+            undecompressed
+        );
+    }
+}
+
+/// Print the alternate data streams found by a scan to stderr, so that they don't mix with the
+/// groups on stdout.
+pub fn print_streams(stats: &ScanStats) {
+    eprintln!(
+        "alternate data streams: {} in {} files, {} bytes",
+        stats.streams.load(Ordering::Relaxed),
+        stats.files_with_streams.load(Ordering::Relaxed),
+        stats.stream_bytes.load(Ordering::Relaxed)
+    );
+}
+
+/// Print how many directories and files a scan was `denied` access to, and which ones if `list`,
+/// to stderr, so that they don't mix with the groups on stdout.
+pub fn print_denied(denied: &AccessDenied, list: bool) {
+    eprintln!(
+        "permission denied: {} directories, {} files",
+        denied.dirs.len(),
+        denied.files.len()
+    );
+    if list {
+        for dir in denied.dirs.iter() {
+            eprintln!("  {:?} (directory)", dir);
+        }
+        for file in denied.files.iter() {
+            eprintln!("  {:?}", file);
+        }
+    }
+}
+
+/// Print `dirs`, directories without any regular files, to stderr, so that they don't mix with
+/// the groups on stdout.
+pub fn print_empty_dirs(dirs: &[PathBuf]) {
+    eprintln!("empty directories: {}", dirs.len());
+    for dir in dirs {
+        eprintln!("  {:?}", dir);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::cache::identity;
+use crate::fdup::DuplicateGroup;
+use crate::output::path_bytes;
+use std::fs::symlink_metadata;
+use std::io;
+use std::io::Write;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Write the comment lines `rdfind` starts its `results.txt` with to `out`.
+pub fn write_rdfind_header<W: Write>(mut out: W) -> io::Result<()> {
+    writeln!(out, "# Automatically generated")?;
+    writeln!(out, "# duptype id depth size device inode priority name")
+}
+
+/// Write the lines of `group` to `out` as `rdfind` does in its `results.txt`: the copy to keep
+/// first, with the group's `index` as id, the others with its negation. All files count as found
+/// in the same tree, with priority 1, since fdup scans a single one.
+///
+/// # Parameters
+///
+/// 1. `out` -- where to write the lines
+/// 1. `group` -- files with identical contents
+/// 1. `shown` -- paths of the files as they should be written
+/// 1. `survivor` -- position of the copy to keep in `group`
+/// 1. `index` -- position of the group in the output, starting from 1
+pub fn write_rdfind_group<W: Write>(
+    mut out: W,
+    group: &DuplicateGroup,
+    shown: &[PathBuf],
+    survivor: usize,
+    index: usize,
+) -> io::Result<()> {
+    let others = (0..group.paths.len()).filter(|i| *i != survivor);
+    for i in Some(survivor).into_iter().chain(others) {
+        let (duptype, id) = match i == survivor {
+            true => ("DUPTYPE_FIRST_OCCURRENCE", index as i64),
+            false => ("DUPTYPE_WITHIN_SAME_TREE", -(index as i64)),
+        };
+        let (device, inode) = symlink_metadata(&group.paths[i])
+            .map(|meta| identity(&meta, &group.paths[i]))
+            .unwrap_or((0, 0));
+        write!(
+            out,
+            "{} {} {} {} {} {} 1 ",
+            duptype,
+            id,
+            depth(&shown[i]),
+            group.size,
+            device as u64,
+            inode as u64
+        )?;
+        // like rdfind, the name is the rest of the line, unquoted
+        out.write_all(&path_bytes(&shown[i]))?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Write the comment line `rdfind` ends its `results.txt` with to `out`.
+pub fn write_rdfind_footer<W: Write>(mut out: W) -> io::Result<()> {
+    writeln!(out, "# end of file")
+}
+
+/// # Returns
+///
+/// Number of directories named in `path` above the file, so 0 for the files directly in the root
+/// when paths are relative to it.
+fn depth(path: &Path) -> usize {
+    path.parent()
+        .map(|parent| {
+            parent
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+
+    #[test]
+    fn groups_are_written_like_rdfind() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(root.join("sub")).unwrap();
+        write(root.join("a"), "same").unwrap();
+        write(root.join("sub/b c"), "same").unwrap();
+        let group = DuplicateGroup {
+            hash: vec![0],
+            size: 4,
+            paths: vec![root.join("a"), root.join("sub/b c")],
+        };
+        let shown = vec![PathBuf::from("./a"), PathBuf::from("./sub/b c")];
+
+        let mut written = Vec::new();
+        write_rdfind_group(&mut written, &group, &shown, 1, 7).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        let lines: Vec<Vec<&str>> = written
+            .lines()
+            .map(|line| line.splitn(8, ' ').collect())
+            .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!(
+            vec!["DUPTYPE_FIRST_OCCURRENCE", "7", "1", "4"],
+            lines[0][..4]
+        );
+        assert_eq!("./sub/b c", lines[0][7]);
+        assert_eq!(
+            vec!["DUPTYPE_WITHIN_SAME_TREE", "-7", "0", "4"],
+            lines[1][..4]
+        );
+        assert_eq!("./a", lines[1][7]);
+        assert_ne!("0", lines[0][5]); // inode
+
+        assert_eq!(0, depth(Path::new("a")));
+        assert_eq!(2, depth(Path::new("/dir/sub/a")));
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,362 @@
+use crate::fdup::DuplicateGroup;
+use crate::time::mtime;
+use chrono::DateTime;
+use serde::Deserialize;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Serializable form of a scan's results, as written by `--format json`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Report {
+    /// When the scan started, in RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started: Option<String>,
+    pub groups: Vec<ReportGroup>,
+    /// When the scan finished, in RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished: Option<String>,
+}
+
+/// Serializable form of a `DuplicateGroup`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReportGroup {
+    /// Hex-encoded sha512 checksum of the contents
+    pub hash: String,
+    /// Size in bytes of each file
+    pub size: u64,
+    /// Paths to the files, with anything that isn't valid unicode replaced
+    pub paths: Vec<String>,
+    /// Exact bytes of each path that isn't valid unicode, in the same order as `paths`, `None`
+    /// where the entry of `paths` is exact already; empty if all of them are
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub raw_paths: Vec<Option<Vec<u8>>>,
+    /// Modification time of each file in RFC 3339, in the same order as `paths`, or `None` where
+    /// it couldn't be read
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mtimes: Vec<Option<String>>,
+}
+
+impl ReportGroup {
+    /// # Parameters
+    ///
+    /// 1. `group` -- files with identical contents
+    /// 1. `local` -- render mtimes in the local time zone instead of UTC
+    pub fn new(group: &DuplicateGroup, local: bool) -> ReportGroup {
+        ReportGroup::shown_as(group, &group.paths, local)
+    }
+
+    /// Same as `new`, except that the paths are written as `shown`, e.g. relative to some root,
+    /// while mtimes are still read from the actual paths.
+    pub fn shown_as(group: &DuplicateGroup, shown: &[PathBuf], local: bool) -> ReportGroup {
+        let raw_paths: Vec<_> = shown.iter().map(|path| raw_bytes(path)).collect();
+        ReportGroup {
+            hash: hex(&group.hash),
+            size: group.size,
+            paths: shown
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect(),
+            raw_paths: match raw_paths.iter().any(Option::is_some) {
+                true => raw_paths,
+                false => Vec::new(),
+            },
+            mtimes: group.paths.iter().map(|path| mtime(path, local)).collect(),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// The paths exactly as the scan found them, taking `raw_paths` into account.
+    pub fn path_bufs(&self) -> Vec<PathBuf> {
+        self.paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| match self.raw_paths.get(i) {
+                Some(Some(raw)) => from_raw_bytes(raw),
+                _ => PathBuf::from(path),
+            })
+            .collect()
+    }
+}
+
+/// # Returns
+///
+/// Raw bytes of `path` as the OS sees them, if it isn't valid unicode.
+#[cfg(unix)]
+fn raw_bytes(path: &Path) -> Option<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    match path.to_str() {
+        Some(_) => None,
+        None => Some(path.as_os_str().as_bytes().to_vec()),
+    }
+}
+
+/// # Returns
+///
+/// `None`, since paths can only be stored losslessly as bytes on unix.
+#[cfg(not(unix))]
+fn raw_bytes(_path: &Path) -> Option<Vec<u8>> {
+    None
+}
+
+/// # Returns
+///
+/// Path made of exactly `raw`.
+#[cfg(unix)]
+pub fn from_raw_bytes(raw: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(raw))
+}
+
+/// # Returns
+///
+/// Path made of `raw`, with anything that isn't valid unicode replaced.
+#[cfg(not(unix))]
+pub fn from_raw_bytes(raw: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(raw).into_owned())
+}
+
+/// A path in a report as its text, plus its exact bytes where the text isn't exact.
+type PathKey<'a> = (&'a str, Option<&'a [u8]>);
+
+/// Combine reports from several shards or runs into one, regrouping files by checksum.
+///
+/// A path listed more than once is only counted once, telling paths apart by their exact bytes; if
+/// it was listed under different checksums (because the file changed between runs), the entry from
+/// the latest report wins. Groups left with fewer than two files are dropped. The merged run spans
+/// from the earliest start to the latest finish of any report.
+///
+/// # Parameters
+///
+/// - `reports`: reports to merge, from oldest to newest
+///
+/// # Returns
+///
+/// Merged report, with groups ordered by checksum and paths ordered lexicographically.
+pub fn merge(reports: &[Report]) -> Report {
+    let mut sizes: HashMap<&str, u64> = HashMap::new();
+    let mut hash_of: HashMap<PathKey, &str> = HashMap::new();
+    let mut mtime_of: HashMap<PathKey, &Option<String>> = HashMap::new();
+    for group in reports.iter().flat_map(|report| report.groups.iter()) {
+        sizes.insert(&group.hash, group.size);
+        for (i, path) in group.paths.iter().enumerate() {
+            let raw = group.raw_paths.get(i).and_then(|raw| raw.as_deref());
+            hash_of.insert((path, raw), &group.hash); // later reports overwrite earlier ones
+            mtime_of.insert((path, raw), group.mtimes.get(i).unwrap_or(&None));
+        }
+    }
+
+    let mut paths_of: BTreeMap<&str, BTreeSet<PathKey>> = BTreeMap::new();
+    for (path, hash) in hash_of {
+        paths_of.entry(hash).or_default().insert(path);
+    }
+
+    let groups = paths_of
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(hash, paths)| {
+            let mtimes = match paths.iter().any(|path| mtime_of[path].is_some()) {
+                true => paths.iter().map(|path| mtime_of[path].clone()).collect(),
+                false => Vec::new(),
+            };
+            let raw_paths = match paths.iter().any(|(_, raw)| raw.is_some()) {
+                true => paths
+                    .iter()
+                    .map(|(_, raw)| raw.map(<[u8]>::to_vec))
+                    .collect(),
+                false => Vec::new(),
+            };
+            ReportGroup {
+                hash: String::from(hash),
+                size: sizes[hash],
+                paths: paths.iter().map(|(path, _)| String::from(*path)).collect(),
+                raw_paths,
+                mtimes,
+            }
+        })
+        .collect();
+    let started = reports.iter().filter_map(|r| r.started.as_ref());
+    let finished = reports.iter().filter_map(|r| r.finished.as_ref());
+    Report {
+        started: started
+            .min_by_key(|time| DateTime::parse_from_rfc3339(time).ok())
+            .cloned(),
+        groups,
+        finished: finished
+            .max_by_key(|time| DateTime::parse_from_rfc3339(time).ok())
+            .cloned(),
+    }
+}
+
+/// How the duplicate groups of a tree changed from one report of it to a later one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReportDiff {
+    /// Groups only in the later report
+    pub new: Vec<ReportGroup>,
+    /// Groups only in the earlier report
+    pub resolved: Vec<ReportGroup>,
+    /// Groups with more copies in the later report, as they were and as they are
+    pub grown: Vec<(ReportGroup, ReportGroup)>,
+    /// Groups with fewer copies in the later report, as they were and as they are
+    pub shrunk: Vec<(ReportGroup, ReportGroup)>,
+    /// Number of groups with as many copies in both reports, wherever they are
+    pub unchanged: usize,
+}
+
+/// Match up the groups of two reports by checksum. Each report is regrouped as by `merge` first,
+/// so a path listed twice only counts once.
+///
+/// # Parameters
+///
+/// 1. `old` -- earlier report
+/// 1. `new` -- later report
+///
+/// # Returns
+///
+/// The differences between the reports, with groups ordered by checksum.
+pub fn diff(old: &Report, new: &Report) -> ReportDiff {
+    let by_hash = |report: &Report| -> BTreeMap<String, ReportGroup> {
+        merge(std::slice::from_ref(report))
+            .groups
+            .into_iter()
+            .map(|group| (group.hash.clone(), group))
+            .collect()
+    };
+    let (mut old, new) = (by_hash(old), by_hash(new));
+
+    let mut diff = ReportDiff::default();
+    for (hash, group) in new {
+        let before = match old.remove(&hash) {
+            Some(before) => before,
+            None => {
+                diff.new.push(group);
+                continue;
+            }
+        };
+        match group.paths.len().cmp(&before.paths.len()) {
+            Ordering::Greater => diff.grown.push((before, group)),
+            Ordering::Less => diff.shrunk.push((before, group)),
+            Ordering::Equal => diff.unchanged += 1,
+        }
+    }
+    diff.resolved = old.into_values().collect();
+    diff
+}
+
+/// # Returns
+///
+/// Lowercase hexadecimal representation of `bytes`.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// # Returns
+///
+/// Bytes written as `text` in hexadecimal, in either case, or `None` if it isn't.
+pub fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(hash: &str, paths: &[&str]) -> ReportGroup {
+        ReportGroup {
+            hash: String::from(hash),
+            size: 1,
+            paths: paths.iter().map(|&p| String::from(p)).collect(),
+            raw_paths: Vec::new(),
+            mtimes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn merge_regroups_by_hash() {
+        let shard1 = Report {
+            started: Some(String::from("2020-01-01T10:00:00Z")),
+            groups: vec![group("aa", &["a1", "a2"]), group("bb", &["b1", "b2"])],
+            finished: Some(String::from("2020-01-01T11:00:00Z")),
+        };
+        let shard2 = Report {
+            started: Some(String::from("2020-01-01T10:30:00+01:00")),
+            groups: vec![group("aa", &["a3", "a1"]), group("cc", &["b2", "c1"])],
+            finished: Some(String::from("2020-01-01T10:45:00+01:00")),
+        };
+        let expected = Report {
+            started: Some(String::from("2020-01-01T10:30:00+01:00")),
+            groups: vec![group("aa", &["a1", "a2", "a3"]), group("cc", &["b2", "c1"])],
+            finished: Some(String::from("2020-01-01T11:00:00Z")),
+        };
+        assert_eq!(expected, merge(&[shard1, shard2]));
+    }
+
+    #[test]
+    fn diff_matches_groups_by_hash() {
+        let old = Report {
+            groups: vec![
+                group("aa", &["gone1", "gone2"]),
+                group("bb", &["x", "y"]),
+                group("cc", &["p", "q", "r"]),
+                group("dd", &["moved1", "moved2"]),
+            ],
+            ..Report::default()
+        };
+        let new = Report {
+            groups: vec![
+                group("bb", &["x", "y", "z"]),
+                group("cc", &["p", "q"]),
+                group("dd", &["renamed1", "renamed2"]),
+                group("ee", &["fresh1", "fresh2"]),
+            ],
+            ..Report::default()
+        };
+        let hashes = |groups: &[ReportGroup]| -> Vec<String> {
+            groups.iter().map(|group| group.hash.clone()).collect()
+        };
+        let diff = diff(&old, &new);
+        assert_eq!(vec!["ee"], hashes(&diff.new));
+        assert_eq!(vec!["aa"], hashes(&diff.resolved));
+        assert_eq!(1, diff.grown.len());
+        assert_eq!(vec!["x", "y", "z"], diff.grown[0].1.paths);
+        assert_eq!(1, diff.shrunk.len());
+        assert_eq!(3, diff.shrunk[0].0.paths.len());
+        assert_eq!(1, diff.unchanged);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_unicode_paths_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = |bytes: &[u8]| PathBuf::from(std::ffi::OsStr::from_bytes(bytes));
+        let group = DuplicateGroup {
+            hash: vec![0xaa],
+            size: 1,
+            paths: vec![path(b"a\xff"), path(b"a\xfe"), path(b"b")],
+        };
+        let report = ReportGroup::new(&group, false);
+        assert_eq!(report.paths[0], report.paths[1]); // both lossy
+        assert_eq!(None, report.raw_paths[2]);
+
+        let json = serde_json::to_string(&report).unwrap();
+        let parsed: ReportGroup = serde_json::from_str(&json).unwrap();
+        assert_eq!(group.paths, parsed.path_bufs());
+
+        let merged = merge(&[Report {
+            groups: vec![parsed],
+            ..Report::default()
+        }]);
+        assert_eq!(3, merged.groups[0].paths.len());
+    }
+}
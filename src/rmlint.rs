@@ -0,0 +1,190 @@
+use crate::cache::identity;
+use crate::cache::mtime_nanos;
+use crate::cache::RunSummary;
+use crate::fdup::DuplicateGroup;
+use crate::report::hex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::env;
+use std::fs::symlink_metadata;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// First object of an rmlint JSON dump, describing the run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct RmlintHeader {
+    description: String,
+    cwd: String,
+    args: String,
+    version: String,
+    rev: String,
+    progress: u8,
+    checksum_type: String,
+}
+
+/// One file of a duplicate group in an rmlint JSON dump, as its review and removal tools (e.g.
+/// `rmlint --replay`) read it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RmlintFile {
+    /// Unique within the dump: the position of the group in the upper 32 bits, of the file
+    /// within it in the lower ones
+    pub id: u64,
+    /// Always `duplicate_file`
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub progress: u8,
+    /// Hex-encoded sha512 checksum of the contents
+    pub checksum: String,
+    pub path: String,
+    pub size: u64,
+    /// Number of components of `path`
+    pub depth: usize,
+    pub inode: u64,
+    pub disk_id: u64,
+    /// Whether this is the copy to keep
+    pub is_original: bool,
+    /// Seconds since the unix epoch
+    pub mtime: i64,
+}
+
+/// Last object of an rmlint JSON dump, with the totals of the run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct RmlintFooter {
+    aborted: bool,
+    progress: u8,
+    duplicates: i64,
+    duplicate_sets: i64,
+    total_lint_size: i64,
+}
+
+/// Write the opening bracket and header object of an rmlint JSON dump to `out`.
+pub fn write_rmlint_header<W: Write>(mut out: W) -> io::Result<()> {
+    let header = RmlintHeader {
+        description: String::from("rmlint json-dump of lint files"),
+        cwd: env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        args: env::args().collect::<Vec<_>>().join(" "),
+        version: String::from(env!("CARGO_PKG_VERSION")),
+        rev: String::from("fdup"),
+        progress: 0,
+        checksum_type: String::from("sha512"),
+    };
+    out.write_all(b"[\n")?;
+    serde_json::to_writer(&mut out, &header)?;
+    Ok(())
+}
+
+/// # Parameters
+///
+/// 1. `group` -- files with identical contents
+/// 1. `shown` -- paths of the files as they should be written
+/// 1. `survivor` -- position of the copy to keep in `group`
+/// 1. `index` -- position of the group in the output, starting from 1
+///
+/// # Returns
+///
+/// Entries of the files of `group` in an rmlint JSON dump. Metadata that can't be read is 0.
+pub fn rmlint_files(
+    group: &DuplicateGroup,
+    shown: &[PathBuf],
+    survivor: usize,
+    index: usize,
+) -> Vec<RmlintFile> {
+    let checksum = hex(&group.hash);
+    group
+        .paths
+        .iter()
+        .zip(shown.iter())
+        .enumerate()
+        .map(|(i, (path, shown))| {
+            let meta = symlink_metadata(path).ok();
+            let (disk_id, inode) = meta
+                .as_ref()
+                .map(|meta| identity(meta, path))
+                .unwrap_or((0, 0));
+            let mtime = meta.as_ref().and_then(mtime_nanos).unwrap_or(0);
+            RmlintFile {
+                id: (index as u64) << 32 | i as u64,
+                kind: String::from("duplicate_file"),
+                progress: 100,
+                checksum: checksum.clone(),
+                path: shown.to_string_lossy().into_owned(),
+                size: group.size,
+                depth: shown.components().count(),
+                inode: inode as u64,
+                disk_id: disk_id as u64,
+                is_original: i == survivor,
+                mtime: mtime.div_euclid(1_000_000_000),
+            }
+        })
+        .collect()
+}
+
+/// Write the footer object with the totals of `summary` and the closing bracket of an rmlint JSON
+/// dump to `out`.
+pub fn write_rmlint_footer<W: Write>(mut out: W, summary: &RunSummary) -> io::Result<()> {
+    let footer = RmlintFooter {
+        aborted: false,
+        progress: 100,
+        duplicates: summary.redundant_files,
+        duplicate_sets: summary.groups,
+        total_lint_size: summary.redundant_bytes,
+    };
+    out.write_all(b",\n")?;
+    serde_json::to_writer(&mut out, &footer)?;
+    out.write_all(b"\n]\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::write;
+
+    #[test]
+    fn dumps_parse_as_json_arrays() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&root).unwrap();
+        write(root.join("a"), "same").unwrap();
+        write(root.join("b"), "same").unwrap();
+        let group = DuplicateGroup {
+            hash: vec![0xab, 0xcd],
+            size: 4,
+            paths: vec![root.join("a"), root.join("b")],
+        };
+        let shown = vec![PathBuf::from("./a"), PathBuf::from("./b")];
+
+        let mut dump = Vec::new();
+        write_rmlint_header(&mut dump).unwrap();
+        for file in rmlint_files(&group, &shown, 1, 3) {
+            dump.extend_from_slice(b",\n");
+            serde_json::to_writer(&mut dump, &file).unwrap();
+        }
+        let summary = RunSummary {
+            groups: 1,
+            redundant_files: 1,
+            redundant_bytes: 4,
+            ..RunSummary::default()
+        };
+        write_rmlint_footer(&mut dump, &summary).unwrap();
+
+        let dump: Vec<serde_json::Value> = serde_json::from_slice(&dump).unwrap();
+        assert_eq!(4, dump.len());
+        assert_eq!("sha512", dump[0]["checksum_type"]);
+        let file: RmlintFile = serde_json::from_value(dump[2].clone()).unwrap();
+        assert_eq!("duplicate_file", file.kind);
+        assert_eq!("abcd", file.checksum);
+        assert_eq!("./b", file.path);
+        assert_eq!(2, file.depth);
+        assert!(file.is_original);
+        assert!(file.mtime > 0);
+        assert_eq!(3 << 32 | 1, file.id);
+        assert_eq!(4, dump[3]["total_lint_size"]);
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use crate::fdup::DuplicateGroup;
+use crate::report::hex;
+use crate::time::rfc3339;
+use std::fs::symlink_metadata;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Uniformly random sample of a stream of duplicate groups of unknown length, kept in memory
+/// proportional to the size of the sample rather than the number of groups.
+#[derive(Clone, Debug)]
+pub struct Sampler {
+    /// Seed the sample was drawn with, so that it can be drawn again
+    pub seed: u64,
+    /// Most groups to keep
+    size: usize,
+    /// Number of groups offered so far
+    seen: usize,
+    /// Groups kept so far, with their positions in the output
+    kept: Vec<(usize, DuplicateGroup)>,
+    rng: SplitMix64,
+}
+
+impl Sampler {
+    /// # Parameters
+    ///
+    /// 1. `size` -- most groups to keep
+    /// 1. `seed` -- seed of the random choices, or `None` for one derived from the clock
+    pub fn new(size: usize, seed: Option<u64>) -> Sampler {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_nanos() as u64)
+                .unwrap_or(0)
+        });
+        Sampler {
+            seed,
+            size,
+            seen: 0,
+            kept: Vec::with_capacity(size),
+            rng: SplitMix64(seed),
+        }
+    }
+
+    /// Consider `group`, at position `index` of the output, for the sample. It is only cloned if
+    /// it makes it in.
+    pub fn offer(&mut self, index: usize, group: &DuplicateGroup) {
+        self.seen += 1;
+        match self.kept.len() < self.size {
+            true => self.kept.push((index, group.clone())),
+            false => {
+                // reservoir sampling: replace a kept group with probability size / seen
+                let slot = (self.rng.next() % self.seen as u64) as usize;
+                if slot < self.size {
+                    self.kept[slot] = (index, group.clone());
+                }
+            }
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Number of groups offered so far, and the sampled groups with their positions in the
+    /// output, ordered by position.
+    pub fn finish(mut self) -> (usize, Vec<(usize, DuplicateGroup)>) {
+        self.kept.sort_by_key(|(index, _)| *index);
+        (self.seen, self.kept)
+    }
+}
+
+/// Small, fast, non-cryptographic generator; plenty for picking groups to look at.
+#[derive(Clone, Debug)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Print the groups drawn by `sampler` to stderr, with everything needed to judge by hand whether
+/// they really are duplicates: checksum, and the size, mtime, permissions and path of each file,
+/// as they are now.
+///
+/// # Parameters
+///
+/// 1. `sampler` -- sampler every group of the run was offered to
+/// 1. `local` -- render mtimes in the local time zone instead of UTC
+pub fn print_sample(sampler: Sampler, local: bool) -> io::Result<()> {
+    let seed = sampler.seed;
+    let (seen, sample) = sampler.finish();
+    let stderr = io::stderr();
+    let mut out = stderr.lock();
+
+    writeln!(
+        out,
+        "review sample: {} of {} groups (seed {})",
+        sample.len(),
+        seen,
+        seed
+    )?;
+    for (index, group) in sample.iter() {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "group {}: {} files of {} bytes",
+            index,
+            group.paths.len(),
+            group.size
+        )?;
+        writeln!(out, "  sha512 {}", hex(&group.hash))?;
+        for path in group.paths.iter() {
+            writeln!(out, "  {}  {:?}", describe(path, local), path)?;
+        }
+    }
+    Ok(())
+}
+
+/// # Returns
+///
+/// Size, mtime and permissions of the file at `path`, in fixed width columns.
+fn describe(path: &Path, local: bool) -> String {
+    match symlink_metadata(path) {
+        Ok(meta) => {
+            let mtime = meta
+                .modified()
+                .map(|time| rfc3339(time, local))
+                .unwrap_or_else(|_| String::from("?"));
+            format!("{:>12}  {:<20}  {}", meta.len(), mtime, permissions(&meta))
+        }
+        Err(err) => format!("unreadable: {}", err),
+    }
+}
+
+/// # Returns
+///
+/// Permission bits of `meta` in octal.
+#[cfg(unix)]
+fn permissions(meta: &std::fs::Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    format!("{:04o}", meta.permissions().mode() & 0o7777)
+}
+
+/// # Returns
+///
+/// Whether `meta` is read-only.
+#[cfg(not(unix))]
+fn permissions(meta: &std::fs::Metadata) -> String {
+    match meta.permissions().readonly() {
+        true => String::from("read-only"),
+        false => String::from("writable"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn sample(size: usize, groups: usize, seed: u64) -> (usize, Vec<usize>) {
+        let mut sampler = Sampler::new(size, Some(seed));
+        for index in 1..=groups {
+            let group = DuplicateGroup {
+                hash: vec![index as u8],
+                size: 1,
+                paths: vec![PathBuf::from("a"), PathBuf::from("b")],
+            };
+            sampler.offer(index, &group);
+        }
+        let (seen, kept) = sampler.finish();
+        (seen, kept.into_iter().map(|(index, _)| index).collect())
+    }
+
+    #[test]
+    fn samples_are_reproducible() {
+        assert_eq!((3, vec![1, 2, 3]), sample(5, 3, 7));
+
+        let (seen, indices) = sample(10, 1000, 7);
+        assert_eq!(1000, seen);
+        assert_eq!(10, indices.len());
+        assert!(indices.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(indices, sample(10, 1000, 7).1);
+        assert_ne!(indices, sample(10, 1000, 8).1);
+    }
+}
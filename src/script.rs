@@ -0,0 +1,164 @@
+use crate::action::choose_survivor;
+use crate::action::symlink_target;
+use crate::action::Action;
+use crate::action::Policy;
+use crate::fdup::DuplicateGroup;
+use crate::output::shell_quote;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Values accepted by `--emit-script`, in the same order as the variants of `ScriptKind`.
+pub const SCRIPT_VALUES: &[&str] = &["sh", "ps1"];
+
+/// Language of the script written by `--emit-script`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptKind {
+    /// Bash
+    Sh,
+    /// PowerShell
+    Ps1,
+}
+
+impl FromStr for ScriptKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sh" => Ok(ScriptKind::Sh),
+            "ps1" => Ok(ScriptKind::Ps1),
+            _ => Err(format!("unknown script kind {:?}", s)),
+        }
+    }
+}
+
+/// Print whatever has to precede the commands of the first group.
+pub fn print_script_header(kind: ScriptKind) -> io::Result<()> {
+    let mut out = io::stdout();
+    match kind {
+        ScriptKind::Sh => {
+            writeln!(out, "#!/usr/bin/env bash")?;
+            writeln!(out, "# generated by fdup; review before running")?;
+            writeln!(out, "set -eu")
+        }
+        ScriptKind::Ps1 => {
+            writeln!(out, "# generated by fdup; review before running")?;
+            writeln!(out, "$ErrorActionPreference = 'Stop'")
+        }
+    }
+}
+
+/// Print the commands that apply `action` to every file in `group` except the one chosen by
+/// `policy`.
+pub fn print_script_group(
+    kind: ScriptKind,
+    action: Action,
+    policy: &Policy,
+    index: usize,
+    group: &DuplicateGroup,
+) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let survivor = &group.paths[choose_survivor(policy.keep, &policy.prefer, &group.paths)];
+    let quote = |path: &Path| match kind {
+        ScriptKind::Sh => shell_quote(path),
+        ScriptKind::Ps1 => powershell_quote(path),
+    };
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "# group {}: {} files of {} bytes",
+        index,
+        group.paths.len(),
+        group.size
+    )?;
+    out.write_all(b"# keep ")?;
+    out.write_all(&quote(survivor))?;
+    writeln!(out)?;
+    for path in group.paths.iter().filter(|path| *path != survivor) {
+        if policy.is_protected(path) {
+            out.write_all(b"# protected ")?;
+            out.write_all(&quote(path))?;
+            writeln!(out)?;
+            continue;
+        }
+        let target = match action {
+            Action::Symlink(style) => match symlink_target(style, survivor, path) {
+                Ok(target) => quote(&target),
+                Err(err) => {
+                    writeln!(out, "# skipping a file: {}", err)?;
+                    continue;
+                }
+            },
+            _ => quote(survivor),
+        };
+        let path = quote(path);
+        let parts: Vec<&[u8]> = match (kind, action) {
+            (ScriptKind::Sh, Action::Delete) => vec![b"rm -- ", &path],
+            (ScriptKind::Sh, Action::Trash) => vec![b"gio trash -- ", &path],
+            (ScriptKind::Sh, Action::Hardlink) => vec![b"ln -f -- ", &target, b" ", &path],
+            (ScriptKind::Sh, Action::Symlink(_)) => vec![b"ln -sf -- ", &target, b" ", &path],
+            (ScriptKind::Sh, Action::Reflink) => {
+                vec![b"cp --reflink=always -- ", &target, b" ", &path]
+            }
+            (ScriptKind::Ps1, Action::Delete) => vec![b"Remove-Item -LiteralPath ", &path],
+            (ScriptKind::Ps1, Action::Trash) => vec![
+                b"Add-Type -AssemblyName Microsoft.VisualBasic; ",
+                b"[Microsoft.VisualBasic.FileIO.FileSystem]::DeleteFile(",
+                &path,
+                b", 'OnlyErrorDialogs', 'SendToRecycleBin')",
+            ],
+            (ScriptKind::Ps1, Action::Hardlink) => vec![
+                b"New-Item -ItemType HardLink -Force -Path ",
+                &path,
+                b" -Value ",
+                &target,
+            ],
+            (ScriptKind::Ps1, Action::Symlink(_)) => vec![
+                b"New-Item -ItemType SymbolicLink -Force -Path ",
+                &path,
+                b" -Value ",
+                &target,
+            ],
+            (ScriptKind::Ps1, Action::Reflink) => {
+                vec![b"# reflinks are not supported on Windows: ", &path]
+            }
+        };
+        for part in parts {
+            out.write_all(part)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// # Returns
+///
+/// `path` as a PowerShell single-quoted string, in which everything is literal except for single
+/// quotes (including the typographic ones PowerShell also accepts), which are doubled.
+pub fn powershell_quote(path: &Path) -> Vec<u8> {
+    let mut quoted = String::from("'");
+    for c in path.to_string_lossy().chars() {
+        if let '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' = c {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn powershell_quotes() {
+        let quote = |s: &str| String::from_utf8(powershell_quote(Path::new(s))).unwrap();
+        assert_eq!("'plain $x'", quote("plain $x"));
+        assert_eq!("'it''s'", quote("it's"));
+        assert_eq!("'it\u{2019}\u{2019}s'", quote("it\u{2019}s"));
+    }
+}
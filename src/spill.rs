@@ -0,0 +1,166 @@
+use crate::output::path_bytes;
+use crate::report::from_raw_bytes;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Files inserted per transaction; fewer commits are faster, but SQLite holds on to the pages of
+/// a transaction until it commits.
+const BATCH: usize = 10_000;
+
+/// Paths of files by size, kept in a temporary SQLite database rather than in memory, so that a
+/// tree with more files than fit in memory can still be scanned one size at a time.
+pub struct SpilledSizes {
+    conn: Connection,
+    file: PathBuf,
+    pending: usize,
+}
+
+impl SpilledSizes {
+    /// # Returns
+    ///
+    /// Empty index in a new file in the temporary directory, which is removed again once the index
+    /// is dropped.
+    pub fn create() -> rusqlite::Result<SpilledSizes> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.subsec_nanos())
+            .unwrap_or(0);
+        let file = env::temp_dir().join(format!("fdup-spill-{}-{}.sqlite", process::id(), nanos));
+        let spilled = SpilledSizes {
+            conn: Connection::open(&file)?,
+            file,
+            pending: 0,
+        };
+        // nothing to recover after a crash, so no journal and no syncing
+        spilled.conn.execute_batch(
+            "PRAGMA journal_mode = OFF;
+             PRAGMA synchronous = OFF;
+             CREATE TABLE files (size INTEGER NOT NULL, path BLOB NOT NULL);
+             BEGIN;",
+        )?;
+        Ok(spilled)
+    }
+
+    /// Add the file at `path`, which is `size` bytes long.
+    pub fn insert(&mut self, size: u64, path: &Path) -> rusqlite::Result<()> {
+        self.conn
+            .prepare_cached("INSERT INTO files (size, path) VALUES (?1, ?2)")?
+            .execute(params![size as i64, path_bytes(path)])?;
+        self.pending += 1;
+        if self.pending == BATCH {
+            self.conn.execute_batch("COMMIT; BEGIN;")?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    /// # Parameters
+    ///
+    /// 1. `threshold` -- only sizes shared by more than this many files are included
+    ///
+    /// # Returns
+    ///
+    /// Each size shared by enough files, in increasing order, with the paths of those files in
+    /// the order they were inserted. Only one size is read into memory at a time.
+    pub fn buckets(self, threshold: usize) -> rusqlite::Result<Buckets> {
+        self.conn.execute_batch(
+            "COMMIT;
+             CREATE INDEX by_size ON files (size);",
+        )?;
+        Ok(Buckets {
+            spilled: self,
+            threshold,
+            after: -1,
+        })
+    }
+}
+
+impl Drop for SpilledSizes {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.file);
+    }
+}
+
+/// Iterator returned by `SpilledSizes::buckets`.
+pub struct Buckets {
+    spilled: SpilledSizes,
+    threshold: usize,
+    /// largest size returned so far
+    after: i64,
+}
+
+impl Buckets {
+    fn next_bucket(&mut self) -> rusqlite::Result<Option<(u64, Vec<PathBuf>)>> {
+        let conn = &self.spilled.conn;
+        let size: i64 = match conn
+            .query_row(
+                "SELECT size FROM files WHERE size > ?1
+                 GROUP BY size HAVING COUNT(*) > ?2 ORDER BY size LIMIT 1",
+                params![self.after, self.threshold as i64],
+                |row| row.get(0),
+            )
+            .optional()?
+        {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        self.after = size;
+        let mut paths =
+            conn.prepare_cached("SELECT path FROM files WHERE size = ?1 ORDER BY rowid")?;
+        let paths = paths
+            .query_map(params![size], |row| row.get::<_, Vec<u8>>(0))?
+            .map(|raw| raw.map(|raw| from_raw_bytes(&raw)))
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Some((size as u64, paths)))
+    }
+}
+
+impl Iterator for Buckets {
+    type Item = (u64, Vec<PathBuf>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_bucket() {
+            Ok(bucket) => bucket,
+            Err(err) => {
+                eprintln!("ERROR reading spilled sizes: {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_come_out_by_size() {
+        let mut spilled = SpilledSizes::create().unwrap();
+        let file = spilled.file.clone();
+        for (size, path) in [
+            (3, "c1"),
+            (1, "a"),
+            (3, "c2"),
+            (2, "b1"),
+            (2, "b2"),
+            (3, "c3"),
+        ] {
+            spilled.insert(size, Path::new(path)).unwrap();
+        }
+        let buckets: Vec<_> = spilled.buckets(1).unwrap().collect();
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect();
+        assert_eq!(
+            vec![(2, paths(&["b1", "b2"])), (3, paths(&["c1", "c2", "c3"]))],
+            buckets
+        );
+        assert!(!file.exists());
+    }
+}
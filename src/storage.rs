@@ -0,0 +1,99 @@
+use std::fmt;
+use std::path::Path;
+
+/// Hashing threads to use by default when any root is on rotational storage, since more readers
+/// only make the heads seek back and forth between them.
+pub const ROTATIONAL_THREADS: usize = 1;
+
+/// Kind of storage a directory lives on, as far as reading it concurrently goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageKind {
+    /// Spinning disk, which is fastest read by a single reader
+    Rotational,
+    /// SSD or NVMe drive, which is fastest read by many readers at once
+    SolidState,
+    /// Network file system, virtual file system, or anything else that can't be told
+    Unknown,
+}
+
+impl fmt::Display for StorageKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageKind::Rotational => write!(f, "rotational"),
+            StorageKind::SolidState => write!(f, "solid-state"),
+            StorageKind::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// # Returns
+///
+/// Kind of storage the file system holding `path` is on, as the kernel reports it in sysfs for
+/// the block device, or for the disk a partition is on.
+#[cfg(target_os = "linux")]
+pub fn storage_kind(path: &Path) -> StorageKind {
+    use std::fs::read_to_string;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::PathBuf;
+
+    let dev = match path.metadata() {
+        Ok(meta) => meta.dev(),
+        Err(_) => return StorageKind::Unknown,
+    };
+    let (major, minor) = (libc::major(dev), libc::minor(dev));
+    if major == 0 {
+        return StorageKind::Unknown; // anonymous device, e.g. NFS, tmpfs or overlayfs
+    }
+    let device = match PathBuf::from(format!("/sys/dev/block/{}:{}", major, minor)).canonicalize() {
+        Ok(device) => device,
+        Err(_) => return StorageKind::Unknown,
+    };
+    // partitions have no queue of their own, the disk they are on does
+    let rotational = [Some(device.as_path()), device.parent()]
+        .iter()
+        .flatten()
+        .find_map(|dir| read_to_string(dir.join("queue/rotational")).ok());
+    match rotational.as_deref().map(str::trim) {
+        Some("1") => StorageKind::Rotational,
+        Some("0") => StorageKind::SolidState,
+        _ => StorageKind::Unknown,
+    }
+}
+
+/// # Returns
+///
+/// `Unknown`, since storage can only be told apart on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn storage_kind(_path: &Path) -> StorageKind {
+    StorageKind::Unknown
+}
+
+/// # Returns
+///
+/// Number of hashing threads suited to reading from storage of all of `kinds` at once, or `None`
+/// for one per CPU.
+pub fn default_threads(kinds: &[StorageKind]) -> Option<usize> {
+    match kinds.contains(&StorageKind::Rotational) {
+        true => Some(ROTATIONAL_THREADS),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotational_storage_gets_one_reader() {
+        let ssd = StorageKind::SolidState;
+        let hdd = StorageKind::Rotational;
+        assert_eq!(None, default_threads(&[ssd, StorageKind::Unknown]));
+        assert_eq!(Some(1), default_threads(&[ssd, hdd]));
+        assert_eq!(None, default_threads(&[]));
+        assert_eq!(
+            StorageKind::Unknown,
+            storage_kind(Path::new("/nonexistent/at/all"))
+        );
+        assert_eq!("solid-state", ssd.to_string());
+    }
+}
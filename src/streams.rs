@@ -0,0 +1,160 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Values accepted by `--streams`, in the same order as the variants of `Streams`.
+pub const STREAMS_VALUES: &[&str] = &["ignore", "count", "hash"];
+
+/// What to do with the alternate data streams of files on NTFS, which hold data that reading a
+/// file the usual way never sees.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Streams {
+    /// Compare the main contents only, without looking for streams
+    #[default]
+    Ignore,
+    /// Compare the main contents only, but count the streams, so that it's known what was left
+    /// out
+    Count,
+    /// Compare the streams along with the main contents, so that files only match if their
+    /// streams do too
+    Hash,
+}
+
+impl FromStr for Streams {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Streams::Ignore),
+            "count" => Ok(Streams::Count),
+            "hash" => Ok(Streams::Hash),
+            _ => Err(format!("unknown way to handle streams {:?}", s)),
+        }
+    }
+}
+
+/// A named data stream of a file, besides its main contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AlternateStream {
+    /// Name as NTFS reports it, e.g. `:Zone.Identifier:$DATA`
+    pub name: OsString,
+    /// Size in bytes
+    pub size: u64,
+}
+
+impl AlternateStream {
+    /// # Returns
+    ///
+    /// Path that opens this stream of the file at `file`.
+    pub fn path(&self, file: &Path) -> PathBuf {
+        let mut path = file.as_os_str().to_os_string();
+        path.push(&self.name);
+        PathBuf::from(path)
+    }
+}
+
+/// # Returns
+///
+/// The alternate data streams of the file at `path`, ordered by name.
+#[cfg(windows)]
+pub fn alternate_streams(path: &Path) -> io::Result<Vec<AlternateStream>> {
+    use std::ffi::c_void;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::ffi::OsStringExt;
+
+    const MAX_STREAM_NAME: usize = 260 + 36; // MAX_PATH + 36
+    const ERROR_HANDLE_EOF: i32 = 38;
+    const FIND_STREAM_INFO_STANDARD: i32 = 0;
+
+    /// WIN32_FIND_STREAM_DATA
+    #[repr(C)]
+    struct FindStreamData {
+        stream_size: i64,
+        stream_name: [u16; MAX_STREAM_NAME],
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn FindFirstStreamW(
+            file_name: *const u16,
+            info_level: i32,
+            data: *mut FindStreamData,
+            flags: u32,
+        ) -> *mut c_void;
+        fn FindNextStreamW(find: *mut c_void, data: *mut FindStreamData) -> i32;
+        fn FindClose(find: *mut c_void) -> i32;
+    }
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data = FindStreamData {
+        stream_size: 0,
+        stream_name: [0; MAX_STREAM_NAME],
+    };
+    // safe, since `wide` is NUL-terminated and `data` is laid out as Windows expects
+    let find = unsafe { FindFirstStreamW(wide.as_ptr(), FIND_STREAM_INFO_STANDARD, &mut data, 0) };
+    if find as isize == -1 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(ERROR_HANDLE_EOF) => Ok(Vec::new()), // no streams at all, e.g. a directory
+            _ => Err(err),
+        };
+    }
+    let mut streams = Vec::new();
+    let err = loop {
+        let len = data
+            .stream_name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(MAX_STREAM_NAME);
+        let name = OsString::from_wide(&data.stream_name[..len]);
+        if name != "::$DATA" {
+            // anything but the main contents
+            streams.push(AlternateStream {
+                name,
+                size: data.stream_size.max(0) as u64,
+            });
+        }
+        // safe, since `find` stays valid until it is closed below
+        if unsafe { FindNextStreamW(find, &mut data) } == 0 {
+            break io::Error::last_os_error();
+        }
+    };
+    // safe, since `find` isn't used again
+    unsafe { FindClose(find) };
+    match err.raw_os_error() {
+        Some(ERROR_HANDLE_EOF) => {
+            streams.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(streams)
+        }
+        _ => Err(err),
+    }
+}
+
+/// # Returns
+///
+/// No streams, since files only have alternate data streams on NTFS under Windows.
+#[cfg(not(windows))]
+pub fn alternate_streams(_path: &Path) -> io::Result<Vec<AlternateStream>> {
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_are_opened_by_suffix() {
+        let stream = AlternateStream {
+            name: OsString::from(":Zone.Identifier:$DATA"),
+            size: 26,
+        };
+        assert_eq!(
+            PathBuf::from("C:\\photos\\a.jpg:Zone.Identifier:$DATA"),
+            stream.path(Path::new("C:\\photos\\a.jpg"))
+        );
+        assert_eq!(Ok(Streams::Hash), "hash".parse());
+        assert!(alternate_streams(Path::new(file!())).is_ok());
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Number of bytes from the start of a file looked at by `looks_like_text`, as many as git does.
+pub const SNIFF_LEN: u64 = 8000;
+
+/// Values accepted by `--text-normalize`, in the same order as the variants of `TextNormalize`.
+pub const TEXT_NORMALIZE_VALUES: &[&str] = &["none", "eol", "whitespace"];
+
+/// What differences between text files don't keep them from being reported as copies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextNormalize {
+    /// None, text files are compared byte for byte like any other
+    #[default]
+    None,
+    /// Line endings, so that CRLF and LF checkouts of the same file match
+    Eol,
+    /// Line endings, whitespace at the end of lines and of the file, and how many blank lines
+    /// there are in a row, so that trivially reformatted copies match
+    Whitespace,
+}
+
+impl FromStr for TextNormalize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(TextNormalize::None),
+            "eol" => Ok(TextNormalize::Eol),
+            "whitespace" => Ok(TextNormalize::Whitespace),
+            _ => Err(format!("unknown text normalization {:?}", s)),
+        }
+    }
+}
+
+/// # Returns
+///
+/// Whether the file at `path` looks like text, i.e. there is no NUL byte among its first
+/// `SNIFF_LEN` bytes, which is false if it can't be read.
+pub fn looks_like_text(path: &Path) -> bool {
+    let mut start = Vec::with_capacity(SNIFF_LEN as usize);
+    match File::open(path).and_then(|file| file.take(SNIFF_LEN).read_to_end(&mut start)) {
+        Ok(_) => !start.contains(&0),
+        Err(_) => false,
+    }
+}
+
+/// Rewrites text fed to it a chunk at a time, one line at a time, so that texts that only differ
+/// in what `TextNormalize` leaves out come out the same.
+#[derive(Debug)]
+pub struct Normalizer {
+    normalize: TextNormalize,
+    /// Start of the line that hasn't ended yet
+    line: Vec<u8>,
+    /// Whether blank lines were left out since the last line handed out
+    blank: bool,
+}
+
+impl Normalizer {
+    pub fn new(normalize: TextNormalize) -> Normalizer {
+        Normalizer {
+            normalize,
+            line: Vec::new(),
+            blank: false,
+        }
+    }
+
+    /// Hand each line of `bytes` that ends in them to `out`, normalized, and keep the rest for
+    /// the next call.
+    pub fn feed<F>(&mut self, bytes: &[u8], mut out: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        for &byte in bytes {
+            self.line.push(byte);
+            if byte == b'\n' {
+                self.end_line(&mut out);
+            }
+        }
+    }
+
+    /// Hand whatever follows the last line break to `out`, normalized.
+    pub fn finish<F>(mut self, mut out: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        if !self.line.is_empty() {
+            self.end_line(&mut out);
+        }
+    }
+
+    fn end_line<F>(&mut self, out: &mut F)
+    where
+        F: FnMut(&[u8]),
+    {
+        match self.normalize {
+            TextNormalize::None => (),
+            TextNormalize::Eol => {
+                if self.line.ends_with(b"\r\n") {
+                    let len = self.line.len();
+                    self.line.remove(len - 2);
+                }
+            }
+            TextNormalize::Whitespace => {
+                let len = self
+                    .line
+                    .iter()
+                    .rposition(|byte| !byte.is_ascii_whitespace())
+                    .map_or(0, |last| last + 1);
+                if len == 0 {
+                    self.blank = true;
+                    self.line.clear();
+                    return; // not handed out unless more text follows
+                }
+                if self.blank {
+                    out(b"\n"); // one blank line stands for the whole run
+                    self.blank = false;
+                }
+                self.line.truncate(len);
+                self.line.push(b'\n'); // even at the end of the file
+            }
+        }
+        out(&self.line);
+        self.line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_endings_are_normalized_across_chunks() {
+        let normalized = |normalize, chunks: &[&[u8]]| {
+            let mut text = Vec::new();
+            let mut normalizer = Normalizer::new(normalize);
+            for chunk in chunks {
+                normalizer.feed(chunk, |line| text.extend_from_slice(line));
+            }
+            normalizer.finish(|line| text.extend_from_slice(line));
+            text
+        };
+        let eol = TextNormalize::Eol;
+        assert_eq!(
+            b"a\nb\n\nc".to_vec(),
+            normalized(eol, &[b"a\r\nb\r", b"\n\r\nc"])
+        );
+        assert_eq!(b"a\rb\n".to_vec(), normalized(eol, &[b"a\rb\n"]));
+        let whitespace = TextNormalize::Whitespace;
+        assert_eq!(
+            b"a\n\n b\n".to_vec(),
+            normalized(whitespace, &[b"a \t\r\n\n  \r", b"\n\n b  "])
+        );
+        assert_eq!(b"\na\n".to_vec(), normalized(whitespace, &[b"\n\na\n\n"]));
+        let none = TextNormalize::None;
+        assert_eq!(b"a\r\nb".to_vec(), normalized(none, &[b"a\r", b"\nb"]));
+
+        assert!(looks_like_text(Path::new(file!())));
+        assert!(!looks_like_text(Path::new("/nonexistent/at/all")));
+    }
+}
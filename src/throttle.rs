@@ -0,0 +1,96 @@
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Limits on how hard fdup may read from disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Throttle {
+    /// Most bytes to read per second, on average
+    pub bytes_per_sec: u64,
+    /// After reading for this long, stop for `rest`
+    pub work: Duration,
+    /// How long to stop reading every time `work` has passed
+    pub rest: Duration,
+}
+
+impl Throttle {
+    /// # Returns
+    ///
+    /// Throttle that keeps reads to `megabytes_per_sec` every second, without ever resting.
+    pub fn steady(megabytes_per_sec: f64) -> Throttle {
+        Throttle {
+            bytes_per_sec: ((megabytes_per_sec * 1e6) as u64).max(1),
+            work: Duration::from_secs(1),
+            rest: Duration::from_secs(0),
+        }
+    }
+}
+
+/// Throttle in effect for the whole process, if any, and the progress made under it.
+static PACER: Mutex<Option<Pacer>> = Mutex::new(None);
+
+#[derive(Debug)]
+struct Pacer {
+    throttle: Throttle,
+    /// start of the current stretch of work
+    since: Instant,
+    /// bytes read since `since`
+    bytes: u64,
+}
+
+/// Make every read fdup does from now on, from any thread, obey `throttle`.
+pub fn set_throttle(throttle: Throttle) {
+    let pacer = Pacer {
+        throttle,
+        since: Instant::now(),
+        bytes: 0,
+    };
+    *PACER.lock().unwrap_or_else(|err| err.into_inner()) = Some(pacer);
+}
+
+/// Account for `bytes` that were just read, sleeping for as long as it takes to keep within the
+/// throttle set by `set_throttle`. Returns immediately if there is none.
+pub fn pace(bytes: usize) {
+    let delay = {
+        let mut guard = PACER.lock().unwrap_or_else(|err| err.into_inner());
+        let pacer = match guard.as_mut() {
+            Some(pacer) => pacer,
+            None => return,
+        };
+        pacer.bytes += bytes as u64;
+        let elapsed = pacer.since.elapsed();
+        match elapsed >= pacer.throttle.work {
+            // time for a break, after which a new stretch of work begins
+            true => {
+                pacer.since = Instant::now() + pacer.throttle.rest;
+                pacer.bytes = 0;
+                pacer.throttle.rest
+            }
+            false => {
+                let due = match pacer.throttle.bytes_per_sec {
+                    0 => Duration::from_secs(0),
+                    rate => Duration::from_secs_f64(pacer.bytes as f64 / rate as f64),
+                };
+                due.checked_sub(elapsed).unwrap_or_default()
+            }
+        }
+    };
+    // sleep without holding the lock, so that other threads can account for their reads
+    if delay > Duration::from_secs(0) {
+        sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steady_throttles_never_rest() {
+        let throttle = Throttle::steady(2.5);
+        assert_eq!(2_500_000, throttle.bytes_per_sec);
+        assert_eq!(Duration::from_secs(0), throttle.rest);
+        assert_eq!(1, Throttle::steady(1e-9).bytes_per_sec);
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::DateTime;
+use chrono::Local;
+use chrono::SecondsFormat;
+use chrono::Utc;
+use std::fs::symlink_metadata;
+use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// # Returns
+///
+/// `time` in RFC 3339 format with second precision, in UTC, or in the local time zone (with its
+/// offset) if `local`. Every timestamp fdup prints goes through here, so they all parse the same.
+pub fn rfc3339(time: SystemTime, local: bool) -> String {
+    match local {
+        true => DateTime::<Local>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true),
+        false => DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true),
+    }
+}
+
+/// # Returns
+///
+/// `timestamp`, seconds since the unix epoch, formatted by `rfc3339`.
+pub fn rfc3339_from_secs(timestamp: i64, local: bool) -> String {
+    rfc3339(
+        UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64),
+        local,
+    )
+}
+
+/// # Returns
+///
+/// Modification time of the file at `path`, without following symlinks, formatted by `rfc3339`,
+/// or `None` if it can't be read.
+pub fn mtime(path: &Path, local: bool) -> Option<String> {
+    symlink_metadata(path)
+        .and_then(|meta| meta.modified())
+        .map(|time| rfc3339(time, local))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utc_timestamps() {
+        assert_eq!("1970-01-01T00:00:00Z", rfc3339_from_secs(0, false));
+        assert_eq!(
+            "2001-09-09T01:46:40Z",
+            rfc3339_from_secs(1_000_000_000, false)
+        );
+        assert!(rfc3339_from_secs(1_000_000_000, true).starts_with("2001-09-0"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::error::FdupError;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Longest fdup waits for any one file to be read, if there is a limit.
+static FILE_TIMEOUT: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Give up on reading any file, from any thread, that takes longer than `timeout` from now on.
+pub fn set_file_timeout(timeout: Duration) {
+    *FILE_TIMEOUT.lock().unwrap_or_else(|err| err.into_inner()) = Some(timeout);
+}
+
+/// # Returns
+///
+/// `read(path)`, or an error if a timeout was set by `set_file_timeout` and `read` took longer.
+/// Returns as soon as it does, leaving `read` to block on a thread of its own, so that a file on
+/// a hung network mount or a failing disk stalls neither the caller nor the rest of the scan.
+pub fn timed<K, F>(path: &Path, read: F) -> Result<K, FdupError>
+where
+    F: FnOnce(&Path) -> Result<K, FdupError> + Send + 'static,
+    K: Send + 'static,
+{
+    let timeout = *FILE_TIMEOUT.lock().unwrap_or_else(|err| err.into_inner());
+    timed_within(timeout, path, read)
+}
+
+/// Same as `timed`, but with `timeout` instead of the one set by `set_file_timeout`.
+fn timed_within<K, F>(timeout: Option<Duration>, path: &Path, read: F) -> Result<K, FdupError>
+where
+    F: FnOnce(&Path) -> Result<K, FdupError> + Send + 'static,
+    K: Send + 'static,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return read(path),
+    };
+    let (sender, receiver) = mpsc::channel();
+    let owned = path.to_path_buf();
+    let spawned = thread::Builder::new().spawn(move || {
+        // nobody may be listening anymore, which is fine
+        let _ = sender.send(read(&owned));
+    });
+    if let Err(err) = spawned {
+        return Err(FdupError::io(path, err));
+    }
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            let message = format!("gave up after {:?}", timeout);
+            Err(FdupError::io(
+                path,
+                io::Error::new(io::ErrorKind::TimedOut, message),
+            ))
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            let message = "reading thread panicked";
+            Err(FdupError::io(path, io::Error::other(message)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn slow_reads_are_abandoned() {
+        let path = Path::new("slow");
+        let slow = |_: &Path| {
+            sleep(Duration::from_secs(5));
+            Ok(1)
+        };
+        let err = timed_within(Some(Duration::from_millis(10)), path, slow).unwrap_err();
+        assert_eq!(Path::new("slow"), err.path());
+        assert!(err.to_string().contains("gave up after 10ms"));
+
+        let fast = |_: &Path| Ok(2);
+        assert_eq!(
+            2,
+            timed_within(Some(Duration::from_secs(5)), path, fast).unwrap()
+        );
+        assert_eq!(3, timed_within(None, path, |_: &Path| Ok(3)).unwrap());
+    }
+}
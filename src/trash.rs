@@ -0,0 +1,185 @@
+#[cfg(unix)]
+use std::fs::rename;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+
+/// Move the file at `path` into the trash of the current user, from where it can be restored with
+/// the usual desktop tools. If there is no trash to move it to, the file is left where it is;
+/// it is never unlinked as a fallback.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn trash(path: &Path) -> Result<(), String> {
+    use std::fs::create_dir_all;
+    use std::fs::metadata;
+    use std::fs::remove_file;
+    use std::fs::symlink_metadata;
+    use std::io::Write;
+    use std::os::unix::fs::MetadataExt;
+
+    let path = absolute(path)?;
+    let device = symlink_metadata(&path)
+        .map_err(|err| format!("{}", err))?
+        .dev();
+
+    // the home trash only takes files from its own filesystem, see the freedesktop.org trash spec
+    let home_trash = home_trash_dir();
+    let trash_dir = match home_trash.as_ref().map(|dir| (dir, create_dir_all(dir))) {
+        Some((dir, Ok(()))) if metadata(dir).map(|meta| meta.dev()).ok() == Some(device) => {
+            dir.clone()
+        }
+        _ => {
+            let uid = unsafe { libc::getuid() }; // safe, getuid can't fail
+            top_dir(&path, device).join(format!(".Trash-{}", uid))
+        }
+    };
+    let files = trash_dir.join("files");
+    let info = trash_dir.join("info");
+    for dir in [&files, &info].iter() {
+        create_dir_all(dir).map_err(|err| format!("no trash available in {:?}: {}", dir, err))?;
+    }
+
+    // reserving the name through the info file keeps concurrent trashers from colliding
+    let (name, mut info_file) = reserve_name(&path, &info)?;
+    let info_path = info.join(format!("{}.trashinfo", name));
+    let written = writeln!(
+        info_file,
+        "[Trash Info]\nPath={}\nDeletionDate={}",
+        percent_encode(&path),
+        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+    )
+    .and_then(|()| rename(&path, files.join(&name)));
+    written.map_err(|err| {
+        let _ = remove_file(&info_path); // nothing else to do if this fails too
+        format!("moving to trash {:?}: {}", trash_dir, err)
+    })
+}
+
+/// Move the file at `path` into the trash of the current user. If there is no trash to move it
+/// to, the file is left where it is.
+#[cfg(target_os = "macos")]
+pub fn trash(path: &Path) -> Result<(), String> {
+    let path = absolute(path)?;
+    let trash_dir = std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(".Trash"))
+        .ok_or_else(|| String::from("no trash available: HOME is not set"))?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let target = (1..)
+        .map(|n| match n {
+            1 => trash_dir.join(&*name),
+            _ => trash_dir.join(format!("{} {}", name, n)),
+        })
+        .find(|target| target.symlink_metadata().is_err())
+        .unwrap_or_default();
+    rename(&path, &target).map_err(|err| format!("moving to trash {:?}: {}", trash_dir, err))
+}
+
+/// There is no trash fdup knows how to use on other platforms, so files are always left in place.
+#[cfg(not(unix))]
+pub fn trash(_path: &Path) -> Result<(), String> {
+    Err(String::from("no trash available on this OS"))
+}
+
+/// # Returns
+///
+/// `path` made absolute without resolving its last component, which may be a symlink that is to
+/// be trashed itself.
+#[cfg(unix)]
+fn absolute(path: &Path) -> Result<PathBuf, String> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("{:?} has no file name", path))?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .canonicalize()
+        .map_err(|err| format!("{}", err))?;
+    Ok(parent.join(name))
+}
+
+/// # Returns
+///
+/// `$XDG_DATA_HOME/Trash`, falling back to `~/.local/share/Trash`.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn home_trash_dir() -> Option<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    data_home.map(|dir| dir.join("Trash"))
+}
+
+/// # Returns
+///
+/// Topmost ancestor of the absolute `path` that is still on `device`, i.e. its mount point.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn top_dir(path: &Path, device: u64) -> PathBuf {
+    use std::os::unix::fs::MetadataExt;
+
+    path.ancestors()
+        .skip(1)
+        .take_while(|dir| dir.metadata().map(|meta| meta.dev()).ok() == Some(device))
+        .last()
+        .unwrap_or_else(|| Path::new("/"))
+        .to_path_buf()
+}
+
+/// Create a fresh `.trashinfo` file in `info` for the file at `path`.
+///
+/// # Returns
+///
+/// Name under which the file is to be stored in the trash, and the newly created info file.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn reserve_name(path: &Path, info: &Path) -> Result<(String, std::fs::File), String> {
+    use std::fs::OpenOptions;
+    use std::io;
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    for n in 1.. {
+        let candidate = match n {
+            1 => name.to_string(),
+            _ => format!("{}.{}", name, n),
+        };
+        let created = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(info.join(format!("{}.trashinfo", candidate)));
+        match created {
+            Ok(file) => return Ok((candidate, file)),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(format!("no trash available in {:?}: {}", info, err)),
+        }
+    }
+    unreachable!()
+}
+
+/// # Returns
+///
+/// `path` with every byte other than unreserved URI characters and `/` percent-encoded, as
+/// `.trashinfo` files expect.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn percent_encode(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut encoded = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(all(test, unix, not(target_os = "macos")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trash_paths_are_percent_encoded() {
+        assert_eq!("/a/b_c.txt", percent_encode(Path::new("/a/b_c.txt")));
+        assert_eq!("/x%20y/%25%0A", percent_encode(Path::new("/x y/%\n")));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::action::apply_except;
+use crate::action::Action;
+use crate::action::Policy;
+use crate::fdup::sort_groups;
+use crate::fdup::DuplicateGroup;
+use crate::fdup::SortBy;
+use crate::time::rfc3339;
+use ratatui::crossterm::event;
+use ratatui::crossterm::event::Event;
+use ratatui::crossterm::event::KeyCode;
+use ratatui::crossterm::event::KeyEventKind;
+use ratatui::layout::Constraint;
+use ratatui::layout::Direction;
+use ratatui::layout::Layout;
+use ratatui::style::Modifier;
+use ratatui::style::Style;
+use ratatui::symbols::border;
+use ratatui::widgets::Block;
+use ratatui::widgets::Borders;
+use ratatui::widgets::List;
+use ratatui::widgets::ListItem;
+use ratatui::widgets::ListState;
+use ratatui::widgets::Paragraph;
+use ratatui::Frame;
+use std::fs::symlink_metadata;
+use std::io;
+
+const HELP: &str = "j/k: file  J/K: group  d: delete  l: hardlink  u: unmark  a: apply  q: quit";
+
+/// Borders drawn with `--plain`, which must not contain any box drawing characters.
+const ASCII_BORDER: border::Set = border::Set {
+    top_left: "+",
+    top_right: "+",
+    bottom_left: "+",
+    bottom_right: "+",
+    vertical_left: "|",
+    vertical_right: "|",
+    horizontal_top: "-",
+    horizontal_bottom: "-",
+};
+
+/// What the user wants done to a file once they apply their marks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mark {
+    Keep,
+    Delete,
+    Hardlink,
+}
+
+struct State {
+    groups: Vec<DuplicateGroup>,
+    marks: Vec<Vec<Mark>>,
+    group: usize,
+    file: usize,
+    plain: bool,
+    local: bool,
+}
+
+/// Let the user browse `groups` full-screen, mark files for deletion or hard linking, and apply
+/// the marks on request.
+///
+/// # Parameters
+///
+/// 1. `groups` -- duplicate groups to review
+/// 1. `policy` -- rules for applying the marks
+/// 1. `delete` -- action applied to files marked for deletion
+/// 1. `plain` -- draw with ASCII only, marking the selection with a symbol instead of video
+///    attributes
+/// 1. `local` -- show mtimes in the local time zone instead of UTC
+pub fn run(
+    mut groups: Vec<DuplicateGroup>,
+    policy: &Policy,
+    delete: Action,
+    plain: bool,
+    local: bool,
+) -> io::Result<()> {
+    // biggest wins first
+    sort_groups(&mut groups, SortBy::Size);
+    let marks = groups
+        .iter()
+        .map(|group| vec![Mark::Keep; group.paths.len()])
+        .collect();
+    let mut state = State {
+        groups,
+        marks,
+        group: 0,
+        file: 0,
+        plain,
+        local,
+    };
+
+    let mut terminal = ratatui::init();
+    let apply = loop {
+        if let Err(err) = terminal.draw(|frame| draw(frame, &state)) {
+            ratatui::restore();
+            return Err(err);
+        }
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+            Ok(_) => continue,
+            Err(err) => {
+                ratatui::restore();
+                return Err(err);
+            }
+        };
+        let group_len = state.groups.get(state.group).map_or(0, |g| g.paths.len());
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break false,
+            KeyCode::Char('a') => break true,
+            KeyCode::Char('j') | KeyCode::Down if state.file + 1 < group_len => state.file += 1,
+            KeyCode::Char('k') | KeyCode::Up => state.file = state.file.saturating_sub(1),
+            KeyCode::Char('J') | KeyCode::PageDown if state.group + 1 < state.groups.len() => {
+                state.group += 1;
+                state.file = 0;
+            }
+            KeyCode::Char('K') | KeyCode::PageUp => {
+                state.group = state.group.saturating_sub(1);
+                state.file = 0;
+            }
+            KeyCode::Char('d') => set_mark(&mut state, Mark::Delete),
+            KeyCode::Char('l') => set_mark(&mut state, Mark::Hardlink),
+            KeyCode::Char('u') => set_mark(&mut state, Mark::Keep),
+            _ => (),
+        }
+    };
+    ratatui::restore();
+
+    if apply {
+        let mut reclaimed = 0;
+        for (group, marks) in state.groups.iter().zip(state.marks.iter()) {
+            for &action in [Action::Hardlink, delete].iter() {
+                if let Some(kept) = kept_indices(marks, action) {
+                    reclaimed += apply_except(action, policy, &group.paths, &kept);
+                }
+            }
+        }
+        match policy.dry_run {
+            true => println!("would reclaim {} bytes", reclaimed),
+            false => println!("reclaimed {} bytes", reclaimed),
+        };
+    }
+    Ok(())
+}
+
+fn set_mark(state: &mut State, mark: Mark) {
+    let file = state.file;
+    if let Some(m) = state
+        .marks
+        .get_mut(state.group)
+        .and_then(|marks| marks.get_mut(file))
+    {
+        *m = mark;
+    }
+}
+
+/// # Returns
+///
+/// Indices of the files that `action` must leave alone given `marks`, starting with the survivor
+/// (the first file marked to keep), or `None` if no file is marked for `action`, or no file is
+/// marked to keep.
+fn kept_indices(marks: &[Mark], action: Action) -> Option<Vec<usize>> {
+    let wanted = match action {
+        Action::Hardlink => Mark::Hardlink,
+        _ => Mark::Delete,
+    };
+    let survivor = marks.iter().position(|&mark| mark == Mark::Keep)?;
+    if !marks.contains(&wanted) {
+        return None;
+    }
+    let others = (0..marks.len()).filter(|&i| i != survivor && marks[i] != wanted);
+    Some(std::iter::once(survivor).chain(others).collect())
+}
+
+fn draw(frame: &mut Frame, state: &State) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(3),
+            Constraint::Length(6),
+            Constraint::Length(1),
+        ])
+        .split(frame.area());
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(30), Constraint::Percentage(70)])
+        .split(rows[0]);
+    let (highlight, symbol) = match state.plain {
+        true => (Style::default(), "> "),
+        false => (Style::default().add_modifier(Modifier::REVERSED), ""),
+    };
+
+    let groups: Vec<ListItem> = state
+        .groups
+        .iter()
+        .map(|group| ListItem::new(format!("{} x {} bytes", group.paths.len(), group.size)))
+        .collect();
+    let mut group_state = ListState::default();
+    group_state.select(Some(state.group));
+    frame.render_stateful_widget(
+        List::new(groups)
+            .block(block("Groups", state.plain))
+            .highlight_style(highlight)
+            .highlight_symbol(symbol),
+        columns[0],
+        &mut group_state,
+    );
+
+    let (files, detail) = match state.groups.get(state.group) {
+        Some(group) => {
+            let files: Vec<ListItem> = group
+                .paths
+                .iter()
+                .zip(state.marks[state.group].iter())
+                .map(|(path, mark)| {
+                    let tag = match mark {
+                        Mark::Keep => "    ",
+                        Mark::Delete => "[D] ",
+                        Mark::Hardlink => "[L] ",
+                    };
+                    ListItem::new(format!("{}{}", tag, path.display()))
+                })
+                .collect();
+            let detail = group
+                .paths
+                .get(state.file)
+                .map(|path| describe(path, state.local))
+                .unwrap_or_default();
+            (files, detail)
+        }
+        None => (vec![], String::from("no duplicates found")),
+    };
+    let mut file_state = ListState::default();
+    file_state.select(Some(state.file));
+    frame.render_stateful_widget(
+        List::new(files)
+            .block(block("Files", state.plain))
+            .highlight_style(highlight)
+            .highlight_symbol(symbol),
+        columns[1],
+        &mut file_state,
+    );
+    frame.render_widget(
+        Paragraph::new(detail).block(block("Details", state.plain)),
+        rows[1],
+    );
+    frame.render_widget(Paragraph::new(HELP), rows[2]);
+}
+
+/// # Returns
+///
+/// Bordered block with `title`, drawn with ASCII only if `plain`.
+fn block(title: &'static str, plain: bool) -> Block<'static> {
+    let block = Block::default().borders(Borders::ALL).title(title);
+    match plain {
+        true => block.border_set(ASCII_BORDER),
+        false => block,
+    }
+}
+
+/// # Returns
+///
+/// Human readable metadata of the file at `path`, with its mtime in the local time zone if `local`.
+fn describe(path: &std::path::Path, local: bool) -> String {
+    match symlink_metadata(path) {
+        Ok(meta) => {
+            let mtime = meta
+                .modified()
+                .map(|time| rfc3339(time, local))
+                .unwrap_or_else(|_| String::from("?"));
+            let readonly = match meta.permissions().readonly() {
+                true => "read-only",
+                false => "writable",
+            };
+            format!(
+                "path:     {}\nsize:     {} bytes\nmodified: {}\naccess:   {}",
+                path.display(),
+                meta.len(),
+                mtime,
+                readonly
+            )
+        }
+        Err(err) => format!("path:     {}\nerror:    {}", path.display(), err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survivors_of_marks() {
+        use Mark::*;
+        let marks = [Delete, Keep, Hardlink, Keep];
+        assert_eq!(Some(vec![1, 2, 3]), kept_indices(&marks, Action::Delete));
+        assert_eq!(Some(vec![1, 0, 3]), kept_indices(&marks, Action::Hardlink));
+        assert_eq!(None, kept_indices(&[Delete, Delete], Action::Delete));
+        assert_eq!(None, kept_indices(&[Keep, Keep], Action::Delete));
+    }
+}
@@ -0,0 +1,498 @@
+use crate::error::ErrorLog;
+use crate::error::FdupError;
+use crate::filter::Filter;
+use ignore::gitignore::Gitignore;
+use ignore::gitignore::GitignoreBuilder;
+use ignore::Match;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use walkdir::DirEntry;
+use walkdir::WalkDir;
+
+/// Values accepted by `--traversal`, in the same order as the variants of `Traversal`.
+pub const TRAVERSAL_VALUES: &[&str] = &["dfs", "bfs"];
+
+/// Order in which the directory tree under a root is visited.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Traversal {
+    /// Depth-first, finishing each directory before moving on to its next sibling
+    Dfs,
+    /// Breadth-first, finishing each level of the tree before going deeper
+    Bfs,
+}
+
+impl FromStr for Traversal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dfs" => Ok(Traversal::Dfs),
+            "bfs" => Ok(Traversal::Bfs),
+            _ => Err(format!("unknown traversal {:?}", s)),
+        }
+    }
+}
+
+/// Visit everything under `root`, including `root` itself, in the order given by `traversal`.
+/// Entries that can't be read are skipped, and symlinks are not followed, whatever the order.
+///
+/// # Returns
+///
+/// Entries in the order they were visited.
+pub fn walk(root: &Path, traversal: Traversal) -> Box<dyn Iterator<Item = DirEntry>> {
+    walk_logged(root, traversal, false, None)
+}
+
+/// Visit everything under `root` like `walk`, but if there is a log of `errors`, record the
+/// entries that can't be read there, as having happened during `list`, before skipping them.
+///
+/// # Parameters
+///
+/// 1. `root` -- directory to start from
+/// 1. `traversal` -- order in which to visit the tree
+/// 1. `fdupignore` -- leave out, without descending into them, the entries that a `.fdupignore`
+///    file in the directory they are in, or in any directory above it up to `root`, ignores
+/// 1. `errors` -- if given, entries that can't be read are recorded in it
+///
+/// # Returns
+///
+/// Entries in the order they were visited.
+pub fn walk_logged<'a>(
+    root: &Path,
+    traversal: Traversal,
+    fdupignore: bool,
+    errors: Option<&'a ErrorLog>,
+) -> Box<dyn Iterator<Item = DirEntry> + 'a> {
+    let listed = move |result| listed(errors, result);
+    let mut ignore_files = IgnoreFiles::new(root, fdupignore);
+    match traversal {
+        Traversal::Dfs => Box::new(
+            WalkDir::new(root)
+                .into_iter()
+                .filter_entry(move |entry| !ignore_files.ignores(entry))
+                .filter_map(listed),
+        ),
+        Traversal::Bfs => {
+            let mut pending: VecDeque<DirEntry> = WalkDir::new(root)
+                .max_depth(0)
+                .into_iter()
+                .filter_map(listed)
+                .collect();
+            let mut children: Option<Box<dyn Iterator<Item = DirEntry>>> = None;
+
+            Box::new(std::iter::from_fn(move || loop {
+                // next child of the directory being listed, if any
+                if let Some(entry) = children.as_mut().and_then(Iterator::next) {
+                    if ignore_files.ignores(&entry) {
+                        continue;
+                    }
+                    if entry.file_type().is_dir() {
+                        pending.push_back(entry.clone());
+                    }
+                    return Some(entry);
+                }
+                let entry = pending.pop_front()?;
+                children = Some(Box::new(
+                    WalkDir::new(entry.path())
+                        .min_depth(1)
+                        .max_depth(1)
+                        .into_iter()
+                        .filter_map(listed),
+                ));
+                if entry.depth() == 0 {
+                    return Some(entry);
+                }
+            }))
+        }
+    }
+}
+
+/// Name of the files that list, in gitignore syntax, what beneath the directory they are in to
+/// leave out.
+pub const IGNORE_FILE: &str = ".fdupignore";
+
+/// Rules of the `.fdupignore` files under one root, read as the directories they are in are
+/// visited.
+struct IgnoreFiles {
+    root: PathBuf,
+    enabled: bool,
+    /// Rules of each directory visited so far, if it has any
+    rules: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl IgnoreFiles {
+    /// # Returns
+    ///
+    /// Rules under `root`, which ignore nothing unless `enabled`.
+    fn new(root: &Path, enabled: bool) -> IgnoreFiles {
+        IgnoreFiles {
+            root: root.to_path_buf(),
+            enabled,
+            rules: HashMap::new(),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Whether `entry` is ignored, going by the rules of the directory it is in, then by those of
+    /// the directories above it, up to the root, like git does: the nearest rule that matches
+    /// either ignores it or, negated with `!`, keeps it.
+    fn ignores(&mut self, entry: &DirEntry) -> bool {
+        let path = entry.path();
+        let depth = match (self.enabled, path.strip_prefix(&self.root)) {
+            (false, _) => return false,
+            (true, Ok(relative)) => relative.components().count(),
+            (true, Err(_)) => 1, // only the rules of its own directory apply
+        };
+        let is_dir = entry.file_type().is_dir();
+        for dir in path.ancestors().skip(1).take(depth) {
+            let rules = self
+                .rules
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| read_ignore_file(dir));
+            match rules.as_ref().map(|rules| rules.matched(path, is_dir)) {
+                Some(Match::Ignore(_)) => return true,
+                Some(Match::Whitelist(_)) => return false,
+                Some(Match::None) | None => (),
+            }
+        }
+        false
+    }
+}
+
+/// # Returns
+///
+/// Rules of the `.fdupignore` file in `dir`, if there is one. Lines that can't be parsed are
+/// reported and left out.
+fn read_ignore_file(dir: &Path) -> Option<Gitignore> {
+    let file = dir.join(IGNORE_FILE);
+    if !file.is_file() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(err) = builder.add(&file) {
+        eprintln!("ERROR reading {:?}: {}", file, err);
+    }
+    match builder.build() {
+        Ok(rules) => Some(rules),
+        Err(err) => {
+            eprintln!("ERROR reading {:?}: {}", file, err);
+            None
+        }
+    }
+}
+
+/// # Returns
+///
+/// The entry of `result`, unless it is an error, in which case the error is recorded in `errors`,
+/// if given, and the entry skipped.
+fn listed(errors: Option<&ErrorLog>, result: walkdir::Result<DirEntry>) -> Option<DirEntry> {
+    match (result, errors) {
+        (Ok(entry), _) => Some(entry),
+        (Err(err), Some(errors)) => {
+            let path = err.path().map(Path::to_path_buf).unwrap_or_default();
+            let source = match err.into_io_error() {
+                Some(source) => source,
+                None => io::Error::other("symlink loop"),
+            };
+            errors.record("list", &FdupError::io(&path, source));
+            None
+        }
+        (Err(_), None) => None,
+    }
+}
+
+/// # Returns
+///
+/// `roots` without those that resolve, through symlinks or otherwise, to the same directory as
+/// another one, or to a directory inside another one, so that no file is walked twice and reported
+/// as a copy of itself. Of several spellings of the same directory, the first is kept. Roots that
+/// can't be resolved are kept as they are.
+pub fn distinct_roots<'a>(roots: &[&'a Path]) -> Vec<&'a Path> {
+    let resolved: Vec<Option<PathBuf>> =
+        roots.iter().map(|root| root.canonicalize().ok()).collect();
+    let is_covered = |i: usize, this: &Path| {
+        resolved.iter().enumerate().any(|(j, other)| match other {
+            Some(other) if j != i && this.starts_with(other) => this != other || j < i,
+            _ => false,
+        })
+    };
+    roots
+        .iter()
+        .zip(resolved.iter())
+        .enumerate()
+        .filter(|(i, (_, this))| !this.as_ref().is_some_and(|this| is_covered(*i, this)))
+        .map(|(_, (root, _))| *root)
+        .collect()
+}
+
+/// # Returns
+///
+/// Every regular file under `root` that `filter` admits, sorted by path.
+pub fn files(root: &Path, traversal: Traversal, filter: &Filter) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = walk_logged(root, traversal, filter.fdupignore, None)
+        .filter(|entry| entry.file_type().is_file() && filter.admits_path(entry.path()))
+        .filter(|entry| match entry.metadata() {
+            Ok(meta) => filter.admits(entry.path(), meta.len()) && filter.admits_owner(&meta),
+            Err(_) => false,
+        })
+        .map(DirEntry::into_path)
+        .collect();
+    files.sort();
+    files
+}
+
+/// # Returns
+///
+/// Directories under `root`, not counting `root` itself, without a single regular file anywhere
+/// beneath them, sorted by path. Of nested ones, only the outermost is listed, since removing it
+/// removes the rest.
+pub fn empty_dirs(root: &Path, traversal: Traversal) -> Vec<PathBuf> {
+    let mut dirs = BTreeSet::new();
+    let mut occupied = HashSet::new();
+    for entry in walk(root, traversal).filter(|entry| entry.depth() > 0) {
+        match entry.file_type() {
+            kind if kind.is_dir() => {
+                dirs.insert(entry.into_path());
+            }
+            kind if kind.is_file() => {
+                // every directory on the way down to the file holds it
+                for dir in entry.path().ancestors().skip(1) {
+                    if dir == root || !occupied.insert(dir.to_path_buf()) {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let is_empty = |dir: &Path| dirs.contains(dir) && !occupied.contains(dir);
+    dirs.iter()
+        .filter(|dir| is_empty(dir))
+        .filter(|dir| !dir.parent().is_some_and(is_empty))
+        .cloned()
+        .collect()
+}
+
+/// Paths longer than this many UTF-16 units, counting the terminating NUL, can only be opened on
+/// Windows in their extended-length form.
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// # Returns
+///
+/// `path` made absolute in the extended-length form of Windows, `\\?\C:\...` or
+/// `\\?\UNC\server\share\...`, which is not limited to `MAX_PATH`, so that a tree walked
+/// from it can be arbitrarily deep. `path` itself if it can't be made absolute or is in some other
+/// form already.
+#[cfg(windows)]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::ffi::OsStringExt;
+    use std::path::Component;
+    use std::path::Prefix;
+
+    // also resolves `.` and `..`, which extended-length paths take literally
+    let absolute = match std::path::absolute(path) {
+        Ok(absolute) => absolute,
+        Err(_) => return path.to_path_buf(),
+    };
+    let wide: Vec<u16> = absolute.as_os_str().encode_wide().collect();
+    let extended: Vec<u16> = match absolute.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => r"\\?\".encode_utf16().chain(wide).collect(),
+            // \\server\share becomes \\?\UNC\server\share
+            Prefix::UNC(..) => r"\\?\UNC"
+                .encode_utf16()
+                .chain(wide[1..].iter().copied())
+                .collect(),
+            _ => return absolute,
+        },
+        _ => return absolute,
+    };
+    PathBuf::from(OsString::from_wide(&extended))
+}
+
+/// # Returns
+///
+/// `path` itself, since only Windows limits the length of paths that can be opened.
+#[cfg(not(windows))]
+pub fn extended_length_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// # Returns
+///
+/// `path` without the prefix of `extended_length_path`, for printing, wherever it is short enough
+/// to still be opened that way.
+#[cfg(windows)]
+pub fn plain_path(path: &Path) -> PathBuf {
+    let text = match path.to_str() {
+        Some(text) => text,
+        None => return path.to_path_buf(),
+    };
+    let plain = match (text.strip_prefix(r"\\?\UNC\"), text.strip_prefix(r"\\?\")) {
+        (Some(unc), _) => format!(r"\\{}", unc),
+        (None, Some(disk)) if disk.as_bytes().get(1) == Some(&b':') => String::from(disk),
+        _ => return path.to_path_buf(),
+    };
+    match plain.encode_utf16().count() < MAX_PATH {
+        true => PathBuf::from(plain),
+        false => path.to_path_buf(),
+    }
+}
+
+/// # Returns
+///
+/// `path` itself, which is never in extended-length form outside of Windows.
+#[cfg(not(windows))]
+pub fn plain_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::File;
+    use std::path::PathBuf;
+
+    #[cfg(windows)]
+    #[test]
+    fn long_paths_are_extended() {
+        assert_eq!(
+            PathBuf::from(r"\\?\C:\a\b"),
+            extended_length_path(Path::new(r"C:\a\.\x\..\b"))
+        );
+        assert_eq!(
+            PathBuf::from(r"\\?\UNC\server\share\a"),
+            extended_length_path(Path::new(r"\\server\share\a"))
+        );
+        assert_eq!(
+            PathBuf::from(r"C:\a\b"),
+            plain_path(Path::new(r"\\?\C:\a\b"))
+        );
+        let long = format!(r"\\?\C:\{}", "a".repeat(300));
+        assert_eq!(PathBuf::from(&long), plain_path(Path::new(&long)));
+    }
+
+    #[test]
+    fn orders_visit_the_same_entries() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(root.join("a/b/c")).unwrap();
+        create_dir_all(root.join("d")).unwrap();
+        for file in ["x", "a/y", "a/b/c/z", "d/w"].iter() {
+            File::create(root.join(file)).unwrap();
+        }
+
+        let visit = |traversal| {
+            let entries: Vec<DirEntry> = walk(&root, traversal).collect();
+            let depths: Vec<usize> = entries
+                .iter()
+                .map(|entry| entry.path().components().count())
+                .collect();
+            let mut paths: Vec<PathBuf> = entries.into_iter().map(DirEntry::into_path).collect();
+            paths.sort();
+            (paths, depths)
+        };
+        let (dfs_paths, _) = visit(Traversal::Dfs);
+        let (bfs_paths, bfs_depths) = visit(Traversal::Bfs);
+        assert_eq!(9, dfs_paths.len());
+        assert_eq!(dfs_paths, bfs_paths);
+        assert!(bfs_depths.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn fdupignore_files_prune_the_walk() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(root.join("build/deep")).unwrap();
+        create_dir_all(root.join("src/cache")).unwrap();
+        for file in [
+            "a",
+            "a.tmp",
+            "build/deep/b",
+            "src/c",
+            "src/keep.tmp",
+            "src/cache/d",
+        ]
+        .iter()
+        {
+            File::create(root.join(file)).unwrap();
+        }
+        std::fs::write(root.join(IGNORE_FILE), "build/\n*.tmp\n").unwrap();
+        std::fs::write(root.join("src").join(IGNORE_FILE), "!keep.tmp\ncache\n").unwrap();
+
+        let names = |traversal, fdupignore| {
+            let filter = Filter {
+                fdupignore,
+                ..Filter::default()
+            };
+            let files = files(&root, traversal, &filter);
+            let names: Vec<PathBuf> = files
+                .iter()
+                .map(|file| file.strip_prefix(&root).unwrap().to_path_buf())
+                .collect();
+            names
+        };
+        let expected: Vec<PathBuf> = [IGNORE_FILE, "a", "src/.fdupignore", "src/c", "src/keep.tmp"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(expected, names(Traversal::Dfs, true));
+        assert_eq!(expected, names(Traversal::Bfs, true));
+        assert_eq!(8, names(Traversal::Bfs, false).len());
+
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn empty_dirs_are_outermost_husks() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        for dir in ["husk/a/b", "husk/c", "kept/empty", "full/deep/er"].iter() {
+            create_dir_all(root.join(dir)).unwrap();
+        }
+        File::create(root.join("kept/x")).unwrap();
+        File::create(root.join("full/deep/er/y")).unwrap();
+
+        assert_eq!(
+            vec![root.join("husk"), root.join("kept/empty")],
+            empty_dirs(&root, Traversal::Bfs)
+        );
+
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn roots_are_walked_once() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(root.join("data/sub")).unwrap();
+        create_dir_all(root.join("other")).unwrap();
+        let link = root.join("link");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(root.join("data"), &link).unwrap();
+
+        let (data, sub, other) = (root.join("data"), root.join("data/sub"), root.join("other"));
+        let dotted = root.join("other/../data");
+        assert_eq!(vec![&*data], distinct_roots(&[&data, &link, &dotted]));
+        assert_eq!(vec![&*link], distinct_roots(&[&sub, &link]));
+        assert_eq!(
+            vec![&*data, &*other],
+            distinct_roots(&[&data, &other, &sub])
+        );
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+use crate::cache::HashCache;
+use crate::fdup::checksum;
+use crate::fdup::DuplicateGroup;
+use crate::filter::Filter;
+use crate::walk::files;
+use crate::walk::Traversal;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Sizes and checksums of the files of a tree, kept up to date one changed path at a time, so
+/// that a file that appears can be matched against the rest without scanning them again.
+///
+/// Files are only hashed once another file of the same size turns up, like in a full scan.
+pub struct WatchIndex<'a> {
+    cache: Option<&'a HashCache>,
+    filter: &'a Filter,
+    traversal: Traversal,
+    by_size: HashMap<u64, BTreeSet<PathBuf>>,
+    size_of: HashMap<PathBuf, u64>,
+    hash_of: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl<'a> WatchIndex<'a> {
+    /// # Parameters
+    ///
+    /// 1. `cache` -- if given, checksums are read from and written to it instead of always hashing
+    /// 1. `filter` -- conditions files have to meet to be considered at all
+    /// 1. `traversal` -- order in which to walk directories
+    /// 1. `root` -- directory whose files to index
+    pub fn new(
+        cache: Option<&'a HashCache>,
+        filter: &'a Filter,
+        traversal: Traversal,
+        root: &Path,
+    ) -> WatchIndex<'a> {
+        let mut index = WatchIndex {
+            cache,
+            filter,
+            traversal,
+            by_size: HashMap::new(),
+            size_of: HashMap::new(),
+            hash_of: HashMap::new(),
+        };
+        for path in files(root, traversal, filter) {
+            if let Ok(meta) = path.metadata() {
+                index.insert(path, meta.len());
+            }
+        }
+        index
+    }
+
+    /// # Returns
+    ///
+    /// Number of files indexed.
+    pub fn len(&self) -> usize {
+        self.size_of.len()
+    }
+
+    /// # Returns
+    ///
+    /// Whether no files are indexed.
+    pub fn is_empty(&self) -> bool {
+        self.size_of.is_empty()
+    }
+
+    /// Bring the index up to date with whatever is at `path` now, be it a new or changed file, a
+    /// directory moved in, or nothing at all.
+    ///
+    /// # Returns
+    ///
+    /// The groups files under `path` newly joined, each sorted by path. A file that changed but
+    /// still has the same contents joins nothing new.
+    pub fn update(&mut self, path: &Path) -> Vec<DuplicateGroup> {
+        let meta = match path.symlink_metadata() {
+            Ok(meta) => meta,
+            Err(_) => {
+                self.remove(path);
+                return Vec::new();
+            }
+        };
+        if meta.is_dir() {
+            return files(path, self.traversal, self.filter)
+                .iter()
+                .flat_map(|file| self.update(file))
+                .collect();
+        }
+        let previous = self.hash_of.get(path).cloned();
+        self.remove(path);
+        if !meta.is_file() || !self.filter.admits(path, meta.len()) {
+            return Vec::new();
+        }
+        self.insert(path.to_path_buf(), meta.len());
+        let group = self.group_of(path);
+        match (group, previous) {
+            (Some(group), Some(previous)) if group.hash == previous => Vec::new(),
+            (Some(group), _) => vec![group],
+            (None, _) => Vec::new(),
+        }
+    }
+
+    /// Forget the file at `path`, or every file under it if it was a directory.
+    pub fn remove(&mut self, path: &Path) {
+        let removed: Vec<PathBuf> = match self.size_of.contains_key(path) {
+            true => vec![path.to_path_buf()],
+            false => self
+                .size_of
+                .keys()
+                .filter(|file| file.starts_with(path))
+                .cloned()
+                .collect(),
+        };
+        for file in removed {
+            if let Some(size) = self.size_of.remove(&file) {
+                let same_size = self.by_size.get_mut(&size).unwrap();
+                same_size.remove(&file);
+                if same_size.is_empty() {
+                    self.by_size.remove(&size);
+                }
+            }
+            self.hash_of.remove(&file);
+        }
+    }
+
+    fn insert(&mut self, path: PathBuf, size: u64) {
+        self.by_size.entry(size).or_default().insert(path.clone());
+        self.size_of.insert(path, size);
+    }
+
+    /// # Returns
+    ///
+    /// Every indexed file with the same contents as the one at `path`, including itself, if there
+    /// are at least two of them.
+    fn group_of(&mut self, path: &Path) -> Option<DuplicateGroup> {
+        let size = self.size_of[path];
+        let same_size: Vec<PathBuf> = self.by_size[&size].iter().cloned().collect();
+        if same_size.len() < 2 {
+            return None;
+        }
+        let hash = self.hash(path)?;
+        let paths: Vec<PathBuf> = same_size
+            .into_iter()
+            .filter(|other| self.hash(other).as_ref() == Some(&hash))
+            .collect();
+        match paths.len() {
+            0 | 1 => None,
+            _ => Some(DuplicateGroup { hash, size, paths }),
+        }
+    }
+
+    /// # Returns
+    ///
+    /// Checksum of the file at `path`, hashing it only if it hasn't been already, or `None` if it
+    /// can't be read.
+    fn hash(&mut self, path: &Path) -> Option<Vec<u8>> {
+        if let Some(hash) = self.hash_of.get(path) {
+            return Some(hash.clone());
+        }
+        let hash = match self.cache {
+            Some(cache) => cache.checksum(path),
+            None => checksum(path),
+        };
+        match hash {
+            Ok(hash) => {
+                self.hash_of.insert(path.to_path_buf(), hash.clone());
+                Some(hash)
+            }
+            Err(err) => {
+                eprintln!("ERROR with {}", err);
+                None
+            }
+        }
+    }
+}
+
+/// Watch the tree under `root` for changes, until the watch fails, passing each group a changed
+/// file newly joins to `report`.
+///
+/// # Parameters
+///
+/// 1. `index` -- files of the tree as they were when the watch started
+/// 1. `root` -- directory to watch
+/// 1. `report` -- called with every new group
+#[cfg(feature = "watch")]
+pub fn watch<F>(index: &mut WatchIndex, root: &Path, mut report: F) -> notify::Result<()>
+where
+    F: FnMut(&DuplicateGroup),
+{
+    use notify::event::AccessKind;
+    use notify::event::AccessMode;
+    use notify::event::ModifyKind;
+    use notify::EventKind;
+    use notify::RecursiveMode;
+    use notify::Watcher;
+    use std::sync::mpsc::channel;
+
+    let (sender, receiver) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+    for event in receiver {
+        let event = event?;
+        // hashing opens and reads files too, so only writes are worth a look; where closing a
+        // written file is reported, files are only hashed once they are complete
+        let changed = match event.kind {
+            EventKind::Modify(ModifyKind::Data(_)) | EventKind::Modify(ModifyKind::Metadata(_)) => {
+                !cfg!(target_os = "linux")
+            }
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => true,
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => false,
+        };
+        if !changed {
+            continue;
+        }
+        for path in event.paths {
+            for group in index.update(&path) {
+                report(&group);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+    use std::fs::remove_file;
+    use std::fs::rename;
+    use std::fs::write;
+
+    #[test]
+    fn changes_join_groups() {
+        let root = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(root.join("inbox")).unwrap();
+        write(root.join("a"), "same").unwrap();
+        write(root.join("b"), "diff").unwrap();
+        let filter = Filter::default();
+        let mut index = WatchIndex::new(None, &filter, Traversal::Dfs, &root);
+        assert_eq!(2, index.len());
+
+        write(root.join("inbox/c"), "same").unwrap();
+        let groups = index.update(&root.join("inbox/c"));
+        assert_eq!(1, groups.len());
+        assert_eq!(vec![root.join("a"), root.join("inbox/c")], groups[0].paths);
+
+        // touched without changing contents
+        write(root.join("inbox/c"), "same").unwrap();
+        assert!(index.update(&root.join("inbox/c")).is_empty());
+
+        write(root.join("b"), "same").unwrap();
+        assert_eq!(3, index.update(&root.join("b"))[0].paths.len());
+
+        remove_file(root.join("a")).unwrap();
+        assert!(index.update(&root.join("a")).is_empty());
+        assert_eq!(2, index.len());
+
+        // a whole directory moved in at once
+        let outside = root.with_extension("outside");
+        create_dir_all(&outside).unwrap();
+        write(outside.join("d"), "diff").unwrap();
+        write(outside.join("e"), "diff").unwrap();
+        rename(&outside, root.join("moved")).unwrap();
+        let groups = index.update(&root.join("moved"));
+        assert_eq!(1, groups.len());
+        assert_eq!(
+            vec![root.join("moved/d"), root.join("moved/e")],
+            groups[0].paths
+        );
+        assert_eq!(4, index.len());
+
+        remove_dir_all(root.join("moved")).unwrap();
+        index.update(&root.join("moved"));
+        assert_eq!(2, index.len());
+
+        remove_dir_all(&root).unwrap();
+    }
+}
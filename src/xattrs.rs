@@ -0,0 +1,144 @@
+use std::io;
+use std::path::Path;
+
+/// Name and value of an extended attribute.
+pub type Xattr = (Vec<u8>, Vec<u8>);
+
+/// # Parameters
+///
+/// 1. `path` -- file whose attributes to read
+/// 1. `acls` -- whether to include the POSIX ACLs, which Linux keeps as extended attributes too
+///
+/// # Returns
+///
+/// The extended attributes of the file at `path` that users set, such as tags, ordered by name:
+/// those in the `user` namespace on Linux, and all of them on macOS, which has no namespaces.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn extended_attributes(path: &Path, acls: bool) -> io::Result<Vec<Xattr>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // safe, since `c_path` is NUL-terminated and `sized` passes a buffer at least `len` long
+    let names = sized(|buf, len| unsafe { list(c_path.as_ptr(), buf, len) })?;
+    let mut attributes = Vec::new();
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let acl = name.starts_with(b"system.posix_acl_");
+        if !(cfg!(target_os = "macos") || name.starts_with(b"user.") || (acls && acl)) {
+            continue; // kept by the system or by security modules, not by users
+        }
+        let c_name = CString::new(name)?;
+        // safe, since both names are NUL-terminated and `sized` passes a buffer at least `len`
+        // long
+        let value = sized(|buf, len| unsafe { get(c_path.as_ptr(), c_name.as_ptr(), buf, len) })?;
+        attributes.push((name.to_vec(), value));
+    }
+    attributes.sort();
+    Ok(attributes)
+}
+
+/// # Returns
+///
+/// No attributes, since they are only read on Linux and macOS.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn extended_attributes(_path: &Path, _acls: bool) -> io::Result<Vec<Xattr>> {
+    Ok(Vec::new())
+}
+
+/// # Returns
+///
+/// What `read` puts into a buffer it is asked the size of first, by passing it a null one, then
+/// asked to fill, over again if the size changed in between.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn sized<F>(read: F) -> io::Result<Vec<u8>>
+where
+    F: Fn(*mut libc::c_void, usize) -> isize,
+{
+    loop {
+        let len = read(std::ptr::null_mut(), 0);
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buffer = vec![0u8; len as usize];
+        let read_len = read(buffer.as_mut_ptr() as *mut libc::c_void, buffer.len());
+        if read_len >= 0 {
+            buffer.truncate(read_len as usize);
+            return Ok(buffer);
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn list(path: *const libc::c_char, buf: *mut libc::c_void, len: usize) -> isize {
+    libc::listxattr(path, buf as *mut libc::c_char, len)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn list(path: *const libc::c_char, buf: *mut libc::c_void, len: usize) -> isize {
+    libc::listxattr(path, buf as *mut libc::c_char, len, 0)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn get(
+    path: *const libc::c_char,
+    name: *const libc::c_char,
+    buf: *mut libc::c_void,
+    len: usize,
+) -> isize {
+    libc::getxattr(path, name, buf, len)
+}
+
+#[cfg(target_os = "macos")]
+unsafe fn get(
+    path: *const libc::c_char,
+    name: *const libc::c_char,
+    buf: *mut libc::c_void,
+    len: usize,
+) -> isize {
+    libc::getxattr(path, name, buf, len, 0, 0)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::fs::remove_dir_all;
+
+    #[test]
+    fn user_attributes_are_read() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = env::temp_dir().join(format!("{}_{}_{}", module_path!(), line!(), column!()));
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("a");
+        std::fs::write(&path, "tagged").unwrap();
+        assert_eq!(0, extended_attributes(&path, true).unwrap().len());
+
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let value = b"red";
+        // safe, since both names are NUL-terminated and `value` is as long as it says
+        let set = unsafe {
+            libc::setxattr(
+                c_path.as_ptr(),
+                b"user.tag\0".as_ptr() as *const libc::c_char,
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        };
+        if set == 0 {
+            // not every file system the tests may run on has extended attributes
+            let expected = vec![(b"user.tag".to_vec(), value.to_vec())];
+            assert_eq!(expected, extended_attributes(&path, false).unwrap());
+        }
+        assert!(extended_attributes(&dir.join("missing"), false).is_err());
+
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! Golden tests pinning down the exact output of every format over a canned fixture tree.
+//!
+//! Run with `FDUP_BLESS=1` to rewrite the golden files after an intended change of output. Unix
+//! only, since some of the fixture names are not valid on other platforms.
+#![cfg(unix)]
+
+use std::env;
+use std::fs::create_dir_all;
+use std::fs::read_to_string;
+use std::fs::remove_dir_all;
+use std::fs::write;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
+
+/// Files of the fixture tree and their contents. Names are chosen to trip up quoting.
+const FIXTURE: &[(&str, &str)] = &[
+    ("plain/a.txt", "same contents\n"),
+    ("plain/b.txt", "same contents\n"),
+    ("plain/unique.txt", "nothing else like it\n"),
+    ("weird/with space.txt", "same contents\n"),
+    ("weird/it's.txt", "same contents\n"),
+    ("weird/-dash", "other\n"),
+    ("weird/$(echo pwned)", "other\n"),
+    ("weird/line\nbreak", "other\n"),
+    ("weird/ünïcødé", "another group of a different size\n"),
+    ("plain/ascii", "another group of a different size\n"),
+    ("plain/empty-1", ""),
+    ("weird/empty-2", ""),
+];
+
+/// Modification time of every fixture file, 2001-09-09T01:46:40Z, so that reported mtimes are
+/// stable.
+const FIXTURE_MTIME: Duration = Duration::from_secs(1_000_000_000);
+
+/// Keys of JSON values masked by `mask_varying`.
+const VARYING_KEYS: &[&str] = &[
+    "\"started\":",
+    "\"finished\":",
+    "\"cwd\":",
+    "\"args\":",
+    "\"inode\":",
+    "\"disk_id\":",
+];
+
+/// Create the fixture tree in a fresh directory unique to `name`.
+///
+/// # Returns
+///
+/// Path to the root of the fixture tree.
+fn fixture(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("{}_{}", module_path!(), name));
+    let _ = remove_dir_all(&root); // left over from an aborted run
+    for (path, contents) in FIXTURE {
+        let path = root.join(path);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(&path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(UNIX_EPOCH + FIXTURE_MTIME))
+            .unwrap();
+    }
+    root
+}
+
+/// # Returns
+///
+/// `output` with every value that changes from run to run or from machine to machine replaced by
+/// `...`: the `"started"` and `"finished"` timestamps, and the working directory, command line,
+/// inodes and devices of rmlint's format, and the devices and inodes of rdfind's.
+fn mask_varying(output: &str) -> String {
+    let mut masked = String::from(output);
+    for key in VARYING_KEYS {
+        let mut from = 0;
+        while let Some(start) = masked[from..].find(key).map(|i| from + i + key.len()) {
+            let value = &masked[start..];
+            // strings keep their quotes, numbers end at the first non-digit
+            let (start, len) = match value.strip_prefix('"') {
+                Some(string) => (start + 1, string.find('"').unwrap()),
+                None => (start, value.find(|c: char| !c.is_ascii_digit()).unwrap()),
+            };
+            masked.replace_range(start..start + len, "...");
+            from = start;
+        }
+    }
+    masked
+        .split_inclusive('\n')
+        .map(|line| match line.starts_with("DUPTYPE_") {
+            true => {
+                let mut fields: Vec<&str> = line.splitn(8, ' ').collect();
+                fields[4] = "...";
+                fields[5] = "...";
+                fields.join(" ")
+            }
+            false => line.to_string(),
+        })
+        .collect()
+}
+
+/// Run fdup with `args` on the fixture tree, from inside it so that printed paths are relative,
+/// and compare its stdout to `tests/golden/<name>.txt`.
+fn assert_golden(name: &str, args: &[&str]) {
+    let root = fixture(name);
+    let output = Command::new(env!("CARGO_BIN_EXE_fdup"))
+        .args(args)
+        .arg(".")
+        .current_dir(&root)
+        .env("XDG_CONFIG_HOME", &root)
+        .output()
+        .unwrap();
+    remove_dir_all(&root).unwrap();
+    // 1 just means duplicates were found
+    assert!(
+        matches!(output.status.code(), Some(0) | Some(1)),
+        "{:?} failed: {:?}",
+        args,
+        output
+    );
+    let actual = mask_varying(&String::from_utf8(output.stdout).unwrap());
+
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.txt", name));
+    match env::var_os("FDUP_BLESS") {
+        Some(_) => write(&golden, &actual).unwrap(),
+        None => assert_eq!(read_to_string(&golden).unwrap(), actual, "{:?}", golden),
+    }
+}
+
+#[test]
+fn debug() {
+    assert_golden("debug", &["-s"]);
+}
+
+#[test]
+fn shell() {
+    assert_golden("shell", &["-s", "--format", "shell"]);
+}
+
+#[test]
+fn json() {
+    assert_golden("json", &["-s", "--format", "json"]);
+}
+
+#[test]
+fn script_sh() {
+    assert_golden(
+        "script_sh",
+        &["dedup", "-s", "--delete", "--emit-script", "sh"],
+    );
+}
+
+#[test]
+fn script_ps1() {
+    assert_golden(
+        "script_ps1",
+        &["dedup", "-s", "--hardlink", "--emit-script", "ps1"],
+    );
+}
+
+#[test]
+fn dry_run() {
+    assert_golden(
+        "dry_run",
+        &[
+            "dedup",
+            "-s",
+            "--delete",
+            "--dry-run",
+            "--keep",
+            "shortest-path",
+        ],
+    );
+}
+
+#[test]
+fn reference() {
+    assert_golden(
+        "reference",
+        &[
+            "dedup",
+            "-s",
+            "--reference",
+            "plain",
+            "--delete",
+            "--dry-run",
+        ],
+    );
+}
+
+#[test]
+fn unique() {
+    assert_golden("unique", &["--unique"]);
+}
+
+#[test]
+fn min_copies() {
+    assert_golden("min_copies", &["-s", "--min-copies", "3"]);
+}
+
+#[test]
+fn cross_dir_only() {
+    assert_golden("cross_dir_only", &["-s", "--cross-dir-only"]);
+}
+
+#[test]
+fn same_dir_only() {
+    assert_golden("same_dir_only", &["-s", "--same-dir-only"]);
+}
+
+#[test]
+fn group_empty() {
+    assert_golden("group_empty", &["-s", "--group-empty"]);
+}
+
+#[test]
+fn relative() {
+    assert_golden("relative", &["-s", "--relative"]);
+}
+
+#[test]
+fn shell_relative() {
+    assert_golden("shell_relative", &["-s", "--relative", "--format", "shell"]);
+}
+
+#[test]
+fn show_hash() {
+    assert_golden("show_hash", &["-s", "--show-hash", "--format", "shell"]);
+}
+
+#[test]
+fn show_meta() {
+    assert_golden("show_meta", &["-s", "--show-meta"]);
+}
+
+#[test]
+fn exit_codes() {
+    let root = fixture("exit_codes");
+    let status = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_fdup"))
+            .args(args)
+            .current_dir(&root)
+            .env("XDG_CONFIG_HOME", &root)
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+    assert_eq!(Some(1), status(&["."]));
+    assert_eq!(Some(0), status(&["--min-copies", "5", "."]));
+    assert_eq!(Some(0), status(&["--help"]));
+    assert_eq!(Some(3), status(&["--no-such-option", "."]));
+    assert_eq!(Some(3), status(&[]));
+    remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn quiet() {
+    assert_golden("quiet", &["-q"]);
+}
+
+#[test]
+fn raw() {
+    assert_golden("raw", &["-s", "--format", "raw"]);
+}
+
+#[test]
+fn rmlint_json() {
+    assert_golden("rmlint_json", &["-s", "--format", "rmlint-json"]);
+}
+
+#[test]
+fn rdfind() {
+    assert_golden("rdfind", &["-s", "--format", "rdfind"]);
+}